@@ -0,0 +1,106 @@
+use log::error;
+use tokio::time::{self, Duration, Instant};
+
+use crate::store::{StoreError, StoreResult};
+use crate::structs::AppState;
+
+// Changes of the content of a document and the moves of the comment anchors
+// and suggestion ranges they cause happen one at a time, across instances.
+// The moves of an edit are only right when applied in the order of the
+// edits, and a range read from the content has to be stored before the
+// content moves on.
+
+// Released right after the change, it only runs out if its holder went away
+const LOCK_TTL_MS: i64 = 5000;
+
+// How long a change waits for the ones before it
+const LOCK_WAIT: Duration = Duration::from_secs(5);
+
+const LOCK_RETRY: Duration = Duration::from_millis(5);
+
+pub struct AnchorLock {
+    document_id: String,
+    token: String,
+}
+
+// Waits for the anchor lock of the document
+
+pub async fn lock(state: &AppState, document_id: &str) -> StoreResult<AnchorLock> {
+    let token = format!("{:032x}", rand::random::<u128>());
+    let deadline = Instant::now() + LOCK_WAIT;
+
+    while !state
+        .live
+        .lock_anchors(document_id, &token, LOCK_TTL_MS)
+        .await?
+    {
+        if Instant::now() >= deadline {
+            return Err(StoreError::new(format!(
+                "Timed out waiting for the anchor lock of doc {}",
+                document_id
+            )));
+        }
+        time::sleep(LOCK_RETRY).await;
+    }
+
+    Ok(AnchorLock {
+        document_id: document_id.to_string(),
+        token,
+    })
+}
+
+impl AnchorLock {
+    pub async fn release(self, state: &AppState) {
+        if let Err(e) = state
+            .live
+            .unlock_anchors(&self.document_id, &self.token)
+            .await
+        {
+            error!(
+                "Failed to release the anchor lock of doc {}: {}",
+                self.document_id, e
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::LiveDocCache;
+    use crate::store::memory::MemoryStore;
+
+    #[tokio::test]
+    async fn changes_of_a_document_wait_for_each_other() {
+        let state = MemoryStore::new().app_state();
+        let first = lock(&state, "doc").await.unwrap();
+        let other = lock(&state, "other").await.unwrap();
+
+        let waiting = {
+            let state = state.clone();
+            tokio::spawn(async move { lock(&state, "doc").await.unwrap() })
+        };
+        time::sleep(Duration::from_millis(50)).await;
+        assert!(!waiting.is_finished());
+
+        first.release(&state).await;
+        let second = waiting.await.unwrap();
+        second.release(&state).await;
+        other.release(&state).await;
+    }
+
+    #[tokio::test]
+    async fn a_lock_left_behind_runs_out() {
+        let store = MemoryStore::new();
+        let state = store.app_state();
+        assert!(store.lock_anchors("doc", "gone", 1).await.unwrap());
+        time::sleep(Duration::from_millis(5)).await;
+
+        let lock = lock(&state, "doc").await.unwrap();
+
+        // Only the holder's token releases it
+        store.unlock_anchors("doc", "gone").await.unwrap();
+        assert!(!store.lock_anchors("doc", "other", 1000).await.unwrap());
+        lock.release(&state).await;
+    }
+}
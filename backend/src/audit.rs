@@ -0,0 +1,104 @@
+use axum::{extract::State, http::StatusCode};
+use log::error;
+use serde_json::Value;
+
+use crate::authz::{self, Capability};
+use crate::errors::{ApiError, Json};
+use crate::structs::{AppState, AuditLogResponse, GetAuditLogRequest};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditEvent {
+    Login,
+    LoginFailed,
+    WsOpen,
+    WsClose,
+    RoleResolved,
+    AccessDenied,
+    DocumentCreated,
+    ShareChanged,
+    ShareRemoved,
+    OwnershipTransferred,
+    DocumentDeleted,
+    DocumentRenamed,
+    Published,
+    Unpublished,
+    DocumentMoved,
+    WriteConflict,
+}
+
+impl AuditEvent {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditEvent::Login => "login",
+            AuditEvent::LoginFailed => "login_failed",
+            AuditEvent::WsOpen => "ws_open",
+            AuditEvent::WsClose => "ws_close",
+            AuditEvent::RoleResolved => "role_resolved",
+            AuditEvent::AccessDenied => "access_denied",
+            AuditEvent::DocumentCreated => "document_created",
+            AuditEvent::ShareChanged => "share_changed",
+            AuditEvent::ShareRemoved => "share_removed",
+            AuditEvent::OwnershipTransferred => "ownership_transferred",
+            AuditEvent::DocumentDeleted => "document_deleted",
+            AuditEvent::DocumentRenamed => "document_renamed",
+            AuditEvent::Published => "published",
+            AuditEvent::Unpublished => "unpublished",
+            AuditEvent::DocumentMoved => "document_moved",
+            AuditEvent::WriteConflict => "write_conflict",
+        }
+    }
+}
+
+// Appends an event to the audit log. Failing to write the audit log never
+// fails the request that caused it, the error is only logged.
+
+pub async fn record(
+    state: &AppState,
+    event: AuditEvent,
+    actor: Option<&str>,
+    document_id: Option<&str>,
+    details: Value,
+) {
+    let result = state
+        .audit
+        .append(event.as_str(), actor, document_id, details)
+        .await;
+
+    if let Err(e) = result {
+        error!("Failed to write audit event {}: {}", event.as_str(), e);
+    }
+}
+
+// Fetch the audit trail of a document, newest first. Only the owner may read it.
+
+#[utoipa::path(
+    post,
+    path = "/get_audit_log",
+    tag = "audit",
+    request_body = GetAuditLogRequest,
+    responses((status = 200, body = AuditLogResponse))
+)]
+pub async fn get_audit_log(
+    State(state): State<AppState>,
+    Json(payload): Json<GetAuditLogRequest>,
+) -> Result<(StatusCode, Json<AuditLogResponse>), ApiError> {
+    authz::authorize(
+        &state,
+        &payload.email,
+        &payload.document_id,
+        Capability::Audit,
+    )
+    .await?;
+
+    let limit = payload.limit.unwrap_or(100).clamp(1, 1000);
+
+    let entries = state.audit.entries(&payload, limit).await?;
+
+    Ok((
+        StatusCode::OK,
+        Json(AuditLogResponse {
+            success: true,
+            entries,
+        }),
+    ))
+}
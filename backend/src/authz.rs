@@ -0,0 +1,475 @@
+use std::fmt;
+use std::str::FromStr;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::json;
+use utoipa::ToSchema;
+
+use crate::audit::{self, AuditEvent};
+use crate::errors::ApiError;
+use crate::store::StoreError;
+use crate::structs::AppState;
+
+// Roles as stored in the postgres `role` domain
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Owner,
+    Editor,
+    Suggester,
+    Reader,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capability {
+    View,
+    Comment,
+    Suggest,
+    Edit,
+    Share,
+    Delete,
+    Transfer,
+    Audit,
+}
+
+impl Capability {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Capability::View => "view",
+            Capability::Comment => "comment",
+            Capability::Suggest => "suggest",
+            Capability::Edit => "edit",
+            Capability::Share => "share",
+            Capability::Delete => "delete",
+            Capability::Transfer => "transfer",
+            Capability::Audit => "audit",
+        }
+    }
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Owner => "owner",
+            Role::Editor => "editor",
+            Role::Suggester => "suggester",
+            Role::Reader => "reader",
+        }
+    }
+
+    // Higher values grant more, used to pick between several grants
+    pub fn privilege(&self) -> u8 {
+        match self {
+            Role::Reader => 0,
+            Role::Suggester => 1,
+            Role::Editor => 2,
+            Role::Owner => 3,
+        }
+    }
+
+    pub fn can(&self, capability: Capability) -> bool {
+        match capability {
+            Capability::View | Capability::Comment => true,
+            Capability::Suggest => matches!(self, Role::Owner | Role::Editor | Role::Suggester),
+            Capability::Edit => matches!(self, Role::Owner | Role::Editor),
+            Capability::Share | Capability::Delete | Capability::Transfer | Capability::Audit => {
+                matches!(self, Role::Owner)
+            }
+        }
+    }
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "owner" => Ok(Role::Owner),
+            "editor" => Ok(Role::Editor),
+            "suggester" => Ok(Role::Suggester),
+            "reader" => Ok(Role::Reader),
+            other => Err(format!("Unknown role: {}", other)),
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+// Where a role on a document comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum GrantSource {
+    Direct,
+    Group { group_id: i32 },
+    ShareLink { link_id: i32 },
+    Folder { folder_id: i32 },
+    Public,
+}
+
+// A single row granting a role, before precedence is applied. Grants without
+// an end date are permanent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Grant {
+    pub role: Role,
+    pub source: GrantSource,
+    pub valid_until: Option<DateTime<Utc>>,
+}
+
+// The role a user holds on a document and the grant it comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Access {
+    pub role: Role,
+    pub source: GrantSource,
+    pub valid_until: Option<DateTime<Utc>>,
+}
+
+impl Access {
+    pub fn is_direct(&self) -> bool {
+        matches!(self.source, GrantSource::Direct)
+    }
+
+    // Ownership level capabilities are only granted directly, a group with the
+    // owner role only makes its members editors of the document. Anonymous
+    // visitors of a published document can only read it.
+    pub fn can(&self, capability: Capability) -> bool {
+        match capability {
+            _ if self.source == GrantSource::Public => capability == Capability::View,
+            Capability::Share | Capability::Delete | Capability::Transfer | Capability::Audit
+                if !self.is_direct() =>
+            {
+                false
+            }
+            _ => self.role.can(capability),
+        }
+    }
+}
+
+// Picks the most privileged grant. On equal roles a direct grant wins, since
+// it also carries the ownership level capabilities, and a share link loses,
+// so it is not redeemed when another grant is as good.
+
+pub fn resolve(grants: &[Grant]) -> Option<Access> {
+    grants
+        .iter()
+        .max_by_key(|g| {
+            (
+                g.role.privilege(),
+                g.source == GrantSource::Direct,
+                !matches!(g.source, GrantSource::ShareLink { .. }),
+            )
+        })
+        .map(|g| Access {
+            role: g.role,
+            source: g.source,
+            valid_until: g.valid_until,
+        })
+}
+
+#[derive(Debug)]
+pub enum AuthzError {
+    NoAccess,
+    Forbidden(Capability),
+    Storage(StoreError),
+}
+
+impl From<StoreError> for AuthzError {
+    fn from(e: StoreError) -> Self {
+        AuthzError::Storage(e)
+    }
+}
+
+impl From<AuthzError> for ApiError {
+    fn from(e: AuthzError) -> Self {
+        match e {
+            AuthzError::NoAccess => {
+                ApiError::Unauthorized("No access to this document".to_string())
+            }
+            AuthzError::Forbidden(capability) => ApiError::Forbidden(format!(
+                "Your role does not allow {} on this document",
+                capability.as_str()
+            )),
+            AuthzError::Storage(e) => e.into(),
+        }
+    }
+}
+
+// Checks the access of the resolved role against a capability
+
+pub fn check(access: Option<Access>, capability: Capability) -> Result<Access, AuthzError> {
+    let access = access.ok_or(AuthzError::NoAccess)?;
+    if access.can(capability) {
+        Ok(access)
+    } else {
+        Err(AuthzError::Forbidden(capability))
+    }
+}
+
+// Every handler working on a document goes through this function, which also
+// records the outcome in the audit log
+
+pub async fn authorize(
+    state: &AppState,
+    email: &str,
+    document_id: &str,
+    capability: Capability,
+) -> Result<Access, AuthzError> {
+    authorize_with_token(state, email, document_id, None, capability).await
+}
+
+// Same as `authorize`, also taking into account a share link presented by the user
+
+pub async fn authorize_with_token(
+    state: &AppState,
+    email: &str,
+    document_id: &str,
+    share_token: Option<&str>,
+    capability: Capability,
+) -> Result<Access, AuthzError> {
+    let mut access = user_has_access(email, document_id, share_token, state).await?;
+    let mut result = check(access, capability);
+
+    // A share link is only redeemed once it grants what was asked for. If it
+    // ran out of uses in the meantime the other grants of the user decide.
+    if let Ok(Access {
+        source: GrantSource::ShareLink { link_id },
+        ..
+    }) = result
+        && !state.access.redeem_share_link(link_id, email).await?
+    {
+        access = user_has_access(email, document_id, None, state).await?;
+        result = check(access, capability);
+    }
+
+    let (event, details) = match (&result, access) {
+        (Ok(access), _) => (
+            AuditEvent::RoleResolved,
+            json!({ "capability": capability.as_str(), "role": access.role.as_str(), "source": access.source }),
+        ),
+        (Err(_), Some(access)) => (
+            AuditEvent::AccessDenied,
+            json!({ "capability": capability.as_str(), "role": access.role.as_str(), "source": access.source }),
+        ),
+        (Err(_), None) => (
+            AuditEvent::AccessDenied,
+            json!({ "capability": capability.as_str() }),
+        ),
+    };
+    audit::record(state, event, Some(email), Some(document_id), details).await;
+
+    result
+}
+
+pub async fn user_has_access(
+    email: &str,
+    doc_id: &str,
+    share_token: Option<&str>,
+    state: &AppState,
+) -> Result<Option<Access>, StoreError> {
+    let mut grants = state.access.grants(email, doc_id).await?;
+
+    if let Some(token) = share_token {
+        grants.extend(state.access.share_link_grant(token, email, doc_id).await?);
+    }
+
+    Ok(resolve(&grants))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: [Capability; 8] = [
+        Capability::View,
+        Capability::Comment,
+        Capability::Suggest,
+        Capability::Edit,
+        Capability::Share,
+        Capability::Delete,
+        Capability::Transfer,
+        Capability::Audit,
+    ];
+
+    fn direct(role: Role) -> Option<Access> {
+        Some(Access {
+            role,
+            source: GrantSource::Direct,
+            valid_until: None,
+        })
+    }
+
+    fn group(role: Role) -> Option<Access> {
+        Some(Access {
+            role,
+            source: GrantSource::Group { group_id: 1 },
+            valid_until: None,
+        })
+    }
+
+    fn allowed(access: Option<Access>) -> Vec<Capability> {
+        ALL.into_iter()
+            .filter(|c| check(access, *c).is_ok())
+            .collect()
+    }
+
+    #[test]
+    fn direct_role_matrix() {
+        assert_eq!(allowed(direct(Role::Owner)), ALL.to_vec());
+        assert_eq!(
+            allowed(direct(Role::Editor)),
+            vec![
+                Capability::View,
+                Capability::Comment,
+                Capability::Suggest,
+                Capability::Edit
+            ]
+        );
+        assert_eq!(
+            allowed(direct(Role::Suggester)),
+            vec![Capability::View, Capability::Comment, Capability::Suggest]
+        );
+        assert_eq!(
+            allowed(direct(Role::Reader)),
+            vec![Capability::View, Capability::Comment]
+        );
+    }
+
+    #[test]
+    fn group_roles_grant_the_same_content_capabilities() {
+        for role in [Role::Editor, Role::Suggester, Role::Reader] {
+            assert_eq!(allowed(group(role)), allowed(direct(role)));
+        }
+    }
+
+    #[test]
+    fn group_owner_can_not_share_delete_or_transfer() {
+        assert_eq!(
+            allowed(group(Role::Owner)),
+            vec![
+                Capability::View,
+                Capability::Comment,
+                Capability::Suggest,
+                Capability::Edit
+            ]
+        );
+    }
+
+    #[test]
+    fn no_access_is_not_forbidden() {
+        for capability in ALL {
+            assert!(matches!(check(None, capability), Err(AuthzError::NoAccess)));
+        }
+        assert!(matches!(
+            check(direct(Role::Reader), Capability::Edit),
+            Err(AuthzError::Forbidden(Capability::Edit))
+        ));
+    }
+
+    #[test]
+    fn roles_round_trip_through_strings() {
+        for role in [Role::Owner, Role::Editor, Role::Suggester, Role::Reader] {
+            assert_eq!(role.as_str().parse::<Role>(), Ok(role));
+        }
+        assert!("admin".parse::<Role>().is_err());
+    }
+
+    fn grant(role: Role, source: GrantSource) -> Grant {
+        Grant {
+            role,
+            source,
+            valid_until: None,
+        }
+    }
+
+    #[test]
+    fn owner_in_editor_group_stays_owner() {
+        let access = resolve(&[
+            grant(Role::Editor, GrantSource::Group { group_id: 4 }),
+            grant(Role::Owner, GrantSource::Direct),
+        ]);
+        assert_eq!(access, direct(Role::Owner));
+        assert!(check(access, Capability::Delete).is_ok());
+    }
+
+    #[test]
+    fn highest_privilege_wins_regardless_of_order() {
+        let grants = [
+            grant(Role::Reader, GrantSource::Direct),
+            grant(Role::Suggester, GrantSource::Group { group_id: 2 }),
+            grant(Role::Editor, GrantSource::Group { group_id: 7 }),
+        ];
+        let expected = Some(Access {
+            role: Role::Editor,
+            source: GrantSource::Group { group_id: 7 },
+            valid_until: None,
+        });
+
+        assert_eq!(resolve(&grants), expected);
+
+        let mut reversed = grants;
+        reversed.reverse();
+        assert_eq!(resolve(&reversed), expected);
+    }
+
+    #[test]
+    fn direct_grant_wins_over_equal_group_grant() {
+        let access = resolve(&[
+            grant(Role::Owner, GrantSource::Group { group_id: 3 }),
+            grant(Role::Owner, GrantSource::Direct),
+        ]);
+        assert_eq!(access, direct(Role::Owner));
+    }
+
+    #[test]
+    fn share_link_editor_can_not_share() {
+        let access = resolve(&[
+            grant(Role::Reader, GrantSource::Direct),
+            grant(Role::Editor, GrantSource::ShareLink { link_id: 9 }),
+        ]);
+        assert!(check(access, Capability::Edit).is_ok());
+        assert!(check(access, Capability::Share).is_err());
+    }
+
+    #[test]
+    fn access_keeps_the_expiry_of_the_chosen_grant() {
+        let until = Utc::now();
+        let access = resolve(&[
+            grant(Role::Reader, GrantSource::Direct),
+            Grant {
+                role: Role::Editor,
+                source: GrantSource::Group { group_id: 5 },
+                valid_until: Some(until),
+            },
+        ]);
+        assert_eq!(access.map(|a| a.valid_until), Some(Some(until)));
+    }
+
+    #[test]
+    fn folder_grants_do_not_carry_ownership() {
+        let access = resolve(&[
+            grant(Role::Owner, GrantSource::Folder { folder_id: 2 }),
+            grant(Role::Reader, GrantSource::Direct),
+        ]);
+        assert!(check(access, Capability::Edit).is_ok());
+        assert!(check(access, Capability::Share).is_err());
+        assert!(check(access, Capability::Delete).is_err());
+    }
+
+    #[test]
+    fn public_visitors_can_only_view() {
+        let access = Some(Access {
+            role: Role::Reader,
+            source: GrantSource::Public,
+            valid_until: None,
+        });
+        assert_eq!(allowed(access), vec![Capability::View]);
+    }
+
+    #[test]
+    fn no_grants_means_no_access() {
+        assert_eq!(resolve(&[]), None);
+    }
+}
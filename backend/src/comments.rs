@@ -0,0 +1,599 @@
+use axum::{extract::State, http::StatusCode};
+use chrono::Utc;
+use mongodb::bson::oid::ObjectId;
+
+use crate::anchors;
+use crate::authz::{self, Capability};
+use crate::edits::{self, TextEdit};
+use crate::errors::{ApiError, Json};
+use crate::share_links::ShareToken;
+use crate::store::StoreError;
+use crate::structs::{
+    AppState, CommentEvent, CommentReply, CommentThread, CommentThreadRequest,
+    CreateCommentRequest, GetCommentsRequest, ReplyCommentRequest, TextRange, ThreadResponse,
+    ThreadsResponse, WsClientMessage, WsServerMessage,
+};
+use crate::ws_handler::current_content;
+
+#[derive(Debug)]
+pub enum CommentError {
+    EmptyBody,
+    InvalidRange,
+    NotFound,
+    Storage(StoreError),
+}
+
+impl From<StoreError> for CommentError {
+    fn from(e: StoreError) -> Self {
+        CommentError::Storage(e)
+    }
+}
+
+impl From<CommentError> for ApiError {
+    fn from(e: CommentError) -> Self {
+        match e {
+            CommentError::EmptyBody => ApiError::bad_request("Comment must not be empty"),
+            CommentError::InvalidRange => {
+                ApiError::bad_request("Comment range is outside of the document")
+            }
+            CommentError::NotFound => ApiError::not_found("Comment thread or document not found"),
+            CommentError::Storage(e) => e.into(),
+        }
+    }
+}
+
+// Sends a comment event to every client connected to the document channel
+
+async fn publish_event(
+    state: &AppState,
+    document_id: &str,
+    event: CommentEvent,
+    thread: CommentThread,
+) -> Result<(), CommentError> {
+    state
+        .pubsub
+        .publish(document_id, &WsServerMessage::Comment { event, thread })
+        .await?;
+    Ok(())
+}
+
+pub async fn list_threads(
+    state: &AppState,
+    document_id: &str,
+    include_resolved: bool,
+) -> Result<Vec<CommentThread>, CommentError> {
+    let threads = state
+        .documents
+        .comment_threads(document_id, include_resolved)
+        .await?;
+    Ok(threads)
+}
+
+pub async fn create_thread(
+    state: &AppState,
+    document_id: &str,
+    author: &str,
+    anchor: TextRange,
+    body: String,
+) -> Result<CommentThread, CommentError> {
+    if body.trim().is_empty() {
+        return Err(CommentError::EmptyBody);
+    }
+    if anchor.start >= anchor.end {
+        return Err(CommentError::InvalidRange);
+    }
+
+    // The anchor is stored before the content it was read from can change
+    let lock = anchors::lock(state, document_id).await?;
+    let inserted = insert_thread(state, document_id, author, anchor, body).await;
+    lock.release(state).await;
+    let thread = inserted?;
+
+    publish_event(state, document_id, CommentEvent::Created, thread.clone()).await?;
+    Ok(thread)
+}
+
+async fn insert_thread(
+    state: &AppState,
+    document_id: &str,
+    author: &str,
+    anchor: TextRange,
+    body: String,
+) -> Result<CommentThread, CommentError> {
+    let content = current_content(state, document_id)
+        .await
+        .map_err(|e| CommentError::Storage(StoreError::new(e.to_string())))?
+        .ok_or(CommentError::NotFound)?;
+    let quote = edits::slice(&content, &anchor).ok_or(CommentError::InvalidRange)?;
+
+    let thread = CommentThread {
+        id: ObjectId::new().to_hex(),
+        document_id: document_id.to_string(),
+        anchor,
+        quote,
+        author: author.to_string(),
+        body,
+        created_at: Utc::now(),
+        resolved: false,
+        resolved_by: None,
+        replies: Vec::new(),
+    };
+
+    state.documents.insert_thread(&thread).await?;
+    Ok(thread)
+}
+
+pub async fn reply_to_thread(
+    state: &AppState,
+    document_id: &str,
+    thread_id: &str,
+    author: &str,
+    body: String,
+) -> Result<CommentThread, CommentError> {
+    if body.trim().is_empty() {
+        return Err(CommentError::EmptyBody);
+    }
+
+    let reply = CommentReply {
+        id: ObjectId::new().to_hex(),
+        author: author.to_string(),
+        body,
+        created_at: Utc::now(),
+    };
+
+    let thread = state
+        .documents
+        .add_reply(document_id, thread_id, reply)
+        .await?
+        .ok_or(CommentError::NotFound)?;
+
+    publish_event(state, document_id, CommentEvent::Replied, thread.clone()).await?;
+    Ok(thread)
+}
+
+pub async fn set_resolved(
+    state: &AppState,
+    document_id: &str,
+    thread_id: &str,
+    user: &str,
+    resolved: bool,
+) -> Result<CommentThread, CommentError> {
+    let thread = state
+        .documents
+        .set_resolved(document_id, thread_id, resolved.then_some(user))
+        .await?
+        .ok_or(CommentError::NotFound)?;
+
+    let event = if resolved {
+        CommentEvent::Resolved
+    } else {
+        CommentEvent::Reopened
+    };
+    publish_event(state, document_id, event, thread.clone()).await?;
+    Ok(thread)
+}
+
+// Keeps comment anchors attached to their text after an edit has been applied.
+// Only threads ending at or after the edit can be affected. Called with the
+// anchor lock of the document held, so the moves of edits are applied in the
+// order of the edits.
+
+pub async fn shift_anchors(
+    state: &AppState,
+    document_id: &str,
+    edit: &TextEdit,
+) -> Result<(), CommentError> {
+    let threads = state
+        .documents
+        .threads_ending_from(document_id, edit.start)
+        .await?;
+
+    for thread in threads {
+        let anchor = edits::transform_range(&thread.anchor, edit);
+        if anchor == thread.anchor {
+            continue;
+        }
+
+        state.documents.move_thread(&thread.id, anchor).await?;
+    }
+    Ok(())
+}
+
+// Comment actions received over the document WebSocket. Every role with
+// access to the document may comment, including readers.
+
+pub async fn handle_ws_action(
+    state: &AppState,
+    document_id: &str,
+    user: &str,
+    msg: WsClientMessage,
+) -> Result<(), CommentError> {
+    match msg {
+        WsClientMessage::CreateComment { start, end, body } => {
+            create_thread(state, document_id, user, TextRange { start, end }, body).await?;
+        }
+        WsClientMessage::ReplyComment { thread_id, body } => {
+            reply_to_thread(state, document_id, &thread_id, user, body).await?;
+        }
+        WsClientMessage::ResolveComment { thread_id } => {
+            set_resolved(state, document_id, &thread_id, user, true).await?;
+        }
+        WsClientMessage::ReopenComment { thread_id } => {
+            set_resolved(state, document_id, &thread_id, user, false).await?;
+        }
+        WsClientMessage::Edit { .. }
+        | WsClientMessage::AcquireLock
+        | WsClientMessage::ReleaseLock => {}
+    }
+    Ok(())
+}
+
+// ***************************************************************************************************************************************
+// REST endpoints
+
+#[utoipa::path(
+    post,
+    path = "/get_comments",
+    tag = "comments",
+    params(
+        ("x-share-token" = Option<String>, Header, description = "Token of a share link"),
+    ),
+    request_body = GetCommentsRequest,
+    responses((status = 200, body = ThreadsResponse))
+)]
+pub async fn get_comments(
+    State(state): State<AppState>,
+    ShareToken(share_token): ShareToken,
+    Json(payload): Json<GetCommentsRequest>,
+) -> Result<(StatusCode, Json<ThreadsResponse>), ApiError> {
+    authz::authorize_with_token(
+        &state,
+        &payload.email,
+        &payload.document_id,
+        share_token.as_deref(),
+        Capability::View,
+    )
+    .await?;
+
+    let threads = list_threads(&state, &payload.document_id, payload.include_resolved).await?;
+
+    Ok((
+        StatusCode::OK,
+        Json(ThreadsResponse {
+            success: true,
+            threads,
+        }),
+    ))
+}
+
+#[utoipa::path(
+    post,
+    path = "/create_comment",
+    tag = "comments",
+    params(
+        ("x-share-token" = Option<String>, Header, description = "Token of a share link"),
+    ),
+    request_body = CreateCommentRequest,
+    responses((status = 201, body = ThreadResponse))
+)]
+pub async fn create_comment(
+    State(state): State<AppState>,
+    ShareToken(share_token): ShareToken,
+    Json(payload): Json<CreateCommentRequest>,
+) -> Result<(StatusCode, Json<ThreadResponse>), ApiError> {
+    authz::authorize_with_token(
+        &state,
+        &payload.email,
+        &payload.document_id,
+        share_token.as_deref(),
+        Capability::Comment,
+    )
+    .await?;
+
+    let anchor = TextRange {
+        start: payload.start,
+        end: payload.end,
+    };
+    let thread = create_thread(
+        &state,
+        &payload.document_id,
+        &payload.email,
+        anchor,
+        payload.body,
+    )
+    .await?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(ThreadResponse {
+            success: true,
+            thread,
+        }),
+    ))
+}
+
+#[utoipa::path(
+    post,
+    path = "/reply_comment",
+    tag = "comments",
+    params(
+        ("x-share-token" = Option<String>, Header, description = "Token of a share link"),
+    ),
+    request_body = ReplyCommentRequest,
+    responses((status = 200, body = ThreadResponse))
+)]
+pub async fn reply_comment(
+    State(state): State<AppState>,
+    ShareToken(share_token): ShareToken,
+    Json(payload): Json<ReplyCommentRequest>,
+) -> Result<(StatusCode, Json<ThreadResponse>), ApiError> {
+    authz::authorize_with_token(
+        &state,
+        &payload.email,
+        &payload.document_id,
+        share_token.as_deref(),
+        Capability::Comment,
+    )
+    .await?;
+
+    let thread = reply_to_thread(
+        &state,
+        &payload.document_id,
+        &payload.thread_id,
+        &payload.email,
+        payload.body,
+    )
+    .await?;
+
+    Ok((
+        StatusCode::OK,
+        Json(ThreadResponse {
+            success: true,
+            thread,
+        }),
+    ))
+}
+
+#[utoipa::path(
+    post,
+    path = "/resolve_comment",
+    tag = "comments",
+    params(
+        ("x-share-token" = Option<String>, Header, description = "Token of a share link"),
+    ),
+    request_body = CommentThreadRequest,
+    responses((status = 200, body = ThreadResponse))
+)]
+pub async fn resolve_comment(
+    State(state): State<AppState>,
+    ShareToken(share_token): ShareToken,
+    Json(payload): Json<CommentThreadRequest>,
+) -> Result<(StatusCode, Json<ThreadResponse>), ApiError> {
+    authz::authorize_with_token(
+        &state,
+        &payload.email,
+        &payload.document_id,
+        share_token.as_deref(),
+        Capability::Comment,
+    )
+    .await?;
+
+    let thread = set_resolved(
+        &state,
+        &payload.document_id,
+        &payload.thread_id,
+        &payload.email,
+        true,
+    )
+    .await?;
+
+    Ok((
+        StatusCode::OK,
+        Json(ThreadResponse {
+            success: true,
+            thread,
+        }),
+    ))
+}
+
+#[utoipa::path(
+    post,
+    path = "/reopen_comment",
+    tag = "comments",
+    params(
+        ("x-share-token" = Option<String>, Header, description = "Token of a share link"),
+    ),
+    request_body = CommentThreadRequest,
+    responses((status = 200, body = ThreadResponse))
+)]
+pub async fn reopen_comment(
+    State(state): State<AppState>,
+    ShareToken(share_token): ShareToken,
+    Json(payload): Json<CommentThreadRequest>,
+) -> Result<(StatusCode, Json<ThreadResponse>), ApiError> {
+    authz::authorize_with_token(
+        &state,
+        &payload.email,
+        &payload.document_id,
+        share_token.as_deref(),
+        Capability::Comment,
+    )
+    .await?;
+
+    let thread = set_resolved(
+        &state,
+        &payload.document_id,
+        &payload.thread_id,
+        &payload.email,
+        false,
+    )
+    .await?;
+
+    Ok((
+        StatusCode::OK,
+        Json(ThreadResponse {
+            success: true,
+            thread,
+        }),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::memory::MemoryStore;
+    use crate::store::{DocumentStore, LiveDocCache};
+    use crate::structs::Document;
+    use crate::ws_handler::{WriteConflict, replace_content};
+
+    async fn open_document(content: &str) -> (AppState, String) {
+        let store = MemoryStore::new();
+        let document = Document::new("Notes".to_string(), "txt".to_string(), content.to_string());
+        let document_id = store.insert(document).await.unwrap();
+        store.open(&document_id, content, 0).await.unwrap();
+        (store.app_state(), document_id)
+    }
+
+    // Inserts text the way a session does, again on top of whatever edit
+    // landed in between. Without a position the text is appended.
+    async fn insert(state: &AppState, document_id: &str, at: Option<usize>, text: &str) {
+        loop {
+            let previous = current_content(state, document_id).await.unwrap().unwrap();
+            let at = at.unwrap_or(previous.chars().count());
+            let range = TextRange { start: at, end: at };
+            let content = edits::splice(&previous, &range, text);
+            match replace_content(state, document_id, &previous, &content, "x@example.com").await {
+                Ok(()) => return,
+                Err(e) => assert!(e.downcast_ref::<WriteConflict>().is_some()),
+            }
+        }
+    }
+
+    async fn anchor(state: &AppState, document_id: &str) -> TextRange {
+        let threads = list_threads(state, document_id, true).await.unwrap();
+        threads[0].anchor.clone()
+    }
+
+    async fn comment(state: &AppState, document_id: &str, start: usize, end: usize) -> String {
+        let range = TextRange { start, end };
+        let thread = create_thread(state, document_id, "a@example.com", range, "?".to_string())
+            .await
+            .unwrap();
+        thread.id
+    }
+
+    async fn quoted(state: &AppState, document_id: &str, thread_id: &str) -> (TextRange, String) {
+        let content = current_content(state, document_id).await.unwrap().unwrap();
+        let threads = list_threads(state, document_id, true).await.unwrap();
+        let thread = threads.into_iter().find(|t| t.id == thread_id).unwrap();
+        let quote = edits::slice(&content, &thread.anchor).unwrap();
+        (thread.anchor, quote)
+    }
+
+    #[tokio::test]
+    async fn threads_quote_the_text_they_are_anchored_to() {
+        let (state, document_id) = open_document("Hello world").await;
+        let comment = |start, end, body: &str| {
+            let range = TextRange { start, end };
+            create_thread(
+                &state,
+                &document_id,
+                "a@example.com",
+                range,
+                body.to_string(),
+            )
+        };
+
+        let thread = comment(6, 11, "Which one?").await.unwrap();
+        assert_eq!(thread.quote, "world");
+
+        assert!(matches!(
+            comment(6, 12, "Past the end").await,
+            Err(CommentError::InvalidRange)
+        ));
+        assert!(matches!(
+            comment(6, 6, "Nothing").await,
+            Err(CommentError::InvalidRange)
+        ));
+        assert!(matches!(
+            comment(0, 5, " ").await,
+            Err(CommentError::EmptyBody)
+        ));
+        let threads = list_threads(&state, &document_id, true).await.unwrap();
+        assert_eq!(threads.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn anchors_follow_interleaved_edits() {
+        let (state, document_id) = open_document("The quick brown fox jumps").await;
+        let quick = comment(&state, &document_id, 4, 9).await;
+        let brown = comment(&state, &document_id, 10, 15).await;
+        let fox = comment(&state, &document_id, 16, 19).await;
+
+        insert(&state, &document_id, Some(0), "Look: ").await;
+        insert(&state, &document_id, None, " high").await;
+        // Inside an anchor the text grows with it
+        insert(&state, &document_id, Some(12), "ii").await;
+
+        // Deleted text takes its anchors down to an empty range
+        let previous = current_content(&state, &document_id)
+            .await
+            .unwrap()
+            .unwrap();
+        let removed = edits::splice(&previous, &TextRange { start: 18, end: 24 }, "");
+        replace_content(&state, &document_id, &previous, &removed, "x@example.com")
+            .await
+            .unwrap();
+
+        let content = current_content(&state, &document_id).await.unwrap();
+        assert_eq!(content.as_deref(), Some("Look: The quiiick fox jumps high"));
+        assert_eq!(
+            quoted(&state, &document_id, &quick).await,
+            (TextRange { start: 10, end: 17 }, "quiiick".to_string())
+        );
+        assert_eq!(
+            quoted(&state, &document_id, &brown).await,
+            (TextRange { start: 18, end: 18 }, String::new())
+        );
+        assert_eq!(
+            quoted(&state, &document_id, &fox).await,
+            (TextRange { start: 18, end: 21 }, "fox".to_string())
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn anchors_follow_concurrent_edits() {
+        let (state, document_id) = open_document("Hello world").await;
+        let range = TextRange { start: 6, end: 11 };
+        create_thread(
+            &state,
+            &document_id,
+            "a@example.com",
+            range,
+            "Hi?".to_string(),
+        )
+        .await
+        .unwrap();
+
+        let mut edits = Vec::new();
+        for i in 0..40 {
+            let state = state.clone();
+            let document_id = document_id.clone();
+            let at = (i % 2 == 0).then_some(0);
+            edits.push(tokio::spawn(async move {
+                insert(&state, &document_id, at, "ab").await;
+            }));
+        }
+        for edit in edits {
+            edit.await.unwrap();
+        }
+
+        let content = current_content(&state, &document_id)
+            .await
+            .unwrap()
+            .unwrap();
+        let anchor = anchor(&state, &document_id).await;
+        assert_eq!(anchor, TextRange { start: 46, end: 51 });
+        assert_eq!(edits::slice(&content, &anchor).as_deref(), Some("world"));
+    }
+}
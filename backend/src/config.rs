@@ -0,0 +1,524 @@
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use axum::http::HeaderValue;
+use clap::Parser;
+use log::LevelFilter;
+use serde::Deserialize;
+
+use crate::recovery::RecoveryMode;
+
+// Configuration of the server. Every setting is taken from, in this order, the
+// command line, the environment (a .env file is loaded when there is one), the
+// TOML file given with --config, and the defaults below. Only the database
+// connection settings have no default.
+
+pub const DEFAULT_SERVER_ADDRESS: &str = "127.0.0.1:3000";
+pub const DEFAULT_REDIS_CONNECTION_STRING: &str = "redis://localhost:6379";
+pub const DEFAULT_PG_MAX_CONNECTIONS: u32 = 64;
+pub const DEFAULT_MONGO_MAX_POOL_SIZE: u32 = 10;
+pub const DEFAULT_FLUSH_INTERVAL_SECS: u64 = 10;
+pub const DEFAULT_LOG_LEVEL: &str = "info";
+pub const DEFAULT_RECOVERY_MODE: &str = "flush";
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub server_address: SocketAddr,
+    pub stores: StoreConfig,
+    // How often the live content in Redis is persisted to MongoDB
+    pub flush_interval: Duration,
+    pub cors_origins: CorsOrigins,
+    pub log_level: LevelFilter,
+    // Only a single instance may recover what a crashed server left in Redis,
+    // other instances sharing it would lose their live documents and leases
+    pub single_instance: bool,
+    pub recovery_mode: RecoveryMode,
+}
+
+#[derive(Debug, Clone)]
+pub struct StoreConfig {
+    pub database_url: String,
+    pub mongo_connection_string: String,
+    pub mongo_db_name: String,
+    pub redis_connection_string: String,
+    pub pg_max_connections: u32,
+    pub mongo_max_pool_size: u32,
+}
+
+// Origins browsers may call the API from
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum CorsOrigins {
+    #[default]
+    Any,
+    List(Vec<HeaderValue>),
+}
+
+// Every problem found in the settings, reported together at startup
+#[derive(Debug)]
+pub struct ConfigError(pub Vec<String>);
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Invalid configuration:")?;
+        for error in &self.0 {
+            writeln!(f, "  - {}", error)?;
+        }
+        write!(f, "Run with --help to see the settings")
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+// The settings as given on the command line, in the environment or in the
+// config file. The file uses the names of the fields as keys.
+#[derive(Parser, Deserialize, Debug, Default)]
+#[command(version, about = "PDF United backend")]
+#[serde(deny_unknown_fields)]
+pub struct Settings {
+    #[arg(
+        long,
+        short,
+        env = "CONFIG_FILE",
+        help = "TOML file with any of the settings"
+    )]
+    #[serde(skip)]
+    pub config: Option<PathBuf>,
+
+    #[arg(
+        long,
+        env = "SERVER_ADDRESS",
+        help = "Address to listen on [default: 127.0.0.1:3000]"
+    )]
+    pub server_address: Option<String>,
+
+    #[arg(long, env = "DATABASE_URL", help = "PostgreSQL connection string")]
+    pub database_url: Option<String>,
+
+    #[arg(
+        long,
+        env = "MONGO_CONNECTION_STRING",
+        help = "MongoDB connection string"
+    )]
+    pub mongo_connection_string: Option<String>,
+
+    #[arg(
+        long,
+        env = "MONGO_DB_NAME",
+        help = "MongoDB database holding the documents"
+    )]
+    pub mongo_db_name: Option<String>,
+
+    #[arg(
+        long,
+        env = "REDIS_CONNECTION_STRING",
+        help = "Redis connection string [default: redis://localhost:6379]"
+    )]
+    pub redis_connection_string: Option<String>,
+
+    #[arg(
+        long,
+        env = "PG_MAX_CONNECTIONS",
+        help = "Size of the PostgreSQL pool [default: 64]"
+    )]
+    pub pg_max_connections: Option<u32>,
+
+    #[arg(
+        long,
+        env = "MONGO_MAX_POOL_SIZE",
+        help = "Size of the MongoDB pool [default: 10]"
+    )]
+    pub mongo_max_pool_size: Option<u32>,
+
+    #[arg(
+        long,
+        env = "FLUSH_INTERVAL_SECS",
+        help = "Seconds between two flushes of the live documents [default: 10]"
+    )]
+    pub flush_interval_secs: Option<u64>,
+
+    #[arg(
+        long,
+        env = "CORS_ORIGINS",
+        value_delimiter = ',',
+        help = "Comma separated origins allowed by CORS, * for any [default: *]"
+    )]
+    pub cors_origins: Option<Vec<String>>,
+
+    #[arg(
+        long,
+        env = "LOG_LEVEL",
+        help = "off, error, warn, info, debug or trace [default: info]"
+    )]
+    pub log_level: Option<String>,
+
+    #[arg(
+        long,
+        env = "SINGLE_INSTANCE",
+        help = "false when several instances share Redis, disables crash recovery on startup [default: true]"
+    )]
+    pub single_instance: Option<bool>,
+
+    #[arg(
+        long,
+        env = "RECOVERY_MODE",
+        help = "What to do with content a crashed server left in Redis, flush or version [default: flush]"
+    )]
+    pub recovery_mode: Option<String>,
+}
+
+impl Config {
+    // Reads the settings from every source, exits with the usage when the
+    // command line can not be parsed
+    pub fn load() -> Result<Config, ConfigError> {
+        // A missing .env is fine, the settings can come from anywhere else
+        if let Err(e) = dotenvy::dotenv()
+            && !e.not_found()
+        {
+            return Err(ConfigError(vec![format!("Could not read .env: {}", e)]));
+        }
+
+        let settings = Settings::parse();
+        let file = match &settings.config {
+            Some(path) => Settings::read(path)?,
+            None => Settings::default(),
+        };
+        settings.or(file).resolve()
+    }
+}
+
+impl Settings {
+    pub fn read(path: &Path) -> Result<Settings, ConfigError> {
+        let error = |e: &dyn fmt::Display| ConfigError(vec![format!("{}: {}", path.display(), e)]);
+        let text = std::fs::read_to_string(path).map_err(|e| error(&e))?;
+        toml::from_str(&text).map_err(|e| error(&e))
+    }
+
+    // Settings missing here are taken from the fallback
+    pub fn or(self, fallback: Settings) -> Settings {
+        Settings {
+            config: self.config.or(fallback.config),
+            server_address: self.server_address.or(fallback.server_address),
+            database_url: self.database_url.or(fallback.database_url),
+            mongo_connection_string: self
+                .mongo_connection_string
+                .or(fallback.mongo_connection_string),
+            mongo_db_name: self.mongo_db_name.or(fallback.mongo_db_name),
+            redis_connection_string: self
+                .redis_connection_string
+                .or(fallback.redis_connection_string),
+            pg_max_connections: self.pg_max_connections.or(fallback.pg_max_connections),
+            mongo_max_pool_size: self.mongo_max_pool_size.or(fallback.mongo_max_pool_size),
+            flush_interval_secs: self.flush_interval_secs.or(fallback.flush_interval_secs),
+            cors_origins: self.cors_origins.or(fallback.cors_origins),
+            log_level: self.log_level.or(fallback.log_level),
+            single_instance: self.single_instance.or(fallback.single_instance),
+            recovery_mode: self.recovery_mode.or(fallback.recovery_mode),
+        }
+    }
+
+    // Applies the defaults and checks every setting
+    pub fn resolve(self) -> Result<Config, ConfigError> {
+        let mut errors = Vec::new();
+
+        let server_address = check(
+            &mut errors,
+            "server_address",
+            self.server_address
+                .as_deref()
+                .unwrap_or(DEFAULT_SERVER_ADDRESS)
+                .parse::<SocketAddr>(),
+        );
+        let database_url = url(
+            &mut errors,
+            "database_url",
+            self.database_url,
+            &["postgres://", "postgresql://"],
+        );
+        let mongo_connection_string = url(
+            &mut errors,
+            "mongo_connection_string",
+            self.mongo_connection_string,
+            &["mongodb://", "mongodb+srv://"],
+        );
+        let mongo_db_name = match self.mongo_db_name {
+            Some(name) if !name.trim().is_empty() => Some(name),
+            _ => {
+                errors.push(missing("mongo_db_name"));
+                None
+            }
+        };
+        let redis_connection_string = url(
+            &mut errors,
+            "redis_connection_string",
+            Some(
+                self.redis_connection_string
+                    .unwrap_or(DEFAULT_REDIS_CONNECTION_STRING.to_string()),
+            ),
+            &["redis://", "rediss://"],
+        );
+        let pg_max_connections = positive(
+            &mut errors,
+            "pg_max_connections",
+            self.pg_max_connections
+                .unwrap_or(DEFAULT_PG_MAX_CONNECTIONS),
+        );
+        let mongo_max_pool_size = positive(
+            &mut errors,
+            "mongo_max_pool_size",
+            self.mongo_max_pool_size
+                .unwrap_or(DEFAULT_MONGO_MAX_POOL_SIZE),
+        );
+        let flush_interval_secs = positive(
+            &mut errors,
+            "flush_interval_secs",
+            self.flush_interval_secs
+                .unwrap_or(DEFAULT_FLUSH_INTERVAL_SECS),
+        );
+        let cors_origins = check(
+            &mut errors,
+            "cors_origins",
+            cors_origins(self.cors_origins.unwrap_or_default()),
+        );
+        let log_level = check(
+            &mut errors,
+            "log_level",
+            self.log_level
+                .as_deref()
+                .unwrap_or(DEFAULT_LOG_LEVEL)
+                .parse::<LevelFilter>()
+                .map_err(|_| "expected off, error, warn, info, debug or trace"),
+        );
+        let recovery_mode = check(
+            &mut errors,
+            "recovery_mode",
+            self.recovery_mode
+                .as_deref()
+                .unwrap_or(DEFAULT_RECOVERY_MODE)
+                .parse::<RecoveryMode>(),
+        );
+
+        match (
+            server_address,
+            database_url,
+            mongo_connection_string,
+            mongo_db_name,
+            redis_connection_string,
+            pg_max_connections,
+            mongo_max_pool_size,
+            flush_interval_secs,
+            cors_origins,
+            log_level,
+            recovery_mode,
+        ) {
+            (
+                Some(server_address),
+                Some(database_url),
+                Some(mongo_connection_string),
+                Some(mongo_db_name),
+                Some(redis_connection_string),
+                Some(pg_max_connections),
+                Some(mongo_max_pool_size),
+                Some(flush_interval_secs),
+                Some(cors_origins),
+                Some(log_level),
+                Some(recovery_mode),
+            ) if errors.is_empty() => Ok(Config {
+                server_address,
+                stores: StoreConfig {
+                    database_url,
+                    mongo_connection_string,
+                    mongo_db_name,
+                    redis_connection_string,
+                    pg_max_connections,
+                    mongo_max_pool_size,
+                },
+                flush_interval: Duration::from_secs(flush_interval_secs),
+                cors_origins,
+                log_level,
+                single_instance: self.single_instance.unwrap_or(true),
+                recovery_mode,
+            }),
+            _ => Err(ConfigError(errors)),
+        }
+    }
+}
+
+// ***************************************************************************************************************************************
+// Checks of single settings. Each one records what is wrong and returns None.
+
+fn missing(name: &str) -> String {
+    format!(
+        "{} is not set, pass --{} or set {} or put it in the config file",
+        name,
+        name.replace('_', "-"),
+        name.to_uppercase()
+    )
+}
+
+fn check<T, E: fmt::Display>(
+    errors: &mut Vec<String>,
+    name: &str,
+    result: Result<T, E>,
+) -> Option<T> {
+    result
+        .map_err(|e| errors.push(format!("{}: {}", name, e)))
+        .ok()
+}
+
+fn url(
+    errors: &mut Vec<String>,
+    name: &str,
+    value: Option<String>,
+    schemes: &[&str],
+) -> Option<String> {
+    let Some(value) = value else {
+        errors.push(missing(name));
+        return None;
+    };
+    if !schemes.iter().any(|scheme| value.starts_with(scheme)) {
+        errors.push(format!(
+            "{}: expected a URL starting with {}",
+            name,
+            schemes.join(" or ")
+        ));
+        return None;
+    }
+    Some(value)
+}
+
+fn positive<T: PartialEq + Default>(errors: &mut Vec<String>, name: &str, value: T) -> Option<T> {
+    if value == T::default() {
+        errors.push(format!("{}: must be greater than 0", name));
+        return None;
+    }
+    Some(value)
+}
+
+fn cors_origins(origins: Vec<String>) -> Result<CorsOrigins, String> {
+    let origins: Vec<String> = origins
+        .into_iter()
+        .map(|origin| origin.trim().to_string())
+        .filter(|origin| !origin.is_empty())
+        .collect();
+
+    if origins.is_empty() || origins == ["*"] {
+        return Ok(CorsOrigins::Any);
+    }
+
+    origins
+        .iter()
+        .map(|origin| {
+            if origin == "*" {
+                return Err("* can not be combined with other origins".to_string());
+            }
+            if !origin.starts_with("http://") && !origin.starts_with("https://") {
+                return Err(format!("'{}' is not an http or https origin", origin));
+            }
+            HeaderValue::from_str(origin).map_err(|_| format!("'{}' is not a valid origin", origin))
+        })
+        .collect::<Result<_, _>>()
+        .map(CorsOrigins::List)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn complete() -> Settings {
+        Settings {
+            database_url: Some("postgres://localhost/pdf".to_string()),
+            mongo_connection_string: Some("mongodb://localhost:27017".to_string()),
+            mongo_db_name: Some("pdf".to_string()),
+            ..Settings::default()
+        }
+    }
+
+    #[test]
+    fn defaults_fill_the_optional_settings() {
+        let config = complete().resolve().unwrap();
+
+        assert_eq!(config.server_address, "127.0.0.1:3000".parse().unwrap());
+        assert_eq!(
+            config.stores.redis_connection_string,
+            DEFAULT_REDIS_CONNECTION_STRING
+        );
+        assert_eq!(config.stores.pg_max_connections, DEFAULT_PG_MAX_CONNECTIONS);
+        assert_eq!(config.flush_interval, Duration::from_secs(10));
+        assert_eq!(config.cors_origins, CorsOrigins::Any);
+        assert_eq!(config.log_level, LevelFilter::Info);
+        assert!(config.single_instance);
+        assert_eq!(config.recovery_mode, RecoveryMode::Flush);
+    }
+
+    #[test]
+    fn every_problem_is_reported() {
+        let settings = Settings {
+            server_address: Some("localhost".to_string()),
+            database_url: Some("mysql://localhost".to_string()),
+            flush_interval_secs: Some(0),
+            log_level: Some("loud".to_string()),
+            ..Settings::default()
+        };
+
+        let ConfigError(errors) = settings.resolve().unwrap_err();
+        let names: Vec<&str> = errors
+            .iter()
+            .map(|e| e.split([':', ' ']).next().unwrap())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "server_address",
+                "database_url",
+                "mongo_connection_string",
+                "mongo_db_name",
+                "flush_interval_secs",
+                "log_level",
+            ]
+        );
+        assert!(errors[2].contains("--mongo-connection-string"));
+        assert!(errors[2].contains("MONGO_CONNECTION_STRING"));
+    }
+
+    #[test]
+    fn earlier_sources_win() {
+        let cli = Settings {
+            server_address: Some("0.0.0.0:8080".to_string()),
+            ..Settings::default()
+        };
+        let file: Settings = toml::from_str(
+            r#"
+                server_address = "127.0.0.1:4000"
+                database_url = "postgres://db/pdf"
+                mongo_connection_string = "mongodb://mongo"
+                mongo_db_name = "pdf"
+                cors_origins = ["https://pdf.example.com"]
+                log_level = "debug"
+            "#,
+        )
+        .unwrap();
+
+        let config = cli.or(file).resolve().unwrap();
+        assert_eq!(config.server_address, "0.0.0.0:8080".parse().unwrap());
+        assert_eq!(config.stores.database_url, "postgres://db/pdf");
+        assert_eq!(config.log_level, LevelFilter::Debug);
+        assert_eq!(
+            config.cors_origins,
+            CorsOrigins::List(vec![HeaderValue::from_static("https://pdf.example.com")])
+        );
+    }
+
+    #[test]
+    fn unknown_keys_in_the_file_are_rejected() {
+        assert!(toml::from_str::<Settings>("listen = \"0.0.0.0:80\"").is_err());
+    }
+
+    #[test]
+    fn cors_origins_are_checked() {
+        assert_eq!(cors_origins(vec!["*".to_string()]), Ok(CorsOrigins::Any));
+        assert!(cors_origins(vec!["*".to_string(), "https://a.example".to_string()]).is_err());
+        assert!(cors_origins(vec!["a.example".to_string()]).is_err());
+    }
+}
@@ -0,0 +1,252 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use log::{error, warn};
+use tokio::sync::{Mutex, OwnedMutexGuard};
+use tokio::time::{Duration, Instant};
+
+// Number of open WebSockets per document id
+pub type WsConnections = Arc<Mutex<HashMap<String, usize>>>;
+
+// How often the server pings every client
+pub const PING_INTERVAL: Duration = Duration::from_secs(15);
+
+// A client that has not sent anything, pongs included, for this long is
+// considered gone, e.g. a half-open TCP connection
+pub const PONG_TIMEOUT: Duration = Duration::from_secs(45);
+
+// A client that is connected but has not edited or commented for this long is
+// disconnected
+pub const IDLE_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
+// Counts one open WebSocket on a document for as long as it lives. Sessions
+// release it when closing normally. If a session ends any other way, e.g.
+// the task panics, dropping the guard still takes the connection off the
+// count, the periodic flush then cleans up the Redis key.
+pub struct ConnectionGuard {
+    connections: WsConnections,
+    document_id: String,
+    released: bool,
+}
+
+impl ConnectionGuard {
+    pub async fn acquire(connections: &WsConnections, document_id: &str) -> ConnectionGuard {
+        let mut map = connections.lock().await;
+        *map.entry(document_id.to_string()).or_insert(0) += 1;
+
+        ConnectionGuard {
+            connections: connections.clone(),
+            document_id: document_id.to_string(),
+            released: false,
+        }
+    }
+
+    // Takes the connection off the count and returns the remaining count
+    // along with the locked map, so the caller can clean up the last
+    // connection before a new one comes in
+    pub async fn release(mut self) -> (usize, OwnedMutexGuard<HashMap<String, usize>>) {
+        self.released = true;
+        let mut map = self.connections.clone().lock_owned().await;
+        let remaining = decrement(&mut map, &self.document_id);
+        (remaining, map)
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        if self.released {
+            return;
+        }
+        warn!(
+            "WebSocket on doc {} ended without closing, releasing its connection",
+            self.document_id
+        );
+
+        let connections = self.connections.clone();
+        let document_id = std::mem::take(&mut self.document_id);
+        let release = async move {
+            decrement(&mut *connections.lock().await, &document_id);
+        };
+
+        match tokio::runtime::Handle::try_current() {
+            Ok(handle) => {
+                handle.spawn(release);
+            }
+            Err(_) => error!("No runtime to release the connection on"),
+        }
+    }
+}
+
+fn decrement(map: &mut HashMap<String, usize>, document_id: &str) -> usize {
+    match map.get_mut(document_id) {
+        Some(count) => {
+            *count = count.saturating_sub(1);
+            *count
+        }
+        None => {
+            warn!("No ws_connections count found for id: {}", document_id);
+            0
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Timeout {
+    Dead,
+    Idle,
+}
+
+// Tracks when a client was last heard from and when it last did something
+#[derive(Debug, Clone, Copy)]
+pub struct Liveness {
+    last_seen: Instant,
+    last_activity: Instant,
+}
+
+impl Liveness {
+    pub fn new(now: Instant) -> Liveness {
+        Liveness {
+            last_seen: now,
+            last_activity: now,
+        }
+    }
+
+    // Any frame from the client, pongs included
+    pub fn seen(&mut self, now: Instant) {
+        self.last_seen = now;
+    }
+
+    // A message from the client that edits or comments
+    pub fn active(&mut self, now: Instant) {
+        self.last_seen = now;
+        self.last_activity = now;
+    }
+
+    pub fn check(&self, now: Instant) -> Option<Timeout> {
+        if now.duration_since(self.last_seen) >= PONG_TIMEOUT {
+            Some(Timeout::Dead)
+        } else if now.duration_since(self.last_activity) >= IDLE_TIMEOUT {
+            Some(Timeout::Idle)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DOC: &str = "doc";
+
+    async fn count(connections: &WsConnections) -> Option<usize> {
+        connections.lock().await.get(DOC).copied()
+    }
+
+    // Lets the release spawned by a dropped guard run
+    async fn settle() {
+        for _ in 0..10 {
+            tokio::task::yield_now().await;
+        }
+    }
+
+    #[tokio::test]
+    async fn release_returns_the_remaining_connections() {
+        let connections = WsConnections::default();
+        let first = ConnectionGuard::acquire(&connections, DOC).await;
+        let second = ConnectionGuard::acquire(&connections, DOC).await;
+        assert_eq!(count(&connections).await, Some(2));
+
+        let (remaining, map) = first.release().await;
+        assert_eq!(remaining, 1);
+        drop(map);
+
+        let (remaining, map) = second.release().await;
+        assert_eq!(remaining, 0);
+        drop(map);
+
+        settle().await;
+        assert_eq!(count(&connections).await, Some(0));
+    }
+
+    #[tokio::test]
+    async fn dropped_client_is_taken_off_the_count() {
+        let connections = WsConnections::default();
+        let guard = ConnectionGuard::acquire(&connections, DOC).await;
+
+        drop(guard);
+        settle().await;
+
+        assert_eq!(count(&connections).await, Some(0));
+    }
+
+    #[tokio::test]
+    async fn panicking_session_is_taken_off_the_count() {
+        let connections = WsConnections::default();
+        let task_connections = connections.clone();
+
+        let session = tokio::spawn(async move {
+            let _guard = ConnectionGuard::acquire(&task_connections, DOC).await;
+            panic!("session failed");
+        });
+
+        assert!(session.await.unwrap_err().is_panic());
+        settle().await;
+
+        assert_eq!(count(&connections).await, Some(0));
+    }
+
+    #[tokio::test]
+    async fn aborted_session_is_taken_off_the_count() {
+        let connections = WsConnections::default();
+        let task_connections = connections.clone();
+
+        let session = tokio::spawn(async move {
+            let _guard = ConnectionGuard::acquire(&task_connections, DOC).await;
+            // A client that never sends anything again
+            std::future::pending::<()>().await;
+        });
+
+        settle().await;
+        assert_eq!(count(&connections).await, Some(1));
+
+        session.abort();
+        assert!(session.await.unwrap_err().is_cancelled());
+        settle().await;
+
+        assert_eq!(count(&connections).await, Some(0));
+    }
+
+    #[test]
+    fn silent_client_is_dead_after_the_pong_timeout() {
+        let start = Instant::now();
+        let liveness = Liveness::new(start);
+
+        assert_eq!(liveness.check(start + PING_INTERVAL), None);
+        assert_eq!(liveness.check(start + PONG_TIMEOUT), Some(Timeout::Dead));
+    }
+
+    #[test]
+    fn pongs_keep_a_client_alive_but_not_active() {
+        let start = Instant::now();
+        let mut liveness = Liveness::new(start);
+
+        let mut now = start;
+        while now < start + IDLE_TIMEOUT {
+            now += PING_INTERVAL;
+            liveness.seen(now);
+        }
+
+        assert_eq!(liveness.check(now), Some(Timeout::Idle));
+    }
+
+    #[test]
+    fn activity_resets_the_idle_timeout() {
+        let start = Instant::now();
+        let mut liveness = Liveness::new(start);
+
+        liveness.active(start + IDLE_TIMEOUT - PING_INTERVAL);
+
+        assert_eq!(liveness.check(start + IDLE_TIMEOUT), None);
+    }
+}
@@ -0,0 +1,378 @@
+use crate::structs::TextRange;
+
+// A single replacement computed between two versions of a document.
+// Positions are counted in characters, not bytes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextEdit {
+    pub start: usize,
+    pub deleted: usize,
+    pub inserted: usize,
+}
+
+// Clients send the full document content on every change, so the edit is
+// recovered by stripping the common prefix and suffix of old and new content
+
+pub fn diff(old: &str, new: &str) -> Option<TextEdit> {
+    let old_chars: Vec<char> = old.chars().collect();
+    let new_chars: Vec<char> = new.chars().collect();
+
+    let prefix = old_chars
+        .iter()
+        .zip(new_chars.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let max_suffix = old_chars.len().min(new_chars.len()) - prefix;
+    let suffix = old_chars
+        .iter()
+        .rev()
+        .zip(new_chars.iter().rev())
+        .take(max_suffix)
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let deleted = old_chars.len() - prefix - suffix;
+    let inserted = new_chars.len() - prefix - suffix;
+
+    if deleted == 0 && inserted == 0 {
+        return None;
+    }
+
+    Some(TextEdit {
+        start: prefix,
+        deleted,
+        inserted,
+    })
+}
+
+// Moves a range so it keeps covering the same text after an edit.
+// Text inserted exactly at either boundary stays outside of the range, and a
+// range whose text is replaced ends up covering the replacement (or nothing).
+
+pub fn transform_range(range: &TextRange, edit: &TextEdit) -> TextRange {
+    let edit_end = edit.start + edit.deleted;
+
+    let start = if range.start < edit.start {
+        range.start
+    } else if range.start >= edit_end {
+        range.start - edit.deleted + edit.inserted
+    } else {
+        edit.start
+    };
+
+    let end = if range.end <= edit.start {
+        range.end
+    } else if range.end >= edit_end {
+        range.end - edit.deleted + edit.inserted
+    } else {
+        edit.start + edit.inserted
+    };
+
+    TextRange {
+        start,
+        end: end.max(start),
+    }
+}
+
+// Returns the characters covered by the range, or None if it is out of bounds
+
+pub fn slice(content: &str, range: &TextRange) -> Option<String> {
+    if range.start > range.end || range.end > content.chars().count() {
+        return None;
+    }
+
+    Some(
+        content
+            .chars()
+            .skip(range.start)
+            .take(range.end - range.start)
+            .collect(),
+    )
+}
+
+// Replaces the characters covered by the range with the replacement text
+
+pub fn splice(content: &str, range: &TextRange, replacement: &str) -> String {
+    let mut result: String = content.chars().take(range.start).collect();
+    result.push_str(replacement);
+    result.extend(content.chars().skip(range.end));
+    result
+}
+
+// Past this many changed words and separators, hunks cost more than they are
+// worth and the changed part is taken as one hunk
+const MAX_HUNK_COST: usize = 500;
+
+// The separate replacements between two versions, in order, each with its
+// position in the old content. Words are compared whole, so a changed word is
+// one replacement rather than a few changed letters.
+
+pub fn hunks(old: &str, new: &str) -> Vec<TextEdit> {
+    let Some(edit) = diff(old, new) else {
+        return Vec::new();
+    };
+
+    // Only the part between the common prefix and suffix differs, widened
+    // to whole words
+    let old_all: Vec<char> = old.chars().collect();
+    let mut edit = edit;
+    while edit.start > 0 && old_all[edit.start - 1].is_alphanumeric() {
+        edit.start -= 1;
+        edit.deleted += 1;
+        edit.inserted += 1;
+    }
+    while edit.start + edit.deleted < old_all.len()
+        && old_all[edit.start + edit.deleted].is_alphanumeric()
+    {
+        edit.deleted += 1;
+        edit.inserted += 1;
+    }
+
+    let old_chars = &old_all[edit.start..edit.start + edit.deleted];
+    let new_chars: Vec<char> = new.chars().skip(edit.start).take(edit.inserted).collect();
+    let old_tokens = tokens(old_chars);
+    let new_tokens = tokens(&new_chars);
+    let Some(matches) = matching_tokens(&old_tokens, &new_tokens) else {
+        return vec![edit];
+    };
+
+    let mut hunks = Vec::new();
+    let (mut old_pos, mut new_pos) = (0, 0);
+    let (mut old_char, mut new_char) = (edit.start, 0);
+    let end = (old_tokens.len(), new_tokens.len());
+    for (old_index, new_index) in matches.into_iter().chain([end]) {
+        let deleted: usize = old_tokens[old_pos..old_index].iter().map(|t| t.len()).sum();
+        let inserted: usize = new_tokens[new_pos..new_index].iter().map(|t| t.len()).sum();
+        if deleted > 0 || inserted > 0 {
+            hunks.push(TextEdit {
+                start: old_char,
+                deleted,
+                inserted,
+            });
+        }
+        let same = old_tokens.get(old_index).map_or(0, |t| t.len());
+        old_char += deleted + same;
+        new_char += inserted + same;
+        old_pos = old_index + 1;
+        new_pos = new_index + 1;
+    }
+    debug_assert_eq!(new_char, new_chars.len());
+    hunks
+}
+
+// Words, and every other character on its own
+
+fn tokens(chars: &[char]) -> Vec<&[char]> {
+    let mut tokens = Vec::new();
+    let mut start = 0;
+    while start < chars.len() {
+        let word = chars[start..]
+            .iter()
+            .take_while(|c| c.is_alphanumeric())
+            .count();
+        let end = start + word.max(1);
+        tokens.push(&chars[start..end]);
+        start = end;
+    }
+    tokens
+}
+
+// The tokens both lists keep, as pairs of indexes in order, along the
+// shortest edit script between them (Myers). None when more than
+// MAX_HUNK_COST tokens differ.
+
+fn matching_tokens(old: &[&[char]], new: &[&[char]]) -> Option<Vec<(usize, usize)>> {
+    let (n, m) = (old.len() as isize, new.len() as isize);
+    let max = MAX_HUNK_COST as isize;
+    let index = |k: isize| (k + max + 1) as usize;
+
+    // The furthest x reached on every diagonal k = x - y, for every cost
+    let mut furthest = vec![0isize; 2 * MAX_HUNK_COST + 3];
+    let mut trace = Vec::new();
+    for cost in 0..=max {
+        trace.push(furthest.clone());
+        for k in (-cost..=cost).step_by(2) {
+            let down = k == -cost || (k != cost && furthest[index(k - 1)] < furthest[index(k + 1)]);
+            let mut x = if down {
+                furthest[index(k + 1)]
+            } else {
+                furthest[index(k - 1)] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && old[x as usize] == new[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            furthest[index(k)] = x;
+            if x >= n && y >= m {
+                return Some(backtrack(&trace, n, m, index));
+            }
+        }
+    }
+    None
+}
+
+// Walks the edit script back from the end, collecting the kept tokens
+
+fn backtrack(
+    trace: &[Vec<isize>],
+    n: isize,
+    m: isize,
+    index: impl Fn(isize) -> usize,
+) -> Vec<(usize, usize)> {
+    let mut matches = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for (cost, furthest) in trace.iter().enumerate().rev() {
+        let cost = cost as isize;
+        let k = x - y;
+        let down = k == -cost || (k != cost && furthest[index(k - 1)] < furthest[index(k + 1)]);
+        let previous_k = if down { k + 1 } else { k - 1 };
+        let previous_x = furthest[index(previous_k)];
+        let previous_y = previous_x - previous_k;
+        while x > previous_x && y > previous_y {
+            x -= 1;
+            y -= 1;
+            matches.push((x as usize, y as usize));
+        }
+        x = previous_x;
+        y = previous_y;
+    }
+    matches.reverse();
+    matches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(start: usize, end: usize) -> TextRange {
+        TextRange { start, end }
+    }
+
+    // Moves the range from old to new content and returns the text it covers
+    fn follow(old: &str, new: &str, anchored: TextRange) -> (TextRange, String) {
+        let edit = diff(old, new).unwrap();
+        let moved = transform_range(&anchored, &edit);
+        let text = slice(new, &moved).unwrap();
+        (moved, text)
+    }
+
+    #[test]
+    fn identical_content_has_no_edit() {
+        assert_eq!(diff("same text", "same text"), None);
+        assert_eq!(diff("", ""), None);
+    }
+
+    #[test]
+    fn repeated_characters_are_not_counted_twice() {
+        assert_eq!(
+            diff("aaa", "aaaa"),
+            Some(TextEdit {
+                start: 3,
+                deleted: 0,
+                inserted: 1,
+            })
+        );
+    }
+
+    #[test]
+    fn insertion_at_the_start_of_a_range_stays_outside() {
+        let (moved, text) = follow("say hello world", "say oh hello world", range(4, 9));
+        assert_eq!(moved, range(7, 12));
+        assert_eq!(text, "hello");
+    }
+
+    #[test]
+    fn insertion_at_the_end_of_a_range_stays_outside() {
+        let (moved, text) = follow("say hello world", "say hello there world", range(4, 9));
+        assert_eq!(moved, range(4, 9));
+        assert_eq!(text, "hello");
+    }
+
+    #[test]
+    fn deleting_the_whole_range_collapses_it() {
+        let (moved, text) = follow("say hello world", "say world", range(4, 9));
+        assert_eq!(moved, range(4, 4));
+        assert_eq!(text, "");
+
+        // The deletion reaching past both ends
+        let (moved, _) = follow("say hello world", "sorld", range(4, 9));
+        assert_eq!(moved, range(1, 1));
+    }
+
+    #[test]
+    fn partly_deleted_ranges_keep_what_is_left() {
+        // The end of the range is deleted
+        let (moved, text) = follow("say hello world", "say helworld", range(4, 9));
+        assert_eq!(moved, range(4, 7));
+        assert_eq!(text, "hel");
+
+        // The start of the range is deleted
+        let (moved, text) = follow("say hello world", "salo world", range(4, 9));
+        assert_eq!(moved, range(2, 4));
+        assert_eq!(text, "lo");
+    }
+
+    #[test]
+    fn multibyte_content_is_counted_in_characters() {
+        let edit = diff("héllo wörld", "héllo, wörld").unwrap();
+        assert_eq!(
+            edit,
+            TextEdit {
+                start: 5,
+                deleted: 0,
+                inserted: 1,
+            }
+        );
+
+        let (moved, text) = follow("héllo wörld", "héllo, wörld", range(6, 11));
+        assert_eq!(moved, range(7, 12));
+        assert_eq!(text, "wörld");
+
+        assert_eq!(slice("日本語", &range(1, 3)).as_deref(), Some("本語"));
+        assert_eq!(slice("日本語", &range(1, 4)), None);
+        assert_eq!(splice("日本語", &range(1, 2), "🙂"), "日🙂語");
+    }
+
+    fn edit(start: usize, deleted: usize, inserted: usize) -> TextEdit {
+        TextEdit {
+            start,
+            deleted,
+            inserted,
+        }
+    }
+
+    #[test]
+    fn separate_changes_are_separate_hunks() {
+        assert_eq!(
+            hunks("The cat sat on the mat", "The dog sat on the rug"),
+            vec![edit(4, 3, 3), edit(19, 3, 3)]
+        );
+        assert_eq!(hunks("a b c", "a c d"), vec![edit(2, 2, 0), edit(5, 0, 2)]);
+    }
+
+    #[test]
+    fn changed_words_are_replaced_whole() {
+        assert_eq!(hunks("Hello world", "Hello there"), vec![edit(6, 5, 5)]);
+        assert_eq!(hunks("Hello world", "Hello, world"), vec![edit(5, 0, 1)]);
+        assert_eq!(hunks("same", "same"), Vec::new());
+    }
+
+    #[test]
+    fn hunks_are_counted_in_characters() {
+        assert_eq!(
+            hunks("héllo wörld ünd", "hallo wörld und"),
+            vec![edit(0, 5, 5), edit(12, 3, 3)]
+        );
+    }
+
+    #[test]
+    fn too_many_changes_make_one_hunk() {
+        let old = "a ".repeat(MAX_HUNK_COST);
+        let new = "b ".repeat(MAX_HUNK_COST);
+        assert_eq!(
+            hunks(&old, &new),
+            vec![edit(0, 2 * MAX_HUNK_COST - 1, 2 * MAX_HUNK_COST - 1)]
+        );
+    }
+}
@@ -0,0 +1,159 @@
+use axum::async_trait;
+use axum::extract::{FromRequest, Request};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use log::error;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use utoipa::ToSchema;
+
+use crate::store::{StoreError, StoreErrorKind};
+
+// An error returned to a client. Every variant has a stable code clients can
+// match on, the message is meant for people. Internal errors carry details
+// that are logged and never sent.
+#[derive(Debug)]
+pub enum ApiError {
+    BadRequest(String),
+    Unauthorized(String),
+    Forbidden(String),
+    NotFound(String),
+    Conflict(String),
+    // A user, group or folder the request refers to does not exist
+    InvalidReference(String),
+    Internal(String),
+}
+
+// Body of every error response
+#[derive(Serialize, Debug, ToSchema)]
+pub struct ErrorResponse {
+    pub success: bool,
+    // One of bad_request, unauthorized, forbidden, not_found, conflict,
+    // invalid_reference and internal_error
+    pub code: String,
+    pub message: String,
+}
+
+impl ApiError {
+    pub fn bad_request(message: impl Into<String>) -> ApiError {
+        ApiError::BadRequest(message.into())
+    }
+
+    pub fn not_found(message: impl Into<String>) -> ApiError {
+        ApiError::NotFound(message.into())
+    }
+
+    pub fn internal(detail: impl ToString) -> ApiError {
+        ApiError::Internal(detail.to_string())
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ApiError::BadRequest(_) | ApiError::InvalidReference(_) => StatusCode::BAD_REQUEST,
+            ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::Conflict(_) => StatusCode::CONFLICT,
+            ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::BadRequest(_) => "bad_request",
+            ApiError::Unauthorized(_) => "unauthorized",
+            ApiError::Forbidden(_) => "forbidden",
+            ApiError::NotFound(_) => "not_found",
+            ApiError::Conflict(_) => "conflict",
+            ApiError::InvalidReference(_) => "invalid_reference",
+            ApiError::Internal(_) => "internal_error",
+        }
+    }
+
+    // Logs the details of an internal error and returns the message a client
+    // gets to see
+
+    pub fn report(&self) -> String {
+        match self {
+            ApiError::BadRequest(message)
+            | ApiError::Unauthorized(message)
+            | ApiError::Forbidden(message)
+            | ApiError::NotFound(message)
+            | ApiError::Conflict(message)
+            | ApiError::InvalidReference(message) => message.clone(),
+            ApiError::Internal(detail) => {
+                error!("Internal error: {}", detail);
+                "Internal server error".to_string()
+            }
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = ErrorResponse {
+            success: false,
+            code: self.code().to_string(),
+            message: self.report(),
+        };
+        (self.status(), axum::Json(body)).into_response()
+    }
+}
+
+// JSON request and response body. A body that can not be read is answered
+// with a bad_request error like any other invalid request, where axum's own
+// extractor would answer with plain text.
+pub struct Json<T>(pub T);
+
+#[async_trait]
+impl<T: DeserializeOwned, S: Send + Sync> FromRequest<S> for Json<T> {
+    type Rejection = ApiError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        match axum::Json::<T>::from_request(req, state).await {
+            Ok(axum::Json(value)) => Ok(Json(value)),
+            Err(rejection) => Err(ApiError::bad_request(rejection.body_text())),
+        }
+    }
+}
+
+impl<T: Serialize> IntoResponse for Json<T> {
+    fn into_response(self) -> Response {
+        axum::Json(self.0).into_response()
+    }
+}
+
+impl From<StoreError> for ApiError {
+    fn from(e: StoreError) -> Self {
+        match e.kind() {
+            StoreErrorKind::Duplicate => ApiError::Conflict("This already exists".to_string()),
+            StoreErrorKind::MissingReference => ApiError::InvalidReference(
+                "The request refers to a user, group or folder that does not exist".to_string(),
+            ),
+            StoreErrorKind::Other => ApiError::Internal(e.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn constraint_violations_are_client_errors() {
+        let duplicate = ApiError::from(StoreError::duplicate("unique_group_name"));
+        let missing = ApiError::from(StoreError::missing_reference("group_id_fk"));
+
+        assert_eq!(duplicate.status(), StatusCode::CONFLICT);
+        assert_eq!(missing.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(missing.code(), "invalid_reference");
+    }
+
+    #[test]
+    fn internal_details_are_not_returned() {
+        let error = ApiError::from(StoreError::new("connection refused to 10.0.0.3"));
+
+        assert_eq!(error.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(error.report(), "Internal server error");
+    }
+}
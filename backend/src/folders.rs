@@ -0,0 +1,378 @@
+use axum::{extract::State, http::StatusCode};
+use serde_json::json;
+
+use crate::audit::{self, AuditEvent};
+use crate::authz::{self, Capability, Role};
+use crate::errors::{ApiError, Json};
+use crate::library;
+use crate::store::StoreError;
+use crate::structs::{
+    AppState, CreateFolderRequest, DeleteFolderRequest, FolderContentsResponse, FolderResponse,
+    GetFolderContentsRequest, MessageResponse, MoveDocumentRequest, MoveFolderRequest,
+    ShareFolderRequest, UnshareFolderRequest, UpdateFolderRequest,
+};
+
+#[derive(Debug)]
+pub enum FolderError {
+    EmptyName,
+    InvalidRole,
+    OwnRole,
+    Cycle,
+    NotFound,
+    NotOwner,
+    Storage(StoreError),
+}
+
+impl From<StoreError> for FolderError {
+    fn from(e: StoreError) -> Self {
+        FolderError::Storage(e)
+    }
+}
+
+impl From<FolderError> for ApiError {
+    fn from(e: FolderError) -> Self {
+        match e {
+            FolderError::EmptyName => ApiError::bad_request("Folder name must not be empty"),
+            FolderError::InvalidRole => {
+                ApiError::bad_request("Role must be editor, suggester or reader")
+            }
+            FolderError::OwnRole => {
+                ApiError::bad_request("The owner can not change their own role")
+            }
+            FolderError::Cycle => ApiError::bad_request("A folder can not be moved into itself"),
+            FolderError::NotFound => ApiError::not_found("Folder not found"),
+            FolderError::NotOwner => {
+                ApiError::Forbidden("Only the owner of a folder can change it".to_string())
+            }
+            FolderError::Storage(e) => e.into(),
+        }
+    }
+}
+
+// Subfolders are always created by the owner of their parent, so the owner of
+// a folder owns its whole subtree
+
+async fn require_owner(state: &AppState, email: &str, folder_id: i32) -> Result<(), FolderError> {
+    let owner = state.access.folder_owner(folder_id).await?;
+
+    match owner {
+        Some(owner) if owner == email => Ok(()),
+        Some(_) => Err(FolderError::NotOwner),
+        None => Err(FolderError::NotFound),
+    }
+}
+
+// The role a user holds on a folder. Besides the owner, users see a folder
+// shared with them directly or through an ancestor passing on its permissions.
+
+async fn folder_role(
+    state: &AppState,
+    email: &str,
+    folder_id: i32,
+) -> Result<Option<Role>, FolderError> {
+    let roles = state.access.folder_roles(email, folder_id).await?;
+    Ok(roles.into_iter().max_by_key(Role::privilege))
+}
+
+// ***************************************************************************************************************************************
+// Folder management, reserved to the owner
+
+#[utoipa::path(
+    post,
+    path = "/create_folder",
+    tag = "folders",
+    request_body = CreateFolderRequest,
+    responses((status = 201, body = FolderResponse))
+)]
+pub async fn create_folder(
+    State(state): State<AppState>,
+    Json(payload): Json<CreateFolderRequest>,
+) -> Result<(StatusCode, Json<FolderResponse>), ApiError> {
+    let name = payload.name.trim();
+    if name.is_empty() {
+        return Err(FolderError::EmptyName.into());
+    }
+
+    if let Some(parent_id) = payload.parent_id {
+        require_owner(&state, &payload.email, parent_id).await?;
+    }
+
+    let folder = state
+        .access
+        .create_folder(
+            name,
+            &payload.email,
+            payload.parent_id,
+            payload.inherit_permissions,
+        )
+        .await?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(FolderResponse {
+            success: true,
+            folder,
+        }),
+    ))
+}
+
+#[utoipa::path(
+    post,
+    path = "/update_folder",
+    tag = "folders",
+    request_body = UpdateFolderRequest,
+    responses((status = 200, body = FolderResponse))
+)]
+pub async fn update_folder(
+    State(state): State<AppState>,
+    Json(payload): Json<UpdateFolderRequest>,
+) -> Result<(StatusCode, Json<FolderResponse>), ApiError> {
+    require_owner(&state, &payload.email, payload.folder_id).await?;
+
+    let name = payload.name.as_deref().map(str::trim);
+    if name.is_some_and(str::is_empty) {
+        return Err(FolderError::EmptyName.into());
+    }
+
+    let folder = state
+        .access
+        .update_folder(payload.folder_id, name, payload.inherit_permissions)
+        .await?;
+
+    Ok((
+        StatusCode::OK,
+        Json(FolderResponse {
+            success: true,
+            folder,
+        }),
+    ))
+}
+
+#[utoipa::path(
+    post,
+    path = "/move_folder",
+    tag = "folders",
+    request_body = MoveFolderRequest,
+    responses((status = 200, body = MessageResponse))
+)]
+pub async fn move_folder(
+    State(state): State<AppState>,
+    Json(payload): Json<MoveFolderRequest>,
+) -> Result<(StatusCode, Json<MessageResponse>), ApiError> {
+    require_owner(&state, &payload.email, payload.folder_id).await?;
+
+    if let Some(parent_id) = payload.parent_id {
+        require_owner(&state, &payload.email, parent_id).await?;
+
+        // The new parent must not be the folder itself or one of its
+        // subfolders
+        let cycle = state.access.is_within(parent_id, payload.folder_id).await?;
+
+        if cycle {
+            return Err(FolderError::Cycle.into());
+        }
+    }
+
+    state
+        .access
+        .move_folder(payload.folder_id, payload.parent_id)
+        .await?;
+
+    Ok((StatusCode::OK, Json(MessageResponse::new("Moved"))))
+}
+
+// Deleting a folder removes its subfolders as well. The documents in them are
+// kept and end up outside of any folder.
+
+#[utoipa::path(
+    post,
+    path = "/delete_folder",
+    tag = "folders",
+    request_body = DeleteFolderRequest,
+    responses((status = 200, body = MessageResponse))
+)]
+pub async fn delete_folder(
+    State(state): State<AppState>,
+    Json(payload): Json<DeleteFolderRequest>,
+) -> Result<(StatusCode, Json<MessageResponse>), ApiError> {
+    require_owner(&state, &payload.email, payload.folder_id).await?;
+
+    state.access.delete_folder(payload.folder_id).await?;
+
+    Ok((StatusCode::OK, Json(MessageResponse::new("Deleted"))))
+}
+
+// Documents are filed by their owner into one of their folders, or taken out
+// of any folder without a folder id
+
+#[utoipa::path(
+    post,
+    path = "/move_document",
+    tag = "folders",
+    request_body = MoveDocumentRequest,
+    responses((status = 200, body = MessageResponse))
+)]
+pub async fn move_document(
+    State(state): State<AppState>,
+    Json(payload): Json<MoveDocumentRequest>,
+) -> Result<(StatusCode, Json<MessageResponse>), ApiError> {
+    authz::authorize(
+        &state,
+        &payload.email,
+        &payload.document_id,
+        Capability::Share,
+    )
+    .await?;
+
+    if let Some(folder_id) = payload.folder_id {
+        require_owner(&state, &payload.email, folder_id).await?;
+    }
+
+    state
+        .access
+        .file_document(&payload.document_id, payload.folder_id)
+        .await?;
+
+    audit::record(
+        &state,
+        AuditEvent::DocumentMoved,
+        Some(&payload.email),
+        Some(&payload.document_id),
+        json!({ "folder_id": payload.folder_id }),
+    )
+    .await;
+
+    Ok((StatusCode::OK, Json(MessageResponse::new("Moved"))))
+}
+
+// ***************************************************************************************************************************************
+// Folder sharing. With inherit_permissions set on the folder these roles also
+// apply to every document in it and in its subfolders.
+
+#[utoipa::path(
+    post,
+    path = "/share_folder",
+    tag = "folders",
+    request_body = ShareFolderRequest,
+    responses((status = 200, body = MessageResponse))
+)]
+pub async fn share_folder(
+    State(state): State<AppState>,
+    Json(payload): Json<ShareFolderRequest>,
+) -> Result<(StatusCode, Json<MessageResponse>), ApiError> {
+    require_owner(&state, &payload.email, payload.folder_id).await?;
+
+    let role = match payload.role.parse::<Role>() {
+        Ok(Role::Owner) | Err(_) => return Err(FolderError::InvalidRole.into()),
+        Ok(role) => role,
+    };
+
+    if payload.target_email == payload.email {
+        return Err(FolderError::OwnRole.into());
+    }
+
+    state
+        .access
+        .share_folder(payload.folder_id, &payload.target_email, role)
+        .await?;
+
+    audit::record(
+        &state,
+        AuditEvent::ShareChanged,
+        Some(&payload.email),
+        None,
+        json!({
+            "folder_id": payload.folder_id,
+            "target_email": payload.target_email,
+            "role": role.as_str(),
+        }),
+    )
+    .await;
+
+    Ok((StatusCode::OK, Json(MessageResponse::new("Shared"))))
+}
+
+#[utoipa::path(
+    post,
+    path = "/unshare_folder",
+    tag = "folders",
+    request_body = UnshareFolderRequest,
+    responses((status = 200, body = MessageResponse))
+)]
+pub async fn unshare_folder(
+    State(state): State<AppState>,
+    Json(payload): Json<UnshareFolderRequest>,
+) -> Result<(StatusCode, Json<MessageResponse>), ApiError> {
+    require_owner(&state, &payload.email, payload.folder_id).await?;
+
+    let unshared = state
+        .access
+        .unshare_folder(payload.folder_id, &payload.target_email)
+        .await?;
+
+    if !unshared {
+        return Err(ApiError::not_found("Folder is not shared with this user"));
+    }
+
+    audit::record(
+        &state,
+        AuditEvent::ShareRemoved,
+        Some(&payload.email),
+        None,
+        json!({ "folder_id": payload.folder_id, "target_email": payload.target_email }),
+    )
+    .await;
+
+    Ok((StatusCode::OK, Json(MessageResponse::new("Unshared"))))
+}
+
+// ***************************************************************************************************************************************
+// Listing
+
+// Lists the subfolders and documents of a folder. Only documents the user can
+// open are listed. Without a folder id the top level is listed: the user's own
+// top-level folders, folders shared with them and their documents outside of
+// any folder.
+
+#[utoipa::path(
+    post,
+    path = "/get_folder_contents",
+    tag = "folders",
+    request_body = GetFolderContentsRequest,
+    responses((status = 200, body = FolderContentsResponse))
+)]
+pub async fn get_folder_contents(
+    State(state): State<AppState>,
+    Json(payload): Json<GetFolderContentsRequest>,
+) -> Result<(StatusCode, Json<FolderContentsResponse>), ApiError> {
+    let (folders, document_ids) = match payload.folder_id {
+        Some(folder_id) => {
+            let role = folder_role(&state, &payload.email, folder_id).await?;
+            if role.is_none() {
+                return Err(FolderError::NotFound.into());
+            }
+
+            let folders = state.access.subfolders(folder_id).await?;
+            let document_ids = state.access.folder_documents(folder_id).await?;
+
+            (folders, document_ids)
+        }
+        None => {
+            let folders = state.access.top_level_folders(&payload.email).await?;
+            let document_ids = state.access.unfiled_documents(&payload.email).await?;
+
+            (folders, document_ids)
+        }
+    };
+
+    let documents = library::accessible_documents(&state, &payload.email, document_ids).await?;
+
+    Ok((
+        StatusCode::OK,
+        Json(FolderContentsResponse {
+            success: true,
+            folders,
+            documents,
+        }),
+    ))
+}
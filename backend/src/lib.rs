@@ -0,0 +1,913 @@
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::http::HeaderValue;
+use axum::middleware;
+use axum::response::Response;
+use axum::routing::get;
+use axum::{Router, extract::State, http::StatusCode, routing::post};
+
+use log::{debug, error, warn};
+use serde_json::json;
+
+use sqlx::postgres::PgPoolOptions;
+
+use audit::AuditEvent;
+use authz::{Capability, Role};
+use config::{CorsOrigins, StoreConfig};
+use errors::{ApiError, Json};
+use share_links::ShareToken;
+use store::StoreErrorKind;
+use store::mongo::MongoStore;
+use store::postgres::PgStore;
+use store::redis::RedisStore;
+use structs::{
+    AppState, DeleteDocumentRequest, Document, DocumentCreateRequest, DocumentsResponse,
+    GetDocumentRequest, GetGroupsRequest, GetUserRole, GroupsRequest, GroupsResponse, InsertedId,
+    InsertedIdResponse, LoginRequest, LoginResponse, MessageResponse, RoleResponse,
+    SaveDocumentRequest, ShareDocumentGroupRequest, ShareDocumentRequest, TransferOwnershipRequest,
+    UnshareDocumentGroupRequest, UnshareDocumentRequest,
+};
+use tokio::sync::Mutex;
+use tokio::time;
+
+use tower_http::cors::{AllowOrigin, Any, CorsLayer};
+
+// MongoDB
+use mongodb::Client as MongoClient;
+use mongodb::bson::oid::ObjectId;
+use mongodb::options::ClientOptions as MongoClientOptions;
+
+pub mod anchors;
+pub mod audit;
+pub mod authz;
+pub mod comments;
+pub mod config;
+pub mod connections;
+pub mod edits;
+pub mod errors;
+pub mod folders;
+pub mod library;
+pub mod locks;
+pub mod logging;
+pub mod openapi;
+pub mod publishing;
+pub mod recovery;
+pub mod sessions;
+pub mod share_links;
+pub mod store;
+pub mod structs;
+pub mod suggestions;
+pub mod templates;
+pub mod updates;
+pub mod v1;
+pub mod ws_handler;
+
+// Connects to PostgreSQL, MongoDB and Redis and puts them behind the stores
+// of the application state
+
+pub async fn connect_stores(config: &StoreConfig) -> AppState {
+    // Create a connection pool to the PostgreSQL database
+    let db_pool = PgPoolOptions::new()
+        .max_connections(config.pg_max_connections)
+        .acquire_timeout(Duration::from_secs(5))
+        .connect(&config.database_url)
+        .await
+        .expect("Cannot connect to database");
+
+    // Create a MongoDB client
+    let mut mongo_options = MongoClientOptions::parse(&config.mongo_connection_string)
+        .await
+        .expect("Invalid MongoDB connection string");
+    mongo_options.max_pool_size = Some(config.mongo_max_pool_size);
+    let mongo_client =
+        MongoClient::with_options(mongo_options).expect("Failed to connect to MongoDB");
+
+    // Redis client
+    let redis_client = redis::Client::open(config.redis_connection_string.as_str())
+        .expect("Failed to create Redis client");
+    let redis_store = RedisStore::connect(redis_client)
+        .await
+        .expect("Failed to connect to Redis");
+
+    let pg_store = Arc::new(PgStore::new(db_pool));
+    let redis_store = Arc::new(redis_store);
+
+    AppState {
+        documents: Arc::new(MongoStore::new(
+            mongo_client.database(&config.mongo_db_name),
+        )),
+        access: pg_store.clone(),
+        library: pg_store.clone(),
+        audit: pg_store,
+        live: redis_store.clone(),
+        pubsub: redis_store,
+        ws_connections: Arc::new(Mutex::new(HashMap::new())),
+        ws_sessions: Arc::new(Mutex::new(HashMap::new())),
+    }
+}
+
+// Creating the Axum router and add the needed routes
+
+pub fn router(state: AppState, cors_origins: &CorsOrigins) -> Router {
+    let allowed_origins = match cors_origins {
+        CorsOrigins::Any => AllowOrigin::any(),
+        CorsOrigins::List(origins) => AllowOrigin::list(origins.clone()),
+    };
+    let cors = CorsLayer::new()
+        .allow_origin(allowed_origins)
+        .allow_methods(Any)
+        .allow_headers(Any);
+
+    Router::new()
+        .route("/openapi.json", get(openapi::openapi_json))
+        .route("/ws", get(ws_handler::ws_handler))
+        .route("/public/:public_id", get(publishing::get_public_document))
+        .route("/public/:public_id/ws", get(publishing::public_ws_handler))
+        .nest("/api/v1", v1::routes())
+        .merge(legacy_routes())
+        .layer(cors)
+        .with_state(state)
+}
+
+// Routes from before /api/v1, kept while clients move over. Their responses
+// carry a Deprecation header.
+
+fn legacy_routes() -> Router<AppState> {
+    Router::new()
+        .route("/login", post(login_user))
+        .route("/save_document", post(save_document))
+        .route(
+            "/save_document_and_relations",
+            post(save_document_and_relations),
+        )
+        .route("/get_all_documents_owner", post(get_all_documents_owner))
+        .route("/get_all_documents_shared", post(get_all_documents_shared))
+        .route("/create_group", post(create_groups))
+        .route("/get_groups_by_owner", post(get_groups_by_owner))
+        .route("/get_user_role", post(get_user_role))
+        .route("/share_document", post(share_document))
+        .route("/unshare_document", post(unshare_document))
+        .route("/share_document_group", post(share_document_group))
+        .route("/unshare_document_group", post(unshare_document_group))
+        .route("/delete_document", post(delete_document))
+        .route("/transfer_ownership", post(transfer_ownership))
+        .route("/get_audit_log", post(audit::get_audit_log))
+        .route("/create_share_link", post(share_links::create_share_link))
+        .route("/get_share_links", post(share_links::get_share_links))
+        .route("/revoke_share_link", post(share_links::revoke_share_link))
+        .route("/create_folder", post(folders::create_folder))
+        .route("/update_folder", post(folders::update_folder))
+        .route("/move_folder", post(folders::move_folder))
+        .route("/delete_folder", post(folders::delete_folder))
+        .route("/move_document", post(folders::move_document))
+        .route("/get_folder_contents", post(folders::get_folder_contents))
+        .route("/share_folder", post(folders::share_folder))
+        .route("/unshare_folder", post(folders::unshare_folder))
+        .route("/add_tag", post(library::add_tag))
+        .route("/remove_tag", post(library::remove_tag))
+        .route("/get_tags", post(library::get_tags))
+        .route("/set_favorite", post(library::set_favorite))
+        .route("/get_favorites", post(library::get_favorites))
+        .route("/get_recent_documents", post(library::get_recent_documents))
+        .route("/set_template", post(templates::set_template))
+        .route("/get_templates", post(templates::get_templates))
+        .route(
+            "/create_from_template",
+            post(templates::create_from_template),
+        )
+        .route("/duplicate_document", post(templates::duplicate_document))
+        .route("/set_exclusive_editing", post(locks::set_exclusive_editing))
+        .route("/get_edit_lock", post(locks::get_edit_lock))
+        .route("/publish_document", post(publishing::publish_document))
+        .route("/unpublish_document", post(publishing::unpublish_document))
+        .route("/get_comments", post(comments::get_comments))
+        .route("/create_comment", post(comments::create_comment))
+        .route("/reply_comment", post(comments::reply_comment))
+        .route("/resolve_comment", post(comments::resolve_comment))
+        .route("/reopen_comment", post(comments::reopen_comment))
+        .route("/get_suggestions", post(suggestions::get_suggestions))
+        .route("/accept_suggestion", post(suggestions::accept_suggestion))
+        .route("/reject_suggestion", post(suggestions::reject_suggestion))
+        .layer(middleware::map_response(mark_deprecated))
+}
+
+async fn mark_deprecated(mut response: Response) -> Response {
+    response
+        .headers_mut()
+        .insert("deprecation", HeaderValue::from_static("true"));
+    response
+}
+
+// ***************************************************************************************************************************************
+
+pub async fn start_periodic_flush(state: AppState, interval: Duration) {
+    tokio::spawn(async move {
+        loop {
+            time::sleep(interval).await;
+
+            match flush_all(&state).await {
+                Ok(_) => debug!("Periodic flush successful"),
+                Err(e) => error!("Periodic flush failed: {}", e),
+            }
+        }
+    });
+}
+
+async fn flush_all(state: &AppState) -> Result<(), Box<dyn Error>> {
+    let document_ids = state.live.open_documents().await?;
+    debug!("Flush timer: Open documents: {:?}", document_ids);
+
+    for document_id in document_ids {
+        // A document that can not be flushed stays open until the next
+        // round, the others are still flushed
+        if let Err(e) = ws_handler::flush_document(state, &document_id).await {
+            error!("Failed to flush doc {}: {}", document_id, e);
+            continue;
+        }
+
+        let map = state.ws_connections.lock().await;
+
+        match map.get(&document_id) {
+            Some(count) => {
+                if *count == 0 {
+                    state.live.close(&document_id).await?;
+                }
+            }
+            None => {
+                warn!(
+                    "Failed to find doc id in ws_connections: {}\nCleaning up",
+                    document_id
+                );
+                state.live.close(&document_id).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// This function handles the Login endpoint/request
+
+#[utoipa::path(
+    post,
+    path = "/login",
+    tag = "users",
+    request_body = LoginRequest,
+    responses((status = 200, body = LoginResponse))
+)]
+async fn login_user(
+    State(state): State<AppState>,
+    Json(payload): Json<LoginRequest>,
+) -> Result<(StatusCode, Json<LoginResponse>), ApiError> {
+    let user = state
+        .access
+        .find_user(&payload.email, &payload.password)
+        .await?;
+
+    if let Some(user) = user {
+        audit::record(
+            &state,
+            AuditEvent::Login,
+            Some(&payload.email),
+            None,
+            json!({}),
+        )
+        .await;
+        Ok((
+            StatusCode::OK,
+            Json(LoginResponse {
+                success: true,
+                user,
+            }),
+        ))
+    } else {
+        audit::record(
+            &state,
+            AuditEvent::LoginFailed,
+            Some(&payload.email),
+            None,
+            json!({}),
+        )
+        .await;
+        Err(ApiError::Unauthorized(
+            "Invalid email or password".to_string(),
+        ))
+    }
+}
+
+// This function handles the saving of a document and its relations in both MongoDB and PostgreSQL
+
+#[utoipa::path(
+    post,
+    path = "/save_document_and_relations",
+    tag = "documents",
+    request_body = DocumentCreateRequest,
+    responses((status = 200, body = MessageResponse))
+)]
+async fn save_document_and_relations(
+    State(state): State<AppState>,
+    Json(payload): Json<DocumentCreateRequest>,
+) -> Result<(StatusCode, Json<MessageResponse>), ApiError> {
+    create_document(&state, payload).await?;
+
+    Ok((StatusCode::OK, Json(MessageResponse::new("Created"))))
+}
+
+// Creates a document with its relations and returns its id
+
+async fn create_document(
+    state: &AppState,
+    payload: DocumentCreateRequest,
+) -> Result<String, ApiError> {
+    if payload.title.is_empty() {
+        return Err(ApiError::bad_request("Title must not be empty"));
+    }
+    if payload.format.is_empty() {
+        return Err(ApiError::bad_request("Format must not be empty"));
+    }
+
+    let document = Document::new(payload.title, payload.format, String::new());
+
+    let document_id = state.documents.insert(document).await?;
+
+    let roles = [
+        (std::slice::from_ref(&payload.owner), Role::Owner),
+        (payload.collaborators.as_slice(), Role::Editor),
+        (payload.readers.as_slice(), Role::Reader),
+        (payload.suggesters.as_slice(), Role::Suggester),
+    ];
+    for (users, role) in roles {
+        for user in users {
+            state.access.add_relation(&document_id, user, role).await?;
+        }
+    }
+
+    // Insert document related user groups
+    for group_id in payload.groups.iter() {
+        state
+            .access
+            .add_group_relation(&document_id, *group_id)
+            .await?;
+    }
+
+    audit::record(
+        state,
+        AuditEvent::DocumentCreated,
+        Some(&payload.owner),
+        Some(&document_id),
+        json!({
+            "editors": payload.collaborators,
+            "readers": payload.readers,
+            "suggesters": payload.suggesters,
+            "groups": payload.groups,
+        }),
+    )
+    .await;
+
+    Ok(document_id)
+}
+
+// This function handles the request for getting all documents belonging to the owner
+
+#[utoipa::path(
+    post,
+    path = "/get_all_documents_owner",
+    tag = "documents",
+    request_body = GetDocumentRequest,
+    responses((status = 200, body = DocumentsResponse))
+)]
+async fn get_all_documents_owner(
+    State(state): State<AppState>,
+    Json(payload): Json<GetDocumentRequest>,
+) -> Result<(StatusCode, Json<DocumentsResponse>), ApiError> {
+    let document_ids = state.access.owned_documents(&payload.email).await?;
+
+    let library = library::Library::load(&state, &payload.email).await?;
+
+    let mut documents = Vec::new();
+
+    for document_id in document_ids {
+        if !library.matches(&document_id, &payload) {
+            continue;
+        }
+
+        if let Ok(Some(doc)) = state.documents.get(&document_id).await {
+            documents.push(library.summary(document_id, payload.email.clone(), &doc));
+        }
+    }
+    Ok((
+        StatusCode::OK,
+        Json(DocumentsResponse {
+            success: true,
+            documents,
+        }),
+    ))
+}
+
+// This function handles the request for getting all documents shared with the user signed in
+
+#[utoipa::path(
+    post,
+    path = "/get_all_documents_shared",
+    tag = "documents",
+    request_body = GetDocumentRequest,
+    responses((status = 200, body = DocumentsResponse))
+)]
+async fn get_all_documents_shared(
+    State(state): State<AppState>,
+    Json(payload): Json<GetDocumentRequest>,
+) -> Result<(StatusCode, Json<DocumentsResponse>), ApiError> {
+    let document_ids = state.access.shared_documents(&payload.email).await?;
+
+    if document_ids.is_empty() {
+        return Ok((
+            StatusCode::OK,
+            Json(DocumentsResponse {
+                success: true,
+                documents: Vec::new(),
+            }),
+        ));
+    }
+
+    let library = library::Library::load(&state, &payload.email).await?;
+
+    let mut documents = Vec::new();
+
+    for doc_id in document_ids {
+        if !library.matches(&doc_id, &payload) {
+            continue;
+        }
+        let owner = state.access.owner_of(&doc_id).await?;
+
+        let Some(owner_email) = owner else {
+            continue;
+        };
+
+        let stored = state.documents.get(&doc_id).await?;
+
+        if let Some(doc) = stored {
+            documents.push(library.summary(doc_id, owner_email, &doc));
+        }
+    }
+
+    Ok((
+        StatusCode::OK,
+        Json(DocumentsResponse {
+            success: true,
+            documents,
+        }),
+    ))
+}
+
+// ADD GROUPS INTO POSTGRES TABLE
+
+#[utoipa::path(
+    post,
+    path = "/create_group",
+    tag = "groups",
+    request_body = Vec<GroupsRequest>,
+    responses((status = 201, body = MessageResponse))
+)]
+async fn create_groups(
+    State(state): State<AppState>,
+    Json(payload): Json<Vec<GroupsRequest>>,
+) -> Result<(StatusCode, Json<MessageResponse>), ApiError> {
+    for group in payload {
+        create_group(&state, &group).await?;
+    }
+
+    Ok((
+        StatusCode::CREATED,
+        Json(MessageResponse::new("Group created successfully")),
+    ))
+}
+
+// Creates a group with its members and returns its id
+
+async fn create_group(state: &AppState, group: &GroupsRequest) -> Result<i32, ApiError> {
+    // Check for malformed requests
+    if group.name.is_empty() {
+        return Err(ApiError::bad_request("Group name must not be empty"));
+    }
+
+    if group.members.is_empty() {
+        return Err(ApiError::bad_request("Members must not be empty"));
+    }
+
+    let mut members = HashSet::new();
+    if let Some(member) = group.members.iter().find(|m| !members.insert(m.as_str())) {
+        return Err(ApiError::bad_request(format!(
+            "{} is listed more than once in the members",
+            member
+        )));
+    }
+
+    state
+        .access
+        .create_group(group)
+        .await
+        .map_err(|e| match e.kind() {
+            StoreErrorKind::Duplicate => {
+                ApiError::Conflict(format!("A group named {} already exists", group.name))
+            }
+            StoreErrorKind::MissingReference => ApiError::InvalidReference(
+                "The owner and members of a group must be existing users".to_string(),
+            ),
+            StoreErrorKind::Other => ApiError::internal(format!("Error inserting group: {}", e)),
+        })
+}
+
+#[utoipa::path(
+    post,
+    path = "/get_groups_by_owner",
+    tag = "groups",
+    request_body = GetGroupsRequest,
+    responses((status = 200, body = GroupsResponse))
+)]
+async fn get_groups_by_owner(
+    State(state): State<AppState>,
+    Json(payload): Json<GetGroupsRequest>,
+) -> Result<(StatusCode, Json<GroupsResponse>), ApiError> {
+    let groups = state.access.groups_by_owner(&payload.email).await?;
+
+    Ok((
+        StatusCode::OK,
+        Json(GroupsResponse {
+            success: true,
+            groups,
+        }),
+    ))
+}
+
+// ***************************************************************************************************************************************
+// Fetch the role a user holds on a document, directly or through a group
+
+#[utoipa::path(
+    post,
+    path = "/get_user_role",
+    tag = "documents",
+    params(
+        ("x-share-token" = Option<String>, Header, description = "Token of a share link"),
+    ),
+    request_body = GetUserRole,
+    responses((status = 200, body = RoleResponse))
+)]
+async fn get_user_role(
+    State(state): State<AppState>,
+    ShareToken(share_token): ShareToken,
+    Json(payload): Json<GetUserRole>,
+) -> Result<(StatusCode, Json<RoleResponse>), ApiError> {
+    let access = authz::authorize_with_token(
+        &state,
+        &payload.email,
+        &payload.document_id,
+        share_token.as_deref(),
+        Capability::View,
+    )
+    .await
+    .map_err(|e| match e {
+        authz::AuthzError::NoAccess => {
+            ApiError::not_found("No role found for this user and document")
+        }
+        e => e.into(),
+    })?;
+
+    Ok((
+        StatusCode::OK,
+        Json(RoleResponse {
+            success: true,
+            user_role: vec![access.role.as_str().to_string()],
+            source: access.source,
+        }),
+    ))
+}
+
+// ***************************************************************************************************************************************
+// Sharing, deletion and ownership transfer of documents
+
+#[utoipa::path(
+    post,
+    path = "/share_document",
+    tag = "documents",
+    request_body = ShareDocumentRequest,
+    responses((status = 200, body = MessageResponse))
+)]
+async fn share_document(
+    State(state): State<AppState>,
+    Json(payload): Json<ShareDocumentRequest>,
+) -> Result<(StatusCode, Json<MessageResponse>), ApiError> {
+    authz::authorize(
+        &state,
+        &payload.email,
+        &payload.document_id,
+        Capability::Share,
+    )
+    .await?;
+
+    let role = match payload.role.parse::<Role>() {
+        Ok(Role::Owner) | Err(_) => {
+            return Err(ApiError::bad_request(
+                "Role must be editor, suggester or reader",
+            ));
+        }
+        Ok(role) => role,
+    };
+
+    if payload.target_email == payload.email {
+        return Err(ApiError::bad_request(
+            "The owner can not change their own role",
+        ));
+    }
+
+    check_validity(payload.valid_from, payload.valid_until)?;
+
+    state
+        .access
+        .share(
+            &payload.document_id,
+            &payload.target_email,
+            role,
+            payload.valid_from,
+            payload.valid_until,
+        )
+        .await?;
+
+    audit::record(
+        &state,
+        AuditEvent::ShareChanged,
+        Some(&payload.email),
+        Some(&payload.document_id),
+        json!({
+            "target_email": payload.target_email,
+            "role": role.as_str(),
+            "valid_from": payload.valid_from,
+            "valid_until": payload.valid_until,
+        }),
+    )
+    .await;
+
+    Ok((StatusCode::OK, Json(MessageResponse::new("Shared"))))
+}
+
+#[utoipa::path(
+    post,
+    path = "/unshare_document",
+    tag = "documents",
+    request_body = UnshareDocumentRequest,
+    responses((status = 200, body = MessageResponse))
+)]
+async fn unshare_document(
+    State(state): State<AppState>,
+    Json(payload): Json<UnshareDocumentRequest>,
+) -> Result<(StatusCode, Json<MessageResponse>), ApiError> {
+    authz::authorize(
+        &state,
+        &payload.email,
+        &payload.document_id,
+        Capability::Share,
+    )
+    .await?;
+
+    let unshared = state
+        .access
+        .unshare(&payload.document_id, &payload.target_email)
+        .await?;
+
+    if !unshared {
+        return Err(ApiError::not_found("Document is not shared with this user"));
+    }
+
+    audit::record(
+        &state,
+        AuditEvent::ShareRemoved,
+        Some(&payload.email),
+        Some(&payload.document_id),
+        json!({ "target_email": payload.target_email }),
+    )
+    .await;
+
+    Ok((StatusCode::OK, Json(MessageResponse::new("Unshared"))))
+}
+
+fn check_validity(
+    valid_from: Option<chrono::DateTime<chrono::Utc>>,
+    valid_until: Option<chrono::DateTime<chrono::Utc>>,
+) -> Result<(), ApiError> {
+    match (valid_from, valid_until) {
+        (Some(from), Some(until)) if from >= until => Err(ApiError::bad_request(
+            "valid_from must be before valid_until",
+        )),
+        _ => Ok(()),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/share_document_group",
+    tag = "documents",
+    request_body = ShareDocumentGroupRequest,
+    responses((status = 200, body = MessageResponse))
+)]
+async fn share_document_group(
+    State(state): State<AppState>,
+    Json(payload): Json<ShareDocumentGroupRequest>,
+) -> Result<(StatusCode, Json<MessageResponse>), ApiError> {
+    authz::authorize(
+        &state,
+        &payload.email,
+        &payload.document_id,
+        Capability::Share,
+    )
+    .await?;
+
+    check_validity(payload.valid_from, payload.valid_until)?;
+
+    state
+        .access
+        .share_with_group(
+            &payload.document_id,
+            payload.group_id,
+            payload.valid_from,
+            payload.valid_until,
+        )
+        .await?;
+
+    audit::record(
+        &state,
+        AuditEvent::ShareChanged,
+        Some(&payload.email),
+        Some(&payload.document_id),
+        json!({
+            "group_id": payload.group_id,
+            "valid_from": payload.valid_from,
+            "valid_until": payload.valid_until,
+        }),
+    )
+    .await;
+
+    Ok((StatusCode::OK, Json(MessageResponse::new("Shared"))))
+}
+
+#[utoipa::path(
+    post,
+    path = "/unshare_document_group",
+    tag = "documents",
+    request_body = UnshareDocumentGroupRequest,
+    responses((status = 200, body = MessageResponse))
+)]
+async fn unshare_document_group(
+    State(state): State<AppState>,
+    Json(payload): Json<UnshareDocumentGroupRequest>,
+) -> Result<(StatusCode, Json<MessageResponse>), ApiError> {
+    authz::authorize(
+        &state,
+        &payload.email,
+        &payload.document_id,
+        Capability::Share,
+    )
+    .await?;
+
+    let unshared = state
+        .access
+        .unshare_group(&payload.document_id, payload.group_id)
+        .await?;
+
+    if !unshared {
+        return Err(ApiError::not_found(
+            "Document is not shared with this group",
+        ));
+    }
+
+    audit::record(
+        &state,
+        AuditEvent::ShareRemoved,
+        Some(&payload.email),
+        Some(&payload.document_id),
+        json!({ "group_id": payload.group_id }),
+    )
+    .await;
+
+    Ok((StatusCode::OK, Json(MessageResponse::new("Unshared"))))
+}
+
+#[utoipa::path(
+    post,
+    path = "/delete_document",
+    tag = "documents",
+    request_body = DeleteDocumentRequest,
+    responses((status = 200, body = MessageResponse))
+)]
+async fn delete_document(
+    State(state): State<AppState>,
+    Json(payload): Json<DeleteDocumentRequest>,
+) -> Result<(StatusCode, Json<MessageResponse>), ApiError> {
+    authz::authorize(
+        &state,
+        &payload.email,
+        &payload.document_id,
+        Capability::Delete,
+    )
+    .await?;
+
+    ObjectId::parse_str(&payload.document_id).map_err(|e| ApiError::bad_request(e.to_string()))?;
+
+    state.access.remove_document(&payload.document_id).await?;
+
+    // Relations are gone, so nobody can open the document anymore. Remove the
+    // content and everything attached to it.
+    if let Err(e) = state.documents.delete(&payload.document_id).await {
+        error!(
+            "Failed to delete stored data of document {}: {}",
+            payload.document_id, e
+        );
+    }
+
+    let _ = state.live.close(&payload.document_id).await;
+    let _ = state.pubsub.reset(&payload.document_id).await;
+
+    audit::record(
+        &state,
+        AuditEvent::DocumentDeleted,
+        Some(&payload.email),
+        Some(&payload.document_id),
+        json!({}),
+    )
+    .await;
+
+    Ok((StatusCode::OK, Json(MessageResponse::new("Deleted"))))
+}
+
+#[utoipa::path(
+    post,
+    path = "/transfer_ownership",
+    tag = "documents",
+    request_body = TransferOwnershipRequest,
+    responses((status = 200, body = MessageResponse))
+)]
+async fn transfer_ownership(
+    State(state): State<AppState>,
+    Json(payload): Json<TransferOwnershipRequest>,
+) -> Result<(StatusCode, Json<MessageResponse>), ApiError> {
+    authz::authorize(
+        &state,
+        &payload.email,
+        &payload.document_id,
+        Capability::Transfer,
+    )
+    .await?;
+
+    if payload.new_owner == payload.email {
+        return Err(ApiError::bad_request("New owner must be another user"));
+    }
+
+    // The previous owner stays on the document as an editor
+    state
+        .access
+        .transfer_ownership(&payload.document_id, &payload.email, &payload.new_owner)
+        .await?;
+
+    audit::record(
+        &state,
+        AuditEvent::OwnershipTransferred,
+        Some(&payload.email),
+        Some(&payload.document_id),
+        json!({ "new_owner": payload.new_owner }),
+    )
+    .await;
+
+    Ok((
+        StatusCode::OK,
+        Json(MessageResponse::new("Ownership transferred")),
+    ))
+}
+
+// ***************************************************************************************************************************************
+// This function handles the MongoDB document creation
+
+#[utoipa::path(
+    post,
+    path = "/save_document",
+    tag = "documents",
+    request_body = SaveDocumentRequest,
+    responses((status = 201, body = InsertedIdResponse))
+)]
+async fn save_document(
+    State(state): State<AppState>,
+    Json(payload): Json<SaveDocumentRequest>,
+) -> Result<(StatusCode, Json<InsertedIdResponse>), ApiError> {
+    let document = Document::new(payload.title, payload.format, payload.content);
+
+    match state.documents.insert(document).await {
+        Ok(inserted_id) => Ok((
+            StatusCode::CREATED,
+            Json(InsertedIdResponse {
+                inserted_id: InsertedId { oid: inserted_id },
+            }),
+        )),
+        Err(e) => Err(e.into()),
+    }
+}
+
+// ***************************************************************************************************************************************
@@ -0,0 +1,310 @@
+use std::collections::{HashMap, HashSet};
+
+use axum::{extract::State, http::StatusCode};
+use chrono::{DateTime, Utc};
+use log::error;
+
+use crate::authz::{self, Capability, user_has_access};
+use crate::errors::{ApiError, Json};
+use crate::store::StoreError;
+use crate::structs::{
+    AppState, Document, DocumentEntriesResponse, DocumentEntry, DocumentSummary,
+    DocumentTagRequest, FavoriteResponse, GetDocumentRequest, GetLibraryRequest, MessageResponse,
+    SetFavoriteRequest, TagCount, TagsResponse,
+};
+
+// Number of recently opened documents kept per user
+const RECENT_LIMIT: i64 = 50;
+
+const MAX_TAG_LENGTH: usize = 50;
+
+// The tags, favorites and recently opened documents of a user, loaded once
+// per listing
+pub struct Library {
+    tags: HashMap<String, Vec<String>>,
+    favorites: HashSet<String>,
+    recent: HashMap<String, DateTime<Utc>>,
+}
+
+impl Library {
+    pub async fn load(state: &AppState, email: &str) -> Result<Library, StoreError> {
+        let mut tags: HashMap<String, Vec<String>> = HashMap::new();
+        for (document_id, tag) in state.library.tags(email).await? {
+            tags.entry(document_id).or_default().push(tag);
+        }
+
+        let favorites = state.library.favorites(email).await?.into_iter().collect();
+        let recent = state
+            .library
+            .recent(email, RECENT_LIMIT)
+            .await?
+            .into_iter()
+            .collect();
+
+        Ok(Library {
+            tags,
+            favorites,
+            recent,
+        })
+    }
+
+    // Whether a document passes the filters of a listing request
+    pub fn matches(&self, document_id: &str, filter: &GetDocumentRequest) -> bool {
+        let tagged = match &filter.tag {
+            Some(tag) => self
+                .tags
+                .get(document_id)
+                .is_some_and(|tags| tags.contains(tag)),
+            None => true,
+        };
+        tagged
+            && (!filter.favorites || self.favorites.contains(document_id))
+            && (!filter.recent || self.recent.contains_key(document_id))
+    }
+
+    // Lists a document with the user's tags, favorite flag and last opening
+    pub fn summary(&self, id: String, owner_email: String, doc: &Document) -> DocumentSummary {
+        DocumentSummary {
+            tags: self.tags.get(&id).cloned().unwrap_or_default(),
+            favorite: self.favorites.contains(&id),
+            last_opened_at: self.recent.get(&id).copied(),
+            title: doc.title.clone(),
+            format: doc.format.clone(),
+            owner_email,
+            metadata: doc.metadata(),
+            id,
+        }
+    }
+}
+
+// Called whenever a user opens a document. Only the most recent openings are
+// kept.
+
+pub async fn record_open(state: &AppState, email: &str, document_id: &str) {
+    let result = state
+        .library
+        .record_open(email, document_id, RECENT_LIMIT)
+        .await;
+
+    if let Err(e) = result {
+        error!(
+            "Failed to record opening of doc {} by {}: {}",
+            document_id, email, e
+        );
+    }
+}
+
+// Looks up the documents among the ids that the user can still open, in the
+// order of the ids. Shared by the library listings and folder contents.
+
+pub async fn accessible_documents(
+    state: &AppState,
+    email: &str,
+    document_ids: Vec<String>,
+) -> Result<Vec<DocumentEntry>, ApiError> {
+    let mut documents = Vec::new();
+
+    for document_id in document_ids {
+        let access = user_has_access(email, &document_id, None, state).await?;
+        let Some(access) = access else {
+            continue;
+        };
+        if let Ok(Some(doc)) = state.documents.get(&document_id).await {
+            documents.push(DocumentEntry {
+                role: access.role.as_str().to_string(),
+                metadata: doc.metadata(),
+                title: doc.title,
+                format: doc.format,
+                opened_at: None,
+                id: document_id,
+            });
+        }
+    }
+    Ok(documents)
+}
+
+// ***************************************************************************************************************************************
+// Tags
+
+#[utoipa::path(
+    post,
+    path = "/add_tag",
+    tag = "library",
+    request_body = DocumentTagRequest,
+    responses((status = 200, body = MessageResponse))
+)]
+pub async fn add_tag(
+    State(state): State<AppState>,
+    Json(payload): Json<DocumentTagRequest>,
+) -> Result<(StatusCode, Json<MessageResponse>), ApiError> {
+    authz::authorize(
+        &state,
+        &payload.email,
+        &payload.document_id,
+        Capability::View,
+    )
+    .await?;
+
+    let tag = payload.tag.trim();
+    if tag.is_empty() || tag.chars().count() > MAX_TAG_LENGTH {
+        return Err(ApiError::bad_request(format!(
+            "Tags must be 1 to {} characters",
+            MAX_TAG_LENGTH
+        )));
+    }
+
+    state
+        .library
+        .add_tag(&payload.email, &payload.document_id, tag)
+        .await?;
+
+    Ok((StatusCode::OK, Json(MessageResponse::new("Tagged"))))
+}
+
+#[utoipa::path(
+    post,
+    path = "/remove_tag",
+    tag = "library",
+    request_body = DocumentTagRequest,
+    responses((status = 200, body = MessageResponse))
+)]
+pub async fn remove_tag(
+    State(state): State<AppState>,
+    Json(payload): Json<DocumentTagRequest>,
+) -> Result<(StatusCode, Json<MessageResponse>), ApiError> {
+    let removed = state
+        .library
+        .remove_tag(&payload.email, &payload.document_id, payload.tag.trim())
+        .await?;
+
+    if !removed {
+        return Err(ApiError::not_found("Tag not found"));
+    }
+
+    Ok((StatusCode::OK, Json(MessageResponse::new("Untagged"))))
+}
+
+// Every tag of the user with the number of documents carrying it
+
+#[utoipa::path(
+    post,
+    path = "/get_tags",
+    tag = "library",
+    request_body = GetLibraryRequest,
+    responses((status = 200, body = TagsResponse))
+)]
+pub async fn get_tags(
+    State(state): State<AppState>,
+    Json(payload): Json<GetLibraryRequest>,
+) -> Result<(StatusCode, Json<TagsResponse>), ApiError> {
+    let rows = state.library.tag_counts(&payload.email).await?;
+
+    let tags = rows
+        .into_iter()
+        .map(|(tag, documents)| TagCount { tag, documents })
+        .collect();
+
+    Ok((
+        StatusCode::OK,
+        Json(TagsResponse {
+            success: true,
+            tags,
+        }),
+    ))
+}
+
+// ***************************************************************************************************************************************
+// Favorites and recent documents
+
+#[utoipa::path(
+    post,
+    path = "/set_favorite",
+    tag = "library",
+    request_body = SetFavoriteRequest,
+    responses((status = 200, body = FavoriteResponse))
+)]
+pub async fn set_favorite(
+    State(state): State<AppState>,
+    Json(payload): Json<SetFavoriteRequest>,
+) -> Result<(StatusCode, Json<FavoriteResponse>), ApiError> {
+    if payload.favorite {
+        authz::authorize(
+            &state,
+            &payload.email,
+            &payload.document_id,
+            Capability::View,
+        )
+        .await?;
+    }
+
+    state
+        .library
+        .set_favorite(&payload.email, &payload.document_id, payload.favorite)
+        .await?;
+
+    Ok((
+        StatusCode::OK,
+        Json(FavoriteResponse {
+            success: true,
+            favorite: payload.favorite,
+        }),
+    ))
+}
+
+#[utoipa::path(
+    post,
+    path = "/get_favorites",
+    tag = "library",
+    request_body = GetLibraryRequest,
+    responses((status = 200, body = DocumentEntriesResponse))
+)]
+pub async fn get_favorites(
+    State(state): State<AppState>,
+    Json(payload): Json<GetLibraryRequest>,
+) -> Result<(StatusCode, Json<DocumentEntriesResponse>), ApiError> {
+    let document_ids = state.library.favorites(&payload.email).await?;
+
+    let documents = accessible_documents(&state, &payload.email, document_ids).await?;
+
+    Ok((
+        StatusCode::OK,
+        Json(DocumentEntriesResponse {
+            success: true,
+            documents,
+        }),
+    ))
+}
+
+// Recently opened documents, most recent first
+
+#[utoipa::path(
+    post,
+    path = "/get_recent_documents",
+    tag = "library",
+    request_body = GetLibraryRequest,
+    responses((status = 200, body = DocumentEntriesResponse))
+)]
+pub async fn get_recent_documents(
+    State(state): State<AppState>,
+    Json(payload): Json<GetLibraryRequest>,
+) -> Result<(StatusCode, Json<DocumentEntriesResponse>), ApiError> {
+    let limit = payload.limit.unwrap_or(10).clamp(1, RECENT_LIMIT);
+
+    let rows = state.library.recent(&payload.email, limit).await?;
+
+    let document_ids = rows.iter().map(|(id, _)| id.clone()).collect();
+    let opened: HashMap<String, DateTime<Utc>> = rows.into_iter().collect();
+
+    let mut documents = accessible_documents(&state, &payload.email, document_ids).await?;
+    for document in &mut documents {
+        document.opened_at = opened.get(&document.id).copied();
+    }
+
+    Ok((
+        StatusCode::OK,
+        Json(DocumentEntriesResponse {
+            success: true,
+            documents,
+        }),
+    ))
+}
@@ -0,0 +1,349 @@
+use std::sync::atomic::Ordering;
+
+use axum::{extract::State, http::StatusCode};
+use chrono::{Duration as ChronoDuration, Utc};
+use log::error;
+use mongodb::bson::oid::ObjectId;
+use tokio::time::Duration;
+
+use crate::authz::{self, Capability};
+use crate::errors::{ApiError, Json};
+use crate::store::StoreResult;
+use crate::structs::{
+    AppState, EditLockResponse, ExclusiveEditingResponse, GetEditLockRequest, LockState,
+    SetExclusiveEditingRequest, WsServerMessage,
+};
+
+// A lease not renewed within this time is released, so a writer whose
+// connection silently died does not block the document for long
+const LEASE_MS: i64 = 30_000;
+
+// How often the session holding the lease renews it
+pub const HEARTBEAT: Duration = Duration::from_secs(10);
+
+// Leases belong to a session rather than a user, two tabs of the same user
+// do not share the lock. Session ids are only unique within an instance, so
+// every session leases under a random token of its own instead.
+
+pub fn lease_token() -> String {
+    format!("{:032x}", rand::random::<u128>())
+}
+
+fn lease_value(token: &str, email: &str) -> String {
+    format!("{}:{}", token, email)
+}
+
+// Takes the lease if it is free, renewing it if the session already holds it
+
+pub async fn acquire(
+    state: &AppState,
+    document_id: &str,
+    token: &str,
+    email: &str,
+) -> StoreResult<bool> {
+    state
+        .live
+        .acquire_lease(document_id, &lease_value(token, email), LEASE_MS)
+        .await
+}
+
+pub async fn renew(
+    state: &AppState,
+    document_id: &str,
+    token: &str,
+    email: &str,
+) -> StoreResult<bool> {
+    state
+        .live
+        .renew_lease(document_id, &lease_value(token, email), LEASE_MS)
+        .await
+}
+
+pub async fn release(
+    state: &AppState,
+    document_id: &str,
+    token: &str,
+    email: &str,
+) -> StoreResult<bool> {
+    state
+        .live
+        .release_lease(document_id, &lease_value(token, email))
+        .await
+}
+
+// Releases the lease whoever holds it
+
+pub async fn break_lock(state: &AppState, document_id: &str) -> StoreResult<()> {
+    state.live.break_lease(document_id).await
+}
+
+pub async fn holds(
+    state: &AppState,
+    document_id: &str,
+    token: &str,
+    email: &str,
+) -> StoreResult<bool> {
+    let lease = state.live.lease(document_id).await?;
+    Ok(lease.is_some_and(|lease| lease.value == lease_value(token, email)))
+}
+
+pub async fn lock_state(
+    state: &AppState,
+    document_id: &str,
+    exclusive: bool,
+) -> StoreResult<LockState> {
+    let lease = state.live.lease(document_id).await?;
+
+    let (holder, expires_at) = match lease {
+        Some(lease) => (
+            lease
+                .value
+                .split_once(':')
+                .map(|(_, email)| email.to_string()),
+            (lease.expires_in_ms > 0)
+                .then(|| Utc::now() + ChronoDuration::milliseconds(lease.expires_in_ms)),
+        ),
+        None => (None, None),
+    };
+
+    Ok(LockState {
+        exclusive,
+        holder,
+        expires_at,
+    })
+}
+
+// Sends the lock state to every client of the document
+
+pub async fn broadcast(state: &AppState, document_id: &str, exclusive: bool) -> StoreResult<()> {
+    let lock = lock_state(state, document_id, exclusive).await?;
+    state
+        .pubsub
+        .publish(document_id, &WsServerMessage::Lock { state: lock })
+        .await
+        .map(|_| ())
+}
+
+// ***************************************************************************************************************************************
+// REST endpoints
+
+// Turns exclusive editing on or off. Any lease held when the mode changes is
+// dropped, with exclusive editing on every editor has to take the lock again.
+
+#[utoipa::path(
+    post,
+    path = "/set_exclusive_editing",
+    tag = "locks",
+    request_body = SetExclusiveEditingRequest,
+    responses((status = 200, body = ExclusiveEditingResponse))
+)]
+pub async fn set_exclusive_editing(
+    State(state): State<AppState>,
+    Json(payload): Json<SetExclusiveEditingRequest>,
+) -> Result<(StatusCode, Json<ExclusiveEditingResponse>), ApiError> {
+    authz::authorize(
+        &state,
+        &payload.email,
+        &payload.document_id,
+        Capability::Share,
+    )
+    .await?;
+
+    ObjectId::parse_str(&payload.document_id).map_err(|e| ApiError::bad_request(e.to_string()))?;
+
+    state
+        .documents
+        .set_exclusive_editing(&payload.document_id, payload.enabled)
+        .await?;
+
+    for session in state.ws_sessions.lock().await.values() {
+        if session.document_id == payload.document_id {
+            session.exclusive.store(payload.enabled, Ordering::Relaxed);
+        }
+    }
+
+    let result = async {
+        break_lock(&state, &payload.document_id).await?;
+        broadcast(&state, &payload.document_id, payload.enabled).await
+    }
+    .await;
+
+    if let Err(e) = result {
+        error!(
+            "Failed to reset edit lock of doc {}: {}",
+            payload.document_id, e
+        );
+    }
+
+    Ok((
+        StatusCode::OK,
+        Json(ExclusiveEditingResponse {
+            success: true,
+            exclusive: payload.enabled,
+        }),
+    ))
+}
+
+#[utoipa::path(
+    post,
+    path = "/get_edit_lock",
+    tag = "locks",
+    request_body = GetEditLockRequest,
+    responses((status = 200, body = EditLockResponse))
+)]
+pub async fn get_edit_lock(
+    State(state): State<AppState>,
+    Json(payload): Json<GetEditLockRequest>,
+) -> Result<(StatusCode, Json<EditLockResponse>), ApiError> {
+    authz::authorize(
+        &state,
+        &payload.email,
+        &payload.document_id,
+        Capability::View,
+    )
+    .await?;
+
+    let exclusive = exclusive_editing(&state, &payload.document_id).await;
+
+    let lock = lock_state(&state, &payload.document_id, exclusive).await?;
+
+    Ok((
+        StatusCode::OK,
+        Json(EditLockResponse {
+            success: true,
+            lock,
+        }),
+    ))
+}
+
+// Whether the document is in exclusive editing mode, off when it can not be
+// found
+
+pub async fn exclusive_editing(state: &AppState, document_id: &str) -> bool {
+    match state.documents.get(document_id).await {
+        Ok(Some(doc)) => doc.exclusive_editing,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::memory::MemoryStore;
+
+    #[tokio::test]
+    async fn sessions_of_one_user_do_not_share_the_lock() {
+        let state = MemoryStore::new().app_state();
+        let (first, second) = (lease_token(), lease_token());
+        assert_ne!(first, second);
+
+        assert!(
+            acquire(&state, "doc", &first, "a@example.com")
+                .await
+                .unwrap()
+        );
+        assert!(
+            !acquire(&state, "doc", &second, "a@example.com")
+                .await
+                .unwrap()
+        );
+        assert!(
+            !renew(&state, "doc", &second, "a@example.com")
+                .await
+                .unwrap()
+        );
+        assert!(holds(&state, "doc", &first, "a@example.com").await.unwrap());
+        assert!(
+            !holds(&state, "doc", &second, "a@example.com")
+                .await
+                .unwrap()
+        );
+
+        let lock = lock_state(&state, "doc", true).await.unwrap();
+        assert_eq!(lock.holder.as_deref(), Some("a@example.com"));
+        assert!(lock.expires_at.is_some_and(|at| at > Utc::now()));
+    }
+
+    #[tokio::test]
+    async fn a_lease_that_ran_out_can_be_taken() {
+        let state = MemoryStore::new().app_state();
+        let (first, second) = (lease_token(), lease_token());
+
+        // Left behind by a holder that stopped renewing
+        let value = lease_value(&first, "a@example.com");
+        assert!(state.live.acquire_lease("doc", &value, 1).await.unwrap());
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        let lock = lock_state(&state, "doc", true).await.unwrap();
+        assert_eq!(lock.holder, None);
+        assert!(
+            acquire(&state, "doc", &second, "b@example.com")
+                .await
+                .unwrap()
+        );
+        assert!(!renew(&state, "doc", &first, "a@example.com").await.unwrap());
+        assert!(
+            !release(&state, "doc", &first, "a@example.com")
+                .await
+                .unwrap()
+        );
+
+        let lock = lock_state(&state, "doc", true).await.unwrap();
+        assert_eq!(lock.holder.as_deref(), Some("b@example.com"));
+    }
+
+    #[tokio::test]
+    async fn a_broken_lock_is_lost_to_the_next_taker() {
+        let state = MemoryStore::new().app_state();
+        let (first, second) = (lease_token(), lease_token());
+        assert!(
+            acquire(&state, "doc", &first, "a@example.com")
+                .await
+                .unwrap()
+        );
+
+        break_lock(&state, "doc").await.unwrap();
+        assert!(
+            acquire(&state, "doc", &second, "b@example.com")
+                .await
+                .unwrap()
+        );
+
+        assert!(!renew(&state, "doc", &first, "a@example.com").await.unwrap());
+        assert!(!holds(&state, "doc", &first, "a@example.com").await.unwrap());
+        assert!(
+            renew(&state, "doc", &second, "b@example.com")
+                .await
+                .unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn only_the_holder_releases_the_lock() {
+        let state = MemoryStore::new().app_state();
+        let (first, second) = (lease_token(), lease_token());
+        assert!(
+            acquire(&state, "doc", &first, "a@example.com")
+                .await
+                .unwrap()
+        );
+
+        assert!(
+            !release(&state, "doc", &second, "b@example.com")
+                .await
+                .unwrap()
+        );
+        assert!(holds(&state, "doc", &first, "a@example.com").await.unwrap());
+
+        assert!(
+            release(&state, "doc", &first, "a@example.com")
+                .await
+                .unwrap()
+        );
+        assert!(
+            acquire(&state, "doc", &second, "b@example.com")
+                .await
+                .unwrap()
+        );
+    }
+}
@@ -0,0 +1,40 @@
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+// Writes the records of the `log` macros to the terminal, errors and warnings
+// to stderr and the rest to stdout. Records of the dependencies are only
+// written from the warning level on, whatever the configured level.
+
+struct Logger;
+
+static LOGGER: Logger = Logger;
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        if metadata.target().starts_with(env!("CARGO_CRATE_NAME")) {
+            metadata.level() <= log::max_level()
+        } else {
+            metadata.level() <= Level::Warn && metadata.level() <= log::max_level()
+        }
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        match record.level() {
+            Level::Error | Level::Warn => eprintln!("[{}] {}", record.level(), record.args()),
+            _ => println!("[{}] {}", record.level(), record.args()),
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+// Installs the logger, only the first call has an effect
+
+pub fn init(level: LevelFilter) {
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(level);
+    }
+}
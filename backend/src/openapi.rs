@@ -0,0 +1,215 @@
+use axum::Json;
+use utoipa::openapi::path::{Operation, PathItem};
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, SecurityRequirement, SecurityScheme};
+use utoipa::openapi::{
+    ContentBuilder, Deprecated, OpenApi as OpenApiDocument, Ref, ResponseBuilder,
+};
+use utoipa::{Modify, OpenApi};
+
+use crate::errors::ErrorResponse;
+use crate::structs::{WsClientMessage, WsServerMessage};
+use crate::{
+    audit, comments, folders, library, locks, publishing, share_links, suggestions, templates, v1,
+    ws_handler,
+};
+
+// OpenAPI document of the REST API. The WebSocket messages are not part of any
+// path, they are listed as schemas for client generators.
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "PDF United API"),
+    paths(
+        crate::login_user,
+        crate::save_document,
+        crate::save_document_and_relations,
+        ws_handler::ws_handler,
+        crate::get_all_documents_owner,
+        crate::get_all_documents_shared,
+        crate::create_groups,
+        crate::get_groups_by_owner,
+        crate::get_user_role,
+        crate::share_document,
+        crate::unshare_document,
+        crate::share_document_group,
+        crate::unshare_document_group,
+        crate::delete_document,
+        crate::transfer_ownership,
+        audit::get_audit_log,
+        share_links::create_share_link,
+        share_links::get_share_links,
+        share_links::revoke_share_link,
+        folders::create_folder,
+        folders::update_folder,
+        folders::move_folder,
+        folders::delete_folder,
+        folders::move_document,
+        folders::get_folder_contents,
+        folders::share_folder,
+        folders::unshare_folder,
+        library::add_tag,
+        library::remove_tag,
+        library::get_tags,
+        library::set_favorite,
+        library::get_favorites,
+        library::get_recent_documents,
+        templates::set_template,
+        templates::get_templates,
+        templates::create_from_template,
+        templates::duplicate_document,
+        locks::set_exclusive_editing,
+        locks::get_edit_lock,
+        publishing::publish_document,
+        publishing::unpublish_document,
+        publishing::get_public_document,
+        publishing::public_ws_handler,
+        comments::get_comments,
+        comments::create_comment,
+        comments::reply_comment,
+        comments::resolve_comment,
+        comments::reopen_comment,
+        suggestions::get_suggestions,
+        suggestions::accept_suggestion,
+        suggestions::reject_suggestion,
+        v1::documents::list_owned,
+        v1::documents::list_shared,
+        v1::documents::create,
+        v1::documents::get,
+        v1::documents::update,
+        v1::documents::delete,
+        v1::documents::role,
+        v1::documents::copy,
+        v1::documents::lock,
+        v1::documents::set_lock,
+        v1::documents::set_folder,
+        v1::documents::audit_log,
+        v1::sharing::share,
+        v1::sharing::unshare,
+        v1::sharing::share_group,
+        v1::sharing::unshare_group,
+        v1::sharing::list_links,
+        v1::sharing::create_link,
+        v1::sharing::revoke_link,
+        v1::sharing::publish,
+        v1::sharing::unpublish,
+        v1::sharing::set_template,
+        v1::sharing::unset_template,
+        v1::library::list_tags,
+        v1::library::add_tag,
+        v1::library::remove_tag,
+        v1::library::add_favorite,
+        v1::library::remove_favorite,
+        v1::library::list_favorites,
+        v1::library::list_recent,
+        v1::library::list_templates,
+        v1::library::create_from_template,
+        v1::review::list_comments,
+        v1::review::create_comment,
+        v1::review::reply_comment,
+        v1::review::update_comment,
+        v1::review::list_suggestions,
+        v1::review::review_suggestion,
+        v1::folders::list_top_level,
+        v1::folders::contents,
+        v1::folders::create,
+        v1::folders::update,
+        v1::folders::set_parent,
+        v1::folders::delete,
+        v1::folders::share,
+        v1::folders::unshare,
+        v1::users::create_session,
+        v1::users::list_groups,
+        v1::users::create_group,
+        v1::users::list_members,
+        v1::users::add_member,
+        v1::users::remove_member,
+    ),
+    components(schemas(ErrorResponse, WsClientMessage, WsServerMessage)),
+    modifiers(&ErrorResponses, &UserHeader, &LegacyRoutes)
+)]
+pub struct ApiDoc;
+
+// Every operation can fail with an error carrying a stable code
+struct ErrorResponses;
+
+impl Modify for ErrorResponses {
+    fn modify(&self, openapi: &mut OpenApiDocument) {
+        let response = ResponseBuilder::new()
+            .description("Error with a stable code")
+            .content(
+                "application/json",
+                ContentBuilder::new()
+                    .schema(Some(Ref::from_schema_name("ErrorResponse")))
+                    .build(),
+            )
+            .build();
+
+        for item in openapi.paths.paths.values_mut() {
+            for operation in operations(item) {
+                operation
+                    .responses
+                    .responses
+                    .entry("default".to_string())
+                    .or_insert_with(|| response.clone().into());
+            }
+        }
+    }
+}
+
+// The /api/v1 routes name the user in the X-User-Email header, except the one
+// creating a session
+struct UserHeader;
+
+impl Modify for UserHeader {
+    fn modify(&self, openapi: &mut OpenApiDocument) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "user_email",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new("X-User-Email"))),
+        );
+
+        for (path, item) in openapi.paths.paths.iter_mut() {
+            if !path.starts_with("/api/v1/") || path == "/api/v1/sessions" {
+                continue;
+            }
+            for operation in operations(item) {
+                operation.security = Some(vec![SecurityRequirement::new(
+                    "user_email",
+                    Vec::<String>::new(),
+                )]);
+            }
+        }
+    }
+}
+
+// Routes from before /api/v1 are kept as deprecated aliases. The WebSocket
+// and public routes have no replacement and stay current.
+struct LegacyRoutes;
+
+impl Modify for LegacyRoutes {
+    fn modify(&self, openapi: &mut OpenApiDocument) {
+        for (path, item) in openapi.paths.paths.iter_mut() {
+            if path.starts_with("/api/v1/") || path == "/ws" || path.starts_with("/public/") {
+                continue;
+            }
+            for operation in operations(item) {
+                operation.deprecated = Some(Deprecated::True);
+            }
+        }
+    }
+}
+
+fn operations(item: &mut PathItem) -> impl Iterator<Item = &mut Operation> {
+    [
+        &mut item.get,
+        &mut item.post,
+        &mut item.put,
+        &mut item.patch,
+        &mut item.delete,
+    ]
+    .into_iter()
+    .flatten()
+}
+
+pub async fn openapi_json() -> Json<OpenApiDocument> {
+    Json(ApiDoc::openapi())
+}
@@ -0,0 +1,229 @@
+use axum::extract::ws::WebSocketUpgrade;
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use chrono::Utc;
+use log::error;
+use mongodb::bson::oid::ObjectId;
+use rand::Rng;
+use rand::distributions::Alphanumeric;
+use serde_json::json;
+
+use crate::audit::{self, AuditEvent};
+use crate::authz::{self, Access, Capability, GrantSource, Role};
+use crate::errors::{ApiError, ErrorResponse, Json};
+use crate::structs::{
+    AppState, MessageResponse, PublicDocumentResponse, Publication, PublicationResponse,
+    PublishDocumentRequest, UnpublishDocumentRequest, WsParams,
+};
+use crate::ws_handler::{current_content, handle_socket};
+
+const PUBLIC_ID_LENGTH: usize = 16;
+
+fn generate_public_id() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(PUBLIC_ID_LENGTH)
+        .map(char::from)
+        .collect()
+}
+
+fn not_found() -> ApiError {
+    ApiError::not_found("Document not found")
+}
+
+// ***************************************************************************************************************************************
+// Owner endpoints
+
+#[utoipa::path(
+    post,
+    path = "/publish_document",
+    tag = "publishing",
+    request_body = PublishDocumentRequest,
+    responses((status = 200, body = PublicationResponse))
+)]
+pub async fn publish_document(
+    State(state): State<AppState>,
+    Json(payload): Json<PublishDocumentRequest>,
+) -> Result<(StatusCode, Json<PublicationResponse>), ApiError> {
+    authz::authorize(
+        &state,
+        &payload.email,
+        &payload.document_id,
+        Capability::Share,
+    )
+    .await?;
+
+    ObjectId::parse_str(&payload.document_id).map_err(|e| ApiError::bad_request(e.to_string()))?;
+
+    let document = state
+        .documents
+        .get(&payload.document_id)
+        .await?
+        .ok_or_else(not_found)?;
+
+    // Publishing again keeps the URL and only updates the live setting
+    let public_id = document
+        .publication
+        .map(|p| p.public_id)
+        .unwrap_or_else(generate_public_id);
+
+    let publication = Publication {
+        public_id,
+        published_by: payload.email.clone(),
+        published_at: Utc::now(),
+        live: payload.live,
+    };
+    state
+        .documents
+        .set_publication(&payload.document_id, Some(publication.clone()))
+        .await?;
+
+    audit::record(
+        &state,
+        AuditEvent::Published,
+        Some(&payload.email),
+        Some(&payload.document_id),
+        json!({ "public_id": publication.public_id, "live": publication.live }),
+    )
+    .await;
+
+    Ok((
+        StatusCode::OK,
+        Json(PublicationResponse {
+            success: true,
+            publication,
+        }),
+    ))
+}
+
+#[utoipa::path(
+    post,
+    path = "/unpublish_document",
+    tag = "publishing",
+    request_body = UnpublishDocumentRequest,
+    responses((status = 200, body = MessageResponse))
+)]
+pub async fn unpublish_document(
+    State(state): State<AppState>,
+    Json(payload): Json<UnpublishDocumentRequest>,
+) -> Result<(StatusCode, Json<MessageResponse>), ApiError> {
+    authz::authorize(
+        &state,
+        &payload.email,
+        &payload.document_id,
+        Capability::Share,
+    )
+    .await?;
+
+    ObjectId::parse_str(&payload.document_id).map_err(|e| ApiError::bad_request(e.to_string()))?;
+
+    state
+        .documents
+        .set_publication(&payload.document_id, None)
+        .await?;
+
+    // Anonymous live viewers lose access with the publication
+    for session in state.ws_sessions.lock().await.values() {
+        if session.document_id == payload.document_id
+            && session.access.source == GrantSource::Public
+        {
+            session.kick.notify_one();
+        }
+    }
+
+    audit::record(
+        &state,
+        AuditEvent::Unpublished,
+        Some(&payload.email),
+        Some(&payload.document_id),
+        json!({}),
+    )
+    .await;
+
+    Ok((StatusCode::OK, Json(MessageResponse::new("Unpublished"))))
+}
+
+// ***************************************************************************************************************************************
+// Public endpoints, no account needed
+
+#[utoipa::path(
+    get,
+    path = "/public/{public_id}",
+    tag = "publishing",
+    params(
+        ("public_id" = String, Path, description = "Public id of a published document"),
+    ),
+    responses((status = 200, body = PublicDocumentResponse))
+)]
+pub async fn get_public_document(
+    State(state): State<AppState>,
+    Path(public_id): Path<String>,
+) -> Result<(StatusCode, Json<PublicDocumentResponse>), ApiError> {
+    let document = state
+        .documents
+        .find_published(&public_id)
+        .await?
+        .ok_or_else(not_found)?;
+
+    let document_id = document.id.map(|id| id.to_hex()).unwrap_or_default();
+
+    // Serve the live content when the document is being edited
+    let content = current_content(&state, &document_id)
+        .await
+        .ok()
+        .flatten()
+        .unwrap_or(document.content);
+
+    Ok((
+        StatusCode::OK,
+        Json(PublicDocumentResponse {
+            success: true,
+            title: document.title,
+            format: document.format,
+            content,
+            live: document.publication.is_some_and(|p| p.live),
+        }),
+    ))
+}
+
+// Read-only live view of a published document, the server only sends the
+// content updates of the document
+#[utoipa::path(
+    get,
+    path = "/public/{public_id}/ws",
+    tag = "publishing",
+    params(("public_id" = String, Path, description = "Public id of a published document")),
+    responses(
+        (status = 101, description = "Switches to the document WebSocket"),
+        (status = 404, description = "The document is not published live", body = ErrorResponse),
+    )
+)]
+pub async fn public_ws_handler(
+    ws: WebSocketUpgrade,
+    Path(public_id): Path<String>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    let document = match state.documents.find_published(&public_id).await {
+        Ok(Some(document)) if document.publication.as_ref().is_some_and(|p| p.live) => document,
+        Ok(_) => return not_found().into_response(),
+        Err(e) => {
+            error!("Failed to look up public document {}: {}", public_id, e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let params = WsParams {
+        user_email: "anonymous".to_string(),
+        document_id: document.id.map(|id| id.to_hex()).unwrap_or_default(),
+        share_token: None,
+        last_seq: None,
+    };
+    let access = Access {
+        role: Role::Reader,
+        source: GrantSource::Public,
+        valid_until: None,
+    };
+
+    ws.on_upgrade(move |socket| handle_socket(socket, params, state, access))
+}
@@ -0,0 +1,205 @@
+use std::error::Error;
+use std::str::FromStr;
+
+use log::{error, info};
+
+use crate::store::{RecoveredVersion, StoreResult};
+use crate::structs::AppState;
+use crate::ws_handler;
+
+// What happens to content a crashed server left in Redis
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecoveryMode {
+    // Written to the document, as the periodic flush would have done
+    Flush,
+    // Kept aside in the recovered_versions collection, the document stays at
+    // its last flushed content
+    Version,
+}
+
+impl FromStr for RecoveryMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "flush" => Ok(RecoveryMode::Flush),
+            "version" => Ok(RecoveryMode::Version),
+            _ => Err(format!(
+                "Unknown recovery mode '{}', expected flush or version",
+                s
+            )),
+        }
+    }
+}
+
+// Runs before the server accepts connections. A previous process that
+// crashed left the content of its open documents in Redis, unflushed since
+// the last periodic flush, while the connection counts it kept are gone. Every
+// leftover document is persisted and its live keys removed, so the first
+// client to connect loads it from MongoDB and starts with a fresh count.
+// Every live document and edit lease in Redis is taken for a leftover, so this
+// must only run when no other instance shares Redis (the single_instance
+// setting). Returns the number of documents recovered.
+
+pub async fn recover(state: &AppState, mode: RecoveryMode) -> Result<usize, Box<dyn Error>> {
+    // A lease left by a crashed session would keep the document locked until
+    // it runs out
+    state.live.clear_leases().await?;
+
+    let mut recovered = 0;
+
+    for document_id in state.live.open_documents().await? {
+        let result = match mode {
+            RecoveryMode::Flush => ws_handler::flush_document(state, &document_id).await,
+            RecoveryMode::Version => keep_version(state, &document_id).await,
+        };
+
+        // A document that could not be persisted keeps its live content, the
+        // periodic flush tries again
+        if let Err(e) = result {
+            error!("Failed to recover doc {}: {}", document_id, e);
+            continue;
+        }
+
+        state.live.close(&document_id).await?;
+        recovered += 1;
+        info!("Recovered unflushed content of doc {}", document_id);
+    }
+
+    Ok(recovered)
+}
+
+async fn keep_version(state: &AppState, document_id: &str) -> Result<(), Box<dyn Error>> {
+    let (content, seq) = state.pubsub.snapshot(document_id).await?;
+    let content = content.ok_or_else(|| format!("doc:{} is gone", document_id))?;
+
+    if state.documents.get(document_id).await?.is_none() {
+        return Err("the document no longer exists".into());
+    }
+
+    let last_edited_by = state
+        .live
+        .last_edit(document_id)
+        .await?
+        .map(|(editor, _)| editor);
+    store_version(state, document_id, &content, seq, last_edited_by, "crash").await?;
+
+    // The document did not take the updates of the op log, clients get a
+    // snapshot when they reconnect
+    state.pubsub.reset(document_id).await?;
+    Ok(())
+}
+
+// Keeps content that did not make it into a document aside, along with why
+
+pub async fn store_version(
+    state: &AppState,
+    document_id: &str,
+    content: &str,
+    seq: u64,
+    last_edited_by: Option<String>,
+    reason: &str,
+) -> StoreResult<()> {
+    state
+        .documents
+        .store_version(RecoveredVersion {
+            document_id,
+            content,
+            seq,
+            last_edited_by,
+            reason,
+        })
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::memory::MemoryStore;
+    use crate::store::{DocumentStore, LiveDocCache};
+    use crate::structs::Document;
+
+    // A document a crashed run left open, with an edit it did not flush and
+    // the edit lease of one of its sessions
+    async fn left_open(store: &MemoryStore) -> String {
+        let document = Document::new("Notes".to_string(), "txt".to_string(), "Hello".to_string());
+        let document_id = store.insert(document).await.unwrap();
+        store.open(&document_id, "Hello", 0).await.unwrap();
+
+        let state = store.app_state();
+        ws_handler::replace_content(
+            &state,
+            &document_id,
+            "Hello",
+            "Hello world",
+            "a@example.com",
+        )
+        .await
+        .unwrap();
+        assert!(
+            state
+                .live
+                .acquire_lease(&document_id, "session:a@example.com", 30_000)
+                .await
+                .unwrap()
+        );
+        document_id
+    }
+
+    async fn stored_content(store: &MemoryStore, document_id: &str) -> String {
+        store.get(document_id).await.unwrap().unwrap().content
+    }
+
+    #[tokio::test]
+    async fn flushing_writes_the_leftover_content() {
+        let store = MemoryStore::new();
+        let state = store.app_state();
+        let document_id = left_open(&store).await;
+
+        let recovered = recover(&state, RecoveryMode::Flush).await.unwrap();
+
+        assert_eq!(recovered, 1);
+        assert_eq!(stored_content(&store, &document_id).await, "Hello world");
+        assert!(store.recovered_versions(&document_id).is_empty());
+        assert!(state.live.open_documents().await.unwrap().is_empty());
+        assert!(state.live.lease(&document_id).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn versions_keep_the_leftover_content_aside() {
+        let store = MemoryStore::new();
+        let state = store.app_state();
+        let document_id = left_open(&store).await;
+
+        let recovered = recover(&state, RecoveryMode::Version).await.unwrap();
+
+        assert_eq!(recovered, 1);
+        assert_eq!(stored_content(&store, &document_id).await, "Hello");
+        assert_eq!(store.recovered_versions(&document_id), vec!["Hello world"]);
+        assert!(state.live.open_documents().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn documents_that_can_not_be_kept_stay_live() {
+        let store = MemoryStore::new();
+        let state = store.app_state();
+        let document_id = left_open(&store).await;
+        store.delete(&document_id).await.unwrap();
+
+        let recovered = recover(&state, RecoveryMode::Version).await.unwrap();
+
+        assert_eq!(recovered, 0);
+        assert_eq!(
+            state.live.open_documents().await.unwrap(),
+            vec![document_id.clone()]
+        );
+        assert!(state.live.lease(&document_id).await.unwrap().is_none());
+    }
+
+    #[test]
+    fn modes_are_named_in_the_settings() {
+        assert_eq!("flush".parse(), Ok(RecoveryMode::Flush));
+        assert_eq!("version".parse(), Ok(RecoveryMode::Version));
+        assert!("keep".parse::<RecoveryMode>().is_err());
+    }
+}
@@ -0,0 +1,247 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use chrono::{DateTime, Utc};
+use log::{error, info};
+use tokio::sync::{Mutex, Notify};
+use tokio::time;
+
+use crate::authz::{Access, user_has_access};
+use crate::structs::AppState;
+
+static NEXT_SESSION_ID: AtomicU64 = AtomicU64::new(1);
+
+// An open document WebSocket and the grant it was opened with
+pub struct WsSession {
+    pub user_email: String,
+    pub document_id: String,
+    pub share_token: Option<String>,
+    pub access: Access,
+    pub kick: Arc<Notify>,
+    // Whether the document is in exclusive editing mode, updated by the owner
+    pub exclusive: Arc<AtomicBool>,
+}
+
+pub type WsSessions = Arc<Mutex<HashMap<u64, WsSession>>>;
+
+// Registers a session and returns its id and the signal that closes it
+
+pub async fn register(
+    state: &AppState,
+    user_email: &str,
+    document_id: &str,
+    share_token: Option<String>,
+    access: Access,
+    exclusive: Arc<AtomicBool>,
+) -> (u64, Arc<Notify>) {
+    let id = NEXT_SESSION_ID.fetch_add(1, Ordering::Relaxed);
+    let kick = Arc::new(Notify::new());
+
+    state.ws_sessions.lock().await.insert(
+        id,
+        WsSession {
+            user_email: user_email.to_string(),
+            document_id: document_id.to_string(),
+            share_token,
+            access,
+            kick: kick.clone(),
+            exclusive,
+        },
+    );
+    (id, kick)
+}
+
+pub async fn unregister(state: &AppState, id: u64) {
+    state.ws_sessions.lock().await.remove(&id);
+}
+
+pub async fn start_grant_expiry_check(state: AppState) {
+    tokio::spawn(async move {
+        loop {
+            time::sleep(time::Duration::from_secs(15)).await;
+            expire_sessions(&state, Utc::now()).await;
+        }
+    });
+}
+
+// Closes sessions whose grant has run out. Their access is resolved again
+// first, the user may hold another grant that is still valid. A session is
+// also closed when the remaining grant has a different role, the client
+// reconnects to pick it up.
+
+async fn expire_sessions(state: &AppState, now: DateTime<Utc>) {
+    let expired: Vec<(u64, String, String, Option<String>, Access)> = state
+        .ws_sessions
+        .lock()
+        .await
+        .iter()
+        .filter(|(_, s)| s.access.valid_until.is_some_and(|until| until <= now))
+        .map(|(id, s)| {
+            (
+                *id,
+                s.user_email.clone(),
+                s.document_id.clone(),
+                s.share_token.clone(),
+                s.access,
+            )
+        })
+        .collect();
+
+    for (id, email, document_id, share_token, access) in expired {
+        let renewed =
+            match user_has_access(&email, &document_id, share_token.as_deref(), state).await {
+                Ok(renewed) => renewed,
+                Err(e) => {
+                    error!("Failed to resolve access of session {}: {}", id, e);
+                    continue;
+                }
+            };
+
+        let mut sessions = state.ws_sessions.lock().await;
+        let Some(session) = sessions.get_mut(&id) else {
+            continue;
+        };
+
+        match renewed {
+            Some(renewed) if renewed.role == access.role => session.access = renewed,
+            _ => {
+                info!(
+                    "Grant of user {} on doc {} expired, closing session",
+                    email, document_id
+                );
+                session.kick.notify_one();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    use crate::authz::{GrantSource, Role};
+    use crate::store::memory::MemoryStore;
+    use crate::store::{AccessStore, DocumentStore, NewShareLink};
+    use crate::structs::Document;
+
+    const OWNER: &str = "owner@example.com";
+
+    async fn shared_document(store: &MemoryStore) -> String {
+        store.add_user(OWNER, "password", "Test", "Owner");
+        let document = Document::new("Notes".to_string(), "txt".to_string(), String::new());
+        let document_id = store.insert(document).await.unwrap();
+        store
+            .add_relation(&document_id, OWNER, Role::Owner)
+            .await
+            .unwrap();
+        document_id
+    }
+
+    // Opens a session with a grant that ran out a minute ago
+    async fn expired_session(
+        state: &AppState,
+        email: &str,
+        document_id: &str,
+        share_token: Option<&str>,
+    ) -> (u64, Arc<Notify>) {
+        let access = Access {
+            role: Role::Reader,
+            source: GrantSource::ShareLink { link_id: 1 },
+            valid_until: Some(Utc::now() - Duration::minutes(1)),
+        };
+        register(
+            state,
+            email,
+            document_id,
+            share_token.map(str::to_string),
+            access,
+            Arc::new(AtomicBool::new(false)),
+        )
+        .await
+    }
+
+    async fn kicked(kick: &Notify) -> bool {
+        time::timeout(time::Duration::from_millis(10), kick.notified())
+            .await
+            .is_ok()
+    }
+
+    #[tokio::test]
+    async fn sessions_close_when_their_grant_runs_out() {
+        let store = MemoryStore::new();
+        let state = store.app_state();
+        let document_id = shared_document(&store).await;
+        let link = store
+            .create_share_link(NewShareLink {
+                token: "token",
+                document_id: &document_id,
+                role: Role::Reader,
+                created_by: OWNER,
+                expires_at: Some(Utc::now() - Duration::minutes(1)),
+                max_uses: None,
+            })
+            .await
+            .unwrap();
+
+        let (_, kick) =
+            expired_session(&state, "a@example.com", &document_id, Some(&link.token)).await;
+        expire_sessions(&state, Utc::now()).await;
+
+        assert!(kicked(&kick).await);
+    }
+
+    #[tokio::test]
+    async fn sessions_stay_open_on_another_grant_of_the_same_role() {
+        let store = MemoryStore::new();
+        let state = store.app_state();
+        let document_id = shared_document(&store).await;
+        store.add_user("a@example.com", "password", "Test", "Reader");
+        store
+            .add_relation(&document_id, "a@example.com", Role::Reader)
+            .await
+            .unwrap();
+
+        let (id, kick) = expired_session(&state, "a@example.com", &document_id, None).await;
+        expire_sessions(&state, Utc::now()).await;
+
+        assert!(!kicked(&kick).await);
+        let sessions = state.ws_sessions.lock().await;
+        assert_eq!(sessions[&id].access.source, GrantSource::Direct);
+        assert_eq!(sessions[&id].access.valid_until, None);
+    }
+
+    #[tokio::test]
+    async fn sessions_close_when_the_remaining_grant_has_another_role() {
+        let store = MemoryStore::new();
+        let state = store.app_state();
+        let document_id = shared_document(&store).await;
+        store.add_user("a@example.com", "password", "Test", "Editor");
+        store
+            .add_relation(&document_id, "a@example.com", Role::Editor)
+            .await
+            .unwrap();
+
+        let (_, kick) = expired_session(&state, "a@example.com", &document_id, None).await;
+        let (id, later) = register(
+            &state,
+            "b@example.com",
+            &document_id,
+            None,
+            Access {
+                role: Role::Reader,
+                source: GrantSource::ShareLink { link_id: 1 },
+                valid_until: Some(Utc::now() + Duration::hours(1)),
+            },
+            Arc::new(AtomicBool::new(false)),
+        )
+        .await;
+        expire_sessions(&state, Utc::now()).await;
+
+        assert!(kicked(&kick).await);
+        // Sessions whose grant is still valid are left alone
+        assert!(!kicked(&later).await);
+        assert!(state.ws_sessions.lock().await.contains_key(&id));
+    }
+}
@@ -0,0 +1,335 @@
+use std::convert::Infallible;
+
+use axum::async_trait;
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
+use axum::{extract::State, http::StatusCode};
+use rand::Rng;
+use rand::distributions::Alphanumeric;
+use serde_json::json;
+
+use crate::audit::{self, AuditEvent};
+use crate::authz::{self, Capability, Role};
+use crate::errors::{ApiError, Json};
+use crate::store::NewShareLink;
+use crate::structs::{
+    AppState, CreateShareLinkRequest, GetShareLinksRequest, MessageResponse,
+    RevokeShareLinkRequest, ShareLinkResponse, ShareLinksResponse,
+};
+
+const TOKEN_LENGTH: usize = 32;
+
+// Share link token presented on REST calls through the `X-Share-Token` header
+pub struct ShareToken(pub Option<String>);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for ShareToken {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let token = parts
+            .headers
+            .get("x-share-token")
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        Ok(ShareToken(token))
+    }
+}
+
+fn generate_token() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(TOKEN_LENGTH)
+        .map(char::from)
+        .collect()
+}
+
+// ***************************************************************************************************************************************
+// REST endpoints for the owner of a document
+
+#[utoipa::path(
+    post,
+    path = "/create_share_link",
+    tag = "share links",
+    request_body = CreateShareLinkRequest,
+    responses((status = 201, body = ShareLinkResponse))
+)]
+pub async fn create_share_link(
+    State(state): State<AppState>,
+    Json(payload): Json<CreateShareLinkRequest>,
+) -> Result<(StatusCode, Json<ShareLinkResponse>), ApiError> {
+    authz::authorize(
+        &state,
+        &payload.email,
+        &payload.document_id,
+        Capability::Share,
+    )
+    .await?;
+
+    let role = match payload.role.parse::<Role>() {
+        Ok(role @ (Role::Editor | Role::Reader)) => role,
+        _ => {
+            return Err(ApiError::bad_request("Role must be editor or reader"));
+        }
+    };
+
+    if payload.max_uses.is_some_and(|max| max < 1) {
+        return Err(ApiError::bad_request("Maximum uses must be at least 1"));
+    }
+
+    let link = state
+        .access
+        .create_share_link(NewShareLink {
+            token: &generate_token(),
+            document_id: &payload.document_id,
+            role,
+            created_by: &payload.email,
+            expires_at: payload.expires_at,
+            max_uses: payload.max_uses,
+        })
+        .await?;
+
+    audit::record(
+        &state,
+        AuditEvent::ShareChanged,
+        Some(&payload.email),
+        Some(&payload.document_id),
+        json!({
+            "link_id": link.link_id,
+            "role": role.as_str(),
+            "expires_at": link.expires_at,
+            "max_uses": link.max_uses,
+        }),
+    )
+    .await;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(ShareLinkResponse {
+            success: true,
+            link,
+        }),
+    ))
+}
+
+#[utoipa::path(
+    post,
+    path = "/get_share_links",
+    tag = "share links",
+    request_body = GetShareLinksRequest,
+    responses((status = 200, body = ShareLinksResponse))
+)]
+pub async fn get_share_links(
+    State(state): State<AppState>,
+    Json(payload): Json<GetShareLinksRequest>,
+) -> Result<(StatusCode, Json<ShareLinksResponse>), ApiError> {
+    authz::authorize(
+        &state,
+        &payload.email,
+        &payload.document_id,
+        Capability::Share,
+    )
+    .await?;
+
+    let links = state.access.share_links(&payload.document_id).await?;
+
+    Ok((
+        StatusCode::OK,
+        Json(ShareLinksResponse {
+            success: true,
+            links,
+        }),
+    ))
+}
+
+#[utoipa::path(
+    post,
+    path = "/revoke_share_link",
+    tag = "share links",
+    request_body = RevokeShareLinkRequest,
+    responses((status = 200, body = MessageResponse))
+)]
+pub async fn revoke_share_link(
+    State(state): State<AppState>,
+    Json(payload): Json<RevokeShareLinkRequest>,
+) -> Result<(StatusCode, Json<MessageResponse>), ApiError> {
+    authz::authorize(
+        &state,
+        &payload.email,
+        &payload.document_id,
+        Capability::Share,
+    )
+    .await?;
+
+    let revoked = state
+        .access
+        .revoke_share_link(&payload.document_id, payload.link_id)
+        .await?;
+
+    if !revoked {
+        return Err(ApiError::not_found("Share link not found"));
+    }
+
+    audit::record(
+        &state,
+        AuditEvent::ShareRemoved,
+        Some(&payload.email),
+        Some(&payload.document_id),
+        json!({ "link_id": payload.link_id }),
+    )
+    .await;
+
+    Ok((StatusCode::OK, Json(MessageResponse::new("Revoked"))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{DateTime, Duration, Utc};
+
+    use crate::store::memory::MemoryStore;
+    use crate::store::{AccessStore, DocumentStore};
+    use crate::structs::{Document, ShareLinkRow};
+
+    const OWNER: &str = "owner@example.com";
+
+    async fn shared_document() -> (AppState, String) {
+        let store = MemoryStore::new();
+        store.add_user(OWNER, "password", "Test", "Owner");
+        let document = Document::new("Notes".to_string(), "txt".to_string(), String::new());
+        let document_id = store.insert(document).await.unwrap();
+        store
+            .add_relation(&document_id, OWNER, Role::Owner)
+            .await
+            .unwrap();
+        (store.app_state(), document_id)
+    }
+
+    async fn create(
+        state: &AppState,
+        document_id: &str,
+        role: &str,
+        expires_at: Option<DateTime<Utc>>,
+        max_uses: Option<i32>,
+    ) -> Result<ShareLinkRow, ApiError> {
+        let request = CreateShareLinkRequest {
+            email: OWNER.to_string(),
+            document_id: document_id.to_string(),
+            role: role.to_string(),
+            expires_at,
+            max_uses,
+        };
+        let (_, Json(response)) = create_share_link(State(state.clone()), Json(request)).await?;
+        Ok(response.link)
+    }
+
+    async fn view(state: &AppState, document_id: &str, email: &str, link: &ShareLinkRow) -> bool {
+        authz::authorize_with_token(
+            state,
+            email,
+            document_id,
+            Some(&link.token),
+            Capability::View,
+        )
+        .await
+        .is_ok()
+    }
+
+    #[tokio::test]
+    async fn links_grant_their_role_until_they_expire() {
+        let (state, document_id) = shared_document().await;
+        let expires_at = Utc::now() + Duration::hours(1);
+        let link = create(&state, &document_id, "reader", Some(expires_at), None)
+            .await
+            .unwrap();
+        let expired = create(
+            &state,
+            &document_id,
+            "editor",
+            Some(Utc::now() - Duration::minutes(1)),
+            None,
+        )
+        .await
+        .unwrap();
+        assert_ne!(link.token, expired.token);
+
+        let access = authz::authorize_with_token(
+            &state,
+            "a@example.com",
+            &document_id,
+            Some(&link.token),
+            Capability::View,
+        )
+        .await
+        .unwrap();
+        assert_eq!(access.role, Role::Reader);
+        assert_eq!(access.valid_until, Some(expires_at));
+
+        let edit = authz::authorize_with_token(
+            &state,
+            "a@example.com",
+            &document_id,
+            Some(&link.token),
+            Capability::Edit,
+        )
+        .await;
+        assert!(edit.is_err());
+        assert!(!view(&state, &document_id, "b@example.com", &expired).await);
+    }
+
+    #[tokio::test]
+    async fn links_stop_at_their_maximum_uses() {
+        let (state, document_id) = shared_document().await;
+        let link = create(&state, &document_id, "reader", None, Some(2))
+            .await
+            .unwrap();
+
+        assert!(view(&state, &document_id, "a@example.com", &link).await);
+        assert!(view(&state, &document_id, "b@example.com", &link).await);
+        // Users that redeemed the link keep it, nobody else gets it
+        assert!(view(&state, &document_id, "a@example.com", &link).await);
+        assert!(!view(&state, &document_id, "c@example.com", &link).await);
+
+        let links = state.access.share_links(&document_id).await.unwrap();
+        assert_eq!(links[0].use_count, 2);
+    }
+
+    #[tokio::test]
+    async fn revoked_links_grant_nothing() {
+        let (state, document_id) = shared_document().await;
+        let link = create(&state, &document_id, "reader", None, None)
+            .await
+            .unwrap();
+        assert!(view(&state, &document_id, "a@example.com", &link).await);
+
+        let revoke = |link_id| {
+            revoke_share_link(
+                State(state.clone()),
+                Json(RevokeShareLinkRequest {
+                    email: OWNER.to_string(),
+                    document_id: document_id.clone(),
+                    link_id,
+                }),
+            )
+        };
+        revoke(link.link_id).await.unwrap();
+        let unknown = revoke(link.link_id + 1).await;
+        assert!(matches!(unknown, Err(ApiError::NotFound(_))));
+
+        assert!(!view(&state, &document_id, "a@example.com", &link).await);
+        assert!(!view(&state, &document_id, "b@example.com", &link).await);
+    }
+
+    #[tokio::test]
+    async fn links_are_created_for_readers_and_editors_only() {
+        let (state, document_id) = shared_document().await;
+
+        let owner = create(&state, &document_id, "owner", None, None).await;
+        let unusable = create(&state, &document_id, "reader", None, Some(0)).await;
+
+        assert!(matches!(owner, Err(ApiError::BadRequest(_))));
+        assert!(matches!(unusable, Err(ApiError::BadRequest(_))));
+        let links = state.access.share_links(&document_id).await.unwrap();
+        assert!(links.is_empty());
+    }
+}
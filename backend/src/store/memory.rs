@@ -0,0 +1,2071 @@
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+use mongodb::bson::oid::ObjectId;
+use serde::Serialize;
+use serde_json::Value;
+use tokio::sync::{mpsc, watch};
+
+use crate::authz::{Grant, GrantSource, Role};
+use crate::store::{
+    AccessStore, AuditLog, ContentWrite, DocumentStore, Lease, LibraryStore, LiveDocCache,
+    NewShareLink, PubSub, RecoveredVersion, StoreError, StoreResult, WriteCondition,
+};
+use crate::structs::{
+    AppState, AuditEntry, CommentReply, CommentThread, ContentStats, Document, FolderRow,
+    GetAuditLogRequest, GroupSummary, GroupsRequest, Publication, ShareLinkRow, Suggestion,
+    SuggestionStatus, Template, TextRange, UserRow, WsServerMessage,
+};
+use crate::updates::{self, Change, RETAINED_UPDATES, Resume};
+
+struct User {
+    password: String,
+    row: UserRow,
+}
+
+struct Relation {
+    email: String,
+    document_id: String,
+    role: Role,
+    valid_from: Option<DateTime<Utc>>,
+    valid_until: Option<DateTime<Utc>>,
+}
+
+struct GroupRelation {
+    group_id: i32,
+    document_id: String,
+    valid_from: Option<DateTime<Utc>>,
+    valid_until: Option<DateTime<Utc>>,
+}
+
+struct FolderRelation {
+    folder_id: i32,
+    email: String,
+    role: Role,
+}
+
+struct StoredVersion {
+    document_id: String,
+    content: String,
+}
+
+#[derive(Default)]
+struct Meta {
+    last_edit: Option<(String, DateTime<Utc>)>,
+    revision: Option<i64>,
+}
+
+#[derive(Default)]
+struct OpLog {
+    seq: u64,
+    entries: Vec<(u64, String)>,
+}
+
+// Every table of every backend, behind one lock so each call sees and leaves
+// a consistent state like a transaction would
+#[derive(Default)]
+struct Tables {
+    next_id: i32,
+    users: HashMap<String, User>,
+    relations: Vec<Relation>,
+    groups: Vec<GroupSummary>,
+    members: Vec<(i32, String)>,
+    group_relations: Vec<GroupRelation>,
+    share_links: Vec<(String, ShareLinkRow)>,
+    redemptions: HashSet<(i32, String)>,
+    folders: Vec<FolderRow>,
+    folder_relations: Vec<FolderRelation>,
+    filed: HashMap<String, i32>,
+    tags: Vec<(String, String, String)>,
+    favorites: Vec<(String, String, DateTime<Utc>)>,
+    recent: Vec<(String, String, DateTime<Utc>)>,
+    audit: Vec<AuditEntry>,
+    documents: HashMap<String, Document>,
+    comments: Vec<CommentThread>,
+    suggestions: Vec<Suggestion>,
+    versions: Vec<StoredVersion>,
+    live: HashMap<String, String>,
+    meta: HashMap<String, Meta>,
+    leases: HashMap<String, (String, Instant)>,
+    anchor_locks: HashMap<String, (String, Instant)>,
+    logs: HashMap<String, OpLog>,
+}
+
+impl Tables {
+    fn next_id(&mut self) -> i32 {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    fn folder(&self, folder_id: i32) -> Option<&FolderRow> {
+        self.folders.iter().find(|f| f.folder_id == folder_id)
+    }
+
+    // The folder followed by its ancestors, up to the top level
+    fn ancestors(&self, folder_id: i32) -> Vec<&FolderRow> {
+        let mut ancestors = Vec::new();
+        let mut next = self.folder(folder_id);
+        while let Some(folder) = next {
+            // Guards against cycles, which the handlers never create
+            if ancestors
+                .iter()
+                .any(|a: &&FolderRow| a.folder_id == folder.folder_id)
+            {
+                break;
+            }
+            ancestors.push(folder);
+            next = folder.parent_id.and_then(|parent| self.folder(parent));
+        }
+        ancestors
+    }
+
+    // The folder followed by all of its subfolders
+    fn subtree(&self, folder_id: i32) -> Vec<i32> {
+        let mut subtree = vec![folder_id];
+        let mut i = 0;
+        while i < subtree.len() {
+            let parent = subtree[i];
+            subtree.extend(
+                self.folders
+                    .iter()
+                    .filter(|f| f.parent_id == Some(parent) && !subtree.contains(&f.folder_id))
+                    .map(|f| f.folder_id)
+                    .collect::<Vec<_>>(),
+            );
+            i += 1;
+        }
+        subtree
+    }
+
+    // Mimics the foreign keys on users in PostgreSQL
+    fn require_user(&self, email: &str) -> StoreResult<()> {
+        if self.users.contains_key(email) {
+            Ok(())
+        } else {
+            Err(StoreError::missing_reference(format!(
+                "User {} does not exist",
+                email
+            )))
+        }
+    }
+
+    fn lease(&mut self, document_id: &str) -> Option<&(String, Instant)> {
+        if self
+            .leases
+            .get(document_id)
+            .is_some_and(|(_, expires)| *expires <= Instant::now())
+        {
+            self.leases.remove(document_id);
+        }
+        self.leases.get(document_id)
+    }
+}
+
+fn within(
+    valid_from: Option<DateTime<Utc>>,
+    valid_until: Option<DateTime<Utc>>,
+    now: DateTime<Utc>,
+) -> bool {
+    valid_from.is_none_or(|from| from <= now) && valid_until.is_none_or(|until| until > now)
+}
+
+fn parse_role(role: &str) -> StoreResult<Role> {
+    role.parse::<Role>().map_err(StoreError::new)
+}
+
+// Every store in memory, for running the backend without any database. Clones
+// share the same data.
+#[derive(Clone)]
+pub struct MemoryStore {
+    tables: Arc<Mutex<Tables>>,
+    // Bumped on every append to an op log, wakes up subscriptions
+    appended: Arc<watch::Sender<u64>>,
+}
+
+impl Default for MemoryStore {
+    fn default() -> Self {
+        MemoryStore::new()
+    }
+}
+
+impl MemoryStore {
+    pub fn new() -> MemoryStore {
+        let (appended, _) = watch::channel(0);
+        MemoryStore {
+            tables: Arc::new(Mutex::new(Tables::default())),
+            appended: Arc::new(appended),
+        }
+    }
+
+    // Application state with every store backed by this one
+    pub fn app_state(&self) -> AppState {
+        let store = Arc::new(self.clone());
+        AppState {
+            documents: store.clone(),
+            access: store.clone(),
+            library: store.clone(),
+            audit: store.clone(),
+            live: store.clone(),
+            pubsub: store,
+            ws_connections: Default::default(),
+            ws_sessions: Default::default(),
+        }
+    }
+
+    pub fn add_user(&self, email: &str, password: &str, first_name: &str, last_name: &str) {
+        let user = User {
+            password: password.to_string(),
+            row: UserRow {
+                email: email.to_string(),
+                first_name: first_name.to_string(),
+                last_name: last_name.to_string(),
+            },
+        };
+        self.tables().users.insert(email.to_string(), user);
+    }
+
+    // Content kept aside for a document, oldest first
+    pub fn recovered_versions(&self, document_id: &str) -> Vec<String> {
+        self.tables()
+            .versions
+            .iter()
+            .filter(|v| v.document_id == document_id)
+            .map(|v| v.content.clone())
+            .collect()
+    }
+
+    fn tables(&self) -> MutexGuard<'_, Tables> {
+        self.tables.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // Numbers the update and appends it to the log of the document. A change
+    // of the content is only appended, with the live content swapped along,
+    // while the live content is still `previous`, else 0 is returned.
+    fn append(
+        &self,
+        document_id: &str,
+        message: &impl Serialize,
+        swap: Option<(&str, String)>,
+    ) -> u64 {
+        let mut tables = self.tables();
+        if let Some((previous, content)) = swap {
+            let live = tables.live.get(document_id).map_or("", String::as_str);
+            if live != previous {
+                return 0;
+            }
+            tables.live.insert(document_id.to_string(), content);
+        }
+        let log = tables.logs.entry(document_id.to_string()).or_default();
+        log.seq += 1;
+        let seq = log.seq;
+        log.entries.push((seq, updates::with_seq(message, seq)));
+        drop(tables);
+
+        self.appended.send_modify(|count| *count += 1);
+        seq
+    }
+
+    // The updates of the log after `after_seq`, with the sequence number to
+    // continue from
+    fn read_after(&self, document_id: &str, after_seq: u64) -> (Vec<String>, u64) {
+        let tables = self.tables();
+        let Some(log) = tables.logs.get(document_id) else {
+            return (Vec::new(), after_seq);
+        };
+        let updates: Vec<&(u64, String)> = log
+            .entries
+            .iter()
+            .filter(|(seq, _)| *seq > after_seq)
+            .collect();
+        let last_seq = updates.last().map_or(after_seq, |(seq, _)| *seq);
+        (
+            updates
+                .into_iter()
+                .map(|(_, payload)| payload.clone())
+                .collect(),
+            last_seq,
+        )
+    }
+}
+
+// ***************************************************************************************************************************************
+
+#[async_trait]
+impl DocumentStore for MemoryStore {
+    async fn get(&self, document_id: &str) -> StoreResult<Option<Document>> {
+        Ok(self.tables().documents.get(document_id).cloned())
+    }
+
+    async fn find_published(&self, public_id: &str) -> StoreResult<Option<Document>> {
+        let tables = self.tables();
+        let document = tables.documents.values().find(|d| {
+            d.publication
+                .as_ref()
+                .is_some_and(|p| p.public_id == public_id)
+        });
+        Ok(document.cloned())
+    }
+
+    async fn templates(&self) -> StoreResult<Vec<Document>> {
+        let tables = self.tables();
+        let templates = tables.documents.values().filter(|d| d.template.is_some());
+        Ok(templates.cloned().collect())
+    }
+
+    async fn insert(&self, mut document: Document) -> StoreResult<String> {
+        let obj_id = ObjectId::new();
+        document.id = Some(obj_id);
+        self.tables().documents.insert(obj_id.to_hex(), document);
+        Ok(obj_id.to_hex())
+    }
+
+    async fn delete(&self, document_id: &str) -> StoreResult<()> {
+        let mut tables = self.tables();
+        tables.documents.remove(document_id);
+        tables.comments.retain(|t| t.document_id != document_id);
+        tables.suggestions.retain(|s| s.document_id != document_id);
+        Ok(())
+    }
+
+    async fn set_publication(
+        &self,
+        document_id: &str,
+        publication: Option<Publication>,
+    ) -> StoreResult<()> {
+        if let Some(document) = self.tables().documents.get_mut(document_id) {
+            document.publication = publication;
+        }
+        Ok(())
+    }
+
+    async fn set_template(&self, document_id: &str, template: Option<Template>) -> StoreResult<()> {
+        if let Some(document) = self.tables().documents.get_mut(document_id) {
+            document.template = template;
+        }
+        Ok(())
+    }
+
+    async fn set_exclusive_editing(&self, document_id: &str, enabled: bool) -> StoreResult<()> {
+        if let Some(document) = self.tables().documents.get_mut(document_id) {
+            document.exclusive_editing = enabled;
+        }
+        Ok(())
+    }
+
+    async fn set_title(&self, document_id: &str, title: &str) -> StoreResult<()> {
+        if let Some(document) = self.tables().documents.get_mut(document_id) {
+            document.title = title.to_string();
+        }
+        Ok(())
+    }
+
+    async fn write_content(
+        &self,
+        document_id: &str,
+        write: ContentWrite<'_>,
+        condition: WriteCondition,
+    ) -> StoreResult<Option<Document>> {
+        let mut tables = self.tables();
+        let Some(document) = tables.documents.get_mut(document_id) else {
+            return Ok(None);
+        };
+        let holds = match condition {
+            WriteCondition::AtRevision(revision) => document.revision == revision,
+            WriteCondition::Flush { base, seq } => {
+                document.revision == base || document.snapshot_seq.is_some_and(|s| s <= seq as i64)
+            }
+        };
+        if !holds {
+            return Ok(None);
+        }
+
+        let stats = ContentStats::of(write.content);
+        document.content = write.content.to_string();
+        document.word_count = stats.word_count;
+        document.char_count = stats.char_count;
+        document.content_size = stats.content_size;
+        if let Some((editor, at)) = write.last_edit {
+            document.last_edited_by = Some(editor);
+            document.updated_at = Some(at);
+        }
+        document.revision += 1;
+        document.snapshot_seq = write.snapshot_seq.map(|seq| seq as i64);
+        Ok(Some(document.clone()))
+    }
+
+    async fn store_version(&self, version: RecoveredVersion<'_>) -> StoreResult<()> {
+        self.tables().versions.push(StoredVersion {
+            document_id: version.document_id.to_string(),
+            content: version.content.to_string(),
+        });
+        Ok(())
+    }
+
+    async fn comment_threads(
+        &self,
+        document_id: &str,
+        include_resolved: bool,
+    ) -> StoreResult<Vec<CommentThread>> {
+        let mut threads: Vec<CommentThread> = self
+            .tables()
+            .comments
+            .iter()
+            .filter(|t| t.document_id == document_id && (include_resolved || !t.resolved))
+            .cloned()
+            .collect();
+        threads.sort_by_key(|t| t.anchor.start);
+        Ok(threads)
+    }
+
+    async fn insert_thread(&self, thread: &CommentThread) -> StoreResult<()> {
+        self.tables().comments.push(thread.clone());
+        Ok(())
+    }
+
+    async fn add_reply(
+        &self,
+        document_id: &str,
+        thread_id: &str,
+        reply: CommentReply,
+    ) -> StoreResult<Option<CommentThread>> {
+        let mut tables = self.tables();
+        let thread = tables
+            .comments
+            .iter_mut()
+            .find(|t| t.id == thread_id && t.document_id == document_id);
+        Ok(thread.map(|thread| {
+            thread.replies.push(reply);
+            thread.clone()
+        }))
+    }
+
+    async fn set_resolved(
+        &self,
+        document_id: &str,
+        thread_id: &str,
+        resolved_by: Option<&str>,
+    ) -> StoreResult<Option<CommentThread>> {
+        let mut tables = self.tables();
+        let thread = tables
+            .comments
+            .iter_mut()
+            .find(|t| t.id == thread_id && t.document_id == document_id);
+        Ok(thread.map(|thread| {
+            thread.resolved = resolved_by.is_some();
+            thread.resolved_by = resolved_by.map(str::to_string);
+            thread.clone()
+        }))
+    }
+
+    async fn threads_ending_from(
+        &self,
+        document_id: &str,
+        position: usize,
+    ) -> StoreResult<Vec<CommentThread>> {
+        let threads = self
+            .tables()
+            .comments
+            .iter()
+            .filter(|t| t.document_id == document_id && t.anchor.end >= position)
+            .cloned()
+            .collect();
+        Ok(threads)
+    }
+
+    async fn move_thread(&self, thread_id: &str, anchor: TextRange) -> StoreResult<()> {
+        if let Some(thread) = self
+            .tables()
+            .comments
+            .iter_mut()
+            .find(|t| t.id == thread_id)
+        {
+            thread.anchor = anchor;
+        }
+        Ok(())
+    }
+
+    async fn suggestions(
+        &self,
+        document_id: &str,
+        include_reviewed: bool,
+    ) -> StoreResult<Vec<Suggestion>> {
+        let mut suggestions: Vec<Suggestion> = self
+            .tables()
+            .suggestions
+            .iter()
+            .filter(|s| {
+                s.document_id == document_id
+                    && (include_reviewed || s.status == SuggestionStatus::Pending)
+            })
+            .cloned()
+            .collect();
+        suggestions.sort_by_key(|s| s.range.start);
+        Ok(suggestions)
+    }
+
+    async fn pending_suggestion(
+        &self,
+        document_id: &str,
+        suggestion_id: &str,
+    ) -> StoreResult<Option<Suggestion>> {
+        let tables = self.tables();
+        let suggestion = tables.suggestions.iter().find(|s| {
+            s.id == suggestion_id
+                && s.document_id == document_id
+                && s.status == SuggestionStatus::Pending
+        });
+        Ok(suggestion.cloned())
+    }
+
+    async fn update_overlapping(
+        &self,
+        document_id: &str,
+        author: &str,
+        range: TextRange,
+        original: &str,
+        replacement: &str,
+    ) -> StoreResult<Option<Suggestion>> {
+        let mut tables = self.tables();
+        let suggestion = tables.suggestions.iter_mut().find(|s| {
+            s.document_id == document_id
+                && s.author == author
+                && s.status == SuggestionStatus::Pending
+                && s.range.start <= range.end
+                && s.range.end >= range.start
+        });
+        Ok(suggestion.map(|suggestion| {
+            suggestion.range = range;
+            suggestion.original = original.to_string();
+            suggestion.replacement = replacement.to_string();
+            suggestion.clone()
+        }))
+    }
+
+    async fn insert_suggestion(&self, suggestion: &Suggestion) -> StoreResult<()> {
+        self.tables().suggestions.push(suggestion.clone());
+        Ok(())
+    }
+
+    async fn review_suggestion(
+        &self,
+        document_id: &str,
+        suggestion_id: &str,
+        status: SuggestionStatus,
+        reviewer: &str,
+    ) -> StoreResult<Option<Suggestion>> {
+        let mut tables = self.tables();
+        let suggestion = tables.suggestions.iter_mut().find(|s| {
+            s.id == suggestion_id
+                && s.document_id == document_id
+                && s.status == SuggestionStatus::Pending
+        });
+        Ok(suggestion.map(|suggestion| {
+            suggestion.status = status;
+            suggestion.reviewed_by = Some(reviewer.to_string());
+            suggestion.clone()
+        }))
+    }
+
+    async fn pending_ending_from(
+        &self,
+        document_id: &str,
+        position: usize,
+    ) -> StoreResult<Vec<Suggestion>> {
+        let suggestions = self
+            .tables()
+            .suggestions
+            .iter()
+            .filter(|s| {
+                s.document_id == document_id
+                    && s.status == SuggestionStatus::Pending
+                    && s.range.end >= position
+            })
+            .cloned()
+            .collect();
+        Ok(suggestions)
+    }
+
+    async fn move_suggestion(&self, suggestion_id: &str, range: TextRange) -> StoreResult<()> {
+        let mut tables = self.tables();
+        if let Some(suggestion) = tables
+            .suggestions
+            .iter_mut()
+            .find(|s| s.id == suggestion_id)
+        {
+            suggestion.range = range;
+        }
+        Ok(())
+    }
+}
+
+// ***************************************************************************************************************************************
+
+#[async_trait]
+impl AccessStore for MemoryStore {
+    async fn find_user(&self, email: &str, password: &str) -> StoreResult<Option<UserRow>> {
+        let tables = self.tables();
+        let user = tables.users.get(email).filter(|u| u.password == password);
+        Ok(user.map(|u| u.row.clone()))
+    }
+
+    async fn grants(&self, email: &str, document_id: &str) -> StoreResult<Vec<Grant>> {
+        let tables = self.tables();
+        let now = Utc::now();
+        let mut grants = Vec::new();
+
+        for relation in &tables.relations {
+            if relation.email == email
+                && relation.document_id == document_id
+                && within(relation.valid_from, relation.valid_until, now)
+            {
+                grants.push(Grant {
+                    role: relation.role,
+                    source: GrantSource::Direct,
+                    valid_until: relation.valid_until,
+                });
+            }
+        }
+
+        for relation in &tables.group_relations {
+            let member = tables
+                .members
+                .iter()
+                .any(|(group_id, member)| *group_id == relation.group_id && member == email);
+            if relation.document_id != document_id
+                || !member
+                || !within(relation.valid_from, relation.valid_until, now)
+            {
+                continue;
+            }
+            let Some(group) = tables
+                .groups
+                .iter()
+                .find(|g| g.group_id == relation.group_id)
+            else {
+                continue;
+            };
+            grants.push(Grant {
+                role: parse_role(&group.group_role)?,
+                source: GrantSource::Group {
+                    group_id: relation.group_id,
+                },
+                valid_until: relation.valid_until,
+            });
+        }
+
+        if let Some(folder_id) = tables.filed.get(document_id) {
+            for folder in tables.ancestors(*folder_id) {
+                if !folder.inherit_permissions {
+                    continue;
+                }
+                for relation in &tables.folder_relations {
+                    if relation.folder_id == folder.folder_id && relation.email == email {
+                        grants.push(Grant {
+                            role: relation.role,
+                            source: GrantSource::Folder {
+                                folder_id: folder.folder_id,
+                            },
+                            valid_until: None,
+                        });
+                    }
+                }
+            }
+        }
+
+        Ok(grants)
+    }
+
+    async fn share_link_grant(
+        &self,
+        token: &str,
+        email: &str,
+        document_id: &str,
+    ) -> StoreResult<Option<Grant>> {
+        let tables = self.tables();
+        let now = Utc::now();
+
+        let link = tables.share_links.iter().find(|(link_document, link)| {
+            link.token == token
+                && link_document == document_id
+                && !link.revoked
+                && link.expires_at.is_none_or(|expires| expires > now)
+        });
+        let Some((_, link)) = link else {
+            return Ok(None);
+        };
+
+        let redeemed = tables
+            .redemptions
+            .contains(&(link.link_id, email.to_string()));
+        if !redeemed && link.max_uses.is_some_and(|max| link.use_count >= max) {
+            return Ok(None);
+        }
+
+        Ok(Some(Grant {
+            role: parse_role(&link.link_role)?,
+            source: GrantSource::ShareLink {
+                link_id: link.link_id,
+            },
+            valid_until: link.expires_at,
+        }))
+    }
+
+    async fn redeem_share_link(&self, link_id: i32, email: &str) -> StoreResult<bool> {
+        let mut tables = self.tables();
+        let now = Utc::now();
+        let Tables {
+            share_links,
+            redemptions,
+            ..
+        } = &mut *tables;
+
+        if redemptions.contains(&(link_id, email.to_string())) {
+            return Ok(true);
+        }
+
+        let link = share_links.iter_mut().find(|(_, link)| {
+            link.link_id == link_id
+                && !link.revoked
+                && link.expires_at.is_none_or(|expires| expires > now)
+                && link.max_uses.is_none_or(|max| link.use_count < max)
+        });
+        let Some((_, link)) = link else {
+            return Ok(false);
+        };
+
+        link.use_count += 1;
+        redemptions.insert((link_id, email.to_string()));
+        Ok(true)
+    }
+
+    async fn add_relation(&self, document_id: &str, email: &str, role: Role) -> StoreResult<()> {
+        let mut tables = self.tables();
+        tables.require_user(email)?;
+        if tables
+            .relations
+            .iter()
+            .any(|r| r.email == email && r.document_id == document_id)
+        {
+            return Err(StoreError::duplicate(format!(
+                "{} already has a role on document {}",
+                email, document_id
+            )));
+        }
+        tables.relations.push(Relation {
+            email: email.to_string(),
+            document_id: document_id.to_string(),
+            role,
+            valid_from: None,
+            valid_until: None,
+        });
+        Ok(())
+    }
+
+    async fn add_group_relation(&self, document_id: &str, group_id: i32) -> StoreResult<()> {
+        self.share_with_group(document_id, group_id, None, None)
+            .await
+    }
+
+    async fn share(
+        &self,
+        document_id: &str,
+        email: &str,
+        role: Role,
+        valid_from: Option<DateTime<Utc>>,
+        valid_until: Option<DateTime<Utc>>,
+    ) -> StoreResult<()> {
+        let mut tables = self.tables();
+        tables.require_user(email)?;
+        tables
+            .relations
+            .retain(|r| !(r.email == email && r.document_id == document_id));
+        tables.relations.push(Relation {
+            email: email.to_string(),
+            document_id: document_id.to_string(),
+            role,
+            valid_from,
+            valid_until,
+        });
+        Ok(())
+    }
+
+    async fn unshare(&self, document_id: &str, email: &str) -> StoreResult<bool> {
+        let mut tables = self.tables();
+        let before = tables.relations.len();
+        tables.relations.retain(|r| {
+            !(r.email == email && r.document_id == document_id && r.role != Role::Owner)
+        });
+        Ok(tables.relations.len() < before)
+    }
+
+    async fn share_with_group(
+        &self,
+        document_id: &str,
+        group_id: i32,
+        valid_from: Option<DateTime<Utc>>,
+        valid_until: Option<DateTime<Utc>>,
+    ) -> StoreResult<()> {
+        let mut tables = self.tables();
+        if !tables.groups.iter().any(|g| g.group_id == group_id) {
+            return Err(StoreError::missing_reference(format!(
+                "Group {} does not exist",
+                group_id
+            )));
+        }
+        tables
+            .group_relations
+            .retain(|r| !(r.group_id == group_id && r.document_id == document_id));
+        tables.group_relations.push(GroupRelation {
+            group_id,
+            document_id: document_id.to_string(),
+            valid_from,
+            valid_until,
+        });
+        Ok(())
+    }
+
+    async fn unshare_group(&self, document_id: &str, group_id: i32) -> StoreResult<bool> {
+        let mut tables = self.tables();
+        let before = tables.group_relations.len();
+        tables
+            .group_relations
+            .retain(|r| !(r.group_id == group_id && r.document_id == document_id));
+        Ok(tables.group_relations.len() < before)
+    }
+
+    async fn transfer_ownership(&self, document_id: &str, from: &str, to: &str) -> StoreResult<()> {
+        let mut tables = self.tables();
+        tables.require_user(to)?;
+        for relation in tables.relations.iter_mut() {
+            if relation.document_id == document_id && relation.email == from {
+                relation.role = Role::Editor;
+            }
+        }
+        tables
+            .relations
+            .retain(|r| !(r.email == to && r.document_id == document_id));
+        tables.relations.push(Relation {
+            email: to.to_string(),
+            document_id: document_id.to_string(),
+            role: Role::Owner,
+            valid_from: None,
+            valid_until: None,
+        });
+        Ok(())
+    }
+
+    async fn copy_relations(
+        &self,
+        owner: &str,
+        source_id: &str,
+        document_id: &str,
+        copy_sharing: bool,
+    ) -> StoreResult<()> {
+        let mut tables = self.tables();
+        let mut relations = vec![Relation {
+            email: owner.to_string(),
+            document_id: document_id.to_string(),
+            role: Role::Owner,
+            valid_from: None,
+            valid_until: None,
+        }];
+        let mut group_relations = Vec::new();
+
+        if copy_sharing {
+            relations.extend(
+                tables
+                    .relations
+                    .iter()
+                    .filter(|r| {
+                        r.document_id == source_id && r.role != Role::Owner && r.email != owner
+                    })
+                    .map(|r| Relation {
+                        email: r.email.clone(),
+                        document_id: document_id.to_string(),
+                        role: r.role,
+                        valid_from: r.valid_from,
+                        valid_until: r.valid_until,
+                    }),
+            );
+            group_relations.extend(
+                tables
+                    .group_relations
+                    .iter()
+                    .filter(|r| r.document_id == source_id)
+                    .map(|r| GroupRelation {
+                        group_id: r.group_id,
+                        document_id: document_id.to_string(),
+                        valid_from: r.valid_from,
+                        valid_until: r.valid_until,
+                    }),
+            );
+        }
+
+        tables.relations.extend(relations);
+        tables.group_relations.extend(group_relations);
+        Ok(())
+    }
+
+    async fn remove_document(&self, document_id: &str) -> StoreResult<()> {
+        let mut tables = self.tables();
+        tables.relations.retain(|r| r.document_id != document_id);
+        tables
+            .group_relations
+            .retain(|r| r.document_id != document_id);
+        tables
+            .tags
+            .retain(|(_, document, _)| document != document_id);
+        tables
+            .favorites
+            .retain(|(_, document, _)| document != document_id);
+        tables
+            .recent
+            .retain(|(_, document, _)| document != document_id);
+        tables.filed.remove(document_id);
+
+        let Tables {
+            share_links,
+            redemptions,
+            ..
+        } = &mut *tables;
+        share_links.retain(|(document, link)| {
+            let removed = document == document_id;
+            if removed {
+                redemptions.retain(|(link_id, _)| *link_id != link.link_id);
+            }
+            !removed
+        });
+        Ok(())
+    }
+
+    async fn owned_documents(&self, email: &str) -> StoreResult<Vec<String>> {
+        let document_ids = self
+            .tables()
+            .relations
+            .iter()
+            .filter(|r| r.email == email && r.role == Role::Owner)
+            .map(|r| r.document_id.clone())
+            .collect();
+        Ok(document_ids)
+    }
+
+    async fn shared_documents(&self, email: &str) -> StoreResult<Vec<String>> {
+        let tables = self.tables();
+        let now = Utc::now();
+
+        let relation_ids = tables.relations.iter().filter(|r| {
+            r.email == email && r.role != Role::Owner && within(r.valid_from, r.valid_until, now)
+        });
+        let group_document_ids = tables.group_relations.iter().filter(|r| {
+            tables
+                .members
+                .iter()
+                .any(|(group_id, member)| *group_id == r.group_id && member == email)
+                && within(r.valid_from, r.valid_until, now)
+        });
+
+        let mut document_ids: Vec<String> = Vec::new();
+        for document_id in relation_ids
+            .map(|r| &r.document_id)
+            .chain(group_document_ids.map(|r| &r.document_id))
+        {
+            if !document_ids.contains(document_id) {
+                document_ids.push(document_id.clone());
+            }
+        }
+        Ok(document_ids)
+    }
+
+    async fn owner_of(&self, document_id: &str) -> StoreResult<Option<String>> {
+        let tables = self.tables();
+        let owner = tables
+            .relations
+            .iter()
+            .find(|r| r.document_id == document_id && r.role == Role::Owner);
+        Ok(owner.map(|r| r.email.clone()))
+    }
+
+    async fn create_group(&self, group: &GroupsRequest) -> StoreResult<i32> {
+        let mut tables = self.tables();
+        tables.require_user(&group.owner)?;
+        for member in &group.members {
+            tables.require_user(member)?;
+        }
+        if tables
+            .groups
+            .iter()
+            .any(|g| g.owner_email == group.owner && g.group_name == group.name)
+        {
+            return Err(StoreError::duplicate(format!(
+                "{} already has a group named {}",
+                group.owner, group.name
+            )));
+        }
+        let group_id = tables.next_id();
+        tables.groups.push(GroupSummary {
+            group_id,
+            group_name: group.name.clone(),
+            owner_email: group.owner.clone(),
+            group_role: group.role.clone(),
+        });
+        tables.members.extend(
+            group
+                .members
+                .iter()
+                .map(|member| (group_id, member.clone())),
+        );
+        Ok(group_id)
+    }
+
+    async fn groups_by_owner(&self, email: &str) -> StoreResult<Vec<GroupSummary>> {
+        let groups = self
+            .tables()
+            .groups
+            .iter()
+            .filter(|g| g.owner_email == email)
+            .cloned()
+            .collect();
+        Ok(groups)
+    }
+
+    async fn group_owner(&self, group_id: i32) -> StoreResult<Option<String>> {
+        let owner = self
+            .tables()
+            .groups
+            .iter()
+            .find(|g| g.group_id == group_id)
+            .map(|g| g.owner_email.clone());
+        Ok(owner)
+    }
+
+    async fn group_members(&self, group_id: i32) -> StoreResult<Vec<String>> {
+        let members = self
+            .tables()
+            .members
+            .iter()
+            .filter(|(id, _)| *id == group_id)
+            .map(|(_, email)| email.clone())
+            .collect();
+        Ok(members)
+    }
+
+    async fn add_group_member(&self, group_id: i32, email: &str) -> StoreResult<bool> {
+        let mut tables = self.tables();
+        tables.require_user(email)?;
+        if tables
+            .members
+            .iter()
+            .any(|(id, e)| *id == group_id && e == email)
+        {
+            return Ok(false);
+        }
+        tables.members.push((group_id, email.to_string()));
+        Ok(true)
+    }
+
+    async fn remove_group_member(&self, group_id: i32, email: &str) -> StoreResult<bool> {
+        let mut tables = self.tables();
+        let before = tables.members.len();
+        tables
+            .members
+            .retain(|(id, e)| !(*id == group_id && e == email));
+        Ok(tables.members.len() < before)
+    }
+
+    async fn create_share_link(&self, link: NewShareLink<'_>) -> StoreResult<ShareLinkRow> {
+        let mut tables = self.tables();
+        let row = ShareLinkRow {
+            link_id: tables.next_id(),
+            token: link.token.to_string(),
+            link_role: link.role.as_str().to_string(),
+            created_by: link.created_by.to_string(),
+            created_at: Utc::now(),
+            expires_at: link.expires_at,
+            max_uses: link.max_uses,
+            use_count: 0,
+            revoked: false,
+        };
+        tables
+            .share_links
+            .push((link.document_id.to_string(), row.clone()));
+        Ok(row)
+    }
+
+    async fn share_links(&self, document_id: &str) -> StoreResult<Vec<ShareLinkRow>> {
+        let mut links: Vec<ShareLinkRow> = self
+            .tables()
+            .share_links
+            .iter()
+            .filter(|(document, _)| document == document_id)
+            .map(|(_, link)| link.clone())
+            .collect();
+        links.sort_by_key(|link| Reverse(link.created_at));
+        Ok(links)
+    }
+
+    async fn revoke_share_link(&self, document_id: &str, link_id: i32) -> StoreResult<bool> {
+        let mut tables = self.tables();
+        let link = tables
+            .share_links
+            .iter_mut()
+            .find(|(document, link)| document == document_id && link.link_id == link_id);
+        Ok(link.map(|(_, link)| link.revoked = true).is_some())
+    }
+
+    async fn folder_owner(&self, folder_id: i32) -> StoreResult<Option<String>> {
+        let tables = self.tables();
+        Ok(tables.folder(folder_id).map(|f| f.owner_email.clone()))
+    }
+
+    async fn folder_roles(&self, email: &str, folder_id: i32) -> StoreResult<Vec<Role>> {
+        let tables = self.tables();
+        let mut roles = Vec::new();
+
+        for (depth, folder) in tables.ancestors(folder_id).into_iter().enumerate() {
+            if depth == 0 && folder.owner_email == email {
+                roles.push(Role::Owner);
+            }
+            if depth > 0 && !folder.inherit_permissions {
+                continue;
+            }
+            roles.extend(
+                tables
+                    .folder_relations
+                    .iter()
+                    .filter(|r| r.folder_id == folder.folder_id && r.email == email)
+                    .map(|r| r.role),
+            );
+        }
+        Ok(roles)
+    }
+
+    async fn create_folder(
+        &self,
+        name: &str,
+        owner: &str,
+        parent_id: Option<i32>,
+        inherit_permissions: bool,
+    ) -> StoreResult<FolderRow> {
+        let mut tables = self.tables();
+        tables.require_user(owner)?;
+        let folder = FolderRow {
+            folder_id: tables.next_id(),
+            folder_name: name.to_string(),
+            owner_email: owner.to_string(),
+            parent_id,
+            inherit_permissions,
+            created_at: Utc::now(),
+        };
+        tables.folders.push(folder.clone());
+        Ok(folder)
+    }
+
+    async fn update_folder(
+        &self,
+        folder_id: i32,
+        name: Option<&str>,
+        inherit_permissions: Option<bool>,
+    ) -> StoreResult<FolderRow> {
+        let mut tables = self.tables();
+        let folder = tables
+            .folders
+            .iter_mut()
+            .find(|f| f.folder_id == folder_id)
+            .ok_or_else(|| StoreError::new(format!("Folder {} does not exist", folder_id)))?;
+        if let Some(name) = name {
+            folder.folder_name = name.to_string();
+        }
+        if let Some(inherit_permissions) = inherit_permissions {
+            folder.inherit_permissions = inherit_permissions;
+        }
+        Ok(folder.clone())
+    }
+
+    async fn is_within(&self, folder_id: i32, ancestor_id: i32) -> StoreResult<bool> {
+        let tables = self.tables();
+        let ancestors = tables.ancestors(folder_id);
+        Ok(ancestors.iter().any(|f| f.folder_id == ancestor_id))
+    }
+
+    async fn move_folder(&self, folder_id: i32, parent_id: Option<i32>) -> StoreResult<()> {
+        let mut tables = self.tables();
+        if let Some(folder) = tables.folders.iter_mut().find(|f| f.folder_id == folder_id) {
+            folder.parent_id = parent_id;
+        }
+        Ok(())
+    }
+
+    async fn delete_folder(&self, folder_id: i32) -> StoreResult<()> {
+        let mut tables = self.tables();
+        let subtree = tables.subtree(folder_id);
+        tables.folders.retain(|f| !subtree.contains(&f.folder_id));
+        tables
+            .folder_relations
+            .retain(|r| !subtree.contains(&r.folder_id));
+        tables.filed.retain(|_, folder| !subtree.contains(folder));
+        Ok(())
+    }
+
+    async fn file_document(&self, document_id: &str, folder_id: Option<i32>) -> StoreResult<()> {
+        let mut tables = self.tables();
+        match folder_id {
+            Some(folder_id) => tables.filed.insert(document_id.to_string(), folder_id),
+            None => tables.filed.remove(document_id),
+        };
+        Ok(())
+    }
+
+    async fn share_folder(&self, folder_id: i32, email: &str, role: Role) -> StoreResult<()> {
+        let mut tables = self.tables();
+        tables.require_user(email)?;
+        tables
+            .folder_relations
+            .retain(|r| !(r.folder_id == folder_id && r.email == email));
+        tables.folder_relations.push(FolderRelation {
+            folder_id,
+            email: email.to_string(),
+            role,
+        });
+        Ok(())
+    }
+
+    async fn unshare_folder(&self, folder_id: i32, email: &str) -> StoreResult<bool> {
+        let mut tables = self.tables();
+        let before = tables.folder_relations.len();
+        tables
+            .folder_relations
+            .retain(|r| !(r.folder_id == folder_id && r.email == email));
+        Ok(tables.folder_relations.len() < before)
+    }
+
+    async fn subfolders(&self, folder_id: i32) -> StoreResult<Vec<FolderRow>> {
+        let mut folders: Vec<FolderRow> = self
+            .tables()
+            .folders
+            .iter()
+            .filter(|f| f.parent_id == Some(folder_id))
+            .cloned()
+            .collect();
+        folders.sort_by(|a, b| a.folder_name.cmp(&b.folder_name));
+        Ok(folders)
+    }
+
+    async fn folder_documents(&self, folder_id: i32) -> StoreResult<Vec<String>> {
+        let document_ids = self
+            .tables()
+            .filed
+            .iter()
+            .filter(|(_, folder)| **folder == folder_id)
+            .map(|(document_id, _)| document_id.clone())
+            .collect();
+        Ok(document_ids)
+    }
+
+    async fn top_level_folders(&self, email: &str) -> StoreResult<Vec<FolderRow>> {
+        let tables = self.tables();
+        let mut folders: Vec<FolderRow> = tables
+            .folders
+            .iter()
+            .filter(|f| {
+                (f.owner_email == email && f.parent_id.is_none())
+                    || tables
+                        .folder_relations
+                        .iter()
+                        .any(|r| r.folder_id == f.folder_id && r.email == email)
+            })
+            .cloned()
+            .collect();
+        folders.sort_by(|a, b| a.folder_name.cmp(&b.folder_name));
+        Ok(folders)
+    }
+
+    async fn unfiled_documents(&self, email: &str) -> StoreResult<Vec<String>> {
+        let tables = self.tables();
+        let document_ids = tables
+            .relations
+            .iter()
+            .filter(|r| {
+                r.email == email
+                    && r.role == Role::Owner
+                    && !tables.filed.contains_key(&r.document_id)
+            })
+            .map(|r| r.document_id.clone())
+            .collect();
+        Ok(document_ids)
+    }
+}
+
+// ***************************************************************************************************************************************
+
+#[async_trait]
+impl LibraryStore for MemoryStore {
+    async fn tags(&self, email: &str) -> StoreResult<Vec<(String, String)>> {
+        let mut tags: Vec<(String, String)> = self
+            .tables()
+            .tags
+            .iter()
+            .filter(|(user, _, _)| user == email)
+            .map(|(_, document_id, tag)| (document_id.clone(), tag.clone()))
+            .collect();
+        tags.sort_by(|a, b| a.1.cmp(&b.1));
+        Ok(tags)
+    }
+
+    async fn tag_counts(&self, email: &str) -> StoreResult<Vec<(String, i64)>> {
+        let mut counts: Vec<(String, i64)> = Vec::new();
+        for (_, tag) in self.tags(email).await? {
+            match counts.last_mut() {
+                Some((last, count)) if *last == tag => *count += 1,
+                _ => counts.push((tag, 1)),
+            }
+        }
+        Ok(counts)
+    }
+
+    async fn add_tag(&self, email: &str, document_id: &str, tag: &str) -> StoreResult<()> {
+        let mut tables = self.tables();
+        let entry = (email.to_string(), document_id.to_string(), tag.to_string());
+        if !tables.tags.contains(&entry) {
+            tables.tags.push(entry);
+        }
+        Ok(())
+    }
+
+    async fn remove_tag(&self, email: &str, document_id: &str, tag: &str) -> StoreResult<bool> {
+        let mut tables = self.tables();
+        let before = tables.tags.len();
+        tables
+            .tags
+            .retain(|(u, d, t)| !(u == email && d == document_id && t == tag));
+        Ok(tables.tags.len() < before)
+    }
+
+    async fn favorites(&self, email: &str) -> StoreResult<Vec<String>> {
+        let tables = self.tables();
+        let mut favorites: Vec<&(String, String, DateTime<Utc>)> = tables
+            .favorites
+            .iter()
+            .filter(|(user, _, _)| user == email)
+            .collect();
+        favorites.sort_by_key(|(_, _, at)| Reverse(*at));
+        Ok(favorites.into_iter().map(|(_, d, _)| d.clone()).collect())
+    }
+
+    async fn set_favorite(
+        &self,
+        email: &str,
+        document_id: &str,
+        favorite: bool,
+    ) -> StoreResult<()> {
+        let mut tables = self.tables();
+        let starred = tables
+            .favorites
+            .iter()
+            .any(|(u, d, _)| u == email && d == document_id);
+        if favorite && !starred {
+            tables
+                .favorites
+                .push((email.to_string(), document_id.to_string(), Utc::now()));
+        } else if !favorite {
+            tables
+                .favorites
+                .retain(|(u, d, _)| !(u == email && d == document_id));
+        }
+        Ok(())
+    }
+
+    async fn recent(&self, email: &str, limit: i64) -> StoreResult<Vec<(String, DateTime<Utc>)>> {
+        let tables = self.tables();
+        let mut recent: Vec<(String, DateTime<Utc>)> = tables
+            .recent
+            .iter()
+            .filter(|(user, _, _)| user == email)
+            .map(|(_, document_id, at)| (document_id.clone(), *at))
+            .collect();
+        recent.sort_by_key(|(_, at)| Reverse(*at));
+        recent.truncate(limit.max(0) as usize);
+        Ok(recent)
+    }
+
+    async fn record_open(&self, email: &str, document_id: &str, keep: i64) -> StoreResult<()> {
+        let mut tables = self.tables();
+        tables
+            .recent
+            .retain(|(u, d, _)| !(u == email && d == document_id));
+        tables
+            .recent
+            .push((email.to_string(), document_id.to_string(), Utc::now()));
+
+        let mut opened: Vec<DateTime<Utc>> = tables
+            .recent
+            .iter()
+            .filter(|(user, _, _)| user == email)
+            .map(|(_, _, at)| *at)
+            .collect();
+        opened.sort_by(|a, b| b.cmp(a));
+        if let Some(oldest_kept) = opened.get(keep.max(1) as usize - 1).copied() {
+            tables
+                .recent
+                .retain(|(u, _, at)| u != email || *at >= oldest_kept);
+        }
+        Ok(())
+    }
+}
+
+// ***************************************************************************************************************************************
+
+#[async_trait]
+impl AuditLog for MemoryStore {
+    async fn append(
+        &self,
+        event_type: &str,
+        actor: Option<&str>,
+        document_id: Option<&str>,
+        details: Value,
+    ) -> StoreResult<()> {
+        let mut tables = self.tables();
+        let entry = AuditEntry {
+            event_id: tables.next_id() as i64,
+            occurred_at: Utc::now(),
+            event_type: event_type.to_string(),
+            actor_email: actor.map(str::to_string),
+            document_id: document_id.map(str::to_string),
+            details,
+        };
+        tables.audit.push(entry);
+        Ok(())
+    }
+
+    async fn entries(
+        &self,
+        query: &GetAuditLogRequest,
+        limit: i64,
+    ) -> StoreResult<Vec<AuditEntry>> {
+        let mut entries: Vec<AuditEntry> = self
+            .tables()
+            .audit
+            .iter()
+            .filter(|e| {
+                e.document_id.as_deref() == Some(query.document_id.as_str())
+                    && query.event_type.as_ref().is_none_or(|t| *t == e.event_type)
+                    && query
+                        .actor
+                        .as_ref()
+                        .is_none_or(|a| e.actor_email.as_ref() == Some(a))
+                    && query.since.is_none_or(|since| e.occurred_at >= since)
+                    && query.until.is_none_or(|until| e.occurred_at < until)
+            })
+            .cloned()
+            .collect();
+        entries.sort_by_key(|e| Reverse((e.occurred_at, e.event_id)));
+        entries.truncate(limit.max(0) as usize);
+        Ok(entries)
+    }
+}
+
+// ***************************************************************************************************************************************
+
+#[async_trait]
+impl LiveDocCache for MemoryStore {
+    async fn content(&self, document_id: &str) -> StoreResult<Option<String>> {
+        Ok(self.tables().live.get(document_id).cloned())
+    }
+
+    async fn is_open(&self, document_id: &str) -> StoreResult<bool> {
+        Ok(self.tables().live.contains_key(document_id))
+    }
+
+    async fn open(&self, document_id: &str, content: &str, revision: i64) -> StoreResult<()> {
+        let mut tables = self.tables();
+        tables
+            .live
+            .insert(document_id.to_string(), content.to_string());
+        tables
+            .meta
+            .entry(document_id.to_string())
+            .or_default()
+            .revision = Some(revision);
+        Ok(())
+    }
+
+    async fn open_documents(&self) -> StoreResult<Vec<String>> {
+        Ok(self.tables().live.keys().cloned().collect())
+    }
+
+    async fn close(&self, document_id: &str) -> StoreResult<()> {
+        let mut tables = self.tables();
+        tables.live.remove(document_id);
+        tables.meta.remove(document_id);
+        Ok(())
+    }
+
+    async fn last_edit(&self, document_id: &str) -> StoreResult<Option<(String, DateTime<Utc>)>> {
+        let tables = self.tables();
+        Ok(tables
+            .meta
+            .get(document_id)
+            .and_then(|meta| meta.last_edit.clone()))
+    }
+
+    async fn set_last_edit(
+        &self,
+        document_id: &str,
+        editor: &str,
+        at: DateTime<Utc>,
+    ) -> StoreResult<()> {
+        self.tables()
+            .meta
+            .entry(document_id.to_string())
+            .or_default()
+            .last_edit = Some((editor.to_string(), at));
+        Ok(())
+    }
+
+    async fn clear_last_edit(&self, document_id: &str) -> StoreResult<()> {
+        if let Some(meta) = self.tables().meta.get_mut(document_id) {
+            meta.last_edit = None;
+        }
+        Ok(())
+    }
+
+    async fn base_revision(&self, document_id: &str) -> StoreResult<Option<i64>> {
+        let tables = self.tables();
+        Ok(tables.meta.get(document_id).and_then(|meta| meta.revision))
+    }
+
+    async fn set_base_revision(&self, document_id: &str, revision: i64) -> StoreResult<()> {
+        self.tables()
+            .meta
+            .entry(document_id.to_string())
+            .or_default()
+            .revision = Some(revision);
+        Ok(())
+    }
+
+    async fn acquire_lease(
+        &self,
+        document_id: &str,
+        value: &str,
+        ttl_ms: i64,
+    ) -> StoreResult<bool> {
+        let mut tables = self.tables();
+        let free = match tables.lease(document_id) {
+            Some((holder, _)) => holder == value,
+            None => true,
+        };
+        if free {
+            let expires = Instant::now() + Duration::from_millis(ttl_ms as u64);
+            tables
+                .leases
+                .insert(document_id.to_string(), (value.to_string(), expires));
+        }
+        Ok(free)
+    }
+
+    async fn renew_lease(&self, document_id: &str, value: &str, ttl_ms: i64) -> StoreResult<bool> {
+        let mut tables = self.tables();
+        let held = tables
+            .lease(document_id)
+            .is_some_and(|(holder, _)| holder == value);
+        if held {
+            let expires = Instant::now() + Duration::from_millis(ttl_ms as u64);
+            tables
+                .leases
+                .insert(document_id.to_string(), (value.to_string(), expires));
+        }
+        Ok(held)
+    }
+
+    async fn release_lease(&self, document_id: &str, value: &str) -> StoreResult<bool> {
+        let mut tables = self.tables();
+        let held = tables
+            .lease(document_id)
+            .is_some_and(|(holder, _)| holder == value);
+        if held {
+            tables.leases.remove(document_id);
+        }
+        Ok(held)
+    }
+
+    async fn break_lease(&self, document_id: &str) -> StoreResult<()> {
+        self.tables().leases.remove(document_id);
+        Ok(())
+    }
+
+    async fn lease(&self, document_id: &str) -> StoreResult<Option<Lease>> {
+        let mut tables = self.tables();
+        Ok(tables.lease(document_id).map(|(value, expires)| Lease {
+            value: value.clone(),
+            expires_in_ms: expires
+                .saturating_duration_since(Instant::now())
+                .as_millis() as i64,
+        }))
+    }
+
+    async fn clear_leases(&self) -> StoreResult<()> {
+        self.tables().leases.clear();
+        Ok(())
+    }
+
+    async fn lock_anchors(&self, document_id: &str, token: &str, ttl_ms: i64) -> StoreResult<bool> {
+        let mut tables = self.tables();
+        let now = Instant::now();
+        let free = tables
+            .anchor_locks
+            .get(document_id)
+            .is_none_or(|(_, expires)| *expires <= now);
+        if free {
+            let expires = now + Duration::from_millis(ttl_ms as u64);
+            tables
+                .anchor_locks
+                .insert(document_id.to_string(), (token.to_string(), expires));
+        }
+        Ok(free)
+    }
+
+    async fn unlock_anchors(&self, document_id: &str, token: &str) -> StoreResult<()> {
+        let mut tables = self.tables();
+        if tables
+            .anchor_locks
+            .get(document_id)
+            .is_some_and(|(holder, _)| holder == token)
+        {
+            tables.anchor_locks.remove(document_id);
+        }
+        Ok(())
+    }
+}
+
+// ***************************************************************************************************************************************
+
+#[async_trait]
+impl PubSub for MemoryStore {
+    async fn publish(&self, document_id: &str, message: &WsServerMessage) -> StoreResult<u64> {
+        Ok(self.append(document_id, message, None))
+    }
+
+    async fn publish_change(
+        &self,
+        document_id: &str,
+        previous: &str,
+        change: &Change,
+        _author: &str,
+    ) -> StoreResult<Option<u64>> {
+        let content = change.apply(previous);
+        let seq = self.append(document_id, change, Some((previous, content)));
+        Ok((seq > 0).then_some(seq))
+    }
+
+    async fn snapshot(&self, document_id: &str) -> StoreResult<(Option<String>, u64)> {
+        let tables = self.tables();
+        let content = tables.live.get(document_id).cloned();
+        let seq = tables.logs.get(document_id).map_or(0, |log| log.seq);
+        Ok((content, seq))
+    }
+
+    async fn subscribe(
+        &self,
+        document_id: &str,
+        after_seq: u64,
+    ) -> StoreResult<mpsc::UnboundedReceiver<String>> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let mut appended = self.appended.subscribe();
+        let store = self.clone();
+        let document_id = document_id.to_string();
+
+        tokio::spawn(async move {
+            let mut offset = after_seq;
+            loop {
+                // Marked as seen before reading, an append in between wakes
+                // the next wait right away
+                appended.borrow_and_update();
+                let (payloads, last_seq) = store.read_after(&document_id, offset);
+                offset = last_seq;
+                for payload in payloads {
+                    if tx.send(payload).is_err() {
+                        return;
+                    }
+                }
+
+                tokio::select! {
+                    _ = tx.closed() => return,
+                    changed = appended.changed() => {
+                        if changed.is_err() {
+                            return;
+                        }
+                    }
+                }
+            }
+        });
+        Ok(rx)
+    }
+
+    async fn missed(
+        &self,
+        document_id: &str,
+        last_seq: u64,
+        current: u64,
+    ) -> StoreResult<Option<Vec<String>>> {
+        let tables = self.tables();
+        let entries = tables
+            .logs
+            .get(document_id)
+            .map(|log| log.entries.as_slice())
+            .unwrap_or_default();
+        let oldest = entries.first().map(|(seq, _)| *seq);
+
+        match updates::plan_resume(last_seq, current, oldest) {
+            Resume::UpToDate => Ok(Some(Vec::new())),
+            Resume::Snapshot => Ok(None),
+            Resume::Replay => Ok(Some(
+                entries
+                    .iter()
+                    .filter(|(seq, _)| *seq > last_seq && *seq <= current)
+                    .map(|(_, payload)| payload.clone())
+                    .collect(),
+            )),
+        }
+    }
+
+    async fn compact(&self, document_id: &str, snapshot_seq: u64) -> StoreResult<()> {
+        if snapshot_seq <= RETAINED_UPDATES {
+            return Ok(());
+        }
+        if let Some(log) = self.tables().logs.get_mut(document_id) {
+            log.entries
+                .retain(|(seq, _)| *seq >= snapshot_seq - RETAINED_UPDATES);
+        }
+        Ok(())
+    }
+
+    async fn reset(&self, document_id: &str) -> StoreResult<()> {
+        self.tables().logs.remove(document_id);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::authz::{Capability, authorize_with_token, user_has_access};
+    use crate::ws_handler::{self, WriteConflict};
+
+    async fn document(store: &MemoryStore, owner: &str, content: &str) -> String {
+        let document = Document::new("Notes".to_string(), "txt".to_string(), content.to_string());
+        let document_id = store.insert(document).await.unwrap();
+        store.add_user(owner, "password", "Test", "Owner");
+        store
+            .add_relation(&document_id, owner, Role::Owner)
+            .await
+            .unwrap();
+        document_id
+    }
+
+    #[tokio::test]
+    async fn users_are_found_only_with_their_password() {
+        let store = MemoryStore::new();
+        store.add_user("ada@example.com", "secret", "Ada", "Lovelace");
+
+        let user = store.find_user("ada@example.com", "secret").await.unwrap();
+        let wrong = store.find_user("ada@example.com", "guess").await.unwrap();
+
+        assert_eq!(user.map(|u| u.first_name).as_deref(), Some("Ada"));
+        assert!(wrong.is_none());
+    }
+
+    async fn share_link(store: &MemoryStore, document_id: &str, max_uses: i32) -> ShareLinkRow {
+        store
+            .create_share_link(NewShareLink {
+                token: "token",
+                document_id,
+                role: Role::Reader,
+                created_by: "owner@example.com",
+                expires_at: None,
+                max_uses: Some(max_uses),
+            })
+            .await
+            .unwrap()
+    }
+
+    async fn use_count(store: &MemoryStore, document_id: &str) -> i32 {
+        store.share_links(document_id).await.unwrap()[0].use_count
+    }
+
+    #[tokio::test]
+    async fn share_links_stop_at_their_maximum_uses() {
+        let store = MemoryStore::new();
+        let state = store.app_state();
+        let document_id = document(&store, "owner@example.com", "").await;
+        share_link(&store, &document_id, 1).await;
+
+        let view = |email: &'static str| {
+            authorize_with_token(&state, email, &document_id, Some("token"), Capability::View)
+        };
+        let first = view("a@example.com").await.unwrap();
+        let again = view("a@example.com").await.unwrap();
+        let second = view("b@example.com").await;
+
+        assert_eq!(first.role, Role::Reader);
+        assert_eq!(again.role, Role::Reader);
+        assert!(second.is_err());
+        assert_eq!(use_count(&store, &document_id).await, 1);
+    }
+
+    #[tokio::test]
+    async fn removed_documents_take_their_share_links_along() {
+        let store = MemoryStore::new();
+        let state = store.app_state();
+        let document_id = document(&store, "owner@example.com", "").await;
+        share_link(&store, &document_id, 1).await;
+        authorize_with_token(
+            &state,
+            "a@example.com",
+            &document_id,
+            Some("token"),
+            Capability::View,
+        )
+        .await
+        .unwrap();
+
+        store.remove_document(&document_id).await.unwrap();
+
+        assert!(store.share_links(&document_id).await.unwrap().is_empty());
+        assert!(store.tables().redemptions.is_empty());
+    }
+
+    #[tokio::test]
+    async fn share_links_are_not_used_up_without_granting_anything() {
+        let store = MemoryStore::new();
+        let state = store.app_state();
+        let document_id = document(&store, "owner@example.com", "").await;
+        share_link(&store, &document_id, 1).await;
+
+        // Refused, the link only lets readers view
+        let edit = authorize_with_token(
+            &state,
+            "a@example.com",
+            &document_id,
+            Some("token"),
+            Capability::Edit,
+        )
+        .await;
+        assert!(edit.is_err());
+
+        // The owner does not need the link
+        let owner = authorize_with_token(
+            &state,
+            "owner@example.com",
+            &document_id,
+            Some("token"),
+            Capability::View,
+        )
+        .await
+        .unwrap();
+        assert_eq!(owner.source, GrantSource::Direct);
+
+        // Looking at the access, as the session expiry check does
+        let peek = user_has_access("b@example.com", &document_id, Some("token"), &state)
+            .await
+            .unwrap();
+        assert_eq!(peek.map(|a| a.role), Some(Role::Reader));
+
+        assert_eq!(use_count(&store, &document_id).await, 0);
+    }
+
+    #[tokio::test]
+    async fn folder_roles_pass_down_only_when_inherited() {
+        let store = MemoryStore::new();
+        let state = store.app_state();
+        let document_id = document(&store, "owner@example.com", "").await;
+        let parent = store
+            .create_folder("Team", "owner@example.com", None, true)
+            .await
+            .unwrap();
+        let child = store
+            .create_folder("Drafts", "owner@example.com", Some(parent.folder_id), false)
+            .await
+            .unwrap();
+        store.add_user("reader@example.com", "password", "Test", "Reader");
+        store
+            .share_folder(parent.folder_id, "reader@example.com", Role::Reader)
+            .await
+            .unwrap();
+        store
+            .file_document(&document_id, Some(child.folder_id))
+            .await
+            .unwrap();
+
+        let access = user_has_access("reader@example.com", &document_id, None, &state)
+            .await
+            .unwrap();
+        assert_eq!(access.map(|a| a.role), Some(Role::Reader));
+
+        store
+            .update_folder(parent.folder_id, None, Some(false))
+            .await
+            .unwrap();
+        let access = user_has_access("reader@example.com", &document_id, None, &state)
+            .await
+            .unwrap();
+        assert!(access.is_none());
+    }
+
+    #[tokio::test]
+    async fn shared_documents_are_listed_once() {
+        let store = MemoryStore::new();
+        let document_id = document(&store, "owner@example.com", "").await;
+        store.add_user("reader@example.com", "password", "Test", "Reader");
+        let group_id = store
+            .create_group(&GroupsRequest {
+                owner: "owner@example.com".to_string(),
+                name: "Team".to_string(),
+                role: "reader".to_string(),
+                members: vec!["reader@example.com".to_string()],
+            })
+            .await
+            .unwrap();
+        store
+            .add_relation(&document_id, "reader@example.com", Role::Reader)
+            .await
+            .unwrap();
+        store
+            .add_group_relation(&document_id, group_id)
+            .await
+            .unwrap();
+
+        let shared = store.shared_documents("reader@example.com").await.unwrap();
+
+        assert_eq!(shared, vec![document_id]);
+    }
+
+    // Changes the live content like an edit of a session does
+    async fn edit(store: &MemoryStore, document_id: &str, previous: &str, content: &str) {
+        let change = Change::between(previous, content).unwrap();
+        let seq = store
+            .publish_change(document_id, previous, &change, "x@example.com")
+            .await
+            .unwrap();
+        assert!(seq.is_some());
+    }
+
+    #[tokio::test]
+    async fn subscriptions_deliver_updates_in_order() {
+        let store = MemoryStore::new();
+        store.open("doc", "", 0).await.unwrap();
+        edit(&store, "doc", "", "a").await;
+
+        let mut updates = store.subscribe("doc", 1).await.unwrap();
+        edit(&store, "doc", "a", "ab").await;
+        edit(&store, "doc", "ab", "abc").await;
+
+        assert!(updates.recv().await.unwrap().starts_with(r#"{"seq":2,"#));
+        assert!(updates.recv().await.unwrap().starts_with(r#"{"seq":3,"#));
+        assert_eq!(store.missed("doc", 1, 3).await.unwrap().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn the_log_keeps_changes_rather_than_content() {
+        let store = MemoryStore::new();
+        let long = "x".repeat(10_000);
+        store.open("doc", &long, 0).await.unwrap();
+        edit(&store, "doc", &long, &format!("{}!", long)).await;
+
+        let missed = store.missed("doc", 0, 1).await.unwrap().unwrap();
+        assert_eq!(
+            missed,
+            vec![r#"{"seq":1,"type":"edit","start":10000,"deleted":0,"text":"!"}"#]
+        );
+
+        // A change made to other content is refused
+        let change = Change::between("other", "other!").unwrap();
+        let refused = store
+            .publish_change("doc", "other", &change, "x@example.com")
+            .await
+            .unwrap();
+        assert_eq!(refused, None);
+        assert_eq!(store.snapshot("doc").await.unwrap().1, 1);
+    }
+
+    #[tokio::test]
+    async fn stale_flush_keeps_live_content_aside() {
+        let store = MemoryStore::new();
+        let state = store.app_state();
+        let document_id = document(&store, "owner@example.com", "persisted").await;
+        store.open(&document_id, "persisted", 0).await.unwrap();
+        edit(&store, &document_id, "persisted", "live edit").await;
+
+        // Another writer persists content in between
+        let write = ContentWrite {
+            content: "written elsewhere",
+            last_edit: None,
+            snapshot_seq: None,
+        };
+        store
+            .write_content(&document_id, write, WriteCondition::AtRevision(0))
+            .await
+            .unwrap()
+            .unwrap();
+
+        let flushed = ws_handler::flush_document(&state, &document_id).await;
+
+        assert!(
+            flushed
+                .unwrap_err()
+                .downcast_ref::<WriteConflict>()
+                .is_some()
+        );
+        assert_eq!(store.recovered_versions(&document_id), vec!["live edit"]);
+        assert_eq!(
+            store.content(&document_id).await.unwrap().as_deref(),
+            Some("written elsewhere")
+        );
+    }
+
+    #[tokio::test]
+    async fn live_content_is_only_replaced_where_it_was_read() {
+        let store = MemoryStore::new();
+        let state = store.app_state();
+        let document_id = document(&store, "owner@example.com", "draft").await;
+        store.open(&document_id, "draft", 0).await.unwrap();
+
+        // An edit lands between reading the content and replacing it
+        edit(&store, &document_id, "draft", "draft, edited").await;
+        let replaced = ws_handler::replace_content(
+            &state,
+            &document_id,
+            "draft",
+            "final",
+            "reviewer@example.com",
+        )
+        .await;
+
+        assert!(
+            replaced
+                .unwrap_err()
+                .downcast_ref::<WriteConflict>()
+                .is_some()
+        );
+        assert_eq!(
+            store.content(&document_id).await.unwrap().as_deref(),
+            Some("draft, edited")
+        );
+
+        ws_handler::replace_content(
+            &state,
+            &document_id,
+            "draft, edited",
+            "final",
+            "reviewer@example.com",
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            store.content(&document_id).await.unwrap().as_deref(),
+            Some("final")
+        );
+    }
+}
@@ -0,0 +1,576 @@
+use std::error::Error;
+use std::fmt;
+
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+use tokio::sync::mpsc;
+
+use crate::authz::{Grant, Role};
+use crate::structs::{
+    AuditEntry, CommentReply, CommentThread, Document, FolderRow, GetAuditLogRequest, GroupSummary,
+    GroupsRequest, Publication, ShareLinkRow, Suggestion, SuggestionStatus, Template, TextRange,
+    UserRow, WsServerMessage,
+};
+use crate::updates::Change;
+
+pub mod memory;
+pub mod mongo;
+pub mod postgres;
+pub mod redis;
+
+// What went wrong in a storage backend. Constraint violations are caused by
+// the request, everything else is a failure of the backend.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StoreErrorKind {
+    // A unique constraint, e.g. a second group with the same name
+    Duplicate,
+    // A foreign key, e.g. sharing with a user that does not exist
+    MissingReference,
+    Other,
+}
+
+// A storage backend failed, carries the message of the underlying error
+#[derive(Debug)]
+pub struct StoreError {
+    kind: StoreErrorKind,
+    message: String,
+}
+
+impl StoreError {
+    pub fn new(message: impl Into<String>) -> StoreError {
+        StoreError {
+            kind: StoreErrorKind::Other,
+            message: message.into(),
+        }
+    }
+
+    pub fn duplicate(message: impl Into<String>) -> StoreError {
+        StoreError {
+            kind: StoreErrorKind::Duplicate,
+            message: message.into(),
+        }
+    }
+
+    pub fn missing_reference(message: impl Into<String>) -> StoreError {
+        StoreError {
+            kind: StoreErrorKind::MissingReference,
+            message: message.into(),
+        }
+    }
+
+    pub fn kind(&self) -> StoreErrorKind {
+        self.kind
+    }
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl Error for StoreError {}
+
+impl From<sqlx::Error> for StoreError {
+    fn from(e: sqlx::Error) -> Self {
+        let kind = match e.as_database_error().map(|db| db.kind()) {
+            Some(sqlx::error::ErrorKind::UniqueViolation) => StoreErrorKind::Duplicate,
+            Some(sqlx::error::ErrorKind::ForeignKeyViolation) => StoreErrorKind::MissingReference,
+            _ => StoreErrorKind::Other,
+        };
+        StoreError {
+            kind,
+            message: e.to_string(),
+        }
+    }
+}
+
+impl From<mongodb::error::Error> for StoreError {
+    fn from(e: mongodb::error::Error) -> Self {
+        StoreError::new(e.to_string())
+    }
+}
+
+impl From<mongodb::bson::ser::Error> for StoreError {
+    fn from(e: mongodb::bson::ser::Error) -> Self {
+        StoreError::new(e.to_string())
+    }
+}
+
+impl From<::redis::RedisError> for StoreError {
+    fn from(e: ::redis::RedisError) -> Self {
+        StoreError::new(e.to_string())
+    }
+}
+
+pub type StoreResult<T> = Result<T, StoreError>;
+
+// New content for a document along with, when known, its last edit and the
+// last update of the op log it includes
+pub struct ContentWrite<'a> {
+    pub content: &'a str,
+    pub last_edit: Option<(String, DateTime<Utc>)>,
+    pub snapshot_seq: Option<u64>,
+}
+
+// When a content write goes through, so it never goes backwards
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteCondition {
+    // The document is still at this revision
+    AtRevision(i64),
+    // A flush of live content: the document is still at the revision the
+    // content was loaded from, or at an earlier snapshot of the same op log
+    Flush { base: i64, seq: u64 },
+}
+
+// Content that did not make it into its document, kept for the owner
+pub struct RecoveredVersion<'a> {
+    pub document_id: &'a str,
+    pub content: &'a str,
+    pub seq: u64,
+    pub last_edited_by: Option<String>,
+    pub reason: &'a str,
+}
+
+pub struct NewShareLink<'a> {
+    pub token: &'a str,
+    pub document_id: &'a str,
+    pub role: Role,
+    pub created_by: &'a str,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub max_uses: Option<i32>,
+}
+
+// The lease on the edit lock of a document and how long it has left
+pub struct Lease {
+    pub value: String,
+    pub expires_in_ms: i64,
+}
+
+// ***************************************************************************************************************************************
+// Documents with their comments and suggestions, MongoDB in production
+
+#[async_trait]
+pub trait DocumentStore: Send + Sync {
+    async fn get(&self, document_id: &str) -> StoreResult<Option<Document>>;
+
+    async fn find_published(&self, public_id: &str) -> StoreResult<Option<Document>>;
+
+    async fn templates(&self) -> StoreResult<Vec<Document>>;
+
+    // Returns the id of the new document
+    async fn insert(&self, document: Document) -> StoreResult<String>;
+
+    // Removes the document along with its comments and suggestions
+    async fn delete(&self, document_id: &str) -> StoreResult<()>;
+
+    async fn set_publication(
+        &self,
+        document_id: &str,
+        publication: Option<Publication>,
+    ) -> StoreResult<()>;
+
+    async fn set_template(&self, document_id: &str, template: Option<Template>) -> StoreResult<()>;
+
+    async fn set_exclusive_editing(&self, document_id: &str, enabled: bool) -> StoreResult<()>;
+
+    async fn set_title(&self, document_id: &str, title: &str) -> StoreResult<()>;
+
+    // Persists new content and increments the revision if the condition holds.
+    // Returns the document as written, None when the condition did not hold
+    // or the document does not exist.
+    async fn write_content(
+        &self,
+        document_id: &str,
+        write: ContentWrite<'_>,
+        condition: WriteCondition,
+    ) -> StoreResult<Option<Document>>;
+
+    async fn store_version(&self, version: RecoveredVersion<'_>) -> StoreResult<()>;
+
+    // Threads ordered by the start of their anchor
+    async fn comment_threads(
+        &self,
+        document_id: &str,
+        include_resolved: bool,
+    ) -> StoreResult<Vec<CommentThread>>;
+
+    async fn insert_thread(&self, thread: &CommentThread) -> StoreResult<()>;
+
+    async fn add_reply(
+        &self,
+        document_id: &str,
+        thread_id: &str,
+        reply: CommentReply,
+    ) -> StoreResult<Option<CommentThread>>;
+
+    // Resolves the thread when given the user resolving it, reopens it
+    // otherwise
+    async fn set_resolved(
+        &self,
+        document_id: &str,
+        thread_id: &str,
+        resolved_by: Option<&str>,
+    ) -> StoreResult<Option<CommentThread>>;
+
+    // Threads whose anchor ends at or after the position
+    async fn threads_ending_from(
+        &self,
+        document_id: &str,
+        position: usize,
+    ) -> StoreResult<Vec<CommentThread>>;
+
+    async fn move_thread(&self, thread_id: &str, anchor: TextRange) -> StoreResult<()>;
+
+    // Suggestions ordered by the start of their range
+    async fn suggestions(
+        &self,
+        document_id: &str,
+        include_reviewed: bool,
+    ) -> StoreResult<Vec<Suggestion>>;
+
+    async fn pending_suggestion(
+        &self,
+        document_id: &str,
+        suggestion_id: &str,
+    ) -> StoreResult<Option<Suggestion>>;
+
+    // Updates a pending suggestion of the author overlapping the range, if
+    // there is one
+    async fn update_overlapping(
+        &self,
+        document_id: &str,
+        author: &str,
+        range: TextRange,
+        original: &str,
+        replacement: &str,
+    ) -> StoreResult<Option<Suggestion>>;
+
+    async fn insert_suggestion(&self, suggestion: &Suggestion) -> StoreResult<()>;
+
+    // Marks a pending suggestion as reviewed
+    async fn review_suggestion(
+        &self,
+        document_id: &str,
+        suggestion_id: &str,
+        status: SuggestionStatus,
+        reviewer: &str,
+    ) -> StoreResult<Option<Suggestion>>;
+
+    // Pending suggestions whose range ends at or after the position
+    async fn pending_ending_from(
+        &self,
+        document_id: &str,
+        position: usize,
+    ) -> StoreResult<Vec<Suggestion>>;
+
+    async fn move_suggestion(&self, suggestion_id: &str, range: TextRange) -> StoreResult<()>;
+}
+
+// ***************************************************************************************************************************************
+// Users, groups, folders and who may open which document, PostgreSQL in
+// production
+
+#[async_trait]
+pub trait AccessStore: Send + Sync {
+    async fn find_user(&self, email: &str, password: &str) -> StoreResult<Option<UserRow>>;
+
+    // Every grant the user currently holds on the document, directly, through
+    // groups or through the folders containing it. Grants outside of their
+    // validity window are left out. Folder grants only count for folders that
+    // pass their permissions on to their contents.
+    async fn grants(&self, email: &str, document_id: &str) -> StoreResult<Vec<Grant>>;
+
+    // Resolves a share link token into the grant it would give the user on
+    // the document, without redeeming it. Users that already redeemed the
+    // link keep access as long as it is valid, others only while it has uses
+    // left.
+    async fn share_link_grant(
+        &self,
+        token: &str,
+        email: &str,
+        document_id: &str,
+    ) -> StoreResult<Option<Grant>>;
+
+    // Redeems the link for the user, which counts towards its maximum uses
+    // once per user. Returns false when the link has no uses left or is no
+    // longer valid.
+    async fn redeem_share_link(&self, link_id: i32, email: &str) -> StoreResult<bool>;
+
+    async fn add_relation(&self, document_id: &str, email: &str, role: Role) -> StoreResult<()>;
+
+    async fn add_group_relation(&self, document_id: &str, group_id: i32) -> StoreResult<()>;
+
+    // Gives the user the role, replacing the one they had
+    async fn share(
+        &self,
+        document_id: &str,
+        email: &str,
+        role: Role,
+        valid_from: Option<DateTime<Utc>>,
+        valid_until: Option<DateTime<Utc>>,
+    ) -> StoreResult<()>;
+
+    // Removes any role but the owner's, returns whether there was one
+    async fn unshare(&self, document_id: &str, email: &str) -> StoreResult<bool>;
+
+    async fn share_with_group(
+        &self,
+        document_id: &str,
+        group_id: i32,
+        valid_from: Option<DateTime<Utc>>,
+        valid_until: Option<DateTime<Utc>>,
+    ) -> StoreResult<()>;
+
+    async fn unshare_group(&self, document_id: &str, group_id: i32) -> StoreResult<bool>;
+
+    // The previous owner stays on the document as an editor
+    async fn transfer_ownership(&self, document_id: &str, from: &str, to: &str) -> StoreResult<()>;
+
+    // Makes `owner` the owner of a copy of the source. With `copy_sharing` the
+    // users and groups the source is shared with get the same roles on it.
+    async fn copy_relations(
+        &self,
+        owner: &str,
+        source_id: &str,
+        document_id: &str,
+        copy_sharing: bool,
+    ) -> StoreResult<()>;
+
+    // Removes everything referring to a deleted document
+    async fn remove_document(&self, document_id: &str) -> StoreResult<()>;
+
+    async fn owned_documents(&self, email: &str) -> StoreResult<Vec<String>>;
+
+    // Documents shared with the user directly or through a group, within
+    // their validity window, each once
+    async fn shared_documents(&self, email: &str) -> StoreResult<Vec<String>>;
+
+    async fn owner_of(&self, document_id: &str) -> StoreResult<Option<String>>;
+
+    // Creates the group with its members, returns its id
+    async fn create_group(&self, group: &GroupsRequest) -> StoreResult<i32>;
+
+    async fn groups_by_owner(&self, email: &str) -> StoreResult<Vec<GroupSummary>>;
+
+    async fn group_owner(&self, group_id: i32) -> StoreResult<Option<String>>;
+
+    async fn group_members(&self, group_id: i32) -> StoreResult<Vec<String>>;
+
+    // Returns false when the user already is a member
+    async fn add_group_member(&self, group_id: i32, email: &str) -> StoreResult<bool>;
+
+    async fn remove_group_member(&self, group_id: i32, email: &str) -> StoreResult<bool>;
+
+    async fn create_share_link(&self, link: NewShareLink<'_>) -> StoreResult<ShareLinkRow>;
+
+    // Newest first
+    async fn share_links(&self, document_id: &str) -> StoreResult<Vec<ShareLinkRow>>;
+
+    async fn revoke_share_link(&self, document_id: &str, link_id: i32) -> StoreResult<bool>;
+
+    async fn folder_owner(&self, folder_id: i32) -> StoreResult<Option<String>>;
+
+    // The roles a user holds on a folder: owner, shared directly or through an
+    // ancestor passing on its permissions
+    async fn folder_roles(&self, email: &str, folder_id: i32) -> StoreResult<Vec<Role>>;
+
+    async fn create_folder(
+        &self,
+        name: &str,
+        owner: &str,
+        parent_id: Option<i32>,
+        inherit_permissions: bool,
+    ) -> StoreResult<FolderRow>;
+
+    async fn update_folder(
+        &self,
+        folder_id: i32,
+        name: Option<&str>,
+        inherit_permissions: Option<bool>,
+    ) -> StoreResult<FolderRow>;
+
+    // Whether `folder_id` is `ancestor_id` or one of its subfolders
+    async fn is_within(&self, folder_id: i32, ancestor_id: i32) -> StoreResult<bool>;
+
+    async fn move_folder(&self, folder_id: i32, parent_id: Option<i32>) -> StoreResult<()>;
+
+    // Removes the folder and its subfolders
+    async fn delete_folder(&self, folder_id: i32) -> StoreResult<()>;
+
+    // Files the document into the folder, or takes it out of any folder
+    async fn file_document(&self, document_id: &str, folder_id: Option<i32>) -> StoreResult<()>;
+
+    async fn share_folder(&self, folder_id: i32, email: &str, role: Role) -> StoreResult<()>;
+
+    async fn unshare_folder(&self, folder_id: i32, email: &str) -> StoreResult<bool>;
+
+    // Ordered by name
+    async fn subfolders(&self, folder_id: i32) -> StoreResult<Vec<FolderRow>>;
+
+    async fn folder_documents(&self, folder_id: i32) -> StoreResult<Vec<String>>;
+
+    // The user's own top-level folders and the folders shared with them,
+    // ordered by name
+    async fn top_level_folders(&self, email: &str) -> StoreResult<Vec<FolderRow>>;
+
+    // Documents owned by the user outside of any folder
+    async fn unfiled_documents(&self, email: &str) -> StoreResult<Vec<String>>;
+}
+
+// ***************************************************************************************************************************************
+// Tags, favorites and recently opened documents of each user, PostgreSQL in
+// production
+
+#[async_trait]
+pub trait LibraryStore: Send + Sync {
+    // Document id and tag pairs, ordered by tag
+    async fn tags(&self, email: &str) -> StoreResult<Vec<(String, String)>>;
+
+    // Every tag with the number of documents carrying it, ordered by tag
+    async fn tag_counts(&self, email: &str) -> StoreResult<Vec<(String, i64)>>;
+
+    async fn add_tag(&self, email: &str, document_id: &str, tag: &str) -> StoreResult<()>;
+
+    async fn remove_tag(&self, email: &str, document_id: &str, tag: &str) -> StoreResult<bool>;
+
+    // Most recently starred first
+    async fn favorites(&self, email: &str) -> StoreResult<Vec<String>>;
+
+    async fn set_favorite(&self, email: &str, document_id: &str, favorite: bool)
+    -> StoreResult<()>;
+
+    // Most recently opened first
+    async fn recent(&self, email: &str, limit: i64) -> StoreResult<Vec<(String, DateTime<Utc>)>>;
+
+    // Records an opening, keeping only the `keep` most recent ones
+    async fn record_open(&self, email: &str, document_id: &str, keep: i64) -> StoreResult<()>;
+}
+
+// ***************************************************************************************************************************************
+// Audit trail, PostgreSQL in production
+
+#[async_trait]
+pub trait AuditLog: Send + Sync {
+    async fn append(
+        &self,
+        event_type: &str,
+        actor: Option<&str>,
+        document_id: Option<&str>,
+        details: Value,
+    ) -> StoreResult<()>;
+
+    // Entries of the document matching the filters of the request, newest
+    // first
+    async fn entries(&self, query: &GetAuditLogRequest, limit: i64)
+    -> StoreResult<Vec<AuditEntry>>;
+}
+
+// ***************************************************************************************************************************************
+// Content of open documents and the state shared by the sessions on them,
+// Redis in production
+
+#[async_trait]
+pub trait LiveDocCache: Send + Sync {
+    async fn content(&self, document_id: &str) -> StoreResult<Option<String>>;
+
+    async fn is_open(&self, document_id: &str) -> StoreResult<bool>;
+
+    // Loads persisted content, along with the revision it was persisted at
+    async fn open(&self, document_id: &str, content: &str, revision: i64) -> StoreResult<()>;
+
+    async fn open_documents(&self) -> StoreResult<Vec<String>>;
+
+    // Drops the content and metadata of a document nobody has open anymore
+    async fn close(&self, document_id: &str) -> StoreResult<()>;
+
+    async fn last_edit(&self, document_id: &str) -> StoreResult<Option<(String, DateTime<Utc>)>>;
+
+    async fn set_last_edit(
+        &self,
+        document_id: &str,
+        editor: &str,
+        at: DateTime<Utc>,
+    ) -> StoreResult<()>;
+
+    async fn clear_last_edit(&self, document_id: &str) -> StoreResult<()>;
+
+    // The revision the live content is based on
+    async fn base_revision(&self, document_id: &str) -> StoreResult<Option<i64>>;
+
+    async fn set_base_revision(&self, document_id: &str, revision: i64) -> StoreResult<()>;
+
+    // Takes the lease if it is free, renewing it if it already holds this
+    // value
+    async fn acquire_lease(&self, document_id: &str, value: &str, ttl_ms: i64)
+    -> StoreResult<bool>;
+
+    // Renews the lease only if it still holds this value
+    async fn renew_lease(&self, document_id: &str, value: &str, ttl_ms: i64) -> StoreResult<bool>;
+
+    // Releases the lease only if it still holds this value
+    async fn release_lease(&self, document_id: &str, value: &str) -> StoreResult<bool>;
+
+    async fn break_lease(&self, document_id: &str) -> StoreResult<()>;
+
+    async fn lease(&self, document_id: &str) -> StoreResult<Option<Lease>>;
+
+    async fn clear_leases(&self) -> StoreResult<()>;
+
+    // Takes the anchor lock of a document, false while another token holds
+    // it. It runs out after `ttl_ms` if its holder goes away.
+    async fn lock_anchors(&self, document_id: &str, token: &str, ttl_ms: i64) -> StoreResult<bool>;
+
+    // Releases the anchor lock only if it still holds this token
+    async fn unlock_anchors(&self, document_id: &str, token: &str) -> StoreResult<()>;
+}
+
+// ***************************************************************************************************************************************
+// The append-only op log of every document, the updates sessions relay to
+// their clients. Redis Streams in production.
+
+#[async_trait]
+pub trait PubSub: Send + Sync {
+    // Appends an update to the log of the document, returns its sequence
+    // number
+    async fn publish(&self, document_id: &str, message: &WsServerMessage) -> StoreResult<u64>;
+
+    // Applies a change to the live content and appends it to the log in one
+    // step, returns its sequence number. None, and nothing appended, when the
+    // live content is no longer `previous`.
+    async fn publish_change(
+        &self,
+        document_id: &str,
+        previous: &str,
+        change: &Change,
+        author: &str,
+    ) -> StoreResult<Option<u64>>;
+
+    // The live content with the sequence number it is at, read together
+    async fn snapshot(&self, document_id: &str) -> StoreResult<(Option<String>, u64)>;
+
+    // Delivers every update after `after_seq` as it is appended, until the
+    // receiver is dropped
+    async fn subscribe(
+        &self,
+        document_id: &str,
+        after_seq: u64,
+    ) -> StoreResult<mpsc::UnboundedReceiver<String>>;
+
+    // The updates after `last_seq`, in order. None when they are no longer all
+    // available and the client needs a snapshot.
+    async fn missed(
+        &self,
+        document_id: &str,
+        last_seq: u64,
+        current: u64,
+    ) -> StoreResult<Option<Vec<String>>>;
+
+    // Drops the updates already part of a persisted snapshot, keeping the
+    // most recent ones for reconnecting clients
+    async fn compact(&self, document_id: &str, snapshot_seq: u64) -> StoreResult<()>;
+
+    // Forgets the log, after content was changed around it
+    async fn reset(&self, document_id: &str) -> StoreResult<()>;
+}
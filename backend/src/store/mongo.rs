@@ -0,0 +1,471 @@
+use axum::async_trait;
+use chrono::{DateTime, SecondsFormat, Utc};
+use futures_util::TryStreamExt;
+use mongodb::bson::oid::ObjectId;
+use mongodb::bson::{Bson, doc};
+use mongodb::options::{FindOneAndUpdateOptions, FindOptions, ReturnDocument};
+use mongodb::{Collection, Database};
+
+use crate::store::{ContentWrite, DocumentStore, RecoveredVersion, StoreResult, WriteCondition};
+use crate::structs::{
+    CommentReply, CommentThread, ContentStats, Document, Publication, Suggestion, SuggestionStatus,
+    Template, TextRange,
+};
+
+// Documents, comments and suggestions in MongoDB
+#[derive(Clone)]
+pub struct MongoStore {
+    db: Database,
+}
+
+impl MongoStore {
+    pub fn new(db: Database) -> MongoStore {
+        MongoStore { db }
+    }
+
+    fn documents(&self) -> Collection<Document> {
+        self.db.collection::<Document>("documents")
+    }
+
+    fn comments(&self) -> Collection<CommentThread> {
+        self.db.collection::<CommentThread>("comments")
+    }
+
+    fn suggestions(&self) -> Collection<Suggestion> {
+        self.db.collection::<Suggestion>("suggestions")
+    }
+
+    // Sets a field of a document, or removes it without a value
+    async fn set_field(
+        &self,
+        document_id: &str,
+        field: &str,
+        value: Option<mongodb::bson::Document>,
+    ) -> StoreResult<()> {
+        let Ok(obj_id) = ObjectId::parse_str(document_id) else {
+            return Ok(());
+        };
+        let update = match value {
+            Some(value) => doc! { "$set": { field: value } },
+            None => doc! { "$unset": { field: "" } },
+        };
+        self.documents()
+            .update_one(doc! { "_id": obj_id }, update, None)
+            .await?;
+        Ok(())
+    }
+}
+
+fn return_after() -> FindOneAndUpdateOptions {
+    FindOneAndUpdateOptions::builder()
+        .return_document(ReturnDocument::After)
+        .build()
+}
+
+// The MongoDB update persisting content along with the figures derived from
+// it and, when known, its last edit and the last update of the op log it
+// includes
+
+fn content_update(
+    content: &str,
+    last_edit: Option<(String, DateTime<Utc>)>,
+    snapshot_seq: Option<u64>,
+) -> mongodb::bson::Document {
+    let stats = ContentStats::of(content);
+    let mut set = doc! {
+        "content": content,
+        "word_count": stats.word_count,
+        "char_count": stats.char_count,
+        "content_size": stats.content_size,
+    };
+    if let Some((editor, at)) = last_edit {
+        set.insert("last_edited_by", editor);
+        set.insert(
+            "updated_at",
+            at.to_rfc3339_opts(SecondsFormat::AutoSi, true),
+        );
+    }
+    let revision = doc! { "revision": 1_i64 };
+    match snapshot_seq {
+        Some(seq) => {
+            set.insert("snapshot_seq", seq as i64);
+            doc! { "$set": set, "$inc": revision }
+        }
+        // Content written around the op log starts a new one
+        None => doc! { "$set": set, "$inc": revision, "$unset": { "snapshot_seq": "" } },
+    }
+}
+
+// Filter matching a document at the given revision. Documents persisted
+// before revisions were introduced have none, they count as revision 0.
+fn at_revision(revision: i64) -> mongodb::bson::Document {
+    if revision == 0 {
+        doc! { "revision": { "$in": [0_i64, Bson::Null] } }
+    } else {
+        doc! { "revision": revision }
+    }
+}
+
+#[async_trait]
+impl DocumentStore for MongoStore {
+    async fn get(&self, document_id: &str) -> StoreResult<Option<Document>> {
+        let Ok(obj_id) = ObjectId::parse_str(document_id) else {
+            return Ok(None);
+        };
+        let document = self
+            .documents()
+            .find_one(doc! { "_id": obj_id }, None)
+            .await?;
+        Ok(document)
+    }
+
+    async fn find_published(&self, public_id: &str) -> StoreResult<Option<Document>> {
+        let document = self
+            .documents()
+            .find_one(doc! { "publication.public_id": public_id }, None)
+            .await?;
+        Ok(document)
+    }
+
+    async fn templates(&self) -> StoreResult<Vec<Document>> {
+        let templates = self
+            .documents()
+            .find(doc! { "template": { "$exists": true } }, None)
+            .await?
+            .try_collect()
+            .await?;
+        Ok(templates)
+    }
+
+    async fn insert(&self, mut document: Document) -> StoreResult<String> {
+        let obj_id = ObjectId::new();
+        document.id = Some(obj_id);
+        self.documents().insert_one(document, None).await?;
+        Ok(obj_id.to_hex())
+    }
+
+    async fn delete(&self, document_id: &str) -> StoreResult<()> {
+        if let Ok(obj_id) = ObjectId::parse_str(document_id) {
+            self.documents()
+                .delete_one(doc! { "_id": obj_id }, None)
+                .await?;
+        }
+        let filter = doc! { "document_id": document_id };
+        self.comments().delete_many(filter.clone(), None).await?;
+        self.suggestions().delete_many(filter, None).await?;
+        Ok(())
+    }
+
+    async fn set_publication(
+        &self,
+        document_id: &str,
+        publication: Option<Publication>,
+    ) -> StoreResult<()> {
+        let value = publication
+            .map(|p| mongodb::bson::to_document(&p))
+            .transpose()?;
+        self.set_field(document_id, "publication", value).await
+    }
+
+    async fn set_template(&self, document_id: &str, template: Option<Template>) -> StoreResult<()> {
+        let value = template
+            .map(|t| mongodb::bson::to_document(&t))
+            .transpose()?;
+        self.set_field(document_id, "template", value).await
+    }
+
+    async fn set_exclusive_editing(&self, document_id: &str, enabled: bool) -> StoreResult<()> {
+        let Ok(obj_id) = ObjectId::parse_str(document_id) else {
+            return Ok(());
+        };
+        self.documents()
+            .update_one(
+                doc! { "_id": obj_id },
+                doc! { "$set": { "exclusive_editing": enabled } },
+                None,
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn set_title(&self, document_id: &str, title: &str) -> StoreResult<()> {
+        let Ok(obj_id) = ObjectId::parse_str(document_id) else {
+            return Ok(());
+        };
+        self.documents()
+            .update_one(
+                doc! { "_id": obj_id },
+                doc! { "$set": { "title": title } },
+                None,
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn write_content(
+        &self,
+        document_id: &str,
+        write: ContentWrite<'_>,
+        condition: WriteCondition,
+    ) -> StoreResult<Option<Document>> {
+        let Ok(obj_id) = ObjectId::parse_str(document_id) else {
+            return Ok(None);
+        };
+        let mut filter = match condition {
+            WriteCondition::AtRevision(revision) => at_revision(revision),
+            WriteCondition::Flush { base, seq } => doc! {
+                "$or": [at_revision(base), { "snapshot_seq": { "$lte": seq as i64 } }],
+            },
+        };
+        filter.insert("_id", obj_id);
+
+        let written = self
+            .documents()
+            .find_one_and_update(
+                filter,
+                content_update(write.content, write.last_edit, write.snapshot_seq),
+                return_after(),
+            )
+            .await?;
+        Ok(written)
+    }
+
+    async fn store_version(&self, version: RecoveredVersion<'_>) -> StoreResult<()> {
+        self.db
+            .collection::<mongodb::bson::Document>("recovered_versions")
+            .insert_one(
+                doc! {
+                    "document_id": version.document_id,
+                    "content": version.content,
+                    "seq": version.seq as i64,
+                    "last_edited_by": version.last_edited_by,
+                    "reason": version.reason,
+                    "recovered_at": Utc::now().to_rfc3339(),
+                },
+                None,
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn comment_threads(
+        &self,
+        document_id: &str,
+        include_resolved: bool,
+    ) -> StoreResult<Vec<CommentThread>> {
+        let filter = if include_resolved {
+            doc! { "document_id": document_id }
+        } else {
+            doc! { "document_id": document_id, "resolved": false }
+        };
+        let options = FindOptions::builder()
+            .sort(doc! { "anchor.start": 1 })
+            .build();
+
+        let threads = self
+            .comments()
+            .find(filter, options)
+            .await?
+            .try_collect()
+            .await?;
+        Ok(threads)
+    }
+
+    async fn insert_thread(&self, thread: &CommentThread) -> StoreResult<()> {
+        self.comments().insert_one(thread, None).await?;
+        Ok(())
+    }
+
+    async fn add_reply(
+        &self,
+        document_id: &str,
+        thread_id: &str,
+        reply: CommentReply,
+    ) -> StoreResult<Option<CommentThread>> {
+        let reply_doc = mongodb::bson::to_document(&reply)?;
+        let thread = self
+            .comments()
+            .find_one_and_update(
+                doc! { "_id": thread_id, "document_id": document_id },
+                doc! { "$push": { "replies": reply_doc } },
+                return_after(),
+            )
+            .await?;
+        Ok(thread)
+    }
+
+    async fn set_resolved(
+        &self,
+        document_id: &str,
+        thread_id: &str,
+        resolved_by: Option<&str>,
+    ) -> StoreResult<Option<CommentThread>> {
+        let thread = self
+            .comments()
+            .find_one_and_update(
+                doc! { "_id": thread_id, "document_id": document_id },
+                doc! { "$set": { "resolved": resolved_by.is_some(), "resolved_by": resolved_by } },
+                return_after(),
+            )
+            .await?;
+        Ok(thread)
+    }
+
+    async fn threads_ending_from(
+        &self,
+        document_id: &str,
+        position: usize,
+    ) -> StoreResult<Vec<CommentThread>> {
+        let filter = doc! {
+            "document_id": document_id,
+            "anchor.end": { "$gte": position as i64 },
+        };
+        let threads = self
+            .comments()
+            .find(filter, None)
+            .await?
+            .try_collect()
+            .await?;
+        Ok(threads)
+    }
+
+    async fn move_thread(&self, thread_id: &str, anchor: TextRange) -> StoreResult<()> {
+        self.comments()
+            .update_one(
+                doc! { "_id": thread_id },
+                doc! { "$set": {
+                    "anchor.start": anchor.start as i64,
+                    "anchor.end": anchor.end as i64,
+                } },
+                None,
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn suggestions(
+        &self,
+        document_id: &str,
+        include_reviewed: bool,
+    ) -> StoreResult<Vec<Suggestion>> {
+        let filter = if include_reviewed {
+            doc! { "document_id": document_id }
+        } else {
+            doc! { "document_id": document_id, "status": "pending" }
+        };
+        let options = FindOptions::builder()
+            .sort(doc! { "range.start": 1 })
+            .build();
+
+        let suggestions = self
+            .suggestions()
+            .find(filter, options)
+            .await?
+            .try_collect()
+            .await?;
+        Ok(suggestions)
+    }
+
+    async fn pending_suggestion(
+        &self,
+        document_id: &str,
+        suggestion_id: &str,
+    ) -> StoreResult<Option<Suggestion>> {
+        let suggestion = self
+            .suggestions()
+            .find_one(
+                doc! { "_id": suggestion_id, "document_id": document_id, "status": "pending" },
+                None,
+            )
+            .await?;
+        Ok(suggestion)
+    }
+
+    async fn update_overlapping(
+        &self,
+        document_id: &str,
+        author: &str,
+        range: TextRange,
+        original: &str,
+        replacement: &str,
+    ) -> StoreResult<Option<Suggestion>> {
+        let overlapping = doc! {
+            "document_id": document_id,
+            "author": author,
+            "status": "pending",
+            "range.start": { "$lte": range.end as i64 },
+            "range.end": { "$gte": range.start as i64 },
+        };
+        let suggestion = self
+            .suggestions()
+            .find_one_and_update(
+                overlapping,
+                doc! { "$set": {
+                    "range.start": range.start as i64,
+                    "range.end": range.end as i64,
+                    "original": original,
+                    "replacement": replacement,
+                } },
+                return_after(),
+            )
+            .await?;
+        Ok(suggestion)
+    }
+
+    async fn insert_suggestion(&self, suggestion: &Suggestion) -> StoreResult<()> {
+        self.suggestions().insert_one(suggestion, None).await?;
+        Ok(())
+    }
+
+    async fn review_suggestion(
+        &self,
+        document_id: &str,
+        suggestion_id: &str,
+        status: SuggestionStatus,
+        reviewer: &str,
+    ) -> StoreResult<Option<Suggestion>> {
+        let status = mongodb::bson::to_bson(&status)?;
+        let suggestion = self
+            .suggestions()
+            .find_one_and_update(
+                doc! { "_id": suggestion_id, "document_id": document_id, "status": "pending" },
+                doc! { "$set": { "status": status, "reviewed_by": reviewer } },
+                return_after(),
+            )
+            .await?;
+        Ok(suggestion)
+    }
+
+    async fn pending_ending_from(
+        &self,
+        document_id: &str,
+        position: usize,
+    ) -> StoreResult<Vec<Suggestion>> {
+        let filter = doc! {
+            "document_id": document_id,
+            "status": "pending",
+            "range.end": { "$gte": position as i64 },
+        };
+        let suggestions = self
+            .suggestions()
+            .find(filter, None)
+            .await?
+            .try_collect()
+            .await?;
+        Ok(suggestions)
+    }
+
+    async fn move_suggestion(&self, suggestion_id: &str, range: TextRange) -> StoreResult<()> {
+        self.suggestions()
+            .update_one(
+                doc! { "_id": suggestion_id },
+                doc! { "$set": {
+                    "range.start": range.start as i64,
+                    "range.end": range.end as i64,
+                } },
+                None,
+            )
+            .await?;
+        Ok(())
+    }
+}
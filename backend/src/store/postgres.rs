@@ -0,0 +1,1053 @@
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+use sqlx::{PgPool, Row};
+
+use crate::authz::{Grant, GrantSource, Role};
+use crate::store::{AccessStore, AuditLog, LibraryStore, NewShareLink, StoreError, StoreResult};
+use crate::structs::{
+    AuditEntry, FolderRow, GetAuditLogRequest, GroupSummary, GroupsRequest, ShareLinkRow, UserRow,
+};
+
+// Users, access, libraries and the audit log in PostgreSQL
+#[derive(Clone)]
+pub struct PgStore {
+    pool: PgPool,
+}
+
+impl PgStore {
+    pub fn new(pool: PgPool) -> PgStore {
+        PgStore { pool }
+    }
+}
+
+fn parse_role(role: &str) -> StoreResult<Role> {
+    role.parse::<Role>().map_err(StoreError::new)
+}
+
+// ***************************************************************************************************************************************
+
+#[async_trait]
+impl AccessStore for PgStore {
+    async fn find_user(&self, email: &str, password: &str) -> StoreResult<Option<UserRow>> {
+        let user = sqlx::query_as!(
+            UserRow,
+            "SELECT email, first_name, last_name FROM users WHERE email = $1 AND password = $2",
+            email,
+            password
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(user)
+    }
+
+    async fn grants(&self, email: &str, document_id: &str) -> StoreResult<Vec<Grant>> {
+        let rows = sqlx::query!(
+            r#"
+            WITH RECURSIVE ancestors AS (
+                SELECT f.folder_id, f.parent_id, f.inherit_permissions
+                FROM folder_documents d
+                JOIN folders f ON f.folder_id = d.folder_id
+                WHERE d.document_id = $2
+                UNION ALL
+                SELECT f.folder_id, f.parent_id, f.inherit_permissions
+                FROM folders f
+                JOIN ancestors a ON f.folder_id = a.parent_id
+            )
+            SELECT user_role AS "role!", NULL::int AS group_id, NULL::int AS folder_id, valid_until
+            FROM document_relation
+            WHERE user_email = $1
+            AND document_id = $2
+            AND (valid_from IS NULL OR valid_from <= now())
+            AND (valid_until IS NULL OR valid_until > now())
+            UNION ALL
+            SELECT group_role, group_id, NULL::int, valid_until FROM groups
+            NATURAL JOIN document_relation_group
+            NATURAL JOIN group_members
+            WHERE document_id = $2
+            AND member_email = $1
+            AND (valid_from IS NULL OR valid_from <= now())
+            AND (valid_until IS NULL OR valid_until > now())
+            UNION ALL
+            SELECT r.user_role, NULL::int, r.folder_id, NULL::timestamptz FROM folder_relation r
+            JOIN ancestors a ON a.folder_id = r.folder_id
+            WHERE r.user_email = $1
+            AND a.inherit_permissions;
+            "#,
+            email,
+            document_id,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter()
+            .map(|row| {
+                let source = match (row.group_id, row.folder_id) {
+                    (Some(group_id), _) => GrantSource::Group { group_id },
+                    (None, Some(folder_id)) => GrantSource::Folder { folder_id },
+                    (None, None) => GrantSource::Direct,
+                };
+                Ok(Grant {
+                    role: parse_role(&row.role)?,
+                    source,
+                    valid_until: row.valid_until,
+                })
+            })
+            .collect()
+    }
+
+    async fn share_link_grant(
+        &self,
+        token: &str,
+        email: &str,
+        document_id: &str,
+    ) -> StoreResult<Option<Grant>> {
+        let link = sqlx::query!(
+            r#"
+            SELECT l.link_id, l.link_role AS "link_role!", l.expires_at
+            FROM share_links l
+            WHERE l.token = $1
+            AND l.document_id = $2
+            AND NOT l.revoked
+            AND (l.expires_at IS NULL OR l.expires_at > now())
+            AND (
+                l.max_uses IS NULL
+                OR l.use_count < l.max_uses
+                OR EXISTS (
+                    SELECT 1 FROM share_link_redemptions r
+                    WHERE r.link_id = l.link_id AND r.user_email = $3
+                )
+            )
+            "#,
+            token,
+            document_id,
+            email,
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        let Some(link) = link else {
+            return Ok(None);
+        };
+
+        Ok(Some(Grant {
+            role: parse_role(&link.link_role)?,
+            source: GrantSource::ShareLink {
+                link_id: link.link_id,
+            },
+            valid_until: link.expires_at,
+        }))
+    }
+
+    async fn redeem_share_link(&self, link_id: i32, email: &str) -> StoreResult<bool> {
+        let mut tx = self.pool.begin().await?;
+
+        let inserted = sqlx::query!(
+            "INSERT INTO share_link_redemptions (link_id, user_email) VALUES ($1, $2)
+             ON CONFLICT (link_id, user_email) DO NOTHING",
+            link_id,
+            email,
+        )
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+
+        // Redeemed before, it does not count again
+        if inserted == 0 {
+            return Ok(true);
+        }
+
+        // The row lock taken by the UPDATE keeps concurrent redemptions from
+        // going over the maximum number of uses
+        let counted = sqlx::query!(
+            r#"
+            UPDATE share_links SET use_count = use_count + 1
+            WHERE link_id = $1
+            AND NOT revoked
+            AND (expires_at IS NULL OR expires_at > now())
+            AND (max_uses IS NULL OR use_count < max_uses)
+            "#,
+            link_id,
+        )
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+
+        if counted == 0 {
+            return Ok(false);
+        }
+
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    async fn add_relation(&self, document_id: &str, email: &str, role: Role) -> StoreResult<()> {
+        sqlx::query!(
+            "INSERT INTO document_relation (user_email, document_id, user_role) VALUES ($1, $2, $3)",
+            email,
+            document_id,
+            role.as_str() as &str,
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn add_group_relation(&self, document_id: &str, group_id: i32) -> StoreResult<()> {
+        sqlx::query!(
+            "INSERT INTO document_relation_group (group_id, document_id) VALUES ($1, $2)",
+            group_id,
+            document_id,
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn share(
+        &self,
+        document_id: &str,
+        email: &str,
+        role: Role,
+        valid_from: Option<DateTime<Utc>>,
+        valid_until: Option<DateTime<Utc>>,
+    ) -> StoreResult<()> {
+        sqlx::query!(
+            r#"
+            INSERT INTO document_relation (user_email, document_id, user_role, valid_from, valid_until)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (user_email, document_id) DO UPDATE
+            SET user_role = EXCLUDED.user_role,
+                valid_from = EXCLUDED.valid_from,
+                valid_until = EXCLUDED.valid_until
+            "#,
+            email,
+            document_id,
+            role.as_str() as &str,
+            valid_from,
+            valid_until,
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn unshare(&self, document_id: &str, email: &str) -> StoreResult<bool> {
+        let result = sqlx::query!(
+            "DELETE FROM document_relation WHERE user_email = $1 AND document_id = $2 AND user_role <> $3",
+            email,
+            document_id,
+            "owner" as &str,
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn share_with_group(
+        &self,
+        document_id: &str,
+        group_id: i32,
+        valid_from: Option<DateTime<Utc>>,
+        valid_until: Option<DateTime<Utc>>,
+    ) -> StoreResult<()> {
+        sqlx::query!(
+            r#"
+            INSERT INTO document_relation_group (group_id, document_id, valid_from, valid_until)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (group_id, document_id) DO UPDATE
+            SET valid_from = EXCLUDED.valid_from, valid_until = EXCLUDED.valid_until
+            "#,
+            group_id,
+            document_id,
+            valid_from,
+            valid_until,
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn unshare_group(&self, document_id: &str, group_id: i32) -> StoreResult<bool> {
+        let result = sqlx::query!(
+            "DELETE FROM document_relation_group WHERE group_id = $1 AND document_id = $2",
+            group_id,
+            document_id,
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn transfer_ownership(&self, document_id: &str, from: &str, to: &str) -> StoreResult<()> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query!(
+            "UPDATE document_relation SET user_role = $3 WHERE user_email = $1 AND document_id = $2",
+            from,
+            document_id,
+            "editor" as &str,
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            r#"
+            INSERT INTO document_relation (user_email, document_id, user_role) VALUES ($1, $2, $3)
+            ON CONFLICT (user_email, document_id) DO UPDATE
+            SET user_role = EXCLUDED.user_role, valid_from = NULL, valid_until = NULL
+            "#,
+            to,
+            document_id,
+            "owner" as &str,
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn copy_relations(
+        &self,
+        owner: &str,
+        source_id: &str,
+        document_id: &str,
+        copy_sharing: bool,
+    ) -> StoreResult<()> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query!(
+            "INSERT INTO document_relation (user_email, document_id, user_role) VALUES ($1, $2, $3)",
+            owner,
+            document_id,
+            "owner" as &str,
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        if copy_sharing {
+            sqlx::query!(
+                r#"
+                INSERT INTO document_relation (user_email, document_id, user_role, valid_from, valid_until)
+                SELECT user_email, $2, user_role, valid_from, valid_until
+                FROM document_relation
+                WHERE document_id = $1
+                AND user_role <> 'owner'
+                AND user_email <> $3
+                "#,
+                source_id,
+                document_id,
+                owner,
+            )
+            .execute(&mut *tx)
+            .await?;
+
+            sqlx::query!(
+                r#"
+                INSERT INTO document_relation_group (group_id, document_id, valid_from, valid_until)
+                SELECT group_id, $2, valid_from, valid_until
+                FROM document_relation_group
+                WHERE document_id = $1
+                "#,
+                source_id,
+                document_id,
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn remove_document(&self, document_id: &str) -> StoreResult<()> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query!(
+            "DELETE FROM document_relation_group WHERE document_id = $1",
+            document_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            "DELETE FROM document_tags WHERE document_id = $1",
+            document_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            "DELETE FROM document_favorites WHERE document_id = $1",
+            document_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            "DELETE FROM recent_documents WHERE document_id = $1",
+            document_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            "DELETE FROM folder_documents WHERE document_id = $1",
+            document_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            "DELETE FROM share_link_redemptions WHERE link_id IN
+             (SELECT link_id FROM share_links WHERE document_id = $1)",
+            document_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            "DELETE FROM share_links WHERE document_id = $1",
+            document_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            "DELETE FROM document_relation WHERE document_id = $1",
+            document_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn owned_documents(&self, email: &str) -> StoreResult<Vec<String>> {
+        let document_ids = sqlx::query_scalar!(
+            "SELECT document_id FROM document_relation WHERE user_email = $1 AND user_role = $2",
+            email,
+            "owner" as &str,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(document_ids)
+    }
+
+    async fn shared_documents(&self, email: &str) -> StoreResult<Vec<String>> {
+        let document_ids = sqlx::query_scalar!(
+            r#"
+            SELECT document_id AS "document_id!" FROM document_relation
+            WHERE user_email = $1 AND user_role IN ($2, $3, $4)
+            AND (valid_from IS NULL OR valid_from <= now())
+            AND (valid_until IS NULL OR valid_until > now())
+            UNION
+            SELECT document_id FROM group_members NATURAL JOIN document_relation_group
+            WHERE member_email = $1
+            AND (valid_from IS NULL OR valid_from <= now())
+            AND (valid_until IS NULL OR valid_until > now())
+            "#,
+            email,
+            "editor" as &str,
+            "reader" as &str,
+            "suggester" as &str,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(document_ids)
+    }
+
+    async fn owner_of(&self, document_id: &str) -> StoreResult<Option<String>> {
+        let owner = sqlx::query_scalar!(
+            "SELECT user_email FROM document_relation WHERE document_id = $1 AND user_role = $2",
+            document_id,
+            "owner" as &str,
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(owner)
+    }
+
+    async fn create_group(&self, group: &GroupsRequest) -> StoreResult<i32> {
+        let mut tx = self.pool.begin().await?;
+
+        let row = sqlx::query(
+            "INSERT INTO groups (group_name, owner_email, group_role) VALUES ($1, $2, $3) RETURNING group_id",
+        )
+        .bind(&group.name)
+        .bind(&group.owner)
+        .bind(&group.role)
+        .fetch_one(&mut *tx)
+        .await?;
+        let group_id: i32 = row.try_get("group_id")?;
+
+        for member_email in group.members.iter() {
+            sqlx::query("INSERT INTO group_members (group_id, member_email) VALUES ($1, $2)")
+                .bind(group_id)
+                .bind(member_email)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        tx.commit().await?;
+        Ok(group_id)
+    }
+
+    async fn groups_by_owner(&self, email: &str) -> StoreResult<Vec<GroupSummary>> {
+        let groups = sqlx::query_as!(
+            GroupSummary,
+            r#"
+            SELECT group_id, group_name, owner_email, group_role AS "group_role!"
+            FROM groups WHERE owner_email = $1
+            "#,
+            email
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(groups)
+    }
+
+    async fn group_owner(&self, group_id: i32) -> StoreResult<Option<String>> {
+        let owner = sqlx::query_scalar!(
+            "SELECT owner_email FROM groups WHERE group_id = $1",
+            group_id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(owner)
+    }
+
+    async fn group_members(&self, group_id: i32) -> StoreResult<Vec<String>> {
+        let members = sqlx::query_scalar!(
+            "SELECT member_email FROM group_members WHERE group_id = $1 ORDER BY member_email",
+            group_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(members)
+    }
+
+    async fn add_group_member(&self, group_id: i32, email: &str) -> StoreResult<bool> {
+        // group_members has no key, the membership is checked by the insert
+        let result = sqlx::query!(
+            r#"
+            INSERT INTO group_members (group_id, member_email)
+            SELECT $1::int, $2::varchar
+            WHERE NOT EXISTS (
+                SELECT 1 FROM group_members WHERE group_id = $1 AND member_email = $2
+            )
+            "#,
+            group_id,
+            email
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn remove_group_member(&self, group_id: i32, email: &str) -> StoreResult<bool> {
+        let result = sqlx::query!(
+            "DELETE FROM group_members WHERE group_id = $1 AND member_email = $2",
+            group_id,
+            email
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn create_share_link(&self, link: NewShareLink<'_>) -> StoreResult<ShareLinkRow> {
+        let link = sqlx::query_as!(
+            ShareLinkRow,
+            r#"
+            INSERT INTO share_links (token, document_id, link_role, created_by, expires_at, max_uses)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING link_id, token, link_role AS "link_role!", created_by, created_at, expires_at,
+                max_uses, use_count, revoked
+            "#,
+            link.token,
+            link.document_id,
+            link.role.as_str() as &str,
+            link.created_by,
+            link.expires_at,
+            link.max_uses,
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(link)
+    }
+
+    async fn share_links(&self, document_id: &str) -> StoreResult<Vec<ShareLinkRow>> {
+        let links = sqlx::query_as!(
+            ShareLinkRow,
+            r#"
+            SELECT link_id, token, link_role AS "link_role!", created_by, created_at, expires_at,
+                max_uses, use_count, revoked
+            FROM share_links
+            WHERE document_id = $1
+            ORDER BY created_at DESC
+            "#,
+            document_id,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(links)
+    }
+
+    async fn revoke_share_link(&self, document_id: &str, link_id: i32) -> StoreResult<bool> {
+        let result = sqlx::query!(
+            "UPDATE share_links SET revoked = true WHERE link_id = $1 AND document_id = $2",
+            link_id,
+            document_id,
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn folder_owner(&self, folder_id: i32) -> StoreResult<Option<String>> {
+        let owner = sqlx::query_scalar!(
+            "SELECT owner_email FROM folders WHERE folder_id = $1",
+            folder_id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(owner)
+    }
+
+    async fn folder_roles(&self, email: &str, folder_id: i32) -> StoreResult<Vec<Role>> {
+        let rows = sqlx::query!(
+            r#"
+            WITH RECURSIVE ancestors AS (
+                SELECT folder_id, parent_id, inherit_permissions, owner_email, 0 AS depth
+                FROM folders
+                WHERE folder_id = $1
+                UNION ALL
+                SELECT f.folder_id, f.parent_id, f.inherit_permissions, f.owner_email, a.depth + 1
+                FROM folders f
+                JOIN ancestors a ON f.folder_id = a.parent_id
+            )
+            SELECT 'owner' AS "role!" FROM ancestors WHERE depth = 0 AND owner_email = $2
+            UNION ALL
+            SELECT r.user_role FROM folder_relation r
+            JOIN ancestors a ON a.folder_id = r.folder_id
+            WHERE r.user_email = $2
+            AND (a.depth = 0 OR a.inherit_permissions)
+            "#,
+            folder_id,
+            email,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter().map(|row| parse_role(&row.role)).collect()
+    }
+
+    async fn create_folder(
+        &self,
+        name: &str,
+        owner: &str,
+        parent_id: Option<i32>,
+        inherit_permissions: bool,
+    ) -> StoreResult<FolderRow> {
+        let folder = sqlx::query_as!(
+            FolderRow,
+            r#"
+            INSERT INTO folders (folder_name, owner_email, parent_id, inherit_permissions)
+            VALUES ($1, $2, $3, $4)
+            RETURNING folder_id, folder_name, owner_email, parent_id, inherit_permissions, created_at
+            "#,
+            name,
+            owner,
+            parent_id,
+            inherit_permissions,
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(folder)
+    }
+
+    async fn update_folder(
+        &self,
+        folder_id: i32,
+        name: Option<&str>,
+        inherit_permissions: Option<bool>,
+    ) -> StoreResult<FolderRow> {
+        let folder = sqlx::query_as!(
+            FolderRow,
+            r#"
+            UPDATE folders
+            SET folder_name = COALESCE($2, folder_name),
+                inherit_permissions = COALESCE($3, inherit_permissions)
+            WHERE folder_id = $1
+            RETURNING folder_id, folder_name, owner_email, parent_id, inherit_permissions, created_at
+            "#,
+            folder_id,
+            name,
+            inherit_permissions,
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(folder)
+    }
+
+    async fn is_within(&self, folder_id: i32, ancestor_id: i32) -> StoreResult<bool> {
+        // Walk up from the folder, finding the ancestor on the way means the
+        // folder is the ancestor itself or one of its subfolders
+        let within = sqlx::query_scalar!(
+            r#"
+            WITH RECURSIVE ancestors AS (
+                SELECT folder_id, parent_id FROM folders WHERE folder_id = $1
+                UNION ALL
+                SELECT f.folder_id, f.parent_id FROM folders f
+                JOIN ancestors a ON f.folder_id = a.parent_id
+            )
+            SELECT EXISTS (SELECT 1 FROM ancestors WHERE folder_id = $2) AS "within!"
+            "#,
+            folder_id,
+            ancestor_id,
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(within)
+    }
+
+    async fn move_folder(&self, folder_id: i32, parent_id: Option<i32>) -> StoreResult<()> {
+        sqlx::query!(
+            "UPDATE folders SET parent_id = $2 WHERE folder_id = $1",
+            folder_id,
+            parent_id,
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn delete_folder(&self, folder_id: i32) -> StoreResult<()> {
+        sqlx::query!("DELETE FROM folders WHERE folder_id = $1", folder_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn file_document(&self, document_id: &str, folder_id: Option<i32>) -> StoreResult<()> {
+        match folder_id {
+            Some(folder_id) => {
+                sqlx::query!(
+                    r#"
+                    INSERT INTO folder_documents (document_id, folder_id) VALUES ($1, $2)
+                    ON CONFLICT (document_id) DO UPDATE SET folder_id = EXCLUDED.folder_id
+                    "#,
+                    document_id,
+                    folder_id,
+                )
+                .execute(&self.pool)
+                .await?;
+            }
+            None => {
+                sqlx::query!(
+                    "DELETE FROM folder_documents WHERE document_id = $1",
+                    document_id
+                )
+                .execute(&self.pool)
+                .await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn share_folder(&self, folder_id: i32, email: &str, role: Role) -> StoreResult<()> {
+        sqlx::query!(
+            r#"
+            INSERT INTO folder_relation (user_email, folder_id, user_role) VALUES ($1, $2, $3)
+            ON CONFLICT (user_email, folder_id) DO UPDATE SET user_role = EXCLUDED.user_role
+            "#,
+            email,
+            folder_id,
+            role.as_str() as &str,
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn unshare_folder(&self, folder_id: i32, email: &str) -> StoreResult<bool> {
+        let result = sqlx::query!(
+            "DELETE FROM folder_relation WHERE user_email = $1 AND folder_id = $2",
+            email,
+            folder_id,
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn subfolders(&self, folder_id: i32) -> StoreResult<Vec<FolderRow>> {
+        let folders = sqlx::query_as!(
+            FolderRow,
+            r#"
+            SELECT folder_id, folder_name, owner_email, parent_id, inherit_permissions, created_at
+            FROM folders
+            WHERE parent_id = $1
+            ORDER BY folder_name
+            "#,
+            folder_id,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(folders)
+    }
+
+    async fn folder_documents(&self, folder_id: i32) -> StoreResult<Vec<String>> {
+        let document_ids = sqlx::query_scalar!(
+            "SELECT document_id FROM folder_documents WHERE folder_id = $1",
+            folder_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(document_ids)
+    }
+
+    async fn top_level_folders(&self, email: &str) -> StoreResult<Vec<FolderRow>> {
+        let folders = sqlx::query_as!(
+            FolderRow,
+            r#"
+            SELECT folder_id, folder_name, owner_email, parent_id, inherit_permissions, created_at
+            FROM folders
+            WHERE (owner_email = $1 AND parent_id IS NULL)
+            OR folder_id IN (SELECT folder_id FROM folder_relation WHERE user_email = $1)
+            ORDER BY folder_name
+            "#,
+            email,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(folders)
+    }
+
+    async fn unfiled_documents(&self, email: &str) -> StoreResult<Vec<String>> {
+        let document_ids = sqlx::query_scalar!(
+            r#"
+            SELECT document_id FROM document_relation
+            WHERE user_email = $1 AND user_role = 'owner'
+            AND document_id NOT IN (SELECT document_id FROM folder_documents)
+            "#,
+            email,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(document_ids)
+    }
+}
+
+// ***************************************************************************************************************************************
+
+#[async_trait]
+impl LibraryStore for PgStore {
+    async fn tags(&self, email: &str) -> StoreResult<Vec<(String, String)>> {
+        let rows = sqlx::query!(
+            "SELECT document_id, tag FROM document_tags WHERE user_email = $1 ORDER BY tag",
+            email
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(|row| (row.document_id, row.tag))
+            .collect())
+    }
+
+    async fn tag_counts(&self, email: &str) -> StoreResult<Vec<(String, i64)>> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT tag, COUNT(*) AS "documents!" FROM document_tags
+            WHERE user_email = $1
+            GROUP BY tag
+            ORDER BY tag
+            "#,
+            email,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(|row| (row.tag, row.documents))
+            .collect())
+    }
+
+    async fn add_tag(&self, email: &str, document_id: &str, tag: &str) -> StoreResult<()> {
+        sqlx::query!(
+            r#"
+            INSERT INTO document_tags (user_email, document_id, tag) VALUES ($1, $2, $3)
+            ON CONFLICT DO NOTHING
+            "#,
+            email,
+            document_id,
+            tag,
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn remove_tag(&self, email: &str, document_id: &str, tag: &str) -> StoreResult<bool> {
+        let result = sqlx::query!(
+            "DELETE FROM document_tags WHERE user_email = $1 AND document_id = $2 AND tag = $3",
+            email,
+            document_id,
+            tag,
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn favorites(&self, email: &str) -> StoreResult<Vec<String>> {
+        let document_ids = sqlx::query_scalar!(
+            r#"
+            SELECT document_id FROM document_favorites
+            WHERE user_email = $1
+            ORDER BY starred_at DESC
+            "#,
+            email,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(document_ids)
+    }
+
+    async fn set_favorite(
+        &self,
+        email: &str,
+        document_id: &str,
+        favorite: bool,
+    ) -> StoreResult<()> {
+        if favorite {
+            sqlx::query!(
+                r#"
+                INSERT INTO document_favorites (user_email, document_id) VALUES ($1, $2)
+                ON CONFLICT DO NOTHING
+                "#,
+                email,
+                document_id,
+            )
+            .execute(&self.pool)
+            .await?;
+        } else {
+            sqlx::query!(
+                "DELETE FROM document_favorites WHERE user_email = $1 AND document_id = $2",
+                email,
+                document_id,
+            )
+            .execute(&self.pool)
+            .await?;
+        }
+        Ok(())
+    }
+
+    async fn recent(&self, email: &str, limit: i64) -> StoreResult<Vec<(String, DateTime<Utc>)>> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT document_id, opened_at FROM recent_documents
+            WHERE user_email = $1
+            ORDER BY opened_at DESC
+            LIMIT $2
+            "#,
+            email,
+            limit,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(|row| (row.document_id, row.opened_at))
+            .collect())
+    }
+
+    async fn record_open(&self, email: &str, document_id: &str, keep: i64) -> StoreResult<()> {
+        sqlx::query!(
+            r#"
+            INSERT INTO recent_documents (user_email, document_id) VALUES ($1, $2)
+            ON CONFLICT (user_email, document_id) DO UPDATE SET opened_at = now()
+            "#,
+            email,
+            document_id,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        sqlx::query!(
+            r#"
+            DELETE FROM recent_documents
+            WHERE user_email = $1
+            AND document_id NOT IN (
+                SELECT document_id FROM recent_documents
+                WHERE user_email = $1
+                ORDER BY opened_at DESC
+                LIMIT $2
+            )
+            "#,
+            email,
+            keep,
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+}
+
+// ***************************************************************************************************************************************
+
+#[async_trait]
+impl AuditLog for PgStore {
+    async fn append(
+        &self,
+        event_type: &str,
+        actor: Option<&str>,
+        document_id: Option<&str>,
+        details: Value,
+    ) -> StoreResult<()> {
+        sqlx::query!(
+            "INSERT INTO audit_log (event_type, actor_email, document_id, details) VALUES ($1, $2, $3, $4)",
+            event_type,
+            actor,
+            document_id,
+            details,
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn entries(
+        &self,
+        query: &GetAuditLogRequest,
+        limit: i64,
+    ) -> StoreResult<Vec<AuditEntry>> {
+        let entries = sqlx::query_as!(
+            AuditEntry,
+            r#"
+            SELECT event_id, occurred_at, event_type, actor_email, document_id, details
+            FROM audit_log
+            WHERE document_id = $1
+            AND ($2::text IS NULL OR event_type = $2)
+            AND ($3::text IS NULL OR actor_email = $3)
+            AND ($4::timestamptz IS NULL OR occurred_at >= $4)
+            AND ($5::timestamptz IS NULL OR occurred_at < $5)
+            ORDER BY occurred_at DESC, event_id DESC
+            LIMIT $6
+            "#,
+            query.document_id,
+            query.event_type,
+            query.actor,
+            query.since,
+            query.until,
+            limit,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(entries)
+    }
+}
@@ -0,0 +1,481 @@
+use std::collections::HashMap;
+
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+use log::error;
+use redis::aio::ConnectionManager;
+use redis::streams::{StreamRangeReply, StreamReadOptions, StreamReadReply};
+use redis::{AsyncCommands, Client, RedisResult};
+use serde::Serialize;
+use tokio::sync::mpsc;
+use tokio::time::{self, Duration};
+
+use crate::store::{Lease, LiveDocCache, PubSub, StoreResult};
+use crate::structs::WsServerMessage;
+use crate::updates::{self, Change, RETAINED_UPDATES, Resume};
+
+// The sequence counter and the stream of a document nobody touches for this
+// long go away together
+const STREAM_TTL_SECS: usize = 24 * 60 * 60;
+
+// How long a subscription waits on the stream before checking again
+const READ_BLOCK_MS: usize = 5000;
+
+// Numbers the update and appends it to the document stream, the op log every
+// session reads its updates from. Doing both in one step makes the order of
+// sequence numbers the order of the log. A change of the content is only
+// appended, with the content swapped along, while the content is still the
+// one the change was made to, else nothing is done and 0 returned. The
+// sequence number is put at the front of the JSON object, serde always
+// produces a non-empty object for messages.
+const APPEND_SCRIPT: &str = r#"
+if ARGV[3] == "1" then
+    if (redis.call("GET", KEYS[3]) or "") ~= ARGV[6] then
+        return 0
+    end
+    redis.call("SET", KEYS[3], ARGV[2])
+end
+local seq = redis.call("INCR", KEYS[1])
+local payload = '{"seq":' .. seq .. ',' .. string.sub(ARGV[1], 2)
+if ARGV[4] ~= "" then
+    redis.call("XADD", KEYS[2], seq .. "-1", "payload", payload, "author", ARGV[4])
+else
+    redis.call("XADD", KEYS[2], seq .. "-1", "payload", payload)
+end
+redis.call("EXPIRE", KEYS[1], ARGV[5])
+redis.call("EXPIRE", KEYS[2], ARGV[5])
+return seq
+"#;
+
+// Renews the lease only if it still holds the value
+const RENEW_SCRIPT: &str = r#"
+if redis.call("GET", KEYS[1]) == ARGV[1] then
+    return redis.call("PEXPIRE", KEYS[1], ARGV[2])
+end
+return 0
+"#;
+
+// Deletes the lease only if it still holds the value
+const RELEASE_SCRIPT: &str = r#"
+if redis.call("GET", KEYS[1]) == ARGV[1] then
+    return redis.call("DEL", KEYS[1])
+end
+return 0
+"#;
+
+fn doc_key(document_id: &str) -> String {
+    format!("doc:{}", document_id)
+}
+
+// Hash holding who last edited an open document and when, and the revision
+// the live content is based on
+fn meta_key(document_id: &str) -> String {
+    format!("doc_meta:{}", document_id)
+}
+
+fn seq_key(document_id: &str) -> String {
+    format!("seq:{}", document_id)
+}
+
+fn stream_key(document_id: &str) -> String {
+    format!("stream:{}", document_id)
+}
+
+fn lock_key(document_id: &str) -> String {
+    format!("lock:{}", document_id)
+}
+
+fn anchors_key(document_id: &str) -> String {
+    format!("anchors:{}", document_id)
+}
+
+// Live documents, edit locks and op logs in Redis
+#[derive(Clone)]
+pub struct RedisStore {
+    client: Client,
+    conn: ConnectionManager,
+}
+
+impl RedisStore {
+    pub async fn connect(client: Client) -> RedisResult<RedisStore> {
+        let conn = client.get_tokio_connection_manager().await?;
+        Ok(RedisStore { client, conn })
+    }
+
+    // Appends an update, or a change of the content from `previous` to
+    // `content`
+
+    async fn append(
+        &self,
+        document_id: &str,
+        message: &impl Serialize,
+        swap: Option<(&str, &str)>,
+        author: Option<&str>,
+    ) -> RedisResult<u64> {
+        let payload = serde_json::to_string(message).unwrap();
+        let (previous, content) = swap.unwrap_or_default();
+        redis::Script::new(APPEND_SCRIPT)
+            .key(seq_key(document_id))
+            .key(stream_key(document_id))
+            .key(doc_key(document_id))
+            .arg(payload)
+            .arg(content)
+            .arg(if swap.is_some() { "1" } else { "0" })
+            .arg(author.unwrap_or_default())
+            .arg(STREAM_TTL_SECS)
+            .arg(previous)
+            .invoke_async(&mut self.conn.clone())
+            .await
+    }
+}
+
+// Waits for the updates after `after_seq`. Returns them with the sequence
+// number to continue from, nothing new when the wait timed out. The
+// connection is blocked while waiting.
+
+async fn read_after<C: AsyncCommands>(
+    conn: &mut C,
+    document_id: &str,
+    after_seq: u64,
+) -> RedisResult<(Vec<String>, u64)> {
+    let options = StreamReadOptions::default().block(READ_BLOCK_MS);
+    let reply: Option<StreamReadReply> = conn
+        .xread_options(
+            &[stream_key(document_id)],
+            &[format!("{}-1", after_seq)],
+            &options,
+        )
+        .await?;
+
+    let mut last_seq = after_seq;
+    let mut updates = Vec::new();
+    for entry in reply.into_iter().flat_map(|r| r.keys).flat_map(|k| k.ids) {
+        if let Some(seq) = updates::seq_from_id(&entry.id) {
+            last_seq = last_seq.max(seq);
+        }
+        updates.extend(entry.get::<String>("payload"));
+    }
+    Ok((updates, last_seq))
+}
+
+// ***************************************************************************************************************************************
+
+#[async_trait]
+impl LiveDocCache for RedisStore {
+    async fn content(&self, document_id: &str) -> StoreResult<Option<String>> {
+        let content: Option<String> = self.conn.clone().get(doc_key(document_id)).await?;
+        Ok(content)
+    }
+
+    async fn is_open(&self, document_id: &str) -> StoreResult<bool> {
+        let open: bool = self.conn.clone().exists(doc_key(document_id)).await?;
+        Ok(open)
+    }
+
+    async fn open(&self, document_id: &str, content: &str, revision: i64) -> StoreResult<()> {
+        let mut conn = self.conn.clone();
+        let _: () = conn.set(doc_key(document_id), content).await?;
+        let _: () = conn
+            .hset(meta_key(document_id), "revision", revision)
+            .await?;
+        Ok(())
+    }
+
+    async fn open_documents(&self) -> StoreResult<Vec<String>> {
+        let keys: Vec<String> = self.conn.clone().keys("doc:*").await?;
+        Ok(keys
+            .into_iter()
+            .map(|key| key.replace("doc:", ""))
+            .collect())
+    }
+
+    async fn close(&self, document_id: &str) -> StoreResult<()> {
+        let _: () = self
+            .conn
+            .clone()
+            .del(&[doc_key(document_id), meta_key(document_id)])
+            .await?;
+        Ok(())
+    }
+
+    async fn last_edit(&self, document_id: &str) -> StoreResult<Option<(String, DateTime<Utc>)>> {
+        let meta: HashMap<String, String> =
+            self.conn.clone().hgetall(meta_key(document_id)).await?;
+        let editor = meta.get("last_edited_by");
+        let at = meta
+            .get("updated_at")
+            .and_then(|at| DateTime::parse_from_rfc3339(at).ok());
+        Ok(editor
+            .zip(at)
+            .map(|(editor, at)| (editor.clone(), at.with_timezone(&Utc))))
+    }
+
+    async fn set_last_edit(
+        &self,
+        document_id: &str,
+        editor: &str,
+        at: DateTime<Utc>,
+    ) -> StoreResult<()> {
+        let _: () = self
+            .conn
+            .clone()
+            .hset_multiple(
+                meta_key(document_id),
+                &[
+                    ("last_edited_by", editor.to_string()),
+                    ("updated_at", at.to_rfc3339()),
+                ],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn clear_last_edit(&self, document_id: &str) -> StoreResult<()> {
+        let _: () = self
+            .conn
+            .clone()
+            .hdel(meta_key(document_id), &["last_edited_by", "updated_at"])
+            .await?;
+        Ok(())
+    }
+
+    async fn base_revision(&self, document_id: &str) -> StoreResult<Option<i64>> {
+        let revision: Option<i64> = self
+            .conn
+            .clone()
+            .hget(meta_key(document_id), "revision")
+            .await?;
+        Ok(revision)
+    }
+
+    async fn set_base_revision(&self, document_id: &str, revision: i64) -> StoreResult<()> {
+        let _: () = self
+            .conn
+            .clone()
+            .hset(meta_key(document_id), "revision", revision)
+            .await?;
+        Ok(())
+    }
+
+    async fn acquire_lease(
+        &self,
+        document_id: &str,
+        value: &str,
+        ttl_ms: i64,
+    ) -> StoreResult<bool> {
+        let acquired: Option<String> = redis::cmd("SET")
+            .arg(lock_key(document_id))
+            .arg(value)
+            .arg("NX")
+            .arg("PX")
+            .arg(ttl_ms)
+            .query_async(&mut self.conn.clone())
+            .await?;
+
+        if acquired.is_some() {
+            return Ok(true);
+        }
+        self.renew_lease(document_id, value, ttl_ms).await
+    }
+
+    async fn renew_lease(&self, document_id: &str, value: &str, ttl_ms: i64) -> StoreResult<bool> {
+        let renewed: bool = redis::Script::new(RENEW_SCRIPT)
+            .key(lock_key(document_id))
+            .arg(value)
+            .arg(ttl_ms)
+            .invoke_async(&mut self.conn.clone())
+            .await?;
+        Ok(renewed)
+    }
+
+    async fn release_lease(&self, document_id: &str, value: &str) -> StoreResult<bool> {
+        let released: bool = redis::Script::new(RELEASE_SCRIPT)
+            .key(lock_key(document_id))
+            .arg(value)
+            .invoke_async(&mut self.conn.clone())
+            .await?;
+        Ok(released)
+    }
+
+    async fn break_lease(&self, document_id: &str) -> StoreResult<()> {
+        let _: () = self.conn.clone().del(lock_key(document_id)).await?;
+        Ok(())
+    }
+
+    async fn lease(&self, document_id: &str) -> StoreResult<Option<Lease>> {
+        let key = lock_key(document_id);
+        let (value, ttl): (Option<String>, i64) = redis::pipe()
+            .atomic()
+            .get(&key)
+            .pttl(&key)
+            .query_async(&mut self.conn.clone())
+            .await?;
+        Ok(value.map(|value| Lease {
+            value,
+            expires_in_ms: ttl,
+        }))
+    }
+
+    async fn clear_leases(&self) -> StoreResult<()> {
+        let mut conn = self.conn.clone();
+        let leases: Vec<String> = conn.keys("lock:*").await?;
+        if !leases.is_empty() {
+            let _: () = conn.del(&leases).await?;
+        }
+        Ok(())
+    }
+
+    async fn lock_anchors(&self, document_id: &str, token: &str, ttl_ms: i64) -> StoreResult<bool> {
+        let acquired: Option<String> = redis::cmd("SET")
+            .arg(anchors_key(document_id))
+            .arg(token)
+            .arg("NX")
+            .arg("PX")
+            .arg(ttl_ms)
+            .query_async(&mut self.conn.clone())
+            .await?;
+        Ok(acquired.is_some())
+    }
+
+    async fn unlock_anchors(&self, document_id: &str, token: &str) -> StoreResult<()> {
+        let _: bool = redis::Script::new(RELEASE_SCRIPT)
+            .key(anchors_key(document_id))
+            .arg(token)
+            .invoke_async(&mut self.conn.clone())
+            .await?;
+        Ok(())
+    }
+}
+
+// ***************************************************************************************************************************************
+
+#[async_trait]
+impl PubSub for RedisStore {
+    async fn publish(&self, document_id: &str, message: &WsServerMessage) -> StoreResult<u64> {
+        let seq = self.append(document_id, message, None, None).await?;
+        Ok(seq)
+    }
+
+    async fn publish_change(
+        &self,
+        document_id: &str,
+        previous: &str,
+        change: &Change,
+        author: &str,
+    ) -> StoreResult<Option<u64>> {
+        let content = change.apply(previous);
+        let seq = self
+            .append(
+                document_id,
+                change,
+                Some((previous, &content)),
+                Some(author),
+            )
+            .await?;
+        Ok((seq > 0).then_some(seq))
+    }
+
+    async fn snapshot(&self, document_id: &str) -> StoreResult<(Option<String>, u64)> {
+        let (content, seq): (Option<String>, Option<u64>) = redis::pipe()
+            .atomic()
+            .get(doc_key(document_id))
+            .get(seq_key(document_id))
+            .query_async(&mut self.conn.clone())
+            .await?;
+        Ok((content, seq.unwrap_or(0)))
+    }
+
+    // Blocking reads take a connection of their own, one per subscription
+    async fn subscribe(
+        &self,
+        document_id: &str,
+        after_seq: u64,
+    ) -> StoreResult<mpsc::UnboundedReceiver<String>> {
+        let mut conn = self.client.get_async_connection().await?;
+        let (tx, rx) = mpsc::unbounded_channel();
+        let document_id = document_id.to_string();
+
+        tokio::spawn(async move {
+            let mut offset = after_seq;
+            loop {
+                let read = tokio::select! {
+                    _ = tx.closed() => return,
+                    read = read_after(&mut conn, &document_id, offset) => read,
+                };
+                match read {
+                    Ok((payloads, last_seq)) => {
+                        offset = last_seq;
+                        for payload in payloads {
+                            if tx.send(payload).is_err() {
+                                return;
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        error!("Failed to read updates of doc {}: {}", document_id, e);
+                        time::sleep(Duration::from_secs(1)).await;
+                    }
+                }
+            }
+        });
+        Ok(rx)
+    }
+
+    async fn missed(
+        &self,
+        document_id: &str,
+        last_seq: u64,
+        current: u64,
+    ) -> StoreResult<Option<Vec<String>>> {
+        let mut conn = self.conn.clone();
+        let oldest: StreamRangeReply = conn
+            .xrange_count(stream_key(document_id), "-", "+", 1)
+            .await?;
+        let oldest = oldest
+            .ids
+            .first()
+            .and_then(|entry| updates::seq_from_id(&entry.id));
+
+        match updates::plan_resume(last_seq, current, oldest) {
+            Resume::UpToDate => Ok(Some(Vec::new())),
+            Resume::Snapshot => Ok(None),
+            Resume::Replay => {
+                let reply: StreamRangeReply = conn
+                    .xrange(
+                        stream_key(document_id),
+                        format!("{}-0", last_seq + 1),
+                        format!("{}-1", current),
+                    )
+                    .await?;
+                let updates = reply
+                    .ids
+                    .into_iter()
+                    .filter_map(|entry| entry.get::<String>("payload"))
+                    .collect();
+                Ok(Some(updates))
+            }
+        }
+    }
+
+    async fn compact(&self, document_id: &str, snapshot_seq: u64) -> StoreResult<()> {
+        if snapshot_seq <= RETAINED_UPDATES {
+            return Ok(());
+        }
+        let _: () = redis::cmd("XTRIM")
+            .arg(stream_key(document_id))
+            .arg("MINID")
+            .arg(format!("{}-0", snapshot_seq - RETAINED_UPDATES))
+            .query_async(&mut self.conn.clone())
+            .await?;
+        Ok(())
+    }
+
+    async fn reset(&self, document_id: &str) -> StoreResult<()> {
+        let _: () = self
+            .conn
+            .clone()
+            .del(&[seq_key(document_id), stream_key(document_id)])
+            .await?;
+        Ok(())
+    }
+}
@@ -1,61 +1,191 @@
-use mongodb::{bson::oid::ObjectId};
-use redis::Client;
-use serde::{Deserialize, Serialize};
-use sqlx::PgPool;
-use tokio::sync::Mutex;
-use std::collections::HashMap;
 use std::sync::Arc;
-use crate::MongoDatabase;
+
+use crate::authz::GrantSource;
+use crate::connections::WsConnections;
+use crate::sessions::WsSessions;
+use crate::store::{AccessStore, AuditLog, DocumentStore, LibraryStore, LiveDocCache, PubSub};
+use chrono::{DateTime, Utc};
+use mongodb::bson::oid::ObjectId;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
 
 // Struct for the login request
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 pub struct LoginRequest {
     pub email: String,
     pub password: String,
 }
 
-#[derive(Deserialize)]
+// Document listings, optionally narrowed down to a tag, favorites or
+// recently opened documents of the user
+#[derive(Deserialize, ToSchema)]
 pub struct GetDocumentRequest {
     pub email: String,
+    pub tag: Option<String>,
+    #[serde(default)]
+    pub favorites: bool,
+    #[serde(default)]
+    pub recent: bool,
 }
 
 // Struct for the user row returned from the database
-#[derive(Serialize)]
+#[derive(Serialize, Clone, ToSchema)]
 pub struct UserRow {
     pub email: String,
     pub first_name: String,
     pub last_name: String,
 }
 
+// Storage goes through the traits of the store module, the servers use the
+// database backed implementations
 #[derive(Clone)]
 pub struct AppState {
-    pub pg_pool: PgPool,
-    pub mongo_db: MongoDatabase,
-    pub redis_client: Client,
-    pub ws_connections: Arc<Mutex<HashMap<String, usize>>>,
+    pub documents: Arc<dyn DocumentStore>,
+    pub access: Arc<dyn AccessStore>,
+    pub library: Arc<dyn LibraryStore>,
+    pub audit: Arc<dyn AuditLog>,
+    pub live: Arc<dyn LiveDocCache>,
+    pub pubsub: Arc<dyn PubSub>,
+    pub ws_connections: WsConnections,
+    pub ws_sessions: WsSessions,
 }
 
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, Clone, ToSchema)]
 pub struct Document {
     #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>)]
     pub id: Option<ObjectId>,
     pub title: String,
     pub content: String,
     pub format: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub publication: Option<Publication>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template: Option<Template>,
+    // Metadata, documents created before it was introduced have none
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_edited_by: Option<String>,
+    #[serde(default)]
+    pub word_count: i64,
+    #[serde(default)]
+    pub char_count: i64,
+    #[serde(default)]
+    pub content_size: i64,
+    // Only the holder of the edit lock may change the content
+    #[serde(default)]
+    pub exclusive_editing: bool,
+    // Incremented whenever new content is persisted, writes based on an
+    // older revision are refused
+    #[serde(default)]
+    pub revision: i64,
+    // Last update of the op log included in the content, later updates are
+    // still in the Redis stream
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub snapshot_seq: Option<i64>,
+}
+
+impl Document {
+    pub fn new(title: String, format: String, content: String) -> Document {
+        let now = Utc::now();
+        let stats = ContentStats::of(&content);
+        Document {
+            id: None,
+            title,
+            content,
+            format,
+            publication: None,
+            template: None,
+            created_at: Some(now),
+            updated_at: Some(now),
+            last_edited_by: None,
+            word_count: stats.word_count,
+            char_count: stats.char_count,
+            content_size: stats.content_size,
+            exclusive_editing: false,
+            revision: 0,
+            snapshot_seq: None,
+        }
+    }
+
+    // Metadata returned with the document in listings
+    pub fn metadata(&self) -> DocumentMetadata {
+        DocumentMetadata {
+            created_at: self.created_at,
+            updated_at: self.updated_at,
+            last_edited_by: self.last_edited_by.clone(),
+            word_count: self.word_count,
+            char_count: self.char_count,
+            content_size: self.content_size,
+            revision: self.revision,
+        }
+    }
 }
 
-#[derive(Deserialize, Serialize, Debug)]
+// Figures derived from the content, kept up to date whenever it is persisted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ContentStats {
+    pub word_count: i64,
+    pub char_count: i64,
+    pub content_size: i64,
+}
+
+impl ContentStats {
+    pub fn of(content: &str) -> ContentStats {
+        ContentStats {
+            word_count: content.split_whitespace().count() as i64,
+            char_count: content.chars().count() as i64,
+            content_size: content.len() as i64,
+        }
+    }
+}
+
+// Set on documents published to a public read-only URL
+#[derive(Deserialize, Serialize, Debug, Clone, ToSchema)]
+pub struct Publication {
+    pub public_id: String,
+    pub published_by: String,
+    pub published_at: DateTime<Utc>,
+    pub live: bool,
+}
+
+// Set on documents the owner offers as a starting point for new documents.
+// Templates shared with all users can be instantiated by anyone, others only
+// by the users the document is shared with.
+#[derive(Deserialize, Serialize, Debug, Clone, ToSchema)]
+pub struct Template {
+    pub marked_by: String,
+    pub marked_at: DateTime<Utc>,
+    pub shared_with_all: bool,
+}
+
+// Body of /save_document. Publication, template, locking, revision and
+// metadata are set by the server, never by the client.
+#[derive(Deserialize, Debug, ToSchema)]
+pub struct SaveDocumentRequest {
+    pub title: String,
+    #[serde(default)]
+    pub content: String,
+    pub format: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
 pub struct DocumentCreateRequest {
     pub title: String,
     pub format: String,
     pub collaborators: Vec<String>,
     pub readers: Vec<String>,
+    #[serde(default)]
+    pub suggesters: Vec<String>,
     pub owner: String,
     pub groups: Vec<i32>,
 }
 
 // STRUCT FOR GROUPS REQUEST
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
 pub struct GroupsRequest {
     pub owner: String,
     pub name: String,
@@ -63,19 +193,884 @@ pub struct GroupsRequest {
     pub members: Vec<String>,
 }
 
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Serialize, Debug, Clone, ToSchema)]
+pub struct GroupSummary {
+    pub group_id: i32,
+    pub group_name: String,
+    pub owner_email: String,
+    pub group_role: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
 pub struct GetGroupsRequest {
     pub email: String,
 }
 
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
 pub struct GetUserRole {
     pub email: String,
     pub document_id: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct WsParams {
     pub user_email: String,
     pub document_id: String,
-}
\ No newline at end of file
+    pub share_token: Option<String>,
+    // Last sequence number seen by a reconnecting client
+    pub last_seq: Option<u64>,
+}
+// Character range in a document, end exclusive
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct TextRange {
+    pub start: usize,
+    pub end: usize,
+}
+
+// Comment threads are stored in the MongoDB "comments" collection
+#[derive(Deserialize, Serialize, Debug, Clone, ToSchema)]
+pub struct CommentThread {
+    #[serde(rename = "_id")]
+    pub id: String,
+    pub document_id: String,
+    pub anchor: TextRange,
+    pub quote: String,
+    pub author: String,
+    pub body: String,
+    pub created_at: DateTime<Utc>,
+    pub resolved: bool,
+    pub resolved_by: Option<String>,
+    pub replies: Vec<CommentReply>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, ToSchema)]
+pub struct CommentReply {
+    pub id: String,
+    pub author: String,
+    pub body: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct GetCommentsRequest {
+    pub email: String,
+    pub document_id: String,
+    #[serde(default)]
+    pub include_resolved: bool,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct CreateCommentRequest {
+    pub email: String,
+    pub document_id: String,
+    pub start: usize,
+    pub end: usize,
+    pub body: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct ReplyCommentRequest {
+    pub email: String,
+    pub document_id: String,
+    pub thread_id: String,
+    pub body: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct CommentThreadRequest {
+    pub email: String,
+    pub document_id: String,
+    pub thread_id: String,
+}
+
+// Messages sent by clients over the document WebSocket
+#[derive(Deserialize, Debug, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum WsClientMessage {
+    Edit {
+        content: String,
+    },
+    CreateComment {
+        start: usize,
+        end: usize,
+        body: String,
+    },
+    ReplyComment {
+        thread_id: String,
+        body: String,
+    },
+    ResolveComment {
+        thread_id: String,
+    },
+    ReopenComment {
+        thread_id: String,
+    },
+    AcquireLock,
+    ReleaseLock,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CommentEvent {
+    Created,
+    Replied,
+    Resolved,
+    Reopened,
+}
+
+// Messages sent to clients over the document WebSocket, also used as the
+// payload published on the Redis document channel
+#[derive(Serialize, Debug, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum WsServerMessage {
+    Content {
+        content: String,
+    },
+    Comment {
+        event: CommentEvent,
+        thread: CommentThread,
+    },
+    Suggestion {
+        event: SuggestionEvent,
+        suggestion: Suggestion,
+    },
+    Lock {
+        #[serde(flatten)]
+        state: LockState,
+    },
+    Error {
+        message: String,
+    },
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SuggestionStatus {
+    Pending,
+    Accepted,
+    Rejected,
+}
+
+// Edits made by suggesters are stored in the MongoDB "suggestions" collection
+// instead of being applied to the document
+#[derive(Deserialize, Serialize, Debug, Clone, ToSchema)]
+pub struct Suggestion {
+    #[serde(rename = "_id")]
+    pub id: String,
+    pub document_id: String,
+    pub author: String,
+    pub range: TextRange,
+    pub original: String,
+    pub replacement: String,
+    pub created_at: DateTime<Utc>,
+    pub status: SuggestionStatus,
+    pub reviewed_by: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct GetSuggestionsRequest {
+    pub email: String,
+    pub document_id: String,
+    #[serde(default)]
+    pub include_reviewed: bool,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct ReviewSuggestionRequest {
+    pub email: String,
+    pub document_id: String,
+    pub suggestion_id: String,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SuggestionEvent {
+    Created,
+    Updated,
+    Accepted,
+    Rejected,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct ShareDocumentRequest {
+    pub email: String,
+    pub document_id: String,
+    pub target_email: String,
+    pub role: String,
+    pub valid_from: Option<DateTime<Utc>>,
+    pub valid_until: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct ShareDocumentGroupRequest {
+    pub email: String,
+    pub document_id: String,
+    pub group_id: i32,
+    pub valid_from: Option<DateTime<Utc>>,
+    pub valid_until: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct UnshareDocumentGroupRequest {
+    pub email: String,
+    pub document_id: String,
+    pub group_id: i32,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct UnshareDocumentRequest {
+    pub email: String,
+    pub document_id: String,
+    pub target_email: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct DeleteDocumentRequest {
+    pub email: String,
+    pub document_id: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct TransferOwnershipRequest {
+    pub email: String,
+    pub document_id: String,
+    pub new_owner: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct GetAuditLogRequest {
+    pub email: String,
+    pub document_id: String,
+    pub event_type: Option<String>,
+    pub actor: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Serialize, Debug, Clone, ToSchema)]
+pub struct AuditEntry {
+    pub event_id: i64,
+    pub occurred_at: DateTime<Utc>,
+    pub event_type: String,
+    pub actor_email: Option<String>,
+    pub document_id: Option<String>,
+    pub details: serde_json::Value,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct CreateShareLinkRequest {
+    pub email: String,
+    pub document_id: String,
+    pub role: String,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub max_uses: Option<i32>,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct GetShareLinksRequest {
+    pub email: String,
+    pub document_id: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct RevokeShareLinkRequest {
+    pub email: String,
+    pub document_id: String,
+    pub link_id: i32,
+}
+
+#[derive(Serialize, Debug, Clone, ToSchema)]
+pub struct ShareLinkRow {
+    pub link_id: i32,
+    pub token: String,
+    pub link_role: String,
+    pub created_by: String,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub max_uses: Option<i32>,
+    pub use_count: i32,
+    pub revoked: bool,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct PublishDocumentRequest {
+    pub email: String,
+    pub document_id: String,
+    #[serde(default)]
+    pub live: bool,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct UnpublishDocumentRequest {
+    pub email: String,
+    pub document_id: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct CreateFolderRequest {
+    pub email: String,
+    pub name: String,
+    pub parent_id: Option<i32>,
+    #[serde(default)]
+    pub inherit_permissions: bool,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct UpdateFolderRequest {
+    pub email: String,
+    pub folder_id: i32,
+    pub name: Option<String>,
+    pub inherit_permissions: Option<bool>,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct MoveFolderRequest {
+    pub email: String,
+    pub folder_id: i32,
+    pub parent_id: Option<i32>,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct DeleteFolderRequest {
+    pub email: String,
+    pub folder_id: i32,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct MoveDocumentRequest {
+    pub email: String,
+    pub document_id: String,
+    pub folder_id: Option<i32>,
+}
+
+// Without a folder id the top level of the user's folders is listed
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct GetFolderContentsRequest {
+    pub email: String,
+    pub folder_id: Option<i32>,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct ShareFolderRequest {
+    pub email: String,
+    pub folder_id: i32,
+    pub target_email: String,
+    pub role: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct UnshareFolderRequest {
+    pub email: String,
+    pub folder_id: i32,
+    pub target_email: String,
+}
+
+#[derive(Serialize, Debug, Clone, ToSchema)]
+pub struct FolderRow {
+    pub folder_id: i32,
+    pub folder_name: String,
+    pub owner_email: String,
+    pub parent_id: Option<i32>,
+    pub inherit_permissions: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct DocumentTagRequest {
+    pub email: String,
+    pub document_id: String,
+    pub tag: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct SetFavoriteRequest {
+    pub email: String,
+    pub document_id: String,
+    pub favorite: bool,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct GetLibraryRequest {
+    pub email: String,
+    pub limit: Option<i64>,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct SetTemplateRequest {
+    pub email: String,
+    pub document_id: String,
+    pub template: bool,
+    #[serde(default)]
+    pub shared_with_all: bool,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct GetTemplatesRequest {
+    pub email: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct CreateFromTemplateRequest {
+    pub email: String,
+    pub template_id: String,
+    pub title: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct DuplicateDocumentRequest {
+    pub email: String,
+    pub document_id: String,
+    pub title: Option<String>,
+    #[serde(default)]
+    pub copy_sharing: bool,
+}
+
+// Exclusive editing of a document and who currently holds the edit lock
+#[derive(Serialize, Debug, Clone, ToSchema)]
+pub struct LockState {
+    pub exclusive: bool,
+    pub holder: Option<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct SetExclusiveEditingRequest {
+    pub email: String,
+    pub document_id: String,
+    pub enabled: bool,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct GetEditLockRequest {
+    pub email: String,
+    pub document_id: String,
+}
+
+// ***************************************************************************************************************************************
+// Request bodies and queries of the /api/v1 routes. The user is named by the
+// X-User-Email header and the resource by the path, bodies only carry what
+// changes.
+
+#[derive(Deserialize, Debug, ToSchema)]
+pub struct NewDocument {
+    pub title: String,
+    pub format: String,
+    #[serde(default)]
+    pub collaborators: Vec<String>,
+    #[serde(default)]
+    pub readers: Vec<String>,
+    #[serde(default)]
+    pub suggesters: Vec<String>,
+    #[serde(default)]
+    pub groups: Vec<i32>,
+}
+
+// Fields left out are not changed
+#[derive(Deserialize, Debug, ToSchema)]
+pub struct DocumentPatch {
+    pub title: Option<String>,
+    // Transfers the ownership, the current owner becomes an editor
+    pub owner: Option<String>,
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DocumentListQuery {
+    pub tag: Option<String>,
+    #[serde(default)]
+    pub favorites: bool,
+    #[serde(default)]
+    pub recent: bool,
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct LimitQuery {
+    pub limit: Option<i64>,
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+pub struct MemberGrant {
+    pub role: String,
+    pub valid_from: Option<DateTime<Utc>>,
+    pub valid_until: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+pub struct GroupGrant {
+    pub valid_from: Option<DateTime<Utc>>,
+    pub valid_until: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+pub struct DocumentCopy {
+    pub title: Option<String>,
+    #[serde(default)]
+    pub copy_sharing: bool,
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+pub struct TemplateSettings {
+    #[serde(default)]
+    pub shared_with_all: bool,
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+pub struct NewFromTemplate {
+    pub title: Option<String>,
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AuditLogQuery {
+    pub event_type: Option<String>,
+    pub actor: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+pub struct ShareLinkSettings {
+    pub role: String,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub max_uses: Option<i32>,
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+pub struct LockSettings {
+    pub exclusive: bool,
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+pub struct PublicationSettings {
+    #[serde(default)]
+    pub live: bool,
+}
+
+// A document without a folder id is moved out of its folder
+#[derive(Deserialize, Debug, ToSchema)]
+pub struct DocumentFolder {
+    pub folder_id: Option<i32>,
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct CommentsQuery {
+    #[serde(default)]
+    pub include_resolved: bool,
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+pub struct NewComment {
+    pub start: usize,
+    pub end: usize,
+    pub body: String,
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+pub struct NewReply {
+    pub body: String,
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+pub struct ThreadPatch {
+    pub resolved: bool,
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SuggestionsQuery {
+    #[serde(default)]
+    pub include_reviewed: bool,
+}
+
+// Suggestions are reviewed by setting their status to accepted or rejected
+#[derive(Deserialize, Debug, ToSchema)]
+pub struct SuggestionReview {
+    pub status: SuggestionStatus,
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+pub struct NewFolder {
+    pub name: String,
+    pub parent_id: Option<i32>,
+    #[serde(default)]
+    pub inherit_permissions: bool,
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+pub struct FolderPatch {
+    pub name: Option<String>,
+    pub inherit_permissions: Option<bool>,
+}
+
+// A folder without a parent id is moved to the top level
+#[derive(Deserialize, Debug, ToSchema)]
+pub struct FolderParent {
+    pub parent_id: Option<i32>,
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+pub struct FolderGrant {
+    pub role: String,
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+pub struct NewGroup {
+    pub name: String,
+    pub role: String,
+    pub members: Vec<String>,
+}
+
+// ***************************************************************************************************************************************
+// Responses. Every successful response carries `success: true` next to its
+// payload, errors are described in errors.rs.
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct MessageResponse {
+    pub success: bool,
+    pub message: String,
+}
+
+impl MessageResponse {
+    pub fn new(message: impl Into<String>) -> MessageResponse {
+        MessageResponse {
+            success: true,
+            message: message.into(),
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct LoginResponse {
+    pub success: bool,
+    pub user: UserRow,
+}
+
+// Returned by the raw document creation endpoint, shaped like a MongoDB
+// insert result
+#[derive(Serialize, Debug, ToSchema)]
+pub struct InsertedIdResponse {
+    pub inserted_id: InsertedId,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct InsertedId {
+    #[serde(rename = "$oid")]
+    pub oid: String,
+}
+
+#[derive(Serialize, Debug, Clone, ToSchema)]
+pub struct DocumentMetadata {
+    pub created_at: Option<DateTime<Utc>>,
+    pub updated_at: Option<DateTime<Utc>>,
+    pub last_edited_by: Option<String>,
+    pub word_count: i64,
+    pub char_count: i64,
+    pub content_size: i64,
+    pub revision: i64,
+}
+
+// A document in the owned and shared listings, with the user's library
+// annotations
+#[derive(Serialize, Debug, ToSchema)]
+pub struct DocumentSummary {
+    pub id: String,
+    pub title: String,
+    pub format: String,
+    pub owner_email: String,
+    pub metadata: DocumentMetadata,
+    pub tags: Vec<String>,
+    pub favorite: bool,
+    pub last_opened_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct DocumentsResponse {
+    pub success: bool,
+    pub documents: Vec<DocumentSummary>,
+}
+
+// A document in the favorites, recent and folder listings, with the role the
+// user holds on it
+#[derive(Serialize, Debug, ToSchema)]
+pub struct DocumentEntry {
+    pub id: String,
+    pub title: String,
+    pub format: String,
+    pub role: String,
+    pub metadata: DocumentMetadata,
+    // Only set in the recent documents listing
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub opened_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct DocumentEntriesResponse {
+    pub success: bool,
+    pub documents: Vec<DocumentEntry>,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct GroupsResponse {
+    pub success: bool,
+    pub groups: Vec<GroupSummary>,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct RoleResponse {
+    pub success: bool,
+    // A list for compatibility with older clients, it holds a single role
+    pub user_role: Vec<String>,
+    pub source: GrantSource,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct CreatedDocumentResponse {
+    pub success: bool,
+    pub document_id: String,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct TemplateResponse {
+    pub success: bool,
+    pub template: bool,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct TemplateSummary {
+    pub id: String,
+    pub title: String,
+    pub format: String,
+    pub marked_by: String,
+    pub shared_with_all: bool,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct TemplatesResponse {
+    pub success: bool,
+    pub templates: Vec<TemplateSummary>,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct TagCount {
+    pub tag: String,
+    pub documents: i64,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct TagsResponse {
+    pub success: bool,
+    pub tags: Vec<TagCount>,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct FavoriteResponse {
+    pub success: bool,
+    pub favorite: bool,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct FolderResponse {
+    pub success: bool,
+    pub folder: FolderRow,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct FolderContentsResponse {
+    pub success: bool,
+    pub folders: Vec<FolderRow>,
+    pub documents: Vec<DocumentEntry>,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct ThreadsResponse {
+    pub success: bool,
+    pub threads: Vec<CommentThread>,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct ThreadResponse {
+    pub success: bool,
+    pub thread: CommentThread,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct SuggestionsResponse {
+    pub success: bool,
+    pub suggestions: Vec<Suggestion>,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct SuggestionResponse {
+    pub success: bool,
+    pub suggestion: Suggestion,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct ShareLinkResponse {
+    pub success: bool,
+    pub link: ShareLinkRow,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct ShareLinksResponse {
+    pub success: bool,
+    pub links: Vec<ShareLinkRow>,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct AuditLogResponse {
+    pub success: bool,
+    pub entries: Vec<AuditEntry>,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct ExclusiveEditingResponse {
+    pub success: bool,
+    pub exclusive: bool,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct EditLockResponse {
+    pub success: bool,
+    pub lock: LockState,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct PublicationResponse {
+    pub success: bool,
+    pub publication: Publication,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct PublicDocumentResponse {
+    pub success: bool,
+    pub title: String,
+    pub format: String,
+    pub content: String,
+    pub live: bool,
+}
+
+// A single document with its content, live content if it is being edited
+#[derive(Serialize, Debug, ToSchema)]
+pub struct DocumentResponse {
+    pub success: bool,
+    pub document: DocumentEntry,
+    pub content: String,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct GroupMembersResponse {
+    pub success: bool,
+    pub members: Vec<String>,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct GroupResponse {
+    pub success: bool,
+    pub group: GroupSummary,
+}
@@ -0,0 +1,547 @@
+use axum::{extract::State, http::StatusCode};
+use chrono::Utc;
+use mongodb::bson::oid::ObjectId;
+
+use crate::anchors;
+use crate::authz::{self, Capability};
+use crate::edits::{self, TextEdit};
+use crate::errors::{ApiError, Json};
+use crate::share_links::ShareToken;
+use crate::store::StoreError;
+use crate::structs::{
+    AppState, GetSuggestionsRequest, ReviewSuggestionRequest, Suggestion, SuggestionEvent,
+    SuggestionResponse, SuggestionStatus, SuggestionsResponse, TextRange, WsServerMessage,
+};
+use crate::ws_handler::{WriteConflict, current_content, replace_content};
+
+#[derive(Debug)]
+pub enum SuggestionError {
+    NotFound,
+    Conflict,
+    Storage(StoreError),
+}
+
+impl From<StoreError> for SuggestionError {
+    fn from(e: StoreError) -> Self {
+        SuggestionError::Storage(e)
+    }
+}
+
+impl From<SuggestionError> for ApiError {
+    fn from(e: SuggestionError) -> Self {
+        match e {
+            SuggestionError::NotFound => ApiError::not_found("Suggestion or document not found"),
+            SuggestionError::Conflict => ApiError::Conflict(
+                "The suggested text has been changed since the suggestion was made".to_string(),
+            ),
+            SuggestionError::Storage(e) => e.into(),
+        }
+    }
+}
+
+async fn publish_event(
+    state: &AppState,
+    document_id: &str,
+    event: SuggestionEvent,
+    suggestion: Suggestion,
+) -> Result<(), SuggestionError> {
+    state
+        .pubsub
+        .publish(
+            document_id,
+            &WsServerMessage::Suggestion { event, suggestion },
+        )
+        .await?;
+    Ok(())
+}
+
+pub async fn list_suggestions(
+    state: &AppState,
+    document_id: &str,
+    include_reviewed: bool,
+) -> Result<Vec<Suggestion>, SuggestionError> {
+    let suggestions = state
+        .documents
+        .suggestions(document_id, include_reviewed)
+        .await?;
+    Ok(suggestions)
+}
+
+// Turns the full content sent by a suggester into pending suggestions, one
+// per separate change. Suggesters send their whole local copy on every
+// keystroke, so a pending suggestion of the same author touching a changed
+// range is updated instead of creating a new one per keystroke.
+
+pub async fn record_edit(
+    state: &AppState,
+    document_id: &str,
+    author: &str,
+    proposed: &str,
+) -> Result<(), SuggestionError> {
+    // The ranges are stored before the content they were read from can change
+    let lock = anchors::lock(state, document_id).await?;
+    let recorded = record_hunks(state, document_id, author, proposed).await;
+    lock.release(state).await;
+
+    for (event, suggestion) in recorded? {
+        publish_event(state, document_id, event, suggestion).await?;
+    }
+    Ok(())
+}
+
+async fn record_hunks(
+    state: &AppState,
+    document_id: &str,
+    author: &str,
+    proposed: &str,
+) -> Result<Vec<(SuggestionEvent, Suggestion)>, SuggestionError> {
+    let current = state.live.content(document_id).await?.unwrap_or_default();
+    let pending = state.documents.suggestions(document_id, false).await?;
+    let mut recorded = Vec::new();
+
+    // How much the hunks before have moved the proposed content along
+    let mut shift = 0isize;
+    for hunk in edits::hunks(&current, proposed) {
+        let range = TextRange {
+            start: hunk.start,
+            end: hunk.start + hunk.deleted,
+        };
+        let original = edits::slice(&current, &range).unwrap_or_default();
+        let replacement: String = proposed
+            .chars()
+            .skip((hunk.start as isize + shift) as usize)
+            .take(hunk.inserted)
+            .collect();
+        shift += hunk.inserted as isize - hunk.deleted as isize;
+
+        // Hunks already suggested as they are come back on every keystroke
+        let unchanged = pending.iter().any(|s| {
+            s.author == author
+                && s.range == range
+                && s.original == original
+                && s.replacement == replacement
+        });
+        if unchanged {
+            continue;
+        }
+
+        let updated = state
+            .documents
+            .update_overlapping(document_id, author, range.clone(), &original, &replacement)
+            .await?;
+        if let Some(suggestion) = updated {
+            recorded.push((SuggestionEvent::Updated, suggestion));
+            continue;
+        }
+
+        let suggestion = Suggestion {
+            id: ObjectId::new().to_hex(),
+            document_id: document_id.to_string(),
+            author: author.to_string(),
+            range,
+            original,
+            replacement,
+            created_at: Utc::now(),
+            status: SuggestionStatus::Pending,
+            reviewed_by: None,
+        };
+        state.documents.insert_suggestion(&suggestion).await?;
+        recorded.push((SuggestionEvent::Created, suggestion));
+    }
+    Ok(recorded)
+}
+
+// Applies a pending suggestion to the document, refusing it if the text it
+// replaces has been edited in the meantime
+
+pub async fn accept(
+    state: &AppState,
+    document_id: &str,
+    suggestion_id: &str,
+    reviewer: &str,
+) -> Result<Suggestion, SuggestionError> {
+    let suggestion = state
+        .documents
+        .pending_suggestion(document_id, suggestion_id)
+        .await?
+        .ok_or(SuggestionError::NotFound)?;
+
+    let content = current_content(state, document_id)
+        .await
+        .map_err(|e| SuggestionError::Storage(StoreError::new(e.to_string())))?
+        .ok_or(SuggestionError::NotFound)?;
+
+    if edits::slice(&content, &suggestion.range).as_deref() != Some(suggestion.original.as_str()) {
+        return Err(SuggestionError::Conflict);
+    }
+
+    let updated = edits::splice(&content, &suggestion.range, &suggestion.replacement);
+    replace_content(state, document_id, &content, &updated, reviewer)
+        .await
+        .map_err(|e| match e.downcast_ref::<WriteConflict>() {
+            Some(_) => SuggestionError::Conflict,
+            None => SuggestionError::Storage(StoreError::new(e.to_string())),
+        })?;
+
+    mark_reviewed(
+        state,
+        document_id,
+        suggestion_id,
+        reviewer,
+        SuggestionStatus::Accepted,
+    )
+    .await
+}
+
+pub async fn reject(
+    state: &AppState,
+    document_id: &str,
+    suggestion_id: &str,
+    reviewer: &str,
+) -> Result<Suggestion, SuggestionError> {
+    mark_reviewed(
+        state,
+        document_id,
+        suggestion_id,
+        reviewer,
+        SuggestionStatus::Rejected,
+    )
+    .await
+}
+
+async fn mark_reviewed(
+    state: &AppState,
+    document_id: &str,
+    suggestion_id: &str,
+    reviewer: &str,
+    status: SuggestionStatus,
+) -> Result<Suggestion, SuggestionError> {
+    let suggestion = state
+        .documents
+        .review_suggestion(document_id, suggestion_id, status, reviewer)
+        .await?
+        .ok_or(SuggestionError::NotFound)?;
+
+    let event = match status {
+        SuggestionStatus::Rejected => SuggestionEvent::Rejected,
+        _ => SuggestionEvent::Accepted,
+    };
+    publish_event(state, document_id, event, suggestion.clone()).await?;
+    Ok(suggestion)
+}
+
+// Keeps pending suggestions attached to their text after an edit. Called with
+// the anchor lock of the document held, so the moves of edits are applied in
+// the order of the edits.
+
+pub async fn shift_ranges(
+    state: &AppState,
+    document_id: &str,
+    edit: &TextEdit,
+) -> Result<(), SuggestionError> {
+    let suggestions = state
+        .documents
+        .pending_ending_from(document_id, edit.start)
+        .await?;
+
+    for suggestion in suggestions {
+        let range = edits::transform_range(&suggestion.range, edit);
+        if range == suggestion.range {
+            continue;
+        }
+
+        state
+            .documents
+            .move_suggestion(&suggestion.id, range)
+            .await?;
+    }
+    Ok(())
+}
+
+// ***************************************************************************************************************************************
+// REST endpoints
+
+#[utoipa::path(
+    post,
+    path = "/get_suggestions",
+    tag = "suggestions",
+    params(
+        ("x-share-token" = Option<String>, Header, description = "Token of a share link"),
+    ),
+    request_body = GetSuggestionsRequest,
+    responses((status = 200, body = SuggestionsResponse))
+)]
+pub async fn get_suggestions(
+    State(state): State<AppState>,
+    ShareToken(share_token): ShareToken,
+    Json(payload): Json<GetSuggestionsRequest>,
+) -> Result<(StatusCode, Json<SuggestionsResponse>), ApiError> {
+    authz::authorize_with_token(
+        &state,
+        &payload.email,
+        &payload.document_id,
+        share_token.as_deref(),
+        Capability::View,
+    )
+    .await?;
+
+    let suggestions =
+        list_suggestions(&state, &payload.document_id, payload.include_reviewed).await?;
+
+    Ok((
+        StatusCode::OK,
+        Json(SuggestionsResponse {
+            success: true,
+            suggestions,
+        }),
+    ))
+}
+
+#[utoipa::path(
+    post,
+    path = "/accept_suggestion",
+    tag = "suggestions",
+    params(
+        ("x-share-token" = Option<String>, Header, description = "Token of a share link"),
+    ),
+    request_body = ReviewSuggestionRequest,
+    responses((status = 200, body = SuggestionResponse))
+)]
+pub async fn accept_suggestion(
+    State(state): State<AppState>,
+    ShareToken(share_token): ShareToken,
+    Json(payload): Json<ReviewSuggestionRequest>,
+) -> Result<(StatusCode, Json<SuggestionResponse>), ApiError> {
+    authz::authorize_with_token(
+        &state,
+        &payload.email,
+        &payload.document_id,
+        share_token.as_deref(),
+        Capability::Edit,
+    )
+    .await?;
+
+    let suggestion = accept(
+        &state,
+        &payload.document_id,
+        &payload.suggestion_id,
+        &payload.email,
+    )
+    .await?;
+
+    Ok((
+        StatusCode::OK,
+        Json(SuggestionResponse {
+            success: true,
+            suggestion,
+        }),
+    ))
+}
+
+#[utoipa::path(
+    post,
+    path = "/reject_suggestion",
+    tag = "suggestions",
+    params(
+        ("x-share-token" = Option<String>, Header, description = "Token of a share link"),
+    ),
+    request_body = ReviewSuggestionRequest,
+    responses((status = 200, body = SuggestionResponse))
+)]
+pub async fn reject_suggestion(
+    State(state): State<AppState>,
+    ShareToken(share_token): ShareToken,
+    Json(payload): Json<ReviewSuggestionRequest>,
+) -> Result<(StatusCode, Json<SuggestionResponse>), ApiError> {
+    authz::authorize_with_token(
+        &state,
+        &payload.email,
+        &payload.document_id,
+        share_token.as_deref(),
+        Capability::Edit,
+    )
+    .await?;
+
+    let suggestion = reject(
+        &state,
+        &payload.document_id,
+        &payload.suggestion_id,
+        &payload.email,
+    )
+    .await?;
+
+    Ok((
+        StatusCode::OK,
+        Json(SuggestionResponse {
+            success: true,
+            suggestion,
+        }),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::memory::MemoryStore;
+    use crate::store::{DocumentStore, LiveDocCache};
+    use crate::structs::Document;
+
+    async fn open_document(content: &str) -> (AppState, String) {
+        let store = MemoryStore::new();
+        let document = Document::new("Notes".to_string(), "txt".to_string(), content.to_string());
+        let document_id = store.insert(document).await.unwrap();
+        store.open(&document_id, content, 0).await.unwrap();
+        (store.app_state(), document_id)
+    }
+
+    // Inserts text the way a session does, again on top of whatever edit
+    // landed in between. Without a position the text is appended.
+    async fn insert(state: &AppState, document_id: &str, at: Option<usize>, text: &str) {
+        loop {
+            let previous = current_content(state, document_id).await.unwrap().unwrap();
+            let at = at.unwrap_or(previous.chars().count());
+            let range = TextRange { start: at, end: at };
+            let content = edits::splice(&previous, &range, text);
+            match replace_content(state, document_id, &previous, &content, "x@example.com").await {
+                Ok(()) => return,
+                Err(e) => assert!(e.downcast_ref::<WriteConflict>().is_some()),
+            }
+        }
+    }
+
+    fn summary(suggestions: &[Suggestion]) -> Vec<(usize, usize, &str, &str)> {
+        let mut summary: Vec<_> = suggestions
+            .iter()
+            .map(|s| {
+                let (start, end) = (s.range.start, s.range.end);
+                (start, end, s.original.as_str(), s.replacement.as_str())
+            })
+            .collect();
+        summary.sort();
+        summary
+    }
+
+    #[tokio::test]
+    async fn separate_changes_become_separate_suggestions() {
+        let (state, document_id) = open_document("The cat sat on the mat").await;
+        record_edit(
+            &state,
+            &document_id,
+            "s@example.com",
+            "The dog sat on the rug",
+        )
+        .await
+        .unwrap();
+
+        let suggestions = list_suggestions(&state, &document_id, false).await.unwrap();
+        assert_eq!(
+            summary(&suggestions),
+            vec![(4, 7, "cat", "dog"), (19, 22, "mat", "rug")]
+        );
+    }
+
+    #[tokio::test]
+    async fn keystrokes_update_the_same_suggestion() {
+        let (state, document_id) = open_document("The cat sat").await;
+        for proposed in ["The d sat", "The do sat", "The dog sat", "The dog sat"] {
+            record_edit(&state, &document_id, "s@example.com", proposed)
+                .await
+                .unwrap();
+        }
+        record_edit(&state, &document_id, "t@example.com", "The cow sat")
+            .await
+            .unwrap();
+
+        let suggestions = list_suggestions(&state, &document_id, false).await.unwrap();
+        assert_eq!(
+            summary(&suggestions),
+            vec![(4, 7, "cat", "cow"), (4, 7, "cat", "dog")]
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn ranges_follow_concurrent_edits() {
+        let (state, document_id) = open_document("The cat sat on the mat").await;
+        record_edit(
+            &state,
+            &document_id,
+            "s@example.com",
+            "The cat sat on the rug",
+        )
+        .await
+        .unwrap();
+
+        let mut tasks = Vec::new();
+        for i in 0..40 {
+            let state = state.clone();
+            let document_id = document_id.clone();
+            let at = (i % 2 == 0).then_some(0);
+            tasks.push(tokio::spawn(async move {
+                insert(&state, &document_id, at, "ab").await;
+            }));
+        }
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        let content = current_content(&state, &document_id)
+            .await
+            .unwrap()
+            .unwrap();
+        let suggestions = list_suggestions(&state, &document_id, false).await.unwrap();
+        assert_eq!(suggestions[0].range, TextRange { start: 59, end: 62 });
+        assert_eq!(
+            edits::slice(&content, &suggestions[0].range).as_deref(),
+            Some("mat")
+        );
+    }
+
+    #[tokio::test]
+    async fn accepting_applies_the_replacement_where_the_text_moved() {
+        let (state, document_id) = open_document("The cat sat").await;
+        record_edit(&state, &document_id, "s@example.com", "The dog sat")
+            .await
+            .unwrap();
+        insert(&state, &document_id, Some(0), "Look! ").await;
+
+        let suggestions = list_suggestions(&state, &document_id, false).await.unwrap();
+        accept(&state, &document_id, &suggestions[0].id, "o@example.com")
+            .await
+            .unwrap();
+
+        let content = current_content(&state, &document_id).await.unwrap();
+        assert_eq!(content.as_deref(), Some("Look! The dog sat"));
+        let pending = list_suggestions(&state, &document_id, false).await.unwrap();
+        assert!(pending.is_empty());
+    }
+
+    #[tokio::test]
+    async fn suggestions_on_changed_text_can_only_be_rejected() {
+        let (state, document_id) = open_document("The cat sat").await;
+        record_edit(&state, &document_id, "s@example.com", "The dog sat")
+            .await
+            .unwrap();
+        replace_content(
+            &state,
+            &document_id,
+            "The cat sat",
+            "The cow sat",
+            "o@example.com",
+        )
+        .await
+        .unwrap();
+
+        let suggestions = list_suggestions(&state, &document_id, false).await.unwrap();
+        let id = &suggestions[0].id;
+        let accepted = accept(&state, &document_id, id, "o@example.com").await;
+        assert!(matches!(accepted, Err(SuggestionError::Conflict)));
+
+        let rejected = reject(&state, &document_id, id, "o@example.com")
+            .await
+            .unwrap();
+        assert_eq!(rejected.status, SuggestionStatus::Rejected);
+        assert_eq!(rejected.reviewed_by.as_deref(), Some("o@example.com"));
+        let content = current_content(&state, &document_id).await.unwrap();
+        assert_eq!(content.as_deref(), Some("The cow sat"));
+    }
+}
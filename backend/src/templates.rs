@@ -0,0 +1,270 @@
+use axum::{extract::State, http::StatusCode};
+use chrono::Utc;
+use log::error;
+use mongodb::bson::oid::ObjectId;
+use serde_json::json;
+
+use crate::audit::{self, AuditEvent};
+use crate::authz::{self, Capability, user_has_access};
+use crate::errors::{ApiError, Json};
+use crate::structs::{
+    AppState, CreateFromTemplateRequest, CreatedDocumentResponse, Document,
+    DuplicateDocumentRequest, GetTemplatesRequest, SetTemplateRequest, Template, TemplateResponse,
+    TemplateSummary, TemplatesResponse,
+};
+use crate::ws_handler::current_content;
+
+async fn find_document(state: &AppState, document_id: &str) -> Result<Document, ApiError> {
+    ObjectId::parse_str(document_id).map_err(|e| ApiError::bad_request(e.to_string()))?;
+
+    state
+        .documents
+        .get(document_id)
+        .await?
+        .ok_or_else(|| ApiError::not_found("Document not found"))
+}
+
+// Creates a new document owned by `owner` from the current content of the
+// source, taking the live content if the source is open. With `copy_sharing`
+// the users and groups the source is shared with get the same roles on the
+// copy.
+
+async fn create_copy(
+    state: &AppState,
+    owner: &str,
+    source_id: &str,
+    source: Document,
+    title: Option<String>,
+    copy_sharing: bool,
+) -> Result<String, ApiError> {
+    let title = title
+        .map(|t| t.trim().to_string())
+        .unwrap_or_else(|| source.title.clone());
+    if title.is_empty() {
+        return Err(ApiError::bad_request("Title must not be empty"));
+    }
+
+    let content = current_content(state, source_id)
+        .await
+        .ok()
+        .flatten()
+        .unwrap_or(source.content);
+
+    let document_id = state
+        .documents
+        .insert(Document::new(title, source.format, content))
+        .await?;
+
+    let relations = state
+        .access
+        .copy_relations(owner, source_id, &document_id, copy_sharing)
+        .await;
+    if let Err(e) = relations {
+        // Without an owner the copy could never be opened again
+        if let Err(e) = state.documents.delete(&document_id).await {
+            error!("Failed to remove orphaned copy {}: {}", document_id, e);
+        }
+        return Err(e.into());
+    }
+
+    Ok(document_id)
+}
+
+// ***************************************************************************************************************************************
+// Templates
+
+#[utoipa::path(
+    post,
+    path = "/set_template",
+    tag = "templates",
+    request_body = SetTemplateRequest,
+    responses((status = 200, body = TemplateResponse))
+)]
+pub async fn set_template(
+    State(state): State<AppState>,
+    Json(payload): Json<SetTemplateRequest>,
+) -> Result<(StatusCode, Json<TemplateResponse>), ApiError> {
+    authz::authorize(
+        &state,
+        &payload.email,
+        &payload.document_id,
+        Capability::Share,
+    )
+    .await?;
+
+    ObjectId::parse_str(&payload.document_id).map_err(|e| ApiError::bad_request(e.to_string()))?;
+
+    let template = payload.template.then(|| Template {
+        marked_by: payload.email.clone(),
+        marked_at: Utc::now(),
+        shared_with_all: payload.shared_with_all,
+    });
+
+    state
+        .documents
+        .set_template(&payload.document_id, template)
+        .await?;
+
+    Ok((
+        StatusCode::OK,
+        Json(TemplateResponse {
+            success: true,
+            template: payload.template,
+        }),
+    ))
+}
+
+// Templates the user can instantiate
+
+#[utoipa::path(
+    post,
+    path = "/get_templates",
+    tag = "templates",
+    request_body = GetTemplatesRequest,
+    responses((status = 200, body = TemplatesResponse))
+)]
+pub async fn get_templates(
+    State(state): State<AppState>,
+    Json(payload): Json<GetTemplatesRequest>,
+) -> Result<(StatusCode, Json<TemplatesResponse>), ApiError> {
+    let candidates = state.documents.templates().await?;
+
+    let mut templates = Vec::new();
+
+    for document in candidates {
+        let (Some(id), Some(template)) = (document.id, document.template) else {
+            continue;
+        };
+        let document_id = id.to_hex();
+
+        if !template.shared_with_all {
+            let access = user_has_access(&payload.email, &document_id, None, &state).await?;
+            if access.is_none() {
+                continue;
+            }
+        }
+
+        templates.push(TemplateSummary {
+            id: document_id,
+            title: document.title,
+            format: document.format,
+            marked_by: template.marked_by,
+            shared_with_all: template.shared_with_all,
+        });
+    }
+
+    Ok((
+        StatusCode::OK,
+        Json(TemplatesResponse {
+            success: true,
+            templates,
+        }),
+    ))
+}
+
+#[utoipa::path(
+    post,
+    path = "/create_from_template",
+    tag = "templates",
+    request_body = CreateFromTemplateRequest,
+    responses((status = 201, body = CreatedDocumentResponse))
+)]
+pub async fn create_from_template(
+    State(state): State<AppState>,
+    Json(payload): Json<CreateFromTemplateRequest>,
+) -> Result<(StatusCode, Json<CreatedDocumentResponse>), ApiError> {
+    let source = find_document(&state, &payload.template_id).await?;
+
+    let Some(template) = &source.template else {
+        return Err(ApiError::not_found("Template not found"));
+    };
+
+    if !template.shared_with_all {
+        authz::authorize(
+            &state,
+            &payload.email,
+            &payload.template_id,
+            Capability::View,
+        )
+        .await?;
+    }
+
+    let document_id = create_copy(
+        &state,
+        &payload.email,
+        &payload.template_id,
+        source,
+        payload.title,
+        false,
+    )
+    .await?;
+
+    audit::record(
+        &state,
+        AuditEvent::DocumentCreated,
+        Some(&payload.email),
+        Some(&document_id),
+        json!({ "template_id": payload.template_id }),
+    )
+    .await;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(CreatedDocumentResponse {
+            success: true,
+            document_id,
+        }),
+    ))
+}
+
+// ***************************************************************************************************************************************
+// Duplicates. Anyone who can read a document can duplicate it, copying the
+// sharing settings is reserved to the owner since it reveals who has access.
+
+#[utoipa::path(
+    post,
+    path = "/duplicate_document",
+    tag = "templates",
+    request_body = DuplicateDocumentRequest,
+    responses((status = 201, body = CreatedDocumentResponse))
+)]
+pub async fn duplicate_document(
+    State(state): State<AppState>,
+    Json(payload): Json<DuplicateDocumentRequest>,
+) -> Result<(StatusCode, Json<CreatedDocumentResponse>), ApiError> {
+    let capability = if payload.copy_sharing {
+        Capability::Share
+    } else {
+        Capability::View
+    };
+    authz::authorize(&state, &payload.email, &payload.document_id, capability).await?;
+
+    let source = find_document(&state, &payload.document_id).await?;
+
+    let document_id = create_copy(
+        &state,
+        &payload.email,
+        &payload.document_id,
+        source,
+        payload.title,
+        payload.copy_sharing,
+    )
+    .await?;
+
+    audit::record(
+        &state,
+        AuditEvent::DocumentCreated,
+        Some(&payload.email),
+        Some(&document_id),
+        json!({ "copied_from": payload.document_id, "copy_sharing": payload.copy_sharing }),
+    )
+    .await;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(CreatedDocumentResponse {
+            success: true,
+            document_id,
+        }),
+    ))
+}
@@ -0,0 +1,229 @@
+use serde::{Deserialize, Serialize};
+
+use crate::edits;
+use crate::structs::{TextRange, WsServerMessage};
+
+// Updates kept in the stream after compaction, for clients catching up after
+// a reconnect. Clients further behind get a snapshot instead.
+pub const RETAINED_UPDATES: u64 = 1000;
+
+// Adds a sequence number to a message sent directly to one client
+pub fn with_seq(message: &impl Serialize, seq: u64) -> String {
+    let json = serde_json::to_string(message).unwrap();
+    format!("{{\"seq\":{},{}", seq, &json[1..])
+}
+
+// A change of the content as the op log keeps it, the replaced characters
+// and the text replacing them. The log holds changes rather than the whole
+// content, sessions apply them to their own copy.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename = "edit")]
+pub struct Change {
+    pub start: usize,
+    pub deleted: usize,
+    pub text: String,
+}
+
+impl Change {
+    // The change turning `previous` into `content`, None when they are equal
+    pub fn between(previous: &str, content: &str) -> Option<Change> {
+        let edit = edits::diff(previous, content)?;
+        let text = content
+            .chars()
+            .skip(edit.start)
+            .take(edit.inserted)
+            .collect();
+        Some(Change {
+            start: edit.start,
+            deleted: edit.deleted,
+            text,
+        })
+    }
+
+    pub fn apply(&self, content: &str) -> String {
+        let range = TextRange {
+            start: self.start,
+            end: self.start + self.deleted,
+        };
+        edits::splice(content, &range, &self.text)
+    }
+}
+
+// Turns the updates of the op log into the messages one client gets. Changes
+// are applied to the content the session started from and the client gets
+// the resulting content. Viewers of a published document get nothing but
+// content, comments, suggestions and the lock holder name people.
+pub struct Relay {
+    content: String,
+    content_only: bool,
+}
+
+impl Relay {
+    pub fn new(content: String, content_only: bool) -> Relay {
+        Relay {
+            content,
+            content_only,
+        }
+    }
+
+    // The message for the client, None when the update is not for it
+    pub fn relay(&mut self, payload: String) -> Option<String> {
+        let update: serde_json::Value = serde_json::from_str(&payload).ok()?;
+        if update["type"] == "edit" {
+            let seq = update["seq"].as_u64()?;
+            let change: Change = serde_json::from_value(update).ok()?;
+            self.content = change.apply(&self.content);
+            return Some(self.content_at(seq));
+        }
+        (!self.content_only || update["type"] == "content").then_some(payload)
+    }
+
+    // The updates a reconnecting client missed, up to `seq`. The content the
+    // client has is not known, so the changes among them are replaced by the
+    // content they led to.
+    pub fn replay(&self, payloads: Vec<String>, seq: u64) -> Vec<String> {
+        let mut changed = false;
+        let mut messages = Vec::new();
+        for payload in payloads {
+            let update: serde_json::Value = serde_json::from_str(&payload).unwrap_or_default();
+            if update["type"] == "edit" || update["type"] == "content" {
+                changed = true;
+            } else if !self.content_only {
+                messages.push(payload);
+            }
+        }
+        if changed {
+            messages.push(self.content_at(seq));
+        }
+        messages
+    }
+
+    // The content the session is at, numbered like the update that led to it
+    pub fn content_at(&self, seq: u64) -> String {
+        let message = WsServerMessage::Content {
+            content: self.content.clone(),
+        };
+        with_seq(&message, seq)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Resume {
+    // The client is up to date
+    UpToDate,
+    // The client gets the updates after its last sequence number
+    Replay,
+    // The client is too far behind or its sequence number is unknown
+    Snapshot,
+}
+
+// Decides how a client that last saw `last_seq` catches up, given the
+// current sequence number and the oldest one still in the stream
+
+pub fn plan_resume(last_seq: u64, current: u64, oldest: Option<u64>) -> Resume {
+    if last_seq == current {
+        Resume::UpToDate
+    } else if last_seq > current {
+        // The counter was reset since, e.g. after the keys expired
+        Resume::Snapshot
+    } else if oldest.is_some_and(|oldest| oldest <= last_seq + 1) {
+        Resume::Replay
+    } else {
+        Resume::Snapshot
+    }
+}
+
+// Op log entry ids start with the sequence number of the update
+
+pub fn seq_from_id(id: &str) -> Option<u64> {
+    id.split_once('-')?.0.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn client_at_the_current_sequence_is_up_to_date() {
+        assert_eq!(plan_resume(7, 7, Some(1)), Resume::UpToDate);
+        assert_eq!(plan_resume(0, 0, None), Resume::UpToDate);
+    }
+
+    #[test]
+    fn client_behind_within_the_stream_replays() {
+        assert_eq!(plan_resume(5, 9, Some(1)), Resume::Replay);
+        assert_eq!(plan_resume(5, 9, Some(6)), Resume::Replay);
+    }
+
+    #[test]
+    fn client_behind_the_trimmed_stream_gets_a_snapshot() {
+        assert_eq!(plan_resume(5, 900, Some(7)), Resume::Snapshot);
+        assert_eq!(plan_resume(5, 9, None), Resume::Snapshot);
+    }
+
+    #[test]
+    fn client_ahead_of_a_reset_counter_gets_a_snapshot() {
+        assert_eq!(plan_resume(12, 3, Some(1)), Resume::Snapshot);
+    }
+
+    #[test]
+    fn sequence_numbers_are_read_back_from_payloads() {
+        let message = WsServerMessage::Content {
+            content: "hello".to_string(),
+        };
+        let payload = with_seq(&message, 42);
+
+        assert!(payload.starts_with(r#"{"seq":42,"type":"content""#));
+        assert_eq!(seq_from_id("42-1"), Some(42));
+        assert_eq!(seq_from_id("garbage"), None);
+    }
+
+    #[test]
+    fn changes_rebuild_the_content() {
+        let change = Change::between("héllo world", "héllo, brave world").unwrap();
+
+        assert_eq!(change.start, 5);
+        assert_eq!(change.deleted, 0);
+        assert_eq!(change.text, ", brave");
+        assert_eq!(change.apply("héllo world"), "héllo, brave world");
+        assert_eq!(Change::between("same", "same"), None);
+    }
+
+    #[test]
+    fn relayed_changes_become_content() {
+        let mut relay = Relay::new("Hello".to_string(), false);
+        let change = Change::between("Hello", "Hello world").unwrap();
+        let comment = r#"{"seq":2,"type":"comment","event":"created"}"#.to_string();
+
+        let content = relay.relay(with_seq(&change, 1)).unwrap();
+        assert_eq!(
+            content,
+            r#"{"seq":1,"type":"content","content":"Hello world"}"#
+        );
+        assert_eq!(relay.relay(comment.clone()), Some(comment));
+    }
+
+    #[test]
+    fn content_only_relays_drop_everything_else() {
+        let mut relay = Relay::new(String::new(), true);
+        let change = Change::between("", "Hi").unwrap();
+        let comment = r#"{"seq":2,"type":"comment","event":"created"}"#.to_string();
+        let lock = r#"{"seq":3,"type":"lock","holder":"a@example.com"}"#.to_string();
+
+        assert!(relay.relay(with_seq(&change, 1)).is_some());
+        assert_eq!(relay.relay(comment.clone()), None);
+        assert_eq!(relay.relay(lock.clone()), None);
+        assert_eq!(
+            relay.replay(vec![with_seq(&change, 1), comment, lock], 3),
+            vec![r#"{"seq":3,"type":"content","content":"Hi"}"#]
+        );
+    }
+
+    #[test]
+    fn replays_without_changes_keep_the_content_out() {
+        let relay = Relay::new("Hi".to_string(), false);
+        let comment = r#"{"seq":4,"type":"comment","event":"created"}"#.to_string();
+
+        assert_eq!(relay.replay(vec![comment.clone()], 4), vec![comment]);
+    }
+}
@@ -0,0 +1,366 @@
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use serde_json::json;
+
+use super::CurrentUser;
+use crate::audit::{self, AuditEvent};
+use crate::authz::{self, Capability};
+use crate::errors::{ApiError, Json};
+use crate::share_links::ShareToken;
+use crate::structs::{
+    AppState, AuditLogQuery, AuditLogResponse, CreatedDocumentResponse, DeleteDocumentRequest,
+    DocumentCopy, DocumentCreateRequest, DocumentEntry, DocumentFolder, DocumentListQuery,
+    DocumentPatch, DocumentResponse, DocumentsResponse, DuplicateDocumentRequest, EditLockResponse,
+    ExclusiveEditingResponse, GetAuditLogRequest, GetDocumentRequest, GetEditLockRequest,
+    GetUserRole, LockSettings, MessageResponse, MoveDocumentRequest, NewDocument, RoleResponse,
+    SetExclusiveEditingRequest, TransferOwnershipRequest,
+};
+use crate::ws_handler::current_content;
+use crate::{folders, locks, templates};
+
+fn listing(email: String, query: DocumentListQuery) -> GetDocumentRequest {
+    GetDocumentRequest {
+        email,
+        tag: query.tag,
+        favorites: query.favorites,
+        recent: query.recent,
+    }
+}
+
+// Documents owned by the user
+#[utoipa::path(
+    get,
+    path = "/api/v1/documents",
+    tag = "documents",
+    params(DocumentListQuery),
+    responses((status = 200, body = DocumentsResponse))
+)]
+pub async fn list_owned(
+    State(state): State<AppState>,
+    CurrentUser(email): CurrentUser,
+    Query(query): Query<DocumentListQuery>,
+) -> Result<(StatusCode, Json<DocumentsResponse>), ApiError> {
+    crate::get_all_documents_owner(State(state), Json(listing(email, query))).await
+}
+
+// Documents shared with the user, directly or through a group. Documents
+// reached only through a shared folder are listed with the folder.
+#[utoipa::path(
+    get,
+    path = "/api/v1/documents/shared",
+    tag = "documents",
+    params(DocumentListQuery),
+    responses((status = 200, body = DocumentsResponse))
+)]
+pub async fn list_shared(
+    State(state): State<AppState>,
+    CurrentUser(email): CurrentUser,
+    Query(query): Query<DocumentListQuery>,
+) -> Result<(StatusCode, Json<DocumentsResponse>), ApiError> {
+    crate::get_all_documents_shared(State(state), Json(listing(email, query))).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/documents",
+    tag = "documents",
+    request_body = NewDocument,
+    responses((status = 201, body = CreatedDocumentResponse))
+)]
+pub async fn create(
+    State(state): State<AppState>,
+    CurrentUser(email): CurrentUser,
+    Json(body): Json<NewDocument>,
+) -> Result<(StatusCode, Json<CreatedDocumentResponse>), ApiError> {
+    let request = DocumentCreateRequest {
+        title: body.title,
+        format: body.format,
+        collaborators: body.collaborators,
+        readers: body.readers,
+        suggesters: body.suggesters,
+        owner: email,
+        groups: body.groups,
+    };
+    let document_id = crate::create_document(&state, request).await?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(CreatedDocumentResponse {
+            success: true,
+            document_id,
+        }),
+    ))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/documents/{id}",
+    tag = "documents",
+    params(
+        ("id" = String, Path, description = "Document id"),
+        ("x-share-token" = Option<String>, Header, description = "Token of a share link"),
+    ),
+    responses((status = 200, body = DocumentResponse))
+)]
+pub async fn get(
+    State(state): State<AppState>,
+    CurrentUser(email): CurrentUser,
+    ShareToken(share_token): ShareToken,
+    Path(id): Path<String>,
+) -> Result<(StatusCode, Json<DocumentResponse>), ApiError> {
+    let access = authz::authorize_with_token(
+        &state,
+        &email,
+        &id,
+        share_token.as_deref(),
+        Capability::View,
+    )
+    .await?;
+
+    let document = state
+        .documents
+        .get(&id)
+        .await?
+        .ok_or_else(|| ApiError::not_found("Document not found"))?;
+
+    // Serve the live content when the document is being edited
+    let content = current_content(&state, &id)
+        .await
+        .ok()
+        .flatten()
+        .unwrap_or_else(|| document.content.clone());
+
+    let entry = DocumentEntry {
+        role: access.role.as_str().to_string(),
+        metadata: document.metadata(),
+        title: document.title,
+        format: document.format,
+        opened_at: None,
+        id,
+    };
+
+    Ok((
+        StatusCode::OK,
+        Json(DocumentResponse {
+            success: true,
+            document: entry,
+            content,
+        }),
+    ))
+}
+
+// Renames the document and or transfers its ownership
+#[utoipa::path(
+    patch,
+    path = "/api/v1/documents/{id}",
+    tag = "documents",
+    params(("id" = String, Path, description = "Document id")),
+    request_body = DocumentPatch,
+    responses((status = 200, body = MessageResponse))
+)]
+pub async fn update(
+    State(state): State<AppState>,
+    CurrentUser(email): CurrentUser,
+    Path(id): Path<String>,
+    Json(patch): Json<DocumentPatch>,
+) -> Result<(StatusCode, Json<MessageResponse>), ApiError> {
+    if patch.title.is_none() && patch.owner.is_none() {
+        return Err(ApiError::bad_request("Nothing to update"));
+    }
+
+    // Everything is checked before anything is written, a refused part must
+    // not leave the other one applied
+    let title = patch.title.as_deref().map(str::trim);
+    if title.is_some() {
+        authz::authorize(&state, &email, &id, Capability::Edit).await?;
+    }
+    if title == Some("") {
+        return Err(ApiError::bad_request("Title must not be empty"));
+    }
+    if let Some(new_owner) = &patch.owner {
+        authz::authorize(&state, &email, &id, Capability::Transfer).await?;
+        if *new_owner == email {
+            return Err(ApiError::bad_request("New owner must be another user"));
+        }
+    }
+
+    // Transferred first, it can still fail on an unknown user. The previous
+    // owner stays an editor and may still rename.
+    if let Some(new_owner) = patch.owner {
+        let request = TransferOwnershipRequest {
+            email: email.clone(),
+            document_id: id.clone(),
+            new_owner,
+        };
+        let _ = crate::transfer_ownership(State(state.clone()), Json(request)).await?;
+    }
+
+    if let Some(title) = title {
+        state.documents.set_title(&id, title).await?;
+
+        audit::record(
+            &state,
+            AuditEvent::DocumentRenamed,
+            Some(&email),
+            Some(&id),
+            json!({ "title": title }),
+        )
+        .await;
+    }
+
+    Ok((StatusCode::OK, Json(MessageResponse::new("Updated"))))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/documents/{id}",
+    tag = "documents",
+    params(("id" = String, Path, description = "Document id")),
+    responses((status = 200, body = MessageResponse))
+)]
+pub async fn delete(
+    State(state): State<AppState>,
+    CurrentUser(email): CurrentUser,
+    Path(id): Path<String>,
+) -> Result<(StatusCode, Json<MessageResponse>), ApiError> {
+    let request = DeleteDocumentRequest {
+        email,
+        document_id: id,
+    };
+    crate::delete_document(State(state), Json(request)).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/documents/{id}/role",
+    tag = "documents",
+    params(
+        ("id" = String, Path, description = "Document id"),
+        ("x-share-token" = Option<String>, Header, description = "Token of a share link"),
+    ),
+    responses((status = 200, body = RoleResponse))
+)]
+pub async fn role(
+    State(state): State<AppState>,
+    CurrentUser(email): CurrentUser,
+    share_token: ShareToken,
+    Path(id): Path<String>,
+) -> Result<(StatusCode, Json<RoleResponse>), ApiError> {
+    let request = GetUserRole {
+        email,
+        document_id: id,
+    };
+    crate::get_user_role(State(state), share_token, Json(request)).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/documents/{id}/copies",
+    tag = "documents",
+    params(("id" = String, Path, description = "Document id")),
+    request_body = DocumentCopy,
+    responses((status = 201, body = CreatedDocumentResponse))
+)]
+pub async fn copy(
+    State(state): State<AppState>,
+    CurrentUser(email): CurrentUser,
+    Path(id): Path<String>,
+    Json(body): Json<DocumentCopy>,
+) -> Result<(StatusCode, Json<CreatedDocumentResponse>), ApiError> {
+    let request = DuplicateDocumentRequest {
+        email,
+        document_id: id,
+        title: body.title,
+        copy_sharing: body.copy_sharing,
+    };
+    templates::duplicate_document(State(state), Json(request)).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/documents/{id}/lock",
+    tag = "locks",
+    params(("id" = String, Path, description = "Document id")),
+    responses((status = 200, body = EditLockResponse))
+)]
+pub async fn lock(
+    State(state): State<AppState>,
+    CurrentUser(email): CurrentUser,
+    Path(id): Path<String>,
+) -> Result<(StatusCode, Json<EditLockResponse>), ApiError> {
+    let request = GetEditLockRequest {
+        email,
+        document_id: id,
+    };
+    locks::get_edit_lock(State(state), Json(request)).await
+}
+
+// Turns exclusive editing on or off
+#[utoipa::path(
+    put,
+    path = "/api/v1/documents/{id}/lock",
+    tag = "locks",
+    params(("id" = String, Path, description = "Document id")),
+    request_body = LockSettings,
+    responses((status = 200, body = ExclusiveEditingResponse))
+)]
+pub async fn set_lock(
+    State(state): State<AppState>,
+    CurrentUser(email): CurrentUser,
+    Path(id): Path<String>,
+    Json(body): Json<LockSettings>,
+) -> Result<(StatusCode, Json<ExclusiveEditingResponse>), ApiError> {
+    let request = SetExclusiveEditingRequest {
+        email,
+        document_id: id,
+        enabled: body.exclusive,
+    };
+    locks::set_exclusive_editing(State(state), Json(request)).await
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/v1/documents/{id}/folder",
+    tag = "folders",
+    params(("id" = String, Path, description = "Document id")),
+    request_body = DocumentFolder,
+    responses((status = 200, body = MessageResponse))
+)]
+pub async fn set_folder(
+    State(state): State<AppState>,
+    CurrentUser(email): CurrentUser,
+    Path(id): Path<String>,
+    Json(body): Json<DocumentFolder>,
+) -> Result<(StatusCode, Json<MessageResponse>), ApiError> {
+    let request = MoveDocumentRequest {
+        email,
+        document_id: id,
+        folder_id: body.folder_id,
+    };
+    folders::move_document(State(state), Json(request)).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/documents/{id}/audit",
+    tag = "audit",
+    params(("id" = String, Path, description = "Document id"), AuditLogQuery),
+    responses((status = 200, body = AuditLogResponse))
+)]
+pub async fn audit_log(
+    State(state): State<AppState>,
+    CurrentUser(email): CurrentUser,
+    Path(id): Path<String>,
+    Query(query): Query<AuditLogQuery>,
+) -> Result<(StatusCode, Json<AuditLogResponse>), ApiError> {
+    let request = GetAuditLogRequest {
+        email,
+        document_id: id,
+        event_type: query.event_type,
+        actor: query.actor,
+        since: query.since,
+        until: query.until,
+        limit: query.limit,
+    };
+    audit::get_audit_log(State(state), Json(request)).await
+}
@@ -0,0 +1,183 @@
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+
+use super::CurrentUser;
+use crate::errors::{ApiError, Json};
+use crate::folders;
+use crate::structs::{
+    AppState, CreateFolderRequest, DeleteFolderRequest, FolderContentsResponse, FolderGrant,
+    FolderParent, FolderPatch, FolderResponse, GetFolderContentsRequest, MessageResponse,
+    MoveFolderRequest, NewFolder, ShareFolderRequest, UnshareFolderRequest, UpdateFolderRequest,
+};
+
+// The user's own top-level folders, folders shared with them and their
+// documents outside of any folder
+#[utoipa::path(
+    get,
+    path = "/api/v1/folders",
+    tag = "folders",
+    responses((status = 200, body = FolderContentsResponse))
+)]
+pub async fn list_top_level(
+    State(state): State<AppState>,
+    CurrentUser(email): CurrentUser,
+) -> Result<(StatusCode, Json<FolderContentsResponse>), ApiError> {
+    let request = GetFolderContentsRequest {
+        email,
+        folder_id: None,
+    };
+    folders::get_folder_contents(State(state), Json(request)).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/folders/{id}",
+    tag = "folders",
+    params(("id" = i32, Path, description = "Folder id")),
+    responses((status = 200, body = FolderContentsResponse))
+)]
+pub async fn contents(
+    State(state): State<AppState>,
+    CurrentUser(email): CurrentUser,
+    Path(id): Path<i32>,
+) -> Result<(StatusCode, Json<FolderContentsResponse>), ApiError> {
+    let request = GetFolderContentsRequest {
+        email,
+        folder_id: Some(id),
+    };
+    folders::get_folder_contents(State(state), Json(request)).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/folders",
+    tag = "folders",
+    request_body = NewFolder,
+    responses((status = 201, body = FolderResponse))
+)]
+pub async fn create(
+    State(state): State<AppState>,
+    CurrentUser(email): CurrentUser,
+    Json(body): Json<NewFolder>,
+) -> Result<(StatusCode, Json<FolderResponse>), ApiError> {
+    let request = CreateFolderRequest {
+        email,
+        name: body.name,
+        parent_id: body.parent_id,
+        inherit_permissions: body.inherit_permissions,
+    };
+    folders::create_folder(State(state), Json(request)).await
+}
+
+#[utoipa::path(
+    patch,
+    path = "/api/v1/folders/{id}",
+    tag = "folders",
+    params(("id" = i32, Path, description = "Folder id")),
+    request_body = FolderPatch,
+    responses((status = 200, body = FolderResponse))
+)]
+pub async fn update(
+    State(state): State<AppState>,
+    CurrentUser(email): CurrentUser,
+    Path(id): Path<i32>,
+    Json(patch): Json<FolderPatch>,
+) -> Result<(StatusCode, Json<FolderResponse>), ApiError> {
+    let request = UpdateFolderRequest {
+        email,
+        folder_id: id,
+        name: patch.name,
+        inherit_permissions: patch.inherit_permissions,
+    };
+    folders::update_folder(State(state), Json(request)).await
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/v1/folders/{id}/parent",
+    tag = "folders",
+    params(("id" = i32, Path, description = "Folder id")),
+    request_body = FolderParent,
+    responses((status = 200, body = MessageResponse))
+)]
+pub async fn set_parent(
+    State(state): State<AppState>,
+    CurrentUser(email): CurrentUser,
+    Path(id): Path<i32>,
+    Json(body): Json<FolderParent>,
+) -> Result<(StatusCode, Json<MessageResponse>), ApiError> {
+    let request = MoveFolderRequest {
+        email,
+        folder_id: id,
+        parent_id: body.parent_id,
+    };
+    folders::move_folder(State(state), Json(request)).await
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/folders/{id}",
+    tag = "folders",
+    params(("id" = i32, Path, description = "Folder id")),
+    responses((status = 200, body = MessageResponse))
+)]
+pub async fn delete(
+    State(state): State<AppState>,
+    CurrentUser(email): CurrentUser,
+    Path(id): Path<i32>,
+) -> Result<(StatusCode, Json<MessageResponse>), ApiError> {
+    let request = DeleteFolderRequest {
+        email,
+        folder_id: id,
+    };
+    folders::delete_folder(State(state), Json(request)).await
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/v1/folders/{id}/members/{email}",
+    tag = "folders",
+    params(
+        ("id" = i32, Path, description = "Folder id"),
+        ("email" = String, Path, description = "User the folder is shared with"),
+    ),
+    request_body = FolderGrant,
+    responses((status = 200, body = MessageResponse))
+)]
+pub async fn share(
+    State(state): State<AppState>,
+    CurrentUser(email): CurrentUser,
+    Path((id, target_email)): Path<(i32, String)>,
+    Json(body): Json<FolderGrant>,
+) -> Result<(StatusCode, Json<MessageResponse>), ApiError> {
+    let request = ShareFolderRequest {
+        email,
+        folder_id: id,
+        target_email,
+        role: body.role,
+    };
+    folders::share_folder(State(state), Json(request)).await
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/folders/{id}/members/{email}",
+    tag = "folders",
+    params(
+        ("id" = i32, Path, description = "Folder id"),
+        ("email" = String, Path, description = "User the folder is shared with"),
+    ),
+    responses((status = 200, body = MessageResponse))
+)]
+pub async fn unshare(
+    State(state): State<AppState>,
+    CurrentUser(email): CurrentUser,
+    Path((id, target_email)): Path<(i32, String)>,
+) -> Result<(StatusCode, Json<MessageResponse>), ApiError> {
+    let request = UnshareFolderRequest {
+        email,
+        folder_id: id,
+        target_email,
+    };
+    folders::unshare_folder(State(state), Json(request)).await
+}
@@ -0,0 +1,193 @@
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+
+use super::CurrentUser;
+use crate::errors::{ApiError, Json};
+use crate::structs::{
+    AppState, CreateFromTemplateRequest, CreatedDocumentResponse, DocumentEntriesResponse,
+    DocumentTagRequest, FavoriteResponse, GetLibraryRequest, GetTemplatesRequest, LimitQuery,
+    MessageResponse, NewFromTemplate, SetFavoriteRequest, TagsResponse, TemplatesResponse,
+};
+use crate::{library, templates};
+
+// ***************************************************************************************************************************************
+// Tags
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/tags",
+    tag = "library",
+    responses((status = 200, body = TagsResponse))
+)]
+pub async fn list_tags(
+    State(state): State<AppState>,
+    CurrentUser(email): CurrentUser,
+) -> Result<(StatusCode, Json<TagsResponse>), ApiError> {
+    let request = GetLibraryRequest { email, limit: None };
+    library::get_tags(State(state), Json(request)).await
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/v1/documents/{id}/tags/{tag}",
+    tag = "library",
+    params(
+        ("id" = String, Path, description = "Document id"),
+        ("tag" = String, Path, description = "Tag of the user"),
+    ),
+    responses((status = 200, body = MessageResponse))
+)]
+pub async fn add_tag(
+    State(state): State<AppState>,
+    CurrentUser(email): CurrentUser,
+    Path((id, tag)): Path<(String, String)>,
+) -> Result<(StatusCode, Json<MessageResponse>), ApiError> {
+    let request = DocumentTagRequest {
+        email,
+        document_id: id,
+        tag,
+    };
+    library::add_tag(State(state), Json(request)).await
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/documents/{id}/tags/{tag}",
+    tag = "library",
+    params(
+        ("id" = String, Path, description = "Document id"),
+        ("tag" = String, Path, description = "Tag of the user"),
+    ),
+    responses((status = 200, body = MessageResponse))
+)]
+pub async fn remove_tag(
+    State(state): State<AppState>,
+    CurrentUser(email): CurrentUser,
+    Path((id, tag)): Path<(String, String)>,
+) -> Result<(StatusCode, Json<MessageResponse>), ApiError> {
+    let request = DocumentTagRequest {
+        email,
+        document_id: id,
+        tag,
+    };
+    library::remove_tag(State(state), Json(request)).await
+}
+
+// ***************************************************************************************************************************************
+// Favorites and recently opened documents
+
+async fn set_favorite(
+    state: AppState,
+    email: String,
+    document_id: String,
+    favorite: bool,
+) -> Result<(StatusCode, Json<FavoriteResponse>), ApiError> {
+    let request = SetFavoriteRequest {
+        email,
+        document_id,
+        favorite,
+    };
+    library::set_favorite(State(state), Json(request)).await
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/v1/documents/{id}/favorite",
+    tag = "library",
+    params(("id" = String, Path, description = "Document id")),
+    responses((status = 200, body = FavoriteResponse))
+)]
+pub async fn add_favorite(
+    State(state): State<AppState>,
+    CurrentUser(email): CurrentUser,
+    Path(id): Path<String>,
+) -> Result<(StatusCode, Json<FavoriteResponse>), ApiError> {
+    set_favorite(state, email, id, true).await
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/documents/{id}/favorite",
+    tag = "library",
+    params(("id" = String, Path, description = "Document id")),
+    responses((status = 200, body = FavoriteResponse))
+)]
+pub async fn remove_favorite(
+    State(state): State<AppState>,
+    CurrentUser(email): CurrentUser,
+    Path(id): Path<String>,
+) -> Result<(StatusCode, Json<FavoriteResponse>), ApiError> {
+    set_favorite(state, email, id, false).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/documents/favorites",
+    tag = "library",
+    responses((status = 200, body = DocumentEntriesResponse))
+)]
+pub async fn list_favorites(
+    State(state): State<AppState>,
+    CurrentUser(email): CurrentUser,
+) -> Result<(StatusCode, Json<DocumentEntriesResponse>), ApiError> {
+    let request = GetLibraryRequest { email, limit: None };
+    library::get_favorites(State(state), Json(request)).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/documents/recent",
+    tag = "library",
+    params(LimitQuery),
+    responses((status = 200, body = DocumentEntriesResponse))
+)]
+pub async fn list_recent(
+    State(state): State<AppState>,
+    CurrentUser(email): CurrentUser,
+    Query(query): Query<LimitQuery>,
+) -> Result<(StatusCode, Json<DocumentEntriesResponse>), ApiError> {
+    let request = GetLibraryRequest {
+        email,
+        limit: query.limit,
+    };
+    library::get_recent_documents(State(state), Json(request)).await
+}
+
+// ***************************************************************************************************************************************
+// Templates
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/templates",
+    tag = "templates",
+    responses((status = 200, body = TemplatesResponse))
+)]
+pub async fn list_templates(
+    State(state): State<AppState>,
+    CurrentUser(email): CurrentUser,
+) -> Result<(StatusCode, Json<TemplatesResponse>), ApiError> {
+    let request = GetTemplatesRequest { email };
+    templates::get_templates(State(state), Json(request)).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/templates/{id}/documents",
+    tag = "templates",
+    params(("id" = String, Path, description = "Document id of the template")),
+    request_body = NewFromTemplate,
+    responses((status = 201, body = CreatedDocumentResponse))
+)]
+pub async fn create_from_template(
+    State(state): State<AppState>,
+    CurrentUser(email): CurrentUser,
+    Path(id): Path<String>,
+    Json(body): Json<NewFromTemplate>,
+) -> Result<(StatusCode, Json<CreatedDocumentResponse>), ApiError> {
+    let request = CreateFromTemplateRequest {
+        email,
+        template_id: id,
+        title: body.title,
+    };
+    templates::create_from_template(State(state), Json(request)).await
+}
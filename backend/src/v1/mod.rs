@@ -0,0 +1,139 @@
+// Resource oriented routes served under /api/v1. They take the user from the
+// X-User-Email header and the document, folder or group from the path, and
+// run the same handlers as the legacy routes.
+
+use axum::Router;
+use axum::async_trait;
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
+use axum::routing::{delete, get, patch, post, put};
+
+use crate::errors::ApiError;
+use crate::structs::AppState;
+
+pub mod documents;
+pub mod folders;
+pub mod library;
+pub mod review;
+pub mod sharing;
+pub mod users;
+
+// The user making a request, named by the `X-User-Email` header
+pub struct CurrentUser(pub String);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for CurrentUser {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts
+            .headers
+            .get("x-user-email")
+            .and_then(|value| value.to_str().ok())
+            .filter(|email| !email.is_empty())
+            .map(|email| CurrentUser(email.to_string()))
+            .ok_or_else(|| ApiError::Unauthorized("The X-User-Email header is missing".to_string()))
+    }
+}
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/sessions", post(users::create_session))
+        .route(
+            "/documents",
+            get(documents::list_owned).post(documents::create),
+        )
+        .route("/documents/shared", get(documents::list_shared))
+        .route("/documents/favorites", get(library::list_favorites))
+        .route("/documents/recent", get(library::list_recent))
+        .route(
+            "/documents/:id",
+            get(documents::get)
+                .patch(documents::update)
+                .delete(documents::delete),
+        )
+        .route("/documents/:id/role", get(documents::role))
+        .route("/documents/:id/copies", post(documents::copy))
+        .route(
+            "/documents/:id/lock",
+            get(documents::lock).put(documents::set_lock),
+        )
+        .route("/documents/:id/folder", put(documents::set_folder))
+        .route("/documents/:id/audit", get(documents::audit_log))
+        .route(
+            "/documents/:id/members/:email",
+            put(sharing::share).delete(sharing::unshare),
+        )
+        .route(
+            "/documents/:id/groups/:group_id",
+            put(sharing::share_group).delete(sharing::unshare_group),
+        )
+        .route(
+            "/documents/:id/share_links",
+            get(sharing::list_links).post(sharing::create_link),
+        )
+        .route(
+            "/documents/:id/share_links/:link_id",
+            delete(sharing::revoke_link),
+        )
+        .route(
+            "/documents/:id/publication",
+            put(sharing::publish).delete(sharing::unpublish),
+        )
+        .route(
+            "/documents/:id/template",
+            put(sharing::set_template).delete(sharing::unset_template),
+        )
+        .route(
+            "/documents/:id/tags/:tag",
+            put(library::add_tag).delete(library::remove_tag),
+        )
+        .route(
+            "/documents/:id/favorite",
+            put(library::add_favorite).delete(library::remove_favorite),
+        )
+        .route(
+            "/documents/:id/comments",
+            get(review::list_comments).post(review::create_comment),
+        )
+        .route(
+            "/documents/:id/comments/:thread_id",
+            patch(review::update_comment),
+        )
+        .route(
+            "/documents/:id/comments/:thread_id/replies",
+            post(review::reply_comment),
+        )
+        .route("/documents/:id/suggestions", get(review::list_suggestions))
+        .route(
+            "/documents/:id/suggestions/:suggestion_id",
+            patch(review::review_suggestion),
+        )
+        .route("/tags", get(library::list_tags))
+        .route("/templates", get(library::list_templates))
+        .route(
+            "/templates/:id/documents",
+            post(library::create_from_template),
+        )
+        .route(
+            "/folders",
+            get(folders::list_top_level).post(folders::create),
+        )
+        .route(
+            "/folders/:id",
+            get(folders::contents)
+                .patch(folders::update)
+                .delete(folders::delete),
+        )
+        .route("/folders/:id/parent", put(folders::set_parent))
+        .route(
+            "/folders/:id/members/:email",
+            put(folders::share).delete(folders::unshare),
+        )
+        .route("/groups", get(users::list_groups).post(users::create_group))
+        .route("/groups/:id/members", get(users::list_members))
+        .route(
+            "/groups/:id/members/:email",
+            put(users::add_member).delete(users::remove_member),
+        )
+}
@@ -0,0 +1,197 @@
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+
+use super::CurrentUser;
+use crate::errors::{ApiError, Json};
+use crate::share_links::ShareToken;
+use crate::structs::{
+    AppState, CommentThreadRequest, CommentsQuery, CreateCommentRequest, GetCommentsRequest,
+    GetSuggestionsRequest, NewComment, NewReply, ReplyCommentRequest, ReviewSuggestionRequest,
+    SuggestionResponse, SuggestionReview, SuggestionStatus, SuggestionsQuery, SuggestionsResponse,
+    ThreadPatch, ThreadResponse, ThreadsResponse,
+};
+use crate::{comments, suggestions};
+
+// ***************************************************************************************************************************************
+// Comments
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/documents/{id}/comments",
+    tag = "comments",
+    params(
+        ("id" = String, Path, description = "Document id"),
+        ("x-share-token" = Option<String>, Header, description = "Token of a share link"),
+        CommentsQuery,
+    ),
+    responses((status = 200, body = ThreadsResponse))
+)]
+pub async fn list_comments(
+    State(state): State<AppState>,
+    CurrentUser(email): CurrentUser,
+    share_token: ShareToken,
+    Path(id): Path<String>,
+    Query(query): Query<CommentsQuery>,
+) -> Result<(StatusCode, Json<ThreadsResponse>), ApiError> {
+    let request = GetCommentsRequest {
+        email,
+        document_id: id,
+        include_resolved: query.include_resolved,
+    };
+    comments::get_comments(State(state), share_token, Json(request)).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/documents/{id}/comments",
+    tag = "comments",
+    params(
+        ("id" = String, Path, description = "Document id"),
+        ("x-share-token" = Option<String>, Header, description = "Token of a share link"),
+    ),
+    request_body = NewComment,
+    responses((status = 201, body = ThreadResponse))
+)]
+pub async fn create_comment(
+    State(state): State<AppState>,
+    CurrentUser(email): CurrentUser,
+    share_token: ShareToken,
+    Path(id): Path<String>,
+    Json(body): Json<NewComment>,
+) -> Result<(StatusCode, Json<ThreadResponse>), ApiError> {
+    let request = CreateCommentRequest {
+        email,
+        document_id: id,
+        start: body.start,
+        end: body.end,
+        body: body.body,
+    };
+    comments::create_comment(State(state), share_token, Json(request)).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/documents/{id}/comments/{thread_id}/replies",
+    tag = "comments",
+    params(
+        ("id" = String, Path, description = "Document id"),
+        ("thread_id" = String, Path, description = "Comment thread id"),
+        ("x-share-token" = Option<String>, Header, description = "Token of a share link"),
+    ),
+    request_body = NewReply,
+    responses((status = 200, body = ThreadResponse))
+)]
+pub async fn reply_comment(
+    State(state): State<AppState>,
+    CurrentUser(email): CurrentUser,
+    share_token: ShareToken,
+    Path((id, thread_id)): Path<(String, String)>,
+    Json(body): Json<NewReply>,
+) -> Result<(StatusCode, Json<ThreadResponse>), ApiError> {
+    let request = ReplyCommentRequest {
+        email,
+        document_id: id,
+        thread_id,
+        body: body.body,
+    };
+    comments::reply_comment(State(state), share_token, Json(request)).await
+}
+
+// Resolves or reopens a thread
+#[utoipa::path(
+    patch,
+    path = "/api/v1/documents/{id}/comments/{thread_id}",
+    tag = "comments",
+    params(
+        ("id" = String, Path, description = "Document id"),
+        ("thread_id" = String, Path, description = "Comment thread id"),
+        ("x-share-token" = Option<String>, Header, description = "Token of a share link"),
+    ),
+    request_body = ThreadPatch,
+    responses((status = 200, body = ThreadResponse))
+)]
+pub async fn update_comment(
+    State(state): State<AppState>,
+    CurrentUser(email): CurrentUser,
+    share_token: ShareToken,
+    Path((id, thread_id)): Path<(String, String)>,
+    Json(patch): Json<ThreadPatch>,
+) -> Result<(StatusCode, Json<ThreadResponse>), ApiError> {
+    let request = CommentThreadRequest {
+        email,
+        document_id: id,
+        thread_id,
+    };
+    if patch.resolved {
+        comments::resolve_comment(State(state), share_token, Json(request)).await
+    } else {
+        comments::reopen_comment(State(state), share_token, Json(request)).await
+    }
+}
+
+// ***************************************************************************************************************************************
+// Suggestions
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/documents/{id}/suggestions",
+    tag = "suggestions",
+    params(
+        ("id" = String, Path, description = "Document id"),
+        ("x-share-token" = Option<String>, Header, description = "Token of a share link"),
+        SuggestionsQuery,
+    ),
+    responses((status = 200, body = SuggestionsResponse))
+)]
+pub async fn list_suggestions(
+    State(state): State<AppState>,
+    CurrentUser(email): CurrentUser,
+    share_token: ShareToken,
+    Path(id): Path<String>,
+    Query(query): Query<SuggestionsQuery>,
+) -> Result<(StatusCode, Json<SuggestionsResponse>), ApiError> {
+    let request = GetSuggestionsRequest {
+        email,
+        document_id: id,
+        include_reviewed: query.include_reviewed,
+    };
+    suggestions::get_suggestions(State(state), share_token, Json(request)).await
+}
+
+// Accepts or rejects a pending suggestion
+#[utoipa::path(
+    patch,
+    path = "/api/v1/documents/{id}/suggestions/{suggestion_id}",
+    tag = "suggestions",
+    params(
+        ("id" = String, Path, description = "Document id"),
+        ("suggestion_id" = String, Path, description = "Suggestion id"),
+        ("x-share-token" = Option<String>, Header, description = "Token of a share link"),
+    ),
+    request_body = SuggestionReview,
+    responses((status = 200, body = SuggestionResponse))
+)]
+pub async fn review_suggestion(
+    State(state): State<AppState>,
+    CurrentUser(email): CurrentUser,
+    share_token: ShareToken,
+    Path((id, suggestion_id)): Path<(String, String)>,
+    Json(review): Json<SuggestionReview>,
+) -> Result<(StatusCode, Json<SuggestionResponse>), ApiError> {
+    let request = ReviewSuggestionRequest {
+        email,
+        document_id: id,
+        suggestion_id,
+    };
+    match review.status {
+        SuggestionStatus::Accepted => {
+            suggestions::accept_suggestion(State(state), share_token, Json(request)).await
+        }
+        SuggestionStatus::Rejected => {
+            suggestions::reject_suggestion(State(state), share_token, Json(request)).await
+        }
+        SuggestionStatus::Pending => Err(ApiError::bad_request(
+            "A suggestion can only be accepted or rejected",
+        )),
+    }
+}
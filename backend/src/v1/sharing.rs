@@ -0,0 +1,274 @@
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+
+use super::CurrentUser;
+use crate::errors::{ApiError, Json};
+use crate::structs::{
+    AppState, CreateShareLinkRequest, GetShareLinksRequest, GroupGrant, MemberGrant,
+    MessageResponse, PublicationResponse, PublicationSettings, PublishDocumentRequest,
+    RevokeShareLinkRequest, SetTemplateRequest, ShareDocumentGroupRequest, ShareDocumentRequest,
+    ShareLinkResponse, ShareLinkSettings, ShareLinksResponse, TemplateResponse, TemplateSettings,
+    UnpublishDocumentRequest, UnshareDocumentGroupRequest, UnshareDocumentRequest,
+};
+use crate::{publishing, share_links, templates};
+
+// ***************************************************************************************************************************************
+// Users and groups a document is shared with
+
+#[utoipa::path(
+    put,
+    path = "/api/v1/documents/{id}/members/{email}",
+    tag = "documents",
+    params(
+        ("id" = String, Path, description = "Document id"),
+        ("email" = String, Path, description = "User the document is shared with"),
+    ),
+    request_body = MemberGrant,
+    responses((status = 200, body = MessageResponse))
+)]
+pub async fn share(
+    State(state): State<AppState>,
+    CurrentUser(email): CurrentUser,
+    Path((id, target_email)): Path<(String, String)>,
+    Json(body): Json<MemberGrant>,
+) -> Result<(StatusCode, Json<MessageResponse>), ApiError> {
+    let request = ShareDocumentRequest {
+        email,
+        document_id: id,
+        target_email,
+        role: body.role,
+        valid_from: body.valid_from,
+        valid_until: body.valid_until,
+    };
+    crate::share_document(State(state), Json(request)).await
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/documents/{id}/members/{email}",
+    tag = "documents",
+    params(
+        ("id" = String, Path, description = "Document id"),
+        ("email" = String, Path, description = "User the document is shared with"),
+    ),
+    responses((status = 200, body = MessageResponse))
+)]
+pub async fn unshare(
+    State(state): State<AppState>,
+    CurrentUser(email): CurrentUser,
+    Path((id, target_email)): Path<(String, String)>,
+) -> Result<(StatusCode, Json<MessageResponse>), ApiError> {
+    let request = UnshareDocumentRequest {
+        email,
+        document_id: id,
+        target_email,
+    };
+    crate::unshare_document(State(state), Json(request)).await
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/v1/documents/{id}/groups/{group_id}",
+    tag = "documents",
+    params(
+        ("id" = String, Path, description = "Document id"),
+        ("group_id" = i32, Path, description = "Group the document is shared with"),
+    ),
+    request_body = GroupGrant,
+    responses((status = 200, body = MessageResponse))
+)]
+pub async fn share_group(
+    State(state): State<AppState>,
+    CurrentUser(email): CurrentUser,
+    Path((id, group_id)): Path<(String, i32)>,
+    Json(body): Json<GroupGrant>,
+) -> Result<(StatusCode, Json<MessageResponse>), ApiError> {
+    let request = ShareDocumentGroupRequest {
+        email,
+        document_id: id,
+        group_id,
+        valid_from: body.valid_from,
+        valid_until: body.valid_until,
+    };
+    crate::share_document_group(State(state), Json(request)).await
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/documents/{id}/groups/{group_id}",
+    tag = "documents",
+    params(
+        ("id" = String, Path, description = "Document id"),
+        ("group_id" = i32, Path, description = "Group the document is shared with"),
+    ),
+    responses((status = 200, body = MessageResponse))
+)]
+pub async fn unshare_group(
+    State(state): State<AppState>,
+    CurrentUser(email): CurrentUser,
+    Path((id, group_id)): Path<(String, i32)>,
+) -> Result<(StatusCode, Json<MessageResponse>), ApiError> {
+    let request = UnshareDocumentGroupRequest {
+        email,
+        document_id: id,
+        group_id,
+    };
+    crate::unshare_document_group(State(state), Json(request)).await
+}
+
+// ***************************************************************************************************************************************
+// Share links
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/documents/{id}/share_links",
+    tag = "share links",
+    params(("id" = String, Path, description = "Document id")),
+    responses((status = 200, body = ShareLinksResponse))
+)]
+pub async fn list_links(
+    State(state): State<AppState>,
+    CurrentUser(email): CurrentUser,
+    Path(id): Path<String>,
+) -> Result<(StatusCode, Json<ShareLinksResponse>), ApiError> {
+    let request = GetShareLinksRequest {
+        email,
+        document_id: id,
+    };
+    share_links::get_share_links(State(state), Json(request)).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/documents/{id}/share_links",
+    tag = "share links",
+    params(("id" = String, Path, description = "Document id")),
+    request_body = ShareLinkSettings,
+    responses((status = 201, body = ShareLinkResponse))
+)]
+pub async fn create_link(
+    State(state): State<AppState>,
+    CurrentUser(email): CurrentUser,
+    Path(id): Path<String>,
+    Json(body): Json<ShareLinkSettings>,
+) -> Result<(StatusCode, Json<ShareLinkResponse>), ApiError> {
+    let request = CreateShareLinkRequest {
+        email,
+        document_id: id,
+        role: body.role,
+        expires_at: body.expires_at,
+        max_uses: body.max_uses,
+    };
+    share_links::create_share_link(State(state), Json(request)).await
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/documents/{id}/share_links/{link_id}",
+    tag = "share links",
+    params(
+        ("id" = String, Path, description = "Document id"),
+        ("link_id" = i32, Path, description = "Share link id"),
+    ),
+    responses((status = 200, body = MessageResponse))
+)]
+pub async fn revoke_link(
+    State(state): State<AppState>,
+    CurrentUser(email): CurrentUser,
+    Path((id, link_id)): Path<(String, i32)>,
+) -> Result<(StatusCode, Json<MessageResponse>), ApiError> {
+    let request = RevokeShareLinkRequest {
+        email,
+        document_id: id,
+        link_id,
+    };
+    share_links::revoke_share_link(State(state), Json(request)).await
+}
+
+// ***************************************************************************************************************************************
+// Publication and templates
+
+#[utoipa::path(
+    put,
+    path = "/api/v1/documents/{id}/publication",
+    tag = "publishing",
+    params(("id" = String, Path, description = "Document id")),
+    request_body = PublicationSettings,
+    responses((status = 200, body = PublicationResponse))
+)]
+pub async fn publish(
+    State(state): State<AppState>,
+    CurrentUser(email): CurrentUser,
+    Path(id): Path<String>,
+    Json(body): Json<PublicationSettings>,
+) -> Result<(StatusCode, Json<PublicationResponse>), ApiError> {
+    let request = PublishDocumentRequest {
+        email,
+        document_id: id,
+        live: body.live,
+    };
+    publishing::publish_document(State(state), Json(request)).await
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/documents/{id}/publication",
+    tag = "publishing",
+    params(("id" = String, Path, description = "Document id")),
+    responses((status = 200, body = MessageResponse))
+)]
+pub async fn unpublish(
+    State(state): State<AppState>,
+    CurrentUser(email): CurrentUser,
+    Path(id): Path<String>,
+) -> Result<(StatusCode, Json<MessageResponse>), ApiError> {
+    let request = UnpublishDocumentRequest {
+        email,
+        document_id: id,
+    };
+    publishing::unpublish_document(State(state), Json(request)).await
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/v1/documents/{id}/template",
+    tag = "templates",
+    params(("id" = String, Path, description = "Document id")),
+    request_body = TemplateSettings,
+    responses((status = 200, body = TemplateResponse))
+)]
+pub async fn set_template(
+    State(state): State<AppState>,
+    CurrentUser(email): CurrentUser,
+    Path(id): Path<String>,
+    Json(body): Json<TemplateSettings>,
+) -> Result<(StatusCode, Json<TemplateResponse>), ApiError> {
+    let request = SetTemplateRequest {
+        email,
+        document_id: id,
+        template: true,
+        shared_with_all: body.shared_with_all,
+    };
+    templates::set_template(State(state), Json(request)).await
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/documents/{id}/template",
+    tag = "templates",
+    params(("id" = String, Path, description = "Document id")),
+    responses((status = 200, body = TemplateResponse))
+)]
+pub async fn unset_template(
+    State(state): State<AppState>,
+    CurrentUser(email): CurrentUser,
+    Path(id): Path<String>,
+) -> Result<(StatusCode, Json<TemplateResponse>), ApiError> {
+    let request = SetTemplateRequest {
+        email,
+        document_id: id,
+        template: false,
+        shared_with_all: false,
+    };
+    templates::set_template(State(state), Json(request)).await
+}
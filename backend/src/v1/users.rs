@@ -0,0 +1,159 @@
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+
+use super::CurrentUser;
+use crate::errors::{ApiError, Json};
+use crate::structs::{
+    AppState, GetGroupsRequest, GroupMembersResponse, GroupResponse, GroupSummary, GroupsRequest,
+    GroupsResponse, LoginRequest, LoginResponse, MessageResponse, NewGroup,
+};
+
+// Checks the email and password of a user, the only route without the
+// X-User-Email header
+#[utoipa::path(
+    post,
+    path = "/api/v1/sessions",
+    tag = "users",
+    request_body = LoginRequest,
+    responses((status = 200, body = LoginResponse))
+)]
+pub async fn create_session(
+    State(state): State<AppState>,
+    Json(payload): Json<LoginRequest>,
+) -> Result<(StatusCode, Json<LoginResponse>), ApiError> {
+    crate::login_user(State(state), Json(payload)).await
+}
+
+// ***************************************************************************************************************************************
+// Groups. Only the owner of a group can see and change its members.
+
+async fn authorize_group(state: &AppState, email: &str, group_id: i32) -> Result<(), ApiError> {
+    match state.access.group_owner(group_id).await? {
+        Some(owner) if owner == email => Ok(()),
+        Some(_) => Err(ApiError::Forbidden(
+            "Only the owner of a group can manage it".to_string(),
+        )),
+        None => Err(ApiError::not_found("Group not found")),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/groups",
+    tag = "groups",
+    responses((status = 200, body = GroupsResponse))
+)]
+pub async fn list_groups(
+    State(state): State<AppState>,
+    CurrentUser(email): CurrentUser,
+) -> Result<(StatusCode, Json<GroupsResponse>), ApiError> {
+    crate::get_groups_by_owner(State(state), Json(GetGroupsRequest { email })).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/groups",
+    tag = "groups",
+    request_body = NewGroup,
+    responses((status = 201, body = GroupResponse))
+)]
+pub async fn create_group(
+    State(state): State<AppState>,
+    CurrentUser(email): CurrentUser,
+    Json(body): Json<NewGroup>,
+) -> Result<(StatusCode, Json<GroupResponse>), ApiError> {
+    let group = GroupsRequest {
+        owner: email,
+        name: body.name,
+        role: body.role,
+        members: body.members,
+    };
+    let group_id = crate::create_group(&state, &group).await?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(GroupResponse {
+            success: true,
+            group: GroupSummary {
+                group_id,
+                group_name: group.name,
+                owner_email: group.owner,
+                group_role: group.role,
+            },
+        }),
+    ))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/groups/{id}/members",
+    tag = "groups",
+    params(("id" = i32, Path, description = "Group id")),
+    responses((status = 200, body = GroupMembersResponse))
+)]
+pub async fn list_members(
+    State(state): State<AppState>,
+    CurrentUser(email): CurrentUser,
+    Path(id): Path<i32>,
+) -> Result<(StatusCode, Json<GroupMembersResponse>), ApiError> {
+    authorize_group(&state, &email, id).await?;
+
+    let members = state.access.group_members(id).await?;
+
+    Ok((
+        StatusCode::OK,
+        Json(GroupMembersResponse {
+            success: true,
+            members,
+        }),
+    ))
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/v1/groups/{id}/members/{email}",
+    tag = "groups",
+    params(
+        ("id" = i32, Path, description = "Group id"),
+        ("email" = String, Path, description = "Member of the group"),
+    ),
+    responses((status = 200, body = MessageResponse))
+)]
+pub async fn add_member(
+    State(state): State<AppState>,
+    CurrentUser(email): CurrentUser,
+    Path((id, member)): Path<(i32, String)>,
+) -> Result<(StatusCode, Json<MessageResponse>), ApiError> {
+    authorize_group(&state, &email, id).await?;
+
+    let added = state.access.add_group_member(id, &member).await?;
+
+    let message = if added { "Added" } else { "Already a member" };
+    Ok((StatusCode::OK, Json(MessageResponse::new(message))))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/groups/{id}/members/{email}",
+    tag = "groups",
+    params(
+        ("id" = i32, Path, description = "Group id"),
+        ("email" = String, Path, description = "Member of the group"),
+    ),
+    responses((status = 200, body = MessageResponse))
+)]
+pub async fn remove_member(
+    State(state): State<AppState>,
+    CurrentUser(email): CurrentUser,
+    Path((id, member)): Path<(i32, String)>,
+) -> Result<(StatusCode, Json<MessageResponse>), ApiError> {
+    authorize_group(&state, &email, id).await?;
+
+    let removed = state.access.remove_group_member(id, &member).await?;
+
+    if !removed {
+        return Err(ApiError::not_found("User is not a member of this group"));
+    }
+
+    Ok((StatusCode::OK, Json(MessageResponse::new("Removed"))))
+}
@@ -1,230 +1,731 @@
 use std::error::Error;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
 
-use crate::structs::WsParams;
+use crate::audit::{self, AuditEvent};
+use crate::authz::{Access, Capability, GrantSource};
+use crate::connections::{self, ConnectionGuard, Liveness, Timeout};
+use crate::errors::ApiError;
+use crate::store::{ContentWrite, StoreResult, WriteCondition};
+use crate::structs::{WsClientMessage, WsParams, WsServerMessage};
+use crate::updates::{Change, Relay};
 use crate::*;
+use axum::extract::State;
 use axum::extract::ws::{Message, WebSocket};
 use axum::extract::{Query, WebSocketUpgrade};
 use axum::response::IntoResponse;
-use axum::{extract::State, http::StatusCode};
+use chrono::Utc;
 use futures_util::{SinkExt, StreamExt};
-use mongodb::bson::doc;
-
-// Redis
-use redis::AsyncCommands;
-use redis::aio::ConnectionManager;
+use log::{debug, error, info, warn};
+use tokio::sync::mpsc;
+use tokio::time::Instant;
 
+// Clients send WsClientMessage and receive WsServerMessage as JSON text frames
+#[utoipa::path(
+    get,
+    path = "/ws",
+    tag = "realtime",
+    params(WsParams),
+    responses(
+        (status = 101, description = "Switches to the document WebSocket"),
+        (status = 403, description = "The user can not open the document"),
+    )
+)]
 pub async fn ws_handler(
     ws: WebSocketUpgrade,
     Query(params): Query<WsParams>,
     State(state): State<AppState>,
 ) -> impl IntoResponse {
-    let role_opt = user_has_access(&params.user_email, &params.document_id, &state).await;
-
-    if let Some(role) = role_opt {
-        return ws.on_upgrade(move |socket| handle_socket(socket, params, state, role));
+    match authz::authorize_with_token(
+        &state,
+        &params.user_email,
+        &params.document_id,
+        params.share_token.as_deref(),
+        Capability::View,
+    )
+    .await
+    {
+        Ok(access) => ws.on_upgrade(move |socket| handle_socket(socket, params, state, access)),
+        Err(e) => {
+            let e = ApiError::from(e);
+            warn!(
+                "Refused access to user: {} on doc: {} ({})",
+                &params.user_email,
+                &params.document_id,
+                e.report()
+            );
+            (e.status(), "Unauthorized".to_string()).into_response()
+        }
     }
-
-    println!(
-        "Refused access to user: {} on doc: {}",
-        &params.user_email, &params.document_id
-    );
-    (StatusCode::UNAUTHORIZED, "Unauthorized".to_string()).into_response()
 }
 
-async fn handle_socket(
+pub async fn handle_socket(
     mut socket: WebSocket,
     params: WsParams,
     state: AppState,
-    role: String,
+    access: Access,
 ) {
-    println!(
-        "WebSocket opened for user {} on doc {} with role: {}",
-        params.user_email, params.document_id, role
+    info!(
+        "WebSocket opened for user {} on doc {} with role: {} ({:?})",
+        params.user_email, params.document_id, access.role, access.source
     );
 
     let doc_key = format!("doc:{}", params.document_id);
-    let channel = format!("channel:{}", params.document_id);
+    let content_only = access.source == GrantSource::Public;
+
+    // Counted before the content is loaded, the periodic flush would
+    // otherwise close a document nobody seems to have open
+    let connection = ConnectionGuard::acquire(&state.ws_connections, &params.document_id).await;
 
-    let mut conn = state
-        .redis_client
-        .get_tokio_connection_manager()
+    let doc_open = state
+        .live
+        .is_open(&params.document_id)
         .await
-        .expect("Failed to connect to Redis");
-
-    let mut conn_close = conn.clone();
-
-    let doc_exists: bool = conn.exists(&doc_key).await.unwrap_or(false);
-
-    if !doc_exists {
-        if let Ok(object_id) = ObjectId::parse_str(&params.document_id) {
-            let filter = doc! { "_id": object_id };
-
-            if let Ok(Some(doc)) = state
-                .mongo_db
-                .collection::<Document>("documents")
-                .find_one(filter, None)
-                .await
-            {
-                let content = doc.content.clone();
-                println!("Some document content: {}", content);
-                let _: () = conn.set(&doc_key, content.clone()).await.unwrap();
-            } else {
-                eprintln!("No content was found with objectId: {}", object_id);
+        .unwrap_or(false);
+
+    if !doc_open {
+        match state.documents.get(&params.document_id).await {
+            Ok(Some(doc)) => {
+                debug!("Some document content: {}", doc.content);
+                // The revision the live content is based on, checked when
+                // it is flushed
+                if let Err(e) = state
+                    .live
+                    .open(&params.document_id, &doc.content, doc.revision)
+                    .await
+                {
+                    error!("Failed to open doc {}: {}", &params.document_id, e);
+                    refuse(socket, connection, "The document could not be opened").await;
+                    return;
+                }
             }
-        } else {
-            eprintln!("Invalid ObjectId format: {}", &params.document_id);
+            Ok(None) => warn!("No content was found with id: {}", &params.document_id),
+            Err(e) => error!("Failed to load doc {}: {}", &params.document_id, e),
         }
     }
 
-    let content: String = conn.get(&doc_key).await.unwrap_or_default();
+    let (content, seq) = state
+        .pubsub
+        .snapshot(&params.document_id)
+        .await
+        .unwrap_or_default();
 
-    match socket.send(Message::Text(content.clone())).await {
-        Ok(_) => {
-            let mut map = state.ws_connections.lock().await;
-            *map.entry(params.document_id.clone()).or_insert(0) += 1;
-        }
-        Err(e) => {
-            eprintln!("Error while sending content to client: {e:?}");
+    // A reconnecting client only gets the updates it missed, if they are
+    // still available
+    let missed = match params.last_seq {
+        Some(last_seq) => state
+            .pubsub
+            .missed(&params.document_id, last_seq, seq)
+            .await
+            .unwrap_or_else(|e| {
+                error!("Failed to read missed updates: {}", e);
+                None
+            }),
+        None => None,
+    };
+    let mut relay = Relay::new(content.unwrap_or_default(), content_only);
+    let initial = match missed {
+        Some(missed) => relay.replay(missed, seq),
+        None => vec![relay.content_at(seq)],
+    };
+
+    for payload in initial {
+        if let Err(e) = socket.send(Message::Text(payload)).await {
+            error!("Error while sending content to client: {e:?}");
             return;
         }
     }
+    audit::record(
+        &state,
+        AuditEvent::WsOpen,
+        Some(&params.user_email),
+        Some(&params.document_id),
+        json!({ "role": access.role.as_str(), "source": access.source }),
+    )
+    .await;
 
-    let mut pubsub_conn = state
-        .redis_client
-        .get_async_connection()
-        .await
-        .unwrap()
-        .into_pubsub();
-    pubsub_conn.subscribe(&channel).await.unwrap();
+    if access.source != GrantSource::Public {
+        library::record_open(&state, &params.user_email, &params.document_id).await;
+    }
 
     let (mut sender, mut receiver) = socket.split();
 
-    let redis_to_ws = tokio::spawn(async move {
-        let mut pubsub_stream = pubsub_conn.on_message();
+    // Replies meant only for this client, e.g. errors, go through this channel
+    let (direct_tx, mut direct_rx) = mpsc::unbounded_channel::<Message>();
+
+    // Reads the op log from the update the client is at, the offset of this
+    // session moving along as updates are relayed
+    let mut updates = match state.pubsub.subscribe(&params.document_id, seq).await {
+        Ok(updates) => updates,
+        Err(e) => {
+            error!(
+                "Failed to follow updates of doc {}: {}",
+                params.document_id, e
+            );
+            return;
+        }
+    };
+    let stream_tx = direct_tx.clone();
+    let stream_to_ws = tokio::spawn(async move {
+        while let Some(payload) = updates.recv().await {
+            let Some(payload) = relay.relay(payload) else {
+                continue;
+            };
+            if stream_tx.send(Message::Text(payload)).is_err() {
+                return;
+            }
+        }
+    });
 
-        while let Some(msg) = pubsub_stream.next().await {
-            if let Ok(payload) = msg.get_payload::<String>() {
-                if let Err(e) = sender.send(Message::Text(payload)).await {
-                    eprintln!("Failed Websocket send: {:?}", e);
-                    break;
-                };
+    let mut direct_to_ws = tokio::spawn(async move {
+        while let Some(msg) = direct_rx.recv().await {
+            let closing = matches!(msg, Message::Close(_));
+            if let Err(e) = sender.send(msg).await {
+                error!("Failed Websocket send: {:?}", e);
+                break;
+            };
+            if closing {
+                break;
             }
         }
     });
 
-    let doc_key_close = doc_key.clone();
     let doc_id = &params.document_id.clone();
     let state_close = state.clone();
 
-    if role == "owner" || role == "editor" {
-        let ws_to_redis = tokio::spawn(async move {
-            while let Some(Ok(msg)) = receiver.next().await {
-                match msg {
-                    Message::Text(text) => {
-                        let _: () = conn.set(&doc_key, &text).await.unwrap();
-                        let _: () = conn.publish(&channel, &text).await.unwrap();
+    let exclusive = Arc::new(AtomicBool::new(
+        locks::exclusive_editing(&state, doc_id).await,
+    ));
+
+    let (session_id, kick) = sessions::register(
+        &state,
+        &params.user_email,
+        doc_id,
+        params.share_token.clone(),
+        access,
+        exclusive.clone(),
+    )
+    .await;
+
+    match locks::lock_state(&state, doc_id, exclusive.load(Ordering::Relaxed)).await {
+        Ok(_) if content_only => {}
+        Ok(lock) => {
+            let payload = serde_json::to_string(&WsServerMessage::Lock { state: lock }).unwrap();
+            let _ = direct_tx.send(Message::Text(payload));
+        }
+        Err(e) => error!("Failed to read edit lock of doc {}: {}", doc_id, e),
+    }
+
+    let lease = locks::lease_token();
+    // Whether this session took the edit lock, it keeps renewing it until the
+    // lock is released or lost
+    let mut holding = false;
+    let mut heartbeat = time::interval(locks::HEARTBEAT);
+    let mut ping = time::interval(connections::PING_INTERVAL);
+    let mut liveness = Liveness::new(Instant::now());
+
+    loop {
+        let msg = tokio::select! {
+            msg = receiver.next() => match msg {
+                Some(Ok(msg)) => msg,
+                _ => break,
+            },
+            _ = heartbeat.tick(), if holding && exclusive.load(Ordering::Relaxed) => {
+                match locks::renew(&state, doc_id, &lease, &params.user_email).await {
+                    Ok(true) => {}
+                    // The lease ran out or was broken, and may be held by
+                    // someone else by now
+                    Ok(false) => {
+                        holding = false;
+                        send_error(&direct_tx, "You no longer hold the edit lock");
+                        let _ = locks::broadcast(&state, doc_id, true).await;
+                    }
+                    Err(e) => error!("Failed to renew edit lock of doc {}: {}", doc_id, e),
+                }
+                continue;
+            },
+            _ = ping.tick() => {
+                match liveness.check(Instant::now()) {
+                    Some(Timeout::Dead) => {
+                        info!(
+                            "Client {} on doc {} stopped responding, closing",
+                            params.user_email, doc_id
+                        );
+                        break;
+                    }
+                    Some(Timeout::Idle) => {
+                        send_error(&direct_tx, "Closed after a long time without activity");
+                        let _ = direct_tx.send(Message::Close(None));
+                        let _ = time::timeout(Duration::from_secs(1), &mut direct_to_ws).await;
+                        break;
                     }
-                    Message::Close(frame) => {
-                        println!("Close connection received: {:?}", frame);
-                        return;
+                    None => {
+                        let _ = direct_tx.send(Message::Ping(Vec::new()));
                     }
-                    _ => {}
                 }
+                continue;
+            },
+            _ = kick.notified() => {
+                send_error(&direct_tx, "Your access to this document has expired");
+                let _ = direct_tx.send(Message::Close(None));
+                let _ = time::timeout(Duration::from_secs(1), &mut direct_to_ws).await;
+                break;
             }
-        });
+        };
+
+        match &msg {
+            Message::Text(_) => liveness.active(Instant::now()),
+            _ => liveness.seen(Instant::now()),
+        }
 
-        let _ = ws_to_redis.await;
-    } else {
-        while let Some(Ok(msg)) = receiver.next().await {
-            if let Message::Close(frame) = msg {
-                println!("Close connection received from reader: {:?}", frame);
+        match msg {
+            Message::Text(text) => match serde_json::from_str::<WsClientMessage>(&text) {
+                Ok(WsClientMessage::Edit { .. })
+                    if exclusive.load(Ordering::Relaxed)
+                        && !locks::holds(&state, doc_id, &lease, &params.user_email)
+                            .await
+                            .unwrap_or(false) =>
+                {
+                    send_error(
+                        &direct_tx,
+                        "This document is edited exclusively, take the edit lock first",
+                    );
+                }
+                Ok(WsClientMessage::Edit { content }) => {
+                    if access.can(Capability::Edit) {
+                        if let Err(e) =
+                            publish_content(&state, doc_id, &content, &params.user_email).await
+                        {
+                            error!("Failed to publish edit on doc {}: {}", doc_id, e);
+                            send_error(&direct_tx, "Your edit could not be saved, try again");
+                        }
+                    } else if access.can(Capability::Suggest) {
+                        if let Err(e) =
+                            suggestions::record_edit(&state, doc_id, &params.user_email, &content)
+                                .await
+                        {
+                            send_error(&direct_tx, &ApiError::from(e).report());
+                        }
+                    } else {
+                        send_error(&direct_tx, "Readers can not edit this document");
+                    }
+                }
+                Ok(WsClientMessage::AcquireLock) => {
+                    if !access.can(Capability::Edit) {
+                        send_error(&direct_tx, "Your role does not allow editing");
+                    } else if !exclusive.load(Ordering::Relaxed) {
+                        send_error(&direct_tx, "Exclusive editing is not enabled");
+                    } else {
+                        match locks::acquire(&state, doc_id, &lease, &params.user_email).await {
+                            Ok(true) => {
+                                holding = true;
+                                let _ = locks::broadcast(&state, doc_id, true).await;
+                            }
+                            Ok(false) => {
+                                send_error(&direct_tx, "The edit lock is held by someone else")
+                            }
+                            Err(e) => send_error(&direct_tx, &ApiError::from(e).report()),
+                        }
+                    }
+                }
+                Ok(WsClientMessage::ReleaseLock) => {
+                    // The owner can take the lock away from whoever holds it
+                    let released = if access.can(Capability::Share) {
+                        locks::break_lock(&state, doc_id).await.map(|_| true)
+                    } else {
+                        locks::release(&state, doc_id, &lease, &params.user_email).await
+                    };
+                    match released {
+                        Ok(true) => {
+                            holding = false;
+                            let _ =
+                                locks::broadcast(&state, doc_id, exclusive.load(Ordering::Relaxed))
+                                    .await;
+                        }
+                        Ok(false) => send_error(&direct_tx, "You do not hold the edit lock"),
+                        Err(e) => send_error(&direct_tx, &ApiError::from(e).report()),
+                    }
+                }
+                Ok(action) => {
+                    if !access.can(Capability::Comment) {
+                        send_error(&direct_tx, "Your role does not allow comments");
+                        continue;
+                    }
+                    if let Err(e) =
+                        comments::handle_ws_action(&state, doc_id, &params.user_email, action).await
+                    {
+                        send_error(&direct_tx, &ApiError::from(e).report());
+                    }
+                }
+                Err(e) => send_error(&direct_tx, &format!("Invalid message: {}", e)),
+            },
+            Message::Close(frame) => {
+                debug!("Close connection received: {:?}", frame);
                 break;
             }
+            _ => {}
         }
     }
 
-    redis_to_ws.abort();
+    sessions::unregister(&state, session_id).await;
 
-    let mut map = state_close.ws_connections.lock().await;
-    println!("Got map");
+    // Others can take the lock right away instead of waiting for the lease to
+    // run out
+    if let Ok(true) = locks::release(&state, doc_id, &lease, &params.user_email).await {
+        let _ = locks::broadcast(&state, doc_id, exclusive.load(Ordering::Relaxed)).await;
+    }
+    stream_to_ws.abort();
+    direct_to_ws.abort();
 
-    match map.get_mut(doc_id) {
-        Some(count) => {
-            *count -= 1;
-            println!("count after decrement: {}", count);
-            if *count <= 0 {
-                println!("No more clients connected to: {}", &doc_key_close);
+    let (remaining, mut map) = connection.release().await;
+    debug!("count after decrement: {}", remaining);
 
-                if let Ok(_) = flush_mongo(&state_close, doc_id, &doc_key_close, &mut conn_close)
-                    .await
-                    .map_err(|e| {
-                        eprintln!(
-                            "Error on close flush doc with id: {} Error: {}",
-                            doc_id, e
-                        );
-                    })
-                {
-                    println!("Close Mongo flush for {}", &doc_key_close);
-                    if let Ok(()) = conn_close.del(&doc_key_close).await.map_err(|e| {
-                        eprintln!("Failed to delete Redis key: {}", e);
-                    }) {
-                        map.remove(doc_id);
-                        println!("Removed Redis key: {} ", doc_key_close);
-                    }
-                }
+    if remaining == 0 {
+        debug!("No more clients connected to: {}", &doc_key);
+
+        if flush_document(&state_close, doc_id)
+            .await
+            .map_err(|e| {
+                error!("Error on close flush doc with id: {} Error: {}", doc_id, e);
+            })
+            .is_ok()
+        {
+            debug!("Close flush for {}", &doc_key);
+            if let Ok(()) = state_close.live.close(doc_id).await.map_err(|e| {
+                error!("Failed to close doc {}: {}", doc_id, e);
+            }) {
+                map.remove(doc_id);
+                debug!("Closed live doc: {} ", doc_key);
             }
         }
-        None => {
-            eprintln!("No ws_connections count found for id: {}", doc_id);
-        }
     }
 
-    println!(
+    drop(map);
+
+    audit::record(
+        &state_close,
+        AuditEvent::WsClose,
+        Some(&params.user_email),
+        Some(doc_id),
+        json!({}),
+    )
+    .await;
+
+    info!(
         "WebSocket closed for user {} on doc: {}",
         params.user_email, doc_id
     );
 }
 
-async fn flush_mongo(
+// Keeps everything anchored to a text range attached to its text after an
+// edit. Called with the anchor lock of the document held.
+
+async fn move_anchors(state: &AppState, document_id: &str, previous: &str, content: &str) {
+    let Some(edit) = edits::diff(previous, content) else {
+        return;
+    };
+
+    if let Err(e) = comments::shift_anchors(state, document_id, &edit).await {
+        error!(
+            "Failed to move comment anchors on doc {}: {:?}",
+            document_id, e
+        );
+    }
+    if let Err(e) = suggestions::shift_ranges(state, document_id, &edit).await {
+        error!(
+            "Failed to move suggestion ranges on doc {}: {:?}",
+            document_id, e
+        );
+    }
+}
+
+// Stores new live content and appends the change to the document op log
+
+async fn publish_content(
     state: &AppState,
     document_id: &str,
-    doc_key_flush: &str,
-    conn: &mut ConnectionManager,
-) -> Result<(), Box<dyn Error>> {
-    let content: String = conn.get(doc_key_flush).await?;
-    let obj_id = ObjectId::parse_str(document_id)?;
-    let filter = doc! { "_id": obj_id };
-    let cont = doc! { "$set": {"content": content}};
-    state
-        .mongo_db
-        .collection::<Document>("documents")
-        .find_one_and_update(filter, cont, None)
+    content: &str,
+    editor: &str,
+) -> StoreResult<()> {
+    let lock = anchors::lock(state, document_id).await?;
+    let published = async {
+        let Some(previous) = swap_content(state, document_id, content, editor).await? else {
+            return Ok(());
+        };
+        state
+            .live
+            .set_last_edit(document_id, editor, Utc::now())
+            .await?;
+
+        move_anchors(state, document_id, &previous, content).await;
+        Ok(())
+    }
+    .await;
+    lock.release(state).await;
+    published
+}
+
+// Swaps the live content for the given one, whatever it is now. Returns the
+// content it replaced, None when there was nothing to change.
+
+async fn swap_content(
+    state: &AppState,
+    document_id: &str,
+    content: &str,
+    editor: &str,
+) -> StoreResult<Option<String>> {
+    loop {
+        let (previous, _) = state.pubsub.snapshot(document_id).await?;
+        let previous = previous.unwrap_or_default();
+        let Some(change) = Change::between(&previous, content) else {
+            return Ok(None);
+        };
+        let published = state
+            .pubsub
+            .publish_change(document_id, &previous, &change, editor)
+            .await?;
+        if published.is_some() {
+            return Ok(Some(previous));
+        }
+        // Another change came in between, this one is taken again against it
+    }
+}
+
+// Replaces the content of a document outside of a WebSocket session, going
+// through the live content when the document is open so connected clients
+// are updated
+
+pub async fn replace_content(
+    state: &AppState,
+    document_id: &str,
+    previous: &str,
+    content: &str,
+    editor: &str,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let lock = anchors::lock(state, document_id).await?;
+    let replaced = replace_locked(state, document_id, previous, content, editor).await;
+    lock.release(state).await;
+    replaced
+}
+
+// replace_content, with the anchor lock of the document held
+
+async fn replace_locked(
+    state: &AppState,
+    document_id: &str,
+    previous: &str,
+    content: &str,
+    editor: &str,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    // Only written if the content is still the one the change was made to
+    if state.live.is_open(document_id).await? {
+        let Some(change) = Change::between(previous, content) else {
+            return Ok(());
+        };
+        let published = state
+            .pubsub
+            .publish_change(document_id, previous, &change, editor)
+            .await?;
+        if published.is_none() {
+            return Err(WriteConflict {
+                document_id: document_id.to_string(),
+                found: state.live.base_revision(document_id).await?.unwrap_or(0),
+            }
+            .into());
+        }
+        state
+            .live
+            .set_last_edit(document_id, editor, Utc::now())
+            .await?;
+        move_anchors(state, document_id, previous, content).await;
+        return Ok(());
+    }
+
+    let current = state
+        .documents
+        .get(document_id)
+        .await?
+        .ok_or("Document not found")?;
+    let conflict = WriteConflict {
+        document_id: document_id.to_string(),
+        found: current.revision,
+    };
+    if current.content != previous {
+        return Err(conflict.into());
+    }
+
+    let write = ContentWrite {
+        content,
+        last_edit: Some((editor.to_string(), Utc::now())),
+        snapshot_seq: None,
+    };
+    let written = state
+        .documents
+        .write_content(
+            document_id,
+            write,
+            WriteCondition::AtRevision(current.revision),
+        )
         .await?;
+    if written.is_none() {
+        return Err(conflict.into());
+    }
+
+    // Clients can not catch up on a change that went around the op log, they
+    // get a snapshot when they reconnect
+    state.pubsub.reset(document_id).await?;
+
+    move_anchors(state, document_id, previous, content).await;
     Ok(())
 }
 
-async fn user_has_access(email: &String, doc_id: &String, state: &AppState) -> Option<String> {
-    let user = sqlx::query!(
-        r#"
-        SELECT user_role
-        FROM document_relation
-        WHERE user_email = $1
-        AND document_id = $2
-        UNION
-        SELECT group_role FROM groups AS user_role
-        NATURAL JOIN document_relation_group
-        NATURAL JOIN group_members
-        WHERE document_id = $2
-        AND member_email = $1
-        ORDER BY user_role
-        LIMIT 1;
-        "#,
-        email,
-        doc_id,
+// Ends a session that could not start, before anything was sent to the client
+
+async fn refuse(mut socket: WebSocket, connection: ConnectionGuard, message: &str) {
+    let payload = serde_json::to_string(&WsServerMessage::Error {
+        message: message.to_string(),
+    })
+    .unwrap();
+    let _ = socket.send(Message::Text(payload)).await;
+    let _ = socket.send(Message::Close(None)).await;
+    connection.release().await;
+}
+
+fn send_error(direct_tx: &mpsc::UnboundedSender<Message>, message: &str) {
+    let payload = serde_json::to_string(&WsServerMessage::Error {
+        message: message.to_string(),
+    })
+    .unwrap();
+    let _ = direct_tx.send(Message::Text(payload));
+}
+
+// Returns the live content if the document is open, otherwise the persisted
+// content
+
+pub async fn current_content(
+    state: &AppState,
+    document_id: &str,
+) -> Result<Option<String>, Box<dyn Error + Send + Sync>> {
+    let live = state.live.content(document_id).await?;
+    if live.is_some() {
+        return Ok(live);
+    }
+
+    let doc = state.documents.get(document_id).await?;
+    Ok(doc.map(|d| d.content))
+}
+
+// A write based on a revision of the document that is no longer the current
+// one, another writer persisted newer content in between
+#[derive(Debug)]
+pub struct WriteConflict {
+    pub document_id: String,
+    pub found: i64,
+}
+
+impl fmt::Display for WriteConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Document {} was changed by another writer, it is now at revision {}",
+            self.document_id, self.found
+        )
+    }
+}
+
+impl Error for WriteConflict {}
+
+// Persists the live content as a snapshot, then compacts the op log up to
+// the update the snapshot includes. The write only goes through while the
+// document is still at the revision the live content was loaded from, or at
+// an earlier snapshot of the same op log, it never goes backwards.
+
+pub async fn flush_document(state: &AppState, document_id: &str) -> Result<(), Box<dyn Error>> {
+    let (content, seq) = state.pubsub.snapshot(document_id).await?;
+    let content = content.ok_or_else(|| format!("doc:{} is not open", document_id))?;
+    let last_edit = state.live.last_edit(document_id).await?;
+    let base = state.live.base_revision(document_id).await?.unwrap_or(0);
+
+    let write = ContentWrite {
+        content: &content,
+        last_edit,
+        snapshot_seq: Some(seq),
+    };
+    let flushed = state
+        .documents
+        .write_content(document_id, write, WriteCondition::Flush { base, seq })
+        .await?;
+
+    if let Some(flushed) = flushed {
+        state
+            .live
+            .set_base_revision(document_id, flushed.revision)
+            .await?;
+        state.pubsub.compact(document_id, seq).await?;
+        return Ok(());
+    }
+
+    let Some(current) = state.documents.get(document_id).await? else {
+        // Deleted while open, nothing to keep
+        return Ok(());
+    };
+    if current.snapshot_seq.is_some_and(|s| s >= seq as i64) {
+        // A later snapshot of the same op log was flushed in the meantime
+        return Ok(());
+    }
+
+    resolve_conflict(state, document_id, &content, seq, &current).await?;
+    Err(Box::new(WriteConflict {
+        document_id: document_id.to_string(),
+        found: current.revision,
+    }))
+}
+
+// The document was changed around the live content, e.g. by another instance.
+// The persisted content wins, the live content is kept as a recovered
+// version and connected clients are switched over to the persisted one.
+
+async fn resolve_conflict(
+    state: &AppState,
+    document_id: &str,
+    lost: &str,
+    seq: u64,
+    current: &Document,
+) -> Result<(), Box<dyn Error>> {
+    warn!(
+        "Stale flush of doc {} at seq {}, the document is at revision {}",
+        document_id, seq, current.revision
+    );
+    audit::record(
+        state,
+        AuditEvent::WriteConflict,
+        None,
+        Some(document_id),
+        json!({ "seq": seq, "found_revision": current.revision }),
     )
-    .fetch_optional(&state.pg_pool)
-    .await
-    .ok()??;
+    .await;
 
-    Some(user.user_role.unwrap())
+    let lost_editor = state
+        .live
+        .last_edit(document_id)
+        .await?
+        .map(|(editor, _)| editor);
+    recovery::store_version(state, document_id, lost, seq, lost_editor, "conflict").await?;
+
+    // The metadata of the persisted content stays as it is
+    state.live.clear_last_edit(document_id).await?;
+    state
+        .live
+        .set_base_revision(document_id, current.revision)
+        .await?;
+
+    let author = current.last_edited_by.as_deref().unwrap_or_default();
+    let lock = anchors::lock(state, document_id).await?;
+    let swapped = swap_content(state, document_id, &current.content, author).await;
+    if let Ok(Some(previous)) = &swapped {
+        move_anchors(state, document_id, previous, &current.content).await;
+    }
+    lock.release(state).await;
+    swapped?;
+    Ok(())
 }
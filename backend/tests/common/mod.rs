@@ -0,0 +1,294 @@
+// Runs the backend in-process for the integration tests. The stores are in
+// memory unless TEST_STORES=local, then the PostgreSQL, MongoDB and Redis
+// configured in the environment (or .env) are used, like `cargo run` does.
+
+#![allow(dead_code)]
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use axum::Router;
+use axum::body::{Body, to_bytes};
+use axum::http::{HeaderMap, Method, Request, StatusCode, header};
+use backend::config::{
+    CorsOrigins, DEFAULT_MONGO_MAX_POOL_SIZE, DEFAULT_PG_MAX_CONNECTIONS,
+    DEFAULT_REDIS_CONNECTION_STRING, StoreConfig,
+};
+use backend::store::memory::MemoryStore;
+use backend::structs::{AppState, Document};
+use backend::{connect_stores, router};
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{Value, json};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::{Instant, sleep, timeout};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream, connect_async};
+use tower::ServiceExt;
+
+pub const PASSWORD: &str = "password";
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+pub struct TestApp {
+    pub state: AppState,
+    pub router: Router,
+    pub addr: SocketAddr,
+    // Appended to every email, runs against local stores do not see each
+    // other's users and groups
+    suffix: String,
+    stores: Stores,
+}
+
+enum Stores {
+    Memory(MemoryStore),
+    Local { database_url: String },
+}
+
+impl TestApp {
+    pub async fn start() -> TestApp {
+        let suffix = format!("{:08x}", rand::random::<u32>());
+        let (state, stores) = match std::env::var("TEST_STORES").as_deref() {
+            Ok("local") => local_state().await,
+            _ => {
+                let store = MemoryStore::new();
+                (store.app_state(), Stores::Memory(store))
+            }
+        };
+
+        let router = router(state.clone(), &CorsOrigins::Any);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = router.clone();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        TestApp {
+            state,
+            router,
+            addr,
+            suffix,
+            stores,
+        }
+    }
+
+    pub fn email(&self, name: &str) -> String {
+        format!("{}.{}@example.com", name, self.suffix)
+    }
+
+    // Creates a user that can log in with PASSWORD, returns the email
+    pub async fn user(&self, name: &str) -> String {
+        let email = self.email(name);
+        match &self.stores {
+            Stores::Local { database_url } => {
+                let pool = sqlx::PgPool::connect(database_url).await.unwrap();
+                sqlx::query(
+                    "INSERT INTO users (email, password, first_name, last_name)
+                     VALUES ($1, $2, $3, 'Test') ON CONFLICT (email) DO NOTHING",
+                )
+                .bind(&email)
+                .bind(PASSWORD)
+                .bind(name)
+                .execute(&pool)
+                .await
+                .unwrap();
+            }
+            Stores::Memory(store) => store.add_user(&email, PASSWORD, name, "Test"),
+        }
+        email
+    }
+
+    pub async fn post(&self, path: &str, body: Value) -> (StatusCode, Value) {
+        let request = Request::post(path)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = self.router.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body = serde_json::from_slice(&bytes).unwrap_or(Value::Null);
+        (status, body)
+    }
+
+    pub async fn get(&self, path: &str) -> (StatusCode, Value) {
+        let request = Request::get(path).body(Body::empty()).unwrap();
+        let response = self.router.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body = serde_json::from_slice(&bytes).unwrap_or(Value::Null);
+        (status, body)
+    }
+
+    // Calls an /api/v1 route as `user`, a null body is sent as no body
+    pub async fn call(
+        &self,
+        method: Method,
+        path: &str,
+        user: Option<&str>,
+        body: Value,
+    ) -> (StatusCode, HeaderMap, Value) {
+        let mut request = Request::builder().method(method).uri(path);
+        if let Some(user) = user {
+            request = request.header("x-user-email", user);
+        }
+        let request = if body.is_null() {
+            request.body(Body::empty())
+        } else {
+            request
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string()))
+        }
+        .unwrap();
+
+        let response = self.router.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let headers = response.headers().clone();
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body = serde_json::from_slice(&bytes).unwrap_or(Value::Null);
+        (status, headers, body)
+    }
+
+    // Creates a document owned by `owner` and returns its id
+    pub async fn create_document(&self, owner: &str, editors: &[&str], readers: &[&str]) -> String {
+        let title = format!("Document {}", rand::random::<u32>());
+        let (status, _) = self
+            .post(
+                "/save_document_and_relations",
+                json!({
+                    "title": title,
+                    "format": "txt",
+                    "owner": owner,
+                    "collaborators": editors,
+                    "readers": readers,
+                    "groups": [],
+                }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (_, listing) = self
+            .post("/get_all_documents_owner", json!({ "email": owner }))
+            .await;
+        listing["documents"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|d| d["title"] == title)
+            .map(|d| d["id"].as_str().unwrap().to_string())
+            .expect("created document is not listed")
+    }
+
+    pub async fn role(&self, email: &str, document_id: &str) -> Option<String> {
+        let (status, body) = self
+            .post(
+                "/get_user_role",
+                json!({ "email": email, "document_id": document_id }),
+            )
+            .await;
+        (status == StatusCode::OK).then(|| body["user_role"][0].as_str().unwrap().to_string())
+    }
+
+    pub async fn connect(&self, email: &str, document_id: &str) -> Client {
+        let url = format!(
+            "ws://{}/ws?user_email={}&document_id={}",
+            self.addr, email, document_id
+        );
+        let (socket, _) = connect_async(url).await.expect("WebSocket refused");
+        Client { socket }
+    }
+
+    // Follows a published document like an anonymous viewer does
+    pub async fn connect_public(&self, public_id: &str) -> Client {
+        let url = format!("ws://{}/public/{}/ws", self.addr, public_id);
+        let (socket, _) = connect_async(url).await.expect("WebSocket refused");
+        Client { socket }
+    }
+
+    // Waits for the document to be persisted in a state the check accepts
+    pub async fn persisted(
+        &self,
+        document_id: &str,
+        check: impl Fn(&Document) -> bool,
+    ) -> Document {
+        let deadline = Instant::now() + TIMEOUT;
+        loop {
+            let document = self.state.documents.get(document_id).await.unwrap();
+            match document {
+                Some(document) if check(&document) => return document,
+                _ if Instant::now() > deadline => panic!("document was not persisted in time"),
+                _ => sleep(Duration::from_millis(20)).await,
+            }
+        }
+    }
+}
+
+async fn local_state() -> (AppState, Stores) {
+    let _ = dotenvy::dotenv();
+    let var = |name: &str| std::env::var(name).unwrap_or_else(|_| panic!("{} is not set", name));
+    let database_url = var("DATABASE_URL");
+    let config = StoreConfig {
+        database_url: database_url.clone(),
+        mongo_connection_string: var("MONGO_CONNECTION_STRING"),
+        mongo_db_name: var("MONGO_DB_NAME"),
+        redis_connection_string: std::env::var("REDIS_CONNECTION_STRING")
+            .unwrap_or(DEFAULT_REDIS_CONNECTION_STRING.to_string()),
+        pg_max_connections: DEFAULT_PG_MAX_CONNECTIONS,
+        mongo_max_pool_size: DEFAULT_MONGO_MAX_POOL_SIZE,
+    };
+
+    let state = connect_stores(&config).await;
+    (state, Stores::Local { database_url })
+}
+
+// A WebSocket client on a document
+pub struct Client {
+    socket: WebSocketStream<MaybeTlsStream<TcpStream>>,
+}
+
+impl Client {
+    pub async fn send(&mut self, message: Value) {
+        self.socket
+            .send(Message::Text(message.to_string()))
+            .await
+            .unwrap();
+    }
+
+    pub async fn edit(&mut self, content: &str) {
+        self.send(json!({ "type": "edit", "content": content }))
+            .await;
+    }
+
+    // Skips messages until one of the given type arrives
+    pub async fn next_of(&mut self, kind: &str) -> Value {
+        let wait = async {
+            loop {
+                let message = self.next().await;
+                if message["type"] == kind {
+                    return message;
+                }
+            }
+        };
+        timeout(TIMEOUT, wait)
+            .await
+            .unwrap_or_else(|_| panic!("no {} message in time", kind))
+    }
+
+    // The next message, whatever its type
+    pub async fn next(&mut self) -> Value {
+        let wait = async {
+            while let Some(message) = self.socket.next().await {
+                if let Message::Text(text) = message.unwrap() {
+                    return serde_json::from_str(&text).unwrap();
+                }
+            }
+            panic!("WebSocket closed while waiting for a message");
+        };
+        timeout(TIMEOUT, wait)
+            .await
+            .unwrap_or_else(|_| panic!("no message in time"))
+    }
+
+    pub async fn close(mut self) {
+        self.socket.close(None).await.unwrap();
+        // Read until the server acknowledges the close
+        while let Some(Ok(_)) = self.socket.next().await {}
+    }
+}
@@ -0,0 +1,318 @@
+mod common;
+
+use axum::body::{Body, to_bytes};
+use axum::http::{Request, StatusCode, header};
+use common::{PASSWORD, TestApp};
+use serde_json::{Value, json};
+use tower::ServiceExt;
+
+#[tokio::test]
+async fn login_checks_the_password() {
+    let app = TestApp::start().await;
+    let alice = app.user("alice").await;
+
+    let (status, body) = app
+        .post("/login", json!({ "email": alice, "password": PASSWORD }))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["user"]["email"], alice.as_str());
+
+    let (status, body) = app
+        .post("/login", json!({ "email": alice, "password": "wrong" }))
+        .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(body["success"], false);
+}
+
+#[tokio::test]
+async fn created_documents_are_listed_with_their_roles() {
+    let app = TestApp::start().await;
+    let alice = app.user("alice").await;
+    let bob = app.user("bob").await;
+    let carol = app.user("carol").await;
+
+    let document_id = app.create_document(&alice, &[&bob], &[&carol]).await;
+
+    assert_eq!(
+        app.role(&alice, &document_id).await.as_deref(),
+        Some("owner")
+    );
+    assert_eq!(
+        app.role(&bob, &document_id).await.as_deref(),
+        Some("editor")
+    );
+    assert_eq!(
+        app.role(&carol, &document_id).await.as_deref(),
+        Some("reader")
+    );
+
+    let (_, shared) = app
+        .post("/get_all_documents_shared", json!({ "email": carol }))
+        .await;
+    assert_eq!(shared["documents"][0]["id"], document_id.as_str());
+    assert_eq!(shared["documents"][0]["owner_email"], alice.as_str());
+
+    let stored = app
+        .state
+        .documents
+        .get(&document_id)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(stored.format, "txt");
+    assert_eq!(stored.content, "");
+}
+
+#[tokio::test]
+async fn sharing_is_checked_and_can_be_undone() {
+    let app = TestApp::start().await;
+    let alice = app.user("alice").await;
+    let bob = app.user("bob").await;
+    let dave = app.user("dave").await;
+    let document_id = app.create_document(&alice, &[&bob], &[]).await;
+
+    // Editors can not share
+    let (status, _) = app
+        .post(
+            "/share_document",
+            json!({
+                "email": bob,
+                "document_id": document_id,
+                "target_email": dave,
+                "role": "reader",
+            }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(app.role(&dave, &document_id).await, None);
+
+    let (status, _) = app
+        .post(
+            "/share_document",
+            json!({
+                "email": alice,
+                "document_id": document_id,
+                "target_email": dave,
+                "role": "editor",
+            }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        app.role(&dave, &document_id).await.as_deref(),
+        Some("editor")
+    );
+
+    let (status, _) = app
+        .post(
+            "/unshare_document",
+            json!({ "email": alice, "document_id": document_id, "target_email": dave }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(app.role(&dave, &document_id).await, None);
+}
+
+#[tokio::test]
+async fn group_members_get_the_group_role() {
+    let app = TestApp::start().await;
+    let alice = app.user("alice").await;
+    let erin = app.user("erin").await;
+    let frank = app.user("frank").await;
+    let document_id = app.create_document(&alice, &[], &[]).await;
+
+    // A member listed twice is a malformed request, not an existing group
+    let (status, body) = app
+        .post(
+            "/create_group",
+            json!([{ "owner": alice, "name": "Reviewers", "role": "reader", "members": [erin, erin] }]),
+        )
+        .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(
+        body["message"],
+        format!("{} is listed more than once in the members", erin)
+    );
+
+    let (status, _) = app
+        .post(
+            "/create_group",
+            json!([{ "owner": alice, "name": "Reviewers", "role": "reader", "members": [erin] }]),
+        )
+        .await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    let (_, groups) = app
+        .post("/get_groups_by_owner", json!({ "email": alice }))
+        .await;
+    assert_eq!(groups["groups"][0]["group_name"], "Reviewers");
+    let group_id = groups["groups"][0]["group_id"].as_i64().unwrap();
+
+    let (status, _) = app
+        .post(
+            "/share_document_group",
+            json!({ "email": alice, "document_id": document_id, "group_id": group_id }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, role) = app
+        .post(
+            "/get_user_role",
+            json!({ "email": erin, "document_id": document_id }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(role["user_role"][0], "reader");
+    assert_eq!(role["source"]["group_id"], group_id);
+    assert_eq!(app.role(&frank, &document_id).await, None);
+
+    let (_, shared) = app
+        .post("/get_all_documents_shared", json!({ "email": erin }))
+        .await;
+    assert_eq!(shared["documents"][0]["id"], document_id.as_str());
+
+    let (status, _) = app
+        .post(
+            "/unshare_document_group",
+            json!({ "email": alice, "document_id": document_id, "group_id": group_id }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(app.role(&erin, &document_id).await, None);
+}
+
+#[tokio::test]
+async fn errors_carry_a_stable_code() {
+    let app = TestApp::start().await;
+    let alice = app.user("alice").await;
+    let document_id = app.create_document(&alice, &[], &[]).await;
+    let group = json!([{ "owner": alice, "name": "Team", "role": "editor", "members": [alice] }]);
+
+    let (status, _) = app.post("/create_group", group.clone()).await;
+    assert_eq!(status, StatusCode::CREATED);
+    let (status, body) = app.post("/create_group", group).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(body["code"], "conflict");
+
+    let (status, body) = app
+        .post(
+            "/share_document",
+            json!({
+                "email": alice,
+                "document_id": document_id,
+                "target_email": app.email("nobody"),
+                "role": "reader",
+            }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["code"], "invalid_reference");
+
+    let (status, body) = app
+        .post(
+            "/get_user_role",
+            json!({ "email": app.email("nobody"), "document_id": document_id }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["code"], "not_found");
+}
+
+#[tokio::test]
+async fn unreadable_bodies_are_bad_requests() {
+    let app = TestApp::start().await;
+
+    let request = Request::post("/get_all_documents_owner")
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from("{\"email\": "))
+        .unwrap();
+    let response = app.router.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let body: Value = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(body["code"], "bad_request");
+    assert_eq!(body["success"], false);
+
+    let (status, body) = app
+        .post("/get_all_documents_owner", json!({ "email": 5 }))
+        .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["code"], "bad_request");
+}
+
+#[tokio::test]
+async fn saved_documents_ignore_server_managed_fields() {
+    let app = TestApp::start().await;
+
+    let (status, body) = app
+        .post(
+            "/save_document",
+            json!({
+                "title": "Notes",
+                "content": "hello world",
+                "format": "txt",
+                "publication": { "public_id": "leak", "live": true },
+                "exclusive_editing": true,
+                "revision": 42,
+                "word_count": 1000,
+            }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    let document_id = body["inserted_id"]["$oid"].as_str().unwrap();
+    let stored = app.state.documents.get(document_id).await.unwrap().unwrap();
+    assert!(stored.publication.is_none());
+    assert!(stored.template.is_none());
+    assert!(!stored.exclusive_editing);
+    assert_eq!(stored.revision, 0);
+    assert_eq!(stored.word_count, 2);
+}
+
+#[tokio::test]
+async fn listings_are_typed_json() {
+    let app = TestApp::start().await;
+    let alice = app.user("alice").await;
+    let document_id = app.create_document(&alice, &[], &[]).await;
+
+    let request = Request::post("/get_all_documents_owner")
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(json!({ "email": alice }).to_string()))
+        .unwrap();
+    let response = app.router.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+
+    let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let body: Value = serde_json::from_slice(&bytes).unwrap();
+    let document = &body["documents"][0];
+    assert_eq!(document["id"], document_id.as_str());
+    assert_eq!(document["favorite"], false);
+    assert_eq!(document["tags"], json!([]));
+    assert_eq!(document["metadata"]["revision"], 0);
+}
+
+#[tokio::test]
+async fn openapi_document_describes_the_api() {
+    let app = TestApp::start().await;
+
+    let (status, spec) = app.get("/openapi.json").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(spec["openapi"].as_str().unwrap().starts_with("3."));
+
+    let login = &spec["paths"]["/login"]["post"];
+    assert_eq!(
+        login["requestBody"]["content"]["application/json"]["schema"]["$ref"],
+        "#/components/schemas/LoginRequest"
+    );
+    assert_eq!(
+        login["responses"]["default"]["content"]["application/json"]["schema"]["$ref"],
+        "#/components/schemas/ErrorResponse"
+    );
+    assert!(spec["paths"]["/ws"]["get"].is_object());
+
+    let schemas = &spec["components"]["schemas"];
+    for name in ["DocumentSummary", "WsClientMessage", "WsServerMessage"] {
+        assert!(schemas[name].is_object(), "{} is missing", name);
+    }
+}
@@ -0,0 +1,218 @@
+mod common;
+
+use axum::http::{Method, StatusCode};
+use common::TestApp;
+use serde_json::{Value, json};
+
+#[tokio::test]
+async fn documents_are_resources() {
+    let app = TestApp::start().await;
+    let alice = app.user("alice").await;
+    let bob = app.user("bob").await;
+
+    let (status, _, created) = app
+        .call(
+            Method::POST,
+            "/api/v1/documents",
+            Some(&alice),
+            json!({ "title": "Draft", "format": "txt", "readers": [bob] }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::CREATED);
+    let document_id = created["document_id"].as_str().unwrap().to_string();
+    let path = format!("/api/v1/documents/{}", document_id);
+
+    let (status, _, document) = app.call(Method::GET, &path, Some(&bob), Value::Null).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(document["document"]["title"], "Draft");
+    assert_eq!(document["document"]["role"], "reader");
+    assert_eq!(document["content"], "");
+
+    // Readers can not rename
+    let (status, _, _) = app
+        .call(Method::PATCH, &path, Some(&bob), json!({ "title": "Mine" }))
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let (status, _, _) = app
+        .call(
+            Method::PATCH,
+            &path,
+            Some(&alice),
+            json!({ "title": "Final" }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (_, _, listing) = app
+        .call(Method::GET, "/api/v1/documents", Some(&alice), Value::Null)
+        .await;
+    assert_eq!(listing["documents"][0]["id"], document_id.as_str());
+    assert_eq!(listing["documents"][0]["title"], "Final");
+
+    let (_, _, shared) = app
+        .call(
+            Method::GET,
+            "/api/v1/documents/shared",
+            Some(&bob),
+            Value::Null,
+        )
+        .await;
+    assert_eq!(shared["documents"][0]["id"], document_id.as_str());
+
+    let (status, _, _) = app
+        .call(Method::DELETE, &path, Some(&alice), Value::Null)
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _, _) = app
+        .call(Method::GET, &path, Some(&alice), Value::Null)
+        .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn refused_updates_change_nothing() {
+    let app = TestApp::start().await;
+    let alice = app.user("alice").await;
+    let bob = app.user("bob").await;
+    let document_id = app.create_document(&alice, &[&bob], &[]).await;
+    let path = format!("/api/v1/documents/{}", document_id);
+
+    // Editors may rename but not give the document away
+    let (status, _, _) = app
+        .call(
+            Method::PATCH,
+            &path,
+            Some(&bob),
+            json!({ "title": "Mine", "owner": bob }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let (status, _, body) = app
+        .call(
+            Method::PATCH,
+            &path,
+            Some(&alice),
+            json!({ "title": "Renamed", "owner": app.email("nobody") }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["code"], "invalid_reference");
+
+    let (_, _, document) = app
+        .call(Method::GET, &path, Some(&alice), Value::Null)
+        .await;
+    assert_ne!(document["document"]["title"], "Mine");
+    assert_ne!(document["document"]["title"], "Renamed");
+    assert_eq!(document["document"]["role"], "owner");
+
+    let (status, _, _) = app
+        .call(
+            Method::PATCH,
+            &path,
+            Some(&alice),
+            json!({ "title": "Renamed", "owner": bob }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let (_, _, document) = app.call(Method::GET, &path, Some(&bob), Value::Null).await;
+    assert_eq!(document["document"]["title"], "Renamed");
+    assert_eq!(document["document"]["role"], "owner");
+}
+
+#[tokio::test]
+async fn group_members_are_managed_by_the_owner() {
+    let app = TestApp::start().await;
+    let alice = app.user("alice").await;
+    let erin = app.user("erin").await;
+    let frank = app.user("frank").await;
+
+    let (status, _, created) = app
+        .call(
+            Method::POST,
+            "/api/v1/groups",
+            Some(&alice),
+            json!({ "name": "Reviewers", "role": "reader", "members": [erin] }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::CREATED);
+    let members = format!(
+        "/api/v1/groups/{}/members",
+        created["group"]["group_id"].as_i64().unwrap()
+    );
+
+    let (status, _, _) = app
+        .call(Method::GET, &members, Some(&erin), Value::Null)
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let frank_path = format!("{}/{}", members, frank);
+    let (status, _, _) = app
+        .call(Method::PUT, &frank_path, Some(&alice), Value::Null)
+        .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (_, _, listed) = app
+        .call(Method::GET, &members, Some(&alice), Value::Null)
+        .await;
+    let mut listed: Vec<&str> = listed["members"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|member| member.as_str().unwrap())
+        .collect();
+    listed.sort();
+    assert_eq!(listed, [erin.as_str(), frank.as_str()]);
+
+    let (status, _, _) = app
+        .call(Method::DELETE, &frank_path, Some(&alice), Value::Null)
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _, body) = app
+        .call(Method::DELETE, &frank_path, Some(&alice), Value::Null)
+        .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["success"], false);
+}
+
+#[tokio::test]
+async fn the_user_header_is_required() {
+    let app = TestApp::start().await;
+
+    let (status, _, body) = app
+        .call(Method::GET, "/api/v1/documents", None, Value::Null)
+        .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(body["success"], false);
+}
+
+#[tokio::test]
+async fn legacy_routes_are_deprecated_aliases() {
+    let app = TestApp::start().await;
+    let alice = app.user("alice").await;
+
+    let (status, headers, _) = app
+        .call(
+            Method::POST,
+            "/get_all_documents_owner",
+            None,
+            json!({ "email": alice }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(headers["deprecation"], "true");
+
+    let (_, headers, _) = app
+        .call(Method::GET, "/api/v1/documents", Some(&alice), Value::Null)
+        .await;
+    assert!(headers.get("deprecation").is_none());
+
+    let (_, _, spec) = app
+        .call(Method::GET, "/openapi.json", None, Value::Null)
+        .await;
+    assert_eq!(spec["paths"]["/login"]["post"]["deprecated"], true);
+    let patch = &spec["paths"]["/api/v1/documents/{id}"]["patch"];
+    assert!(patch["deprecated"].is_null());
+    assert_eq!(patch["security"][0]["user_email"], json!([]));
+}
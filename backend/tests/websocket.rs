@@ -0,0 +1,211 @@
+mod common;
+
+use axum::http::StatusCode;
+use common::TestApp;
+use serde_json::json;
+
+#[tokio::test]
+async fn two_editors_and_a_reader_share_one_document() {
+    let app = TestApp::start().await;
+    let alice = app.user("alice").await;
+    let bob = app.user("bob").await;
+    let carol = app.user("carol").await;
+    let document_id = app.create_document(&alice, &[&bob], &[&carol]).await;
+
+    let mut editor = app.connect(&alice, &document_id).await;
+    let mut co_editor = app.connect(&bob, &document_id).await;
+    let mut reader = app.connect(&carol, &document_id).await;
+    for client in [&mut editor, &mut co_editor, &mut reader] {
+        let snapshot = client.next_of("content").await;
+        assert_eq!(snapshot["content"], "");
+    }
+
+    // Every client sees every edit, in the order they were made
+    editor.edit("Hello").await;
+    for client in [&mut editor, &mut co_editor, &mut reader] {
+        let update = client.next_of("content").await;
+        assert_eq!(update["content"], "Hello");
+        assert_eq!(update["seq"], 1);
+    }
+
+    co_editor.edit("Hello world").await;
+    for client in [&mut editor, &mut co_editor, &mut reader] {
+        let update = client.next_of("content").await;
+        assert_eq!(update["content"], "Hello world");
+        assert_eq!(update["seq"], 2);
+    }
+
+    // Only the reader hears about its refused edit
+    reader.edit("Goodbye").await;
+    let error = reader.next_of("error").await;
+    assert_eq!(error["message"], "Readers can not edit this document");
+
+    // The last client to leave persists the content
+    editor.close().await;
+    co_editor.close().await;
+    reader.close().await;
+
+    let stored = app
+        .persisted(&document_id, |d| d.content == "Hello world")
+        .await;
+    assert_eq!(stored.last_edited_by.as_deref(), Some(bob.as_str()));
+    assert_eq!(stored.word_count, 2);
+    assert!(!app.state.live.is_open(&document_id).await.unwrap());
+}
+
+#[tokio::test]
+async fn strangers_can_not_open_a_document() {
+    let app = TestApp::start().await;
+    let alice = app.user("alice").await;
+    let mallory = app.user("mallory").await;
+    let document_id = app.create_document(&alice, &[], &[]).await;
+
+    let url = format!(
+        "ws://{}/ws?user_email={}&document_id={}",
+        app.addr, mallory, document_id
+    );
+    assert!(tokio_tungstenite::connect_async(url).await.is_err());
+}
+
+#[tokio::test]
+async fn suggestions_are_applied_only_when_accepted() {
+    let app = TestApp::start().await;
+    let alice = app.user("alice").await;
+    let bob = app.user("bob").await;
+    let document_id = app.create_document(&alice, &[], &[]).await;
+    let (status, _) = app
+        .post(
+            "/share_document",
+            json!({
+                "email": alice,
+                "document_id": document_id,
+                "target_email": bob,
+                "role": "suggester",
+            }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let mut owner = app.connect(&alice, &document_id).await;
+    let mut suggester = app.connect(&bob, &document_id).await;
+    owner.next_of("content").await;
+    suggester.next_of("content").await;
+    owner.edit("Hello world").await;
+    owner.next_of("content").await;
+    suggester.next_of("content").await;
+
+    // A suggester's edit is only proposed, the content stays as it is
+    suggester.edit("Hello there").await;
+    let created = owner.next_of("suggestion").await;
+    assert_eq!(created["event"], "created");
+    assert_eq!(created["suggestion"]["original"], "world");
+    assert_eq!(created["suggestion"]["replacement"], "there");
+    let review = json!({
+        "email": alice,
+        "document_id": document_id,
+        "suggestion_id": created["suggestion"]["_id"],
+    });
+
+    let (status, _) = app.post("/reject_suggestion", review.clone()).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(owner.next_of("suggestion").await["event"], "rejected");
+    let (status, _) = app.post("/accept_suggestion", review).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(
+        app.state
+            .live
+            .content(&document_id)
+            .await
+            .unwrap()
+            .as_deref(),
+        Some("Hello world")
+    );
+
+    // Accepting applies the replacement for everyone
+    suggester.edit("Hello, world").await;
+    let created = owner.next_of("suggestion").await;
+    assert_eq!(created["event"], "created");
+    let (status, body) = app
+        .post(
+            "/accept_suggestion",
+            json!({
+                "email": alice,
+                "document_id": document_id,
+                "suggestion_id": created["suggestion"]["_id"],
+            }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["suggestion"]["status"], "accepted");
+    assert_eq!(owner.next_of("content").await["content"], "Hello, world");
+
+    owner.close().await;
+    suggester.close().await;
+}
+
+#[tokio::test]
+async fn public_viewers_only_get_content() {
+    let app = TestApp::start().await;
+    let alice = app.user("alice").await;
+    let bob = app.user("bob").await;
+    let document_id = app.create_document(&alice, &[], &[]).await;
+    let (status, _) = app
+        .post(
+            "/share_document",
+            json!({
+                "email": alice,
+                "document_id": document_id,
+                "target_email": bob,
+                "role": "suggester",
+            }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, published) = app
+        .post(
+            "/publish_document",
+            json!({ "email": alice, "document_id": document_id, "live": true }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let public_id = published["publication"]["public_id"].as_str().unwrap();
+
+    let mut owner = app.connect(&alice, &document_id).await;
+    let mut suggester = app.connect(&bob, &document_id).await;
+    let mut viewer = app.connect_public(public_id).await;
+    for client in [&mut owner, &mut suggester, &mut viewer] {
+        assert_eq!(client.next_of("content").await["content"], "");
+    }
+    owner.edit("Hello world").await;
+    owner.next_of("content").await;
+    suggester.next_of("content").await;
+
+    // Comments, suggestions and lock holders all carry the names of people
+    owner
+        .send(json!({ "type": "create_comment", "start": 0, "end": 5, "body": "Hi?" }))
+        .await;
+    owner.next_of("comment").await;
+    suggester.edit("Hello there").await;
+    owner.next_of("suggestion").await;
+    let (status, _) = app
+        .post(
+            "/set_exclusive_editing",
+            json!({ "email": alice, "document_id": document_id, "enabled": true }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    owner.send(json!({ "type": "acquire_lock" })).await;
+    while owner.next_of("lock").await["holder"] != alice.as_str() {}
+    owner.edit("Hello world!").await;
+    owner.next_of("content").await;
+
+    let update = viewer.next().await;
+    assert_eq!(update["content"], "Hello world");
+    let update = viewer.next().await;
+    assert_eq!(update["type"], "content");
+    assert_eq!(update["content"], "Hello world!");
+
+    owner.close().await;
+    suggester.close().await;
+    viewer.close().await;
+}
@@ -18,7 +18,7 @@ export function DocumentEditor({ document, setDocument}: IDocumentEditorProps) {
 
     function handleChange(e: ChangeEvent<HTMLTextAreaElement>) {
         // setDocument({...document, ['content']: e.target.value})
-        sendMessage(e.target.value);
+        sendMessage(JSON.stringify({ type: "edit", content: e.target.value }));
     }
 
     return (
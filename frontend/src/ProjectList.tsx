@@ -41,10 +41,20 @@ export function ProjectList({ setDocument, email, ownedProjects, sharedProjects,
         const url = `ws://localhost:3000/ws?user_email=${encodeURIComponent(email)}&document_id=${encodeURIComponent(doc_id)}`;
 
         connect(url, (event) => {
+            const msg = JSON.parse(event.data);
+
+            if (msg.type === "error") {
+                console.warn("Server:", msg.message);
+                return;
+            }
+            if (msg.type !== "content") {
+                return;
+            }
+
             const document: DocumentData = {
                 doc_id,
                 name,
-                content: event.data,
+                content: msg.content,
                 format,
                 owner_email,
                 /* userRole, */
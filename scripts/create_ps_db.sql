@@ -6,7 +6,7 @@ CREATE DOMAIN public.role
 ALTER DOMAIN public.role OWNER TO postgres;
 
 ALTER DOMAIN public.role
-    ADD CONSTRAINT role_check CHECK (VALUE ~ 'owner'::text OR VALUE ~ 'editor'::text OR VALUE ~ 'reader'::text);
+    ADD CONSTRAINT role_check CHECK (VALUE ~ 'owner'::text OR VALUE ~ 'editor'::text OR VALUE ~ 'reader'::text OR VALUE ~ 'suggester'::text);
 
 
 CREATE TABLE IF NOT EXISTS public.document_relation
@@ -14,6 +14,8 @@ CREATE TABLE IF NOT EXISTS public.document_relation
     user_email character varying(50) COLLATE pg_catalog."default" NOT NULL,
     document_id character varying(100) COLLATE pg_catalog."default" NOT NULL,
     user_role role COLLATE pg_catalog."default" NOT NULL,
+    valid_from timestamp with time zone,
+    valid_until timestamp with time zone,
     CONSTRAINT document_relation_pkey PRIMARY KEY (user_email, document_id)
 );
 
@@ -21,6 +23,8 @@ CREATE TABLE IF NOT EXISTS public.document_relation_group
 (
     group_id serial NOT NULL,
     document_id character varying(100) COLLATE pg_catalog."default" NOT NULL,
+    valid_from timestamp with time zone,
+    valid_until timestamp with time zone,
     CONSTRAINT document_relation_group_pkey PRIMARY KEY (group_id, document_id)
 );
 
@@ -49,6 +53,113 @@ CREATE TABLE IF NOT EXISTS public.users
     CONSTRAINT users_pkey PRIMARY KEY (email)
 );
 
+CREATE TABLE IF NOT EXISTS public.audit_log
+(
+    event_id bigserial NOT NULL,
+    occurred_at timestamp with time zone NOT NULL DEFAULT now(),
+    event_type character varying(50) COLLATE pg_catalog."default" NOT NULL,
+    actor_email character varying(50) COLLATE pg_catalog."default",
+    document_id character varying(100) COLLATE pg_catalog."default",
+    details jsonb NOT NULL DEFAULT '{}'::jsonb,
+    CONSTRAINT audit_log_pkey PRIMARY KEY (event_id)
+);
+
+CREATE INDEX IF NOT EXISTS audit_log_document_idx
+    ON public.audit_log (document_id, occurred_at);
+
+-- The audit log is append-only
+CREATE OR REPLACE FUNCTION public.audit_log_append_only()
+    RETURNS trigger
+    LANGUAGE plpgsql
+AS $$
+BEGIN
+    RAISE EXCEPTION 'audit_log is append-only';
+END;
+$$;
+
+CREATE TRIGGER audit_log_append_only
+    BEFORE UPDATE OR DELETE ON public.audit_log
+    FOR EACH ROW EXECUTE FUNCTION public.audit_log_append_only();
+
+CREATE TABLE IF NOT EXISTS public.share_links
+(
+    link_id serial NOT NULL,
+    token character varying(64) COLLATE pg_catalog."default" NOT NULL,
+    document_id character varying(100) COLLATE pg_catalog."default" NOT NULL,
+    link_role role COLLATE pg_catalog."default" NOT NULL,
+    created_by character varying(50) COLLATE pg_catalog."default" NOT NULL,
+    created_at timestamp with time zone NOT NULL DEFAULT now(),
+    expires_at timestamp with time zone,
+    max_uses integer,
+    use_count integer NOT NULL DEFAULT 0,
+    revoked boolean NOT NULL DEFAULT false,
+    CONSTRAINT share_links_pkey PRIMARY KEY (link_id),
+    CONSTRAINT share_links_token_key UNIQUE (token),
+    CONSTRAINT share_links_role_check CHECK (link_role::text = ANY (ARRAY['reader'::text, 'editor'::text]))
+);
+
+-- Users that have redeemed a share link, each counts as one use
+CREATE TABLE IF NOT EXISTS public.share_link_redemptions
+(
+    link_id integer NOT NULL,
+    user_email character varying(50) COLLATE pg_catalog."default" NOT NULL,
+    redeemed_at timestamp with time zone NOT NULL DEFAULT now(),
+    CONSTRAINT share_link_redemptions_pkey PRIMARY KEY (link_id, user_email)
+);
+
+-- Folders nest through parent_id, top-level folders have none. With
+-- inherit_permissions set, grants on a folder also apply to everything in it.
+CREATE TABLE IF NOT EXISTS public.folders
+(
+    folder_id serial NOT NULL,
+    folder_name character varying(100) COLLATE pg_catalog."default" NOT NULL,
+    owner_email character varying(50) COLLATE pg_catalog."default" NOT NULL,
+    parent_id integer,
+    inherit_permissions boolean NOT NULL DEFAULT false,
+    created_at timestamp with time zone NOT NULL DEFAULT now(),
+    CONSTRAINT folders_pkey PRIMARY KEY (folder_id)
+);
+
+CREATE TABLE IF NOT EXISTS public.folder_documents
+(
+    document_id character varying(100) COLLATE pg_catalog."default" NOT NULL,
+    folder_id integer NOT NULL,
+    CONSTRAINT folder_documents_pkey PRIMARY KEY (document_id)
+);
+
+CREATE TABLE IF NOT EXISTS public.folder_relation
+(
+    user_email character varying(50) COLLATE pg_catalog."default" NOT NULL,
+    folder_id integer NOT NULL,
+    user_role role COLLATE pg_catalog."default" NOT NULL,
+    CONSTRAINT folder_relation_pkey PRIMARY KEY (user_email, folder_id)
+);
+
+-- Per-user organisation of documents: tags, favorites and recently opened
+CREATE TABLE IF NOT EXISTS public.document_tags
+(
+    user_email character varying(50) COLLATE pg_catalog."default" NOT NULL,
+    document_id character varying(100) COLLATE pg_catalog."default" NOT NULL,
+    tag character varying(50) COLLATE pg_catalog."default" NOT NULL,
+    CONSTRAINT document_tags_pkey PRIMARY KEY (user_email, document_id, tag)
+);
+
+CREATE TABLE IF NOT EXISTS public.document_favorites
+(
+    user_email character varying(50) COLLATE pg_catalog."default" NOT NULL,
+    document_id character varying(100) COLLATE pg_catalog."default" NOT NULL,
+    starred_at timestamp with time zone NOT NULL DEFAULT now(),
+    CONSTRAINT document_favorites_pkey PRIMARY KEY (user_email, document_id)
+);
+
+CREATE TABLE IF NOT EXISTS public.recent_documents
+(
+    user_email character varying(50) COLLATE pg_catalog."default" NOT NULL,
+    document_id character varying(100) COLLATE pg_catalog."default" NOT NULL,
+    opened_at timestamp with time zone NOT NULL DEFAULT now(),
+    CONSTRAINT recent_documents_pkey PRIMARY KEY (user_email, document_id)
+);
+
 ALTER TABLE IF EXISTS public.document_relation
     ADD CONSTRAINT email_fk FOREIGN KEY (user_email)
     REFERENCES public.users (email) MATCH SIMPLE
@@ -86,6 +197,72 @@ ALTER TABLE IF EXISTS public.groups
     ON UPDATE NO ACTION
     ON DELETE NO ACTION;
 
+ALTER TABLE IF EXISTS public.share_links
+    ADD CONSTRAINT created_by_fk FOREIGN KEY (created_by)
+    REFERENCES public.users (email) MATCH SIMPLE
+    ON UPDATE NO ACTION
+    ON DELETE NO ACTION;
+
+ALTER TABLE IF EXISTS public.share_link_redemptions
+    ADD CONSTRAINT link_id_fk FOREIGN KEY (link_id)
+    REFERENCES public.share_links (link_id) MATCH SIMPLE
+    ON UPDATE NO ACTION
+    ON DELETE CASCADE;
+
+ALTER TABLE IF EXISTS public.share_link_redemptions
+    ADD CONSTRAINT user_email_fk FOREIGN KEY (user_email)
+    REFERENCES public.users (email) MATCH SIMPLE
+    ON UPDATE NO ACTION
+    ON DELETE NO ACTION;
+
+ALTER TABLE IF EXISTS public.folders
+    ADD CONSTRAINT owner_email_fk FOREIGN KEY (owner_email)
+    REFERENCES public.users (email) MATCH SIMPLE
+    ON UPDATE NO ACTION
+    ON DELETE NO ACTION;
+
+ALTER TABLE IF EXISTS public.folders
+    ADD CONSTRAINT parent_id_fk FOREIGN KEY (parent_id)
+    REFERENCES public.folders (folder_id) MATCH SIMPLE
+    ON UPDATE NO ACTION
+    ON DELETE CASCADE;
+
+ALTER TABLE IF EXISTS public.folder_documents
+    ADD CONSTRAINT folder_id_fk FOREIGN KEY (folder_id)
+    REFERENCES public.folders (folder_id) MATCH SIMPLE
+    ON UPDATE NO ACTION
+    ON DELETE CASCADE;
+
+ALTER TABLE IF EXISTS public.folder_relation
+    ADD CONSTRAINT folder_id_fk FOREIGN KEY (folder_id)
+    REFERENCES public.folders (folder_id) MATCH SIMPLE
+    ON UPDATE NO ACTION
+    ON DELETE CASCADE;
+
+ALTER TABLE IF EXISTS public.folder_relation
+    ADD CONSTRAINT user_email_fk FOREIGN KEY (user_email)
+    REFERENCES public.users (email) MATCH SIMPLE
+    ON UPDATE NO ACTION
+    ON DELETE NO ACTION;
+
+ALTER TABLE IF EXISTS public.document_tags
+    ADD CONSTRAINT user_email_fk FOREIGN KEY (user_email)
+    REFERENCES public.users (email) MATCH SIMPLE
+    ON UPDATE NO ACTION
+    ON DELETE NO ACTION;
+
+ALTER TABLE IF EXISTS public.document_favorites
+    ADD CONSTRAINT user_email_fk FOREIGN KEY (user_email)
+    REFERENCES public.users (email) MATCH SIMPLE
+    ON UPDATE NO ACTION
+    ON DELETE NO ACTION;
+
+ALTER TABLE IF EXISTS public.recent_documents
+    ADD CONSTRAINT user_email_fk FOREIGN KEY (user_email)
+    REFERENCES public.users (email) MATCH SIMPLE
+    ON UPDATE NO ACTION
+    ON DELETE NO ACTION;
+
 ALTER TABLE IF EXISTS public.groups
     ADD CONSTRAINT unique_group_name
     UNIQUE (owner_email, group_name);